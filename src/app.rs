@@ -0,0 +1,215 @@
+use actix::prelude::*;
+use std::{
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    center,
+    control::audit,
+    core::{
+        alerting,
+        app_state,
+        disk_guard,
+        env,
+        http_admin,
+        log_shipper,
+        reload,
+        status_aggregator,
+        telemetry,
+    },
+    worker::{
+        dispatcher,
+        node_registry,
+        processor,
+        router,
+        startup::{self, TaskRegistry},
+        task_history,
+        task_stats,
+        task_tree,
+    },
+};
+
+/// The configuration file used unless set by `PatokaApp::config`.
+pub const DEFAULT_CONFIG: &str = "cfg/patoka.toml";
+
+type ReadyFn = Box<dyn FnOnce() + Send>;
+
+/// Configures the app and starts it in its own thread.
+pub struct PatokaApp {
+    name: String,
+    config: String,
+    registry: Option<TaskRegistry>,
+    http_admin: Option<String>,
+    on_ready: Option<ReadyFn>,
+    center: bool,
+}
+
+impl PatokaApp {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            config: DEFAULT_CONFIG.to_string(),
+            registry: None,
+            http_admin: None,
+            on_ready: None,
+            center: true,
+        }
+    }
+
+    /// The configuration file.
+    pub fn config(mut self, path: &str) -> Self {
+        self.config = path.to_string();
+        self
+    }
+
+    /// The clients of the startup tasks.
+    pub fn with_task_registry(mut self, registry: TaskRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Serve `http_admin` on `addr`, e.g. "127.0.0.1:8081".
+    pub fn with_http_admin(mut self, addr: &str) -> Self {
+        self.http_admin = Some(addr.to_string());
+        self
+    }
+
+    /// Called in the system of the app once the services have started,
+    /// e.g. to submit the tasks.
+    pub fn on_ready<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.on_ready = Some(Box::new(f));
+        self
+    }
+
+    /// Do not connect to the centers. See `connector::disable`.
+    pub fn disable_center(mut self) -> Self {
+        self.center = false;
+        self
+    }
+
+    /// Load the configuration and start the app. Returns once the app is
+    /// ready.
+    pub fn start(self) -> Result<AppHandle, String> {
+        env::load(&self.config)
+            .map_err(|e| format!("{}: {}", self.config, e))?;
+
+        if let Some(registry) = self.registry {
+            registry.register();
+        }
+
+        if !self.center {
+            center::connector::disable();
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let http_admin = self.http_admin;
+        let on_ready = self.on_ready;
+
+        let thread = thread::Builder::new()
+            .name(self.name.clone())
+            .spawn(move || {
+                let system = System::new();
+
+                let ready = system.block_on(async {
+                    start_services();
+
+                    if let Some(addr) = http_admin {
+                        http_admin::start(&addr).await?;
+                    }
+
+                    if let Some(f) = on_ready {
+                        f();
+                    }
+
+                    Ok::<_, String>(System::current())
+                });
+
+                let failed = ready.is_err();
+                let _ = tx.send(ready);
+
+                if failed {
+                    return Ok(());
+                }
+
+                system.run().map_err(|e| e.to_string())
+            })
+            .map_err(|e| e.to_string())?;
+
+        match rx.recv() {
+            Ok(Ok(system)) => Ok(AppHandle { system, thread }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            },
+            Err(_) => Err(format!("{} failed to start", self.name)),
+        }
+    }
+}
+
+/// A started app.
+pub struct AppHandle {
+    system: System,
+    thread: JoinHandle<Result<(), String>>,
+}
+
+impl AppHandle {
+    /// Stop the system of the app. See `await_termination`.
+    pub fn stop(&self) {
+        self.system.stop();
+    }
+
+    /// Block until the app has stopped.
+    pub fn await_termination(self) -> Result<(), String> {
+        self.thread.join()
+            .map_err(|_| "The app has panicked".to_string())?
+    }
+}
+
+/// Start the services of the app in the current system.
+pub(crate) fn start_services() {
+    app_state::start();
+    dispatcher::start();
+    router::start();
+    node_registry::start();
+    task_tree::start();
+    processor::start();
+    center::router::start();
+    log_shipper::start();
+    telemetry::start();
+
+    if status_aggregator::enabled() {
+        status_aggregator::start();
+    }
+
+    if disk_guard::enabled() {
+        disk_guard::start();
+    }
+
+    if startup::enabled() {
+        startup::start();
+    }
+
+    if reload::enabled() {
+        reload::start();
+    }
+
+    if audit::enabled() {
+        audit::start();
+    }
+
+    if alerting::enabled() {
+        alerting::start();
+    }
+
+    if task_stats::enabled() {
+        task_stats::start();
+    }
+
+    if task_history::enabled() {
+        task_history::start();
+    }
+}
@@ -1,20 +1,626 @@
 use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use crate::transport::connector::*;
+use crate::{
+    center::{
+        message,
+        registration::{self, Registration, RegisterAck},
+        signing,
+    },
+    core::{
+        app_state,
+        env::{self, PATOKA_ROOT_DIR},
+        error_bus::{self, PatokaError},
+        logger::create_logger,
+    },
+    transport::{
+        message::RawMessage,
+        router::CONTEXT,
+        router_registry::{self, *},
+    },
+};
 
-pub struct CenterConnectorParameters;
+/// Name of the center configured with `center.address`.
+pub const DEFAULT_CENTER: &str = "default";
 
-impl ConnectorParameters for CenterConnectorParameters {
-    fn name() -> &'static str {
-        "center_connector"
+/// How often the state of the centers is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// See `disable`.
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref ENDPOINTS: Vec<Endpoint> = load_endpoints();
+
+    static ref PARAMS: LivenessParams =
+        env::load_opt("center").unwrap_or_default();
+}
+
+/// Liveness settings of the `[center]` configuration section.
+#[derive(Deserialize)]
+struct LivenessParams {
+    /// Ping the centers that often. 0 to disable: then a center is
+    /// considered alive while connected.
+    #[serde(default)]
+    ping_interval_s: u64,
+
+    /// A center is considered dead if it has not answered a ping for that
+    /// long.
+    #[serde(default = "default_pong_timeout_s")]
+    pong_timeout_s: u64,
+
+    /// Messages kept in memory per center while it is not alive.
+    #[serde(default = "default_buffer_size")]
+    buffer_size: usize,
+
+    /// Write the messages exceeding `buffer_size` to
+    /// `{spill_dir}/center_{name}.spill` instead of dropping the oldest
+    /// ones.
+    #[serde(default)]
+    spill_dir: Option<String>,
+}
+
+fn default_pong_timeout_s() -> u64 { 30 }
+
+fn default_buffer_size() -> usize { 10000 }
+
+impl Default for LivenessParams {
+    fn default() -> Self {
+        Self {
+            ping_interval_s: 0,
+            pong_timeout_s: default_pong_timeout_s(),
+            buffer_size: default_buffer_size(),
+            spill_dir: None,
+        }
+    }
+}
+
+/// `[center.endpoints.<name>]` configuration section.
+#[derive(Deserialize)]
+struct EndpointParams {
+    address: String,
+
+    /// Send only the messages of these subjects, e.g. "task_result".
+    /// All the messages are sent if empty.
+    #[serde(default)]
+    subjects: Vec<String>,
+}
+
+/// A center the app reports to.
+pub struct Endpoint {
+    pub name: String,
+
+    /// Center ZMQ address.
+    pub address: String,
+
+    /// See `EndpointParams::subjects`.
+    pub subjects: Vec<String>,
+
+    /// Router BE address the connector sends the messages to.
+    pub router: String,
+
+    pub state: Arc<EndpointState>,
+}
+
+impl Endpoint {
+    fn new(name: &str, params: EndpointParams) -> Self {
+        let router = if name == DEFAULT_CENTER {
+            "inproc://center_router".to_string()
+        } else {
+            format!("inproc://center_router_{}", name)
+        };
+
+        Self {
+            name: name.to_string(),
+            address: params.address,
+            subjects: params.subjects,
+            router,
+            state: Arc::new(EndpointState::default()),
+        }
     }
 
-    fn router() -> &'static str {
-        "inproc://center_router"
+    fn accepts(&self, subject: &str) -> bool {
+        self.subjects.is_empty() || self.subjects.iter().any(|s| s == subject)
     }
 }
 
-pub type CenterConnector = Connector<CenterConnectorParameters>;
+#[derive(Default)]
+pub struct EndpointState {
+    /// Updated by the center router.
+    pub connected: Arc<AtomicBool>,
+
+    /// Connected, answers the pings and has acknowledged the registration
+    /// if required.
+    pub alive: AtomicBool,
+
+    /// Messages sent to the center.
+    pub sent: AtomicU64,
+
+    /// Messages waiting in memory.
+    pub buffered: AtomicUsize,
+
+    /// Messages waiting on disk.
+    pub spilled: AtomicUsize,
+
+    /// Messages lost because the buffer was full.
+    pub dropped: AtomicU64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CenterStatus {
+    pub name: String,
+    pub address: String,
+    pub connected: bool,
+
+    #[serde(default)]
+    pub alive: bool,
+
+    pub sent: u64,
+
+    #[serde(default)]
+    pub buffered: usize,
+
+    #[serde(default)]
+    pub spilled: usize,
+
+    #[serde(default)]
+    pub dropped: u64,
+}
+
+fn load_endpoints() -> Vec<Endpoint> {
+    if DISABLED.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+
+    // The default center is always there for the backward compatibility.
+    let mut endpoints = vec![Endpoint::new(
+        DEFAULT_CENTER,
+        EndpointParams {
+            address: env::get_opt_var("center.address").unwrap_or_default(),
+            subjects: env::load_opt("center.subjects").unwrap_or_default(),
+        },
+    )];
+
+    let params: HashMap<String, EndpointParams> =
+        env::load_opt("center.endpoints").unwrap_or_default();
+    for (name, p) in params {
+        if name != DEFAULT_CENTER {
+            endpoints.push(Endpoint::new(&name, p));
+        }
+    }
+
+    endpoints
+}
+
+/// Run without any center: the messages to the centers are dropped. Takes
+/// effect if called before the connector and the center routers start.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// All the configured centers.
+pub fn endpoints() -> &'static [Endpoint] {
+    &ENDPOINTS
+}
+
+pub fn status() -> Vec<CenterStatus> {
+    ENDPOINTS.iter()
+        .map(|e| CenterStatus {
+            name: e.name.clone(),
+            address: e.address.clone(),
+            connected: e.state.connected.load(Ordering::Relaxed),
+            alive: e.state.alive.load(Ordering::Relaxed),
+            sent: e.state.sent.load(Ordering::Relaxed),
+            buffered: e.state.buffered.load(Ordering::Relaxed),
+            spilled: e.state.spilled.load(Ordering::Relaxed),
+            dropped: e.state.dropped.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+/// The connection to a center router and the messages waiting for the
+/// center to become alive.
+struct Link {
+    endpoint: &'static Endpoint,
+    socket: zmq::Socket,
+
+    /// Oldest first. The newer messages are spilled when it is full.
+    buffer: VecDeque<String>,
+
+    spill_path: Option<String>,
+
+    was_connected: bool,
+    last_ping: Option<Instant>,
+
+    /// Or when the connection has been established.
+    last_pong: Instant,
+
+    /// The center has acknowledged `Register` since it has been reachable.
+    registered: bool,
+
+    /// Until the next connection.
+    rejected: bool,
+
+    last_register: Option<Instant>,
+}
+
+impl Link {
+    fn new(endpoint: &'static Endpoint) -> Self {
+        let spill_path = PARAMS.spill_dir.as_ref().map(|dir| {
+            let dir = env::full_path(dir, "$PATOKA_ROOT_DIR", &PATOKA_ROOT_DIR);
+            format!("{}/center_{}.spill", dir, endpoint.name)
+        });
+
+        Self {
+            endpoint,
+            socket: CONTEXT.socket(zmq::DEALER).unwrap(),
+            buffer: VecDeque::new(),
+            spill_path,
+            was_connected: false,
+            last_ping: None,
+            last_pong: Instant::now(),
+            registered: false,
+            rejected: false,
+            last_register: None,
+        }
+    }
+
+    fn state(&self) -> &EndpointState {
+        &self.endpoint.state
+    }
+
+    fn is_alive(&self) -> bool {
+        self.state().alive.load(Ordering::Relaxed)
+    }
+
+    fn has_pending(&self) -> bool {
+        !self.buffer.is_empty()
+            || self.state().spilled.load(Ordering::Relaxed) > 0
+    }
+
+    /// Signed now, so that a buffered message is not expired by the center.
+    fn send_now(&self, body: &str) {
+        let body = signing::sign(body.to_string());
+
+        // The identity is not forwarded by the active router.
+        self.socket.send(zmq::Message::new(), zmq::SNDMORE).unwrap();
+        self.socket.send(body.as_bytes(), 0).unwrap();
+        self.state().sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn send(&mut self, body: String, log: &Logger) {
+        if self.is_alive() && !self.has_pending() {
+            self.send_now(&body);
+            return;
+        }
+
+        if self.buffer.len() < PARAMS.buffer_size {
+            self.buffer.push_back(body);
+        } else if let Some(ref path) = self.spill_path {
+            if let Err(e) = spill(path, &body) {
+                error!(log, "Failed to spill a message to {}: {}", path, e);
+                self.state().dropped.fetch_add(1, Ordering::Relaxed);
+            } else {
+                self.state().spilled.fetch_add(1, Ordering::Relaxed);
+            }
+        } else {
+            self.buffer.pop_front();
+            self.buffer.push_back(body);
+            self.state().dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.state().buffered.store(self.buffer.len(), Ordering::Relaxed);
+    }
+
+    /// Send the buffered messages, then the spilled ones.
+    fn flush(&mut self, log: &Logger) {
+        for body in std::mem::take(&mut self.buffer) {
+            self.send_now(&body);
+        }
+        self.state().buffered.store(0, Ordering::Relaxed);
+
+        if self.state().spilled.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let path = match self.spill_path {
+            Some(ref p) => p.clone(),
+            None => return,
+        };
+
+        match read_spilled(&path) {
+            Ok(bodies) => {
+                for body in bodies {
+                    self.send_now(&body);
+                }
+            },
+            Err(e) => error!(log, "Failed to read spilled {}: {}", path, e),
+        }
+
+        let _ = fs::remove_file(&path);
+        self.state().spilled.store(0, Ordering::Relaxed);
+    }
+
+    /// Update the liveness, ping the center if it is time.
+    fn check(&mut self, log: &Logger) {
+        let connected = self.state().connected.load(Ordering::Relaxed);
+        if connected && !self.was_connected {
+            // Give the center time to answer the first ping.
+            self.last_pong = Instant::now();
+            self.rejected = false;
+            self.last_register = None;
+        }
+        self.was_connected = connected;
+
+        let ping_interval = Duration::from_secs(PARAMS.ping_interval_s);
+        let reachable = connected && (ping_interval.is_zero()
+            || self.last_pong.elapsed()
+                < Duration::from_secs(PARAMS.pong_timeout_s));
+
+        // The center may have restarted meanwhile.
+        if !reachable {
+            self.registered = false;
+        }
+
+        let register = registration::enabled();
+        if reachable && register && !self.registered && !self.rejected {
+            let register_due = self.last_register
+                .is_none_or(|t| t.elapsed() >= registration::retry_interval());
+            if register_due {
+                self.register(log);
+            }
+        }
+
+        let alive = reachable && (!register || self.registered);
+
+        if alive != self.is_alive() {
+            if alive {
+                info!(log, "[CENTER] {} is alive.", self.endpoint.name);
+            } else {
+                warn!(
+                    log,
+                    "[CENTER] {} is not alive. Buffering the messages.",
+                    self.endpoint.name,
+                );
+            }
+
+            self.state().alive.store(alive, Ordering::Relaxed);
+        }
+
+        if alive && self.has_pending() {
+            self.flush(log);
+        }
+
+        let ping_due = self.last_ping
+            .is_none_or(|t| t.elapsed() >= ping_interval);
+        if connected && !ping_interval.is_zero() && ping_due {
+            self.ping();
+        }
+    }
+
+    fn register(&mut self, log: &Logger) {
+        info!(log, "[CENTER] {}: registering.", self.endpoint.name);
+
+        let c_msg = message::create(
+            message::Dest::Center,
+            message::Subject::Register,
+            app_state::app_id(),
+            self.endpoint.name.clone(),
+            Registration::collect(),
+        );
+
+        self.send_now(&RawMessage::from(c_msg).body);
+        self.last_register = Some(Instant::now());
+    }
+
+    fn ping(&mut self) {
+        let c_msg = message::create_no_data(
+            message::Dest::Center,
+            message::Subject::Ping,
+            app_state::app_id(),
+            self.endpoint.name.clone(),
+        );
+
+        self.send_now(&RawMessage::from(c_msg).body);
+        self.last_ping = Some(Instant::now());
+    }
+}
+
+fn spill(path: &str, body: &str) -> io::Result<()> {
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", body)
+}
+
+fn read_spilled(path: &str) -> io::Result<Vec<String>> {
+    BufReader::new(fs::File::open(path)?).lines().collect()
+}
+
+/// Replicates the messages to all the centers accepting their subject.
+/// The messages are buffered while a center is not alive.
+pub struct CenterConnector {
+    log: Logger,
+
+    /// Connected to the BE of the respective center routers.
+    links: Vec<Link>,
+}
+
+impl Default for CenterConnector {
+    fn default() -> Self {
+        Self {
+            log: create_logger("center_connector"),
+            links: ENDPOINTS.iter().map(Link::new).collect(),
+        }
+    }
+}
+
+impl Actor for CenterConnector {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Started.");
+
+        let registry_addr = router_registry::start();
+
+        for link in &self.links {
+            let endpoint = link.endpoint;
+
+            // Register itself to be used to control the router.
+            registry_addr.do_send(RegisterRouterControlLinkMessage {
+                address: endpoint.router.clone(),
+                control_link: RegistryValue::Connector(
+                    ctx.address().recipient()
+                ),
+            });
+
+            match link.socket.connect(&endpoint.router) {
+                Ok(_) => {
+                    info!(
+                        self.log,
+                        "Connected to [ROUTER ADDRESS] {} of [CENTER] {}.",
+                        endpoint.router,
+                        endpoint.name,
+                    );
+                },
+                Err(_) => {
+                    error!(
+                        self.log,
+                        "Failed to connect to [ROUTER ADDRESS] {} of \
+                            [CENTER] {}.",
+                        endpoint.router,
+                        endpoint.name,
+                    );
+                }
+            }
+        }
+
+        ctx.run_interval(CHECK_INTERVAL, |act, _| {
+            for link in &mut act.links {
+                link.check(&act.log);
+            }
+        });
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Stopped.");
+    }
+}
+
+impl Supervised for CenterConnector {}
+
+impl SystemService for CenterConnector {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "System service started.")
+    }
+}
+
+#[derive(Deserialize)]
+struct SubjectOnly {
+    #[serde(default)]
+    subject: String,
+}
+
+impl Handler<RawMessage> for CenterConnector {
+    type Result = ();
+
+    /// Sends `msg` to the routers of the centers.
+    fn handle(
+        &mut self,
+        msg: RawMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let subject = serde_json::from_str::<SubjectOnly>(&msg.body)
+            .map(|s| s.subject)
+            .unwrap_or_default();
+
+        for link in &mut self.links {
+            if link.endpoint.accepts(&subject) {
+                link.send(msg.body.clone(), &self.log);
+            }
+        }
+    }
+}
+
+/// The center has answered a ping.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Pong {
+    /// `Endpoint::name`
+    pub center: String,
+}
+
+impl Handler<Pong> for CenterConnector {
+    type Result = ();
+
+    fn handle(&mut self, msg: Pong, _ctx: &mut Self::Context) {
+        let link = self.links.iter_mut()
+            .find(|l| l.endpoint.name == msg.center);
+
+        match link {
+            Some(l) => l.last_pong = Instant::now(),
+            None => {
+                warn!(self.log, "Pong from unknown [CENTER] {}", msg.center);
+            },
+        }
+    }
+}
+
+impl Handler<RegisterAck> for CenterConnector {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterAck, _ctx: &mut Self::Context) {
+        let link = match self.links.iter_mut()
+            .find(|l| l.endpoint.name == msg.center)
+        {
+            Some(l) => l,
+            None => {
+                warn!(self.log, "Ack from unknown [CENTER] {}", msg.center);
+                return;
+            },
+        };
+
+        if !msg.ack.accepted {
+            link.rejected = true;
+            error_bus::publish(PatokaError::error(
+                "center_connector",
+                format!(
+                    "[CENTER] {} has rejected the registration: {}",
+                    msg.center,
+                    msg.ack.reason,
+                ),
+            ));
+            return;
+        }
+
+        info!(self.log, "[CENTER] {}: registered.", msg.center);
+        link.registered = true;
+
+        if let Some(ref id) = msg.ack.app_id {
+            if msg.center == DEFAULT_CENTER && *id != app_state::app_id() {
+                info!(self.log, "App ID assigned by the center: {}", id);
+                app_state::set_app_id(id);
+            }
+        }
+
+        // Send the buffered messages right away.
+        link.check(&self.log);
+    }
+}
 
 pub fn start() -> Addr<CenterConnector> {
     CenterConnector::from_registry()
@@ -5,16 +5,25 @@ use std::collections::HashMap;
 use crate::{
     center::{
         connector::{self, CenterConnector},
-        message::*
+        message::*,
+        registration::{Ack, RegisterAck},
+        signing::{self, Verifier},
     },
     control::{
+        authz,
         message::*,
         registry::{self, *},
     },
-    core::logger::create_logger,
+    core::{
+        error_bus::{self, PatokaError},
+        logger::create_logger,
+    },
     transport::message::*,
 };
 
+/// Module name used to publish errors.
+const MODULE: &str = "center_dispatcher";
+
 pub struct RegisterEntity {
     pub entity_id: String,
     pub entity_addr: Recipient<CenterMessage>,
@@ -24,38 +33,154 @@ impl Message for RegisterEntity {
     type Result = ();
 }
 
+/// Subscribe to all the messages of `subject` from the center regardless of
+/// the entity ID. The entity still receives them.
+pub struct RegisterSubjectSubscriber {
+    pub subject: Subject,
+
+    /// Unique per subject.
+    pub subscriber_id: String,
+
+    /// `None` to unsubscribe.
+    pub subscriber_addr: Option<Recipient<CenterMessage>>,
+}
+
+impl RegisterSubjectSubscriber {
+    pub fn register(
+        subject: Subject,
+        subscriber_id: String,
+        subscriber_addr: Recipient<CenterMessage>,
+    ) -> Self {
+        Self {
+            subject,
+            subscriber_id,
+            subscriber_addr: Some(subscriber_addr),
+        }
+    }
+
+    pub fn unregister(subject: Subject, subscriber_id: String) -> Self {
+        Self {
+            subject,
+            subscriber_id,
+            subscriber_addr: None,
+        }
+    }
+}
+
+impl Message for RegisterSubjectSubscriber {
+    type Result = ();
+}
+
 pub struct CenterDispatcher {
     log: Logger,
     router_addr: Addr<CenterConnector>,
     entities: HashMap<String, Recipient<CenterMessage>>,
+
+    /// Subject --> Subscriber ID --> Subscriber
+    subject_subscribers:
+        HashMap<Subject, HashMap<String, Recipient<CenterMessage>>>,
+
     control_registry_addr: Addr<ControlRegistry>,
+
+    /// Of the control messages from the center, see `signing`.
+    verifier: Option<Verifier>,
 }
 
 impl CenterDispatcher {
-    fn send_to_entity(&self, msg: CenterMessage) {
+    /// Returns whether there are any subscribers of the subject.
+    fn send_to_subject_subscribers(&mut self, msg: &CenterMessage) -> bool {
+        let subscribers = match self.subject_subscribers
+            .get_mut(&msg.payload.subject)
+        {
+            Some(s) => s,
+            None => return false,
+        };
+
+        // The stopped subscribers are forgotten.
+        subscribers.retain(|_, addr| addr.connected());
+        for addr in subscribers.values() {
+            addr.do_send(msg.clone());
+        }
+
+        !subscribers.is_empty()
+    }
+
+    fn send_to_entity(&mut self, msg: CenterMessage) {
+        let subscribed = self.send_to_subject_subscribers(&msg);
+
         if let Some(addr) = self.entities.get(&msg.payload.entity_id) {
             addr.do_send(msg);
-        } else {
-            warn!(
-                self.log,
-                "Unable to send a message to an unregistered [ENTITY ID] {}",
-                msg.payload.entity_id,
-            );
+        } else if !subscribed {
+            error_bus::publish(PatokaError::warning(
+                MODULE,
+                format!(
+                    "Unable to send a message to an unregistered \
+                        [ENTITY ID] {}",
+                    msg.payload.entity_id,
+                ),
+            ));
+        }
+    }
+
+    fn parse_control_msg(
+        &self,
+        data: serde_json::Value,
+    ) -> Option<ControlMessage> {
+        match serde_json::from_value::<ControlMessage>(data) {
+            Ok(msg) => Some(msg),
+            Err(e) => {
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    format!("Invalid control message: {}", e),
+                ));
+                None
+            }
         }
     }
 
-    fn handle_control_msg(&self, msg: ControlMessage) {
-        self.control_registry_addr.do_send(msg);
+    fn handle_register_ack(&self, msg: CenterMessage) {
+        let ack = match serde_json::from_value::<Ack>(msg.payload.data) {
+            Ok(ack) => ack,
+            Err(e) => {
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    format!("Invalid registration ack: {}", e),
+                ));
+                return;
+            },
+        };
+
+        self.router_addr.do_send(RegisterAck {
+            center: msg.payload.message,
+            ack,
+        });
+    }
+
+    fn handle_control_msg(&self, data: serde_json::Value) {
+        if let Some(msg) = self.parse_control_msg(data) {
+            self.control_registry_addr.do_send(msg);
+        }
+    }
+
+    /// The role of the requester is trusted if the message is signed.
+    fn handle_center_control_msg(&self, data: serde_json::Value) {
+        if let Some(mut msg) = self.parse_control_msg(data) {
+            let signed = self.verifier.is_some();
+            msg.role = Some(authz::role(msg.role.take(), signed));
+            self.control_registry_addr.do_send(msg);
+        }
     }
 }
 
 impl Default for CenterDispatcher {
     fn default() -> Self {
         Self {
-            log: create_logger("center_dispatcher"),
+            log: create_logger(MODULE),
             router_addr: connector::start(),
             entities: HashMap::new(),
+            subject_subscribers: HashMap::new(),
             control_registry_addr: registry::start(),
+            verifier: signing::verifier(),
         }
     }
 }
@@ -89,6 +214,11 @@ impl Handler<RawMessage> for CenterDispatcher {
         _ctx: &mut Self::Context
     ) -> Self::Result {
 
+        let verified = match self.verifier {
+            Some(ref mut v) => v.verify(&msg.body),
+            None => Ok(()),
+        };
+
         match RawMessage::to::<CenterMessagePayload>(msg) {
             Ok(center_message) => {
                 trace!(
@@ -101,18 +231,46 @@ impl Handler<RawMessage> for CenterDispatcher {
                     Dest::App => {
                         match center_message.payload.subject {
                             Subject::Control => {
+                                if let Err(e) = verified {
+                                    error_bus::publish(PatokaError::critical(
+                                        MODULE,
+                                        format!(
+                                            "Rejected a control message: {}",
+                                            e,
+                                        ),
+                                    ));
+                                    return;
+                                }
+
                                 /*trace!(
                                     self.log,
                                     "{:?}",
                                     center_message
                                 );*/
 
-                                self.handle_control_msg(
-                                    serde_json::from_value(
-                                        center_message.payload.data
-                                    ).unwrap()
+                                self.handle_center_control_msg(
+                                    center_message.payload.data
                                 );
                             },
+                            Subject::Pong => {
+                                self.router_addr.do_send(connector::Pong {
+                                    center: center_message.payload.message,
+                                });
+                            },
+                            Subject::RegisterAck => {
+                                if let Err(e) = verified {
+                                    error_bus::publish(PatokaError::critical(
+                                        MODULE,
+                                        format!(
+                                            "Rejected a registration ack: {}",
+                                            e,
+                                        ),
+                                    ));
+                                    return;
+                                }
+
+                                self.handle_register_ack(center_message);
+                            },
                             _ => {
                                 self.send_to_entity(center_message);
                             }
@@ -127,7 +285,10 @@ impl Handler<RawMessage> for CenterDispatcher {
                 }
             },
             Err(e) => {
-                warn!(self.log, "Invalid raw center message: {}", e);
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    format!("Invalid raw center message: {}", e),
+                ));
             }
         }
     }
@@ -142,9 +303,7 @@ impl Handler<CenterMessage> for CenterDispatcher {
         _ctx: &mut Self::Context
     ) -> Self::Result {
         if msg.payload.subject == Subject::Control {
-            self.handle_control_msg(
-                serde_json::from_value(msg.payload.data).unwrap()
-            );
+            self.handle_control_msg(msg.payload.data);
 
             return;
         }
@@ -178,6 +337,45 @@ impl Handler<RegisterEntity> for CenterDispatcher {
     }
 }
 
+impl Handler<RegisterSubjectSubscriber> for CenterDispatcher {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: RegisterSubjectSubscriber,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        match msg.subscriber_addr {
+            Some(addr) => {
+                info!(
+                    self.log,
+                    "Subscribing [SUBSCRIBER ID] {} to [SUBJECT] {:?}.",
+                    msg.subscriber_id,
+                    msg.subject,
+                );
+
+                self.subject_subscribers.entry(msg.subject)
+                    .or_default()
+                    .insert(msg.subscriber_id, addr);
+            },
+            None => {
+                info!(
+                    self.log,
+                    "Unsubscribing [SUBSCRIBER ID] {} from [SUBJECT] {:?}.",
+                    msg.subscriber_id,
+                    msg.subject,
+                );
+
+                if let Some(s) = self.subject_subscribers
+                    .get_mut(&msg.subject)
+                {
+                    s.remove(&msg.subscriber_id);
+                }
+            },
+        }
+    }
+}
+
 pub fn start() -> Addr<CenterDispatcher> {
     CenterDispatcher::from_registry()
 }
@@ -4,7 +4,10 @@ use std::fmt;
 
 use crate::{
     transport::message::*,
-    core::timestamp::{Timestamp, now},
+    core::{
+        telemetry::{self, TraceContext},
+        timestamp::{Timestamp, now},
+    },
 };
 
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -43,15 +46,40 @@ impl fmt::Debug for Dest {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Subject {
     AppStatusReport,
     TaskStatusReport,
     TaskStatusUpdate,
+
+    /// App --> Center. `data` is the array of the batched task update
+    /// payloads, e.g. `task_status_update`.
+    TaskUpdateBatch,
+
     TaskResult,
     TaskQuestion,
     Control,
+    Error,
+    CapabilityReport,
+    LogRecord,
+
+    /// App --> Center. See `status_aggregator`.
+    StatusReport,
+
+    /// App --> Center. `message` is the name of the center in the app
+    /// configuration.
+    Ping,
+
+    /// Center --> App. Reply to `Ping` with the same `message`.
+    Pong,
+
+    /// App --> Center. See `registration`.
+    Register,
+
+    /// Center --> App. Reply to `Register` with the same `message`.
+    RegisterAck,
+
     Unknown,
 
     // TODO: Implement `Custom(String)` with a custom (de)serializer.
@@ -63,9 +91,18 @@ impl Subject {
             "app_status_report" => Subject::AppStatusReport,
             "task_status_report" => Subject::TaskStatusReport,
             "task_status_update" => Subject::TaskStatusUpdate,
+            "task_update_batch" => Subject::TaskUpdateBatch,
             "task_result" => Subject::TaskResult,
             "task_question" => Subject::TaskQuestion,
             "control" => Subject::Control,
+            "error" => Subject::Error,
+            "capability_report" => Subject::CapabilityReport,
+            "log_record" => Subject::LogRecord,
+            "status_report" => Subject::StatusReport,
+            "ping" => Subject::Ping,
+            "pong" => Subject::Pong,
+            "register" => Subject::Register,
+            "register_ack" => Subject::RegisterAck,
             _ => Subject::Unknown,
         }
     }
@@ -75,9 +112,18 @@ impl Subject {
             Subject::AppStatusReport => "app_status_report".to_string(),
             Subject::TaskStatusReport => "task_status_report".to_string(),
             Subject::TaskStatusUpdate => "task_status_update".to_string(),
+            Subject::TaskUpdateBatch => "task_update_batch".to_string(),
             Subject::TaskResult => "task_result".to_string(),
             Subject::TaskQuestion => "task_question".to_string(),
             Subject::Control => "control".to_string(),
+            Subject::Error => "error".to_string(),
+            Subject::CapabilityReport => "capability_report".to_string(),
+            Subject::LogRecord => "log_record".to_string(),
+            Subject::StatusReport => "status_report".to_string(),
+            Subject::Ping => "ping".to_string(),
+            Subject::Pong => "pong".to_string(),
+            Subject::Register => "register".to_string(),
+            Subject::RegisterAck => "register_ack".to_string(),
             Subject::Unknown => "unknown".to_string(),
         }
     }
@@ -102,6 +148,10 @@ pub struct CenterMessagePayload {
     pub data: serde_json::Value,
 
     pub ts: Timestamp,
+
+    /// The trace of the task `entity_id`, if any.
+    #[serde(flatten)]
+    pub trace: TraceContext,
 }
 
 impl CenterMessagePayload {
@@ -124,6 +174,7 @@ impl CenterMessagePayload {
             message: String::new(),
             data: serde_json::to_value({}).unwrap(),
             ts: now(),
+            trace: TraceContext::default(),
         }
     }
 
@@ -134,6 +185,13 @@ impl CenterMessagePayload {
         message: String,
         data: D
     ) -> Self {
+        let trace = match dest {
+            Dest::Center => {
+                telemetry::center_message(&entity_id, &subject.as_str())
+            },
+            _ => TraceContext::default(),
+        };
+
         Self {
             dest,
             subject,
@@ -141,6 +199,7 @@ impl CenterMessagePayload {
             message,
             data: serde_json::to_value(data).unwrap(),
             ts: now(),
+            trace,
         }
     }
 
@@ -1,6 +1,9 @@
 pub mod connector;
 pub mod dispatcher;
 pub mod message;
+pub mod registration;
+pub mod reporting;
 pub mod router;
 pub mod send;
+pub mod signing;
 pub mod task_state;
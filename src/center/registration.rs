@@ -0,0 +1,74 @@
+use actix::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::core::{app_state, capabilities::CapabilityReport, env};
+
+/// `center.register`: the messages to a center are buffered until it answers
+/// `Register` with `RegisterAck`.
+pub fn enabled() -> bool {
+    env::get_opt_var("center.register").as_deref() == Some("true")
+}
+
+/// How often `Register` is resent until acknowledged,
+/// `center.register_retry_s`.
+pub fn retry_interval() -> Duration {
+    let s = env::opt_var("center.register_retry_s").ok().flatten();
+    Duration::from_secs(s.unwrap_or(10))
+}
+
+/// `data` of `Subject::Register`. `message` is the name of the center in
+/// the app configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Registration {
+    pub app_id: String,
+
+    /// `general.name`
+    pub name: String,
+
+    /// `general.url`
+    pub url: String,
+
+    /// Crate version.
+    pub version: String,
+
+    pub capabilities: CapabilityReport,
+}
+
+impl Registration {
+    pub fn collect() -> Self {
+        let app_id = app_state::app_id();
+
+        Self {
+            name: env::get_opt_var("general.name").unwrap_or_default(),
+            url: env::get_opt_var("general.url").unwrap_or_default(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: CapabilityReport::collect(&app_id),
+            app_id,
+        }
+    }
+}
+
+/// `data` of `Subject::RegisterAck`. `message` is the one of `Register`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Ack {
+    pub accepted: bool,
+
+    /// Assigned by the center.
+    #[serde(default)]
+    pub app_id: Option<String>,
+
+    /// Why rejected.
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// The center has answered `Register`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegisterAck {
+    /// `Endpoint::name`
+    pub center: String,
+
+    pub ack: Ack,
+}
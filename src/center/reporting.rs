@@ -0,0 +1,99 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::core::env;
+
+lazy_static! {
+    static ref PARAMS: ReportingParams =
+        env::load_opt("status_report").unwrap_or_default();
+}
+
+/// `[status_report]` configuration section.
+#[derive(Deserialize)]
+struct ReportingParams {
+    /// Resend an unchanged status report after that long.
+    #[serde(default = "default_max_interval_s")]
+    max_interval_s: u64,
+
+    /// Collect the task updates for that long and send them to the center
+    /// as a single `task_update_batch` message. 0 to send them one by one.
+    #[serde(default)]
+    batch_interval_ms: u64,
+}
+
+fn default_max_interval_s() -> u64 { 60 }
+
+impl Default for ReportingParams {
+    fn default() -> Self {
+        Self {
+            max_interval_s: default_max_interval_s(),
+            batch_interval_ms: 0,
+        }
+    }
+}
+
+/// `None` if the task updates are not batched.
+pub fn batch_interval() -> Option<Duration> {
+    match PARAMS.batch_interval_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// Lets a status report through only if its material part (`T`) differs
+/// from the last sent one or `status_report.max_interval_s` has passed.
+#[derive(Clone)]
+pub struct ChangeFilter<T> {
+    last: Option<(T, Instant)>,
+}
+
+impl<T: PartialEq> ChangeFilter<T> {
+    pub fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Whether to send the report. If so, `material` is remembered as sent.
+    pub fn pass(&mut self, material: T) -> bool {
+        let max_interval = Duration::from_secs(PARAMS.max_interval_s);
+        let pass = match self.last {
+            Some((ref last, sent_at)) => {
+                *last != material || sent_at.elapsed() >= max_interval
+            },
+            None => true,
+        };
+
+        if pass {
+            self.last = Some((material, Instant::now()));
+        }
+
+        pass
+    }
+
+    /// Send the next report regardless.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+impl<T: PartialEq> Default for ChangeFilter<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_filter() {
+        let mut filter = ChangeFilter::new();
+        assert!(filter.pass(1));
+        assert!(!filter.pass(1));
+        assert!(filter.pass(2));
+
+        filter.reset();
+        assert!(filter.pass(2));
+    }
+}
@@ -1,25 +1,24 @@
 use crate::{
-    center::dispatcher,
-    core::{env, logger::create_logger},
+    center::{connector, dispatcher},
+    core::logger::create_logger,
     transport::router::MessageRouter,
 };
 
+/// Start a router per configured center.
 pub fn start() {
-    let center_addr =
-        match env::get_opt_var("center.address") {
-            Some(v) => { v },
-            None => { String::new() },
+    for endpoint in connector::endpoints() {
+        let log_name = if endpoint.name == connector::DEFAULT_CENTER {
+            "center_message_router".to_string()
+        } else {
+            format!("center_message_router_{}", endpoint.name)
         };
 
-    let frontend_address = center_addr;
-
-    let backend_address = "inproc://center_router".to_string();
-
-    MessageRouter::start(
-        create_logger("center_message_router"),
-        dispatcher::start().into(),
-        frontend_address,
-        backend_address,
-        true,
-    );
+        MessageRouter::start_monitored(
+            create_logger(&log_name),
+            dispatcher::start().into(),
+            endpoint.address.clone(),
+            endpoint.router.clone(),
+            endpoint.state.connected.clone(),
+        );
+    }
 }
@@ -3,11 +3,12 @@ use serde_json::json;
 
 use crate::{
     center::{connector, message},
-    control::message::*,
+    control::{audit, message::*},
     transport::message::RawMessage,
     worker::{
         task::{GenTaskDefinition, TaskStatus},
-        tracker::{self, TaskUpdateTag},
+        task_labels::Labels,
+        tracker::{self, TaskUpdate, TaskUpdateTag},
     },
 };
 
@@ -15,6 +16,7 @@ pub fn send_center_task_started<P: serde::Serialize>(
     task_uuid: &str,
     task_definition: &P,
     name: &str,
+    labels: Labels,
 )
 {
     let c_msg = message::create(
@@ -25,19 +27,20 @@ pub fn send_center_task_started<P: serde::Serialize>(
         json!(task_definition),
     );
 
-    tracker::send(
+    tracker::start().do_send(TaskUpdate::with_center_msg(
         task_uuid.into(),
         TaskStatus::Running,
         c_msg,
         TaskUpdateTag::Started,
         name.into(),
-    );
+    ).with_labels(labels));
 }
 
 pub fn send_center_task_updated<P: serde::Serialize>(
     task_uuid: &str,
     task_definition: &P,
     name: &str,
+    labels: Labels,
 )
 {
     let c_msg = message::create(
@@ -48,13 +51,13 @@ pub fn send_center_task_updated<P: serde::Serialize>(
         json!(task_definition),
     );
 
-    tracker::send(
+    tracker::start().do_send(TaskUpdate::with_center_msg(
         task_uuid.into(),
         TaskStatus::Running,
         c_msg,
         TaskUpdateTag::Updated,
-        name.into()
-    );
+        name.into(),
+    ).with_labels(labels));
 }
 
 pub fn send_center_task_finished(
@@ -121,18 +124,25 @@ pub fn send_center_task_question<D: serde::Serialize>(
     );
 }
 
-pub fn send_center_task_closed(task_uuid: &str) {
-    let c_msg = message::create_no_data(
+pub fn center_task_closed(task_uuid: &str) -> message::CenterMessage {
+    message::create_no_data(
         message::Dest::Center,
         message::Subject::TaskStatusUpdate,
         task_uuid.to_string(),
         "closed".to_string(),
-    );
+    )
+}
 
+pub fn send_center_task_closed(task_uuid: &str) {
+    let c_msg = center_task_closed(task_uuid);
     connector::start().do_send(RawMessage::from(c_msg));
 }
 
 pub fn send_control_msg(msg: ControlMessage) {
+    if msg.type_ == Type::Response {
+        audit::response(&msg);
+    }
+
     let c_msg = message::create(
         message::Dest::Center,
         message::Subject::Control,
@@ -0,0 +1,194 @@
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+use crate::core::{env, timestamp};
+
+lazy_static! {
+    static ref PARAMS: SigningParams =
+        env::load_opt("center.signing").unwrap_or_default();
+}
+
+/// `[center.signing]` configuration section. The inbound `control` messages
+/// are accepted with a valid signature only once a key is configured.
+#[derive(Default, Deserialize)]
+struct SigningParams {
+    /// Shared with the center. No signing if not set.
+    #[serde(default)]
+    key: Option<String>,
+
+    #[serde(default = "default_max_age_s")]
+    max_age_s: u64,
+}
+
+fn default_max_age_s() -> u64 { 60 }
+
+fn key() -> Option<&'static [u8]> {
+    PARAMS.key.as_deref().filter(|k| !k.is_empty()).map(str::as_bytes)
+}
+
+/// Sign the message `body` if a key is configured.
+pub fn sign(body: String) -> String {
+    let key = match key() {
+        Some(k) => k,
+        None => return body,
+    };
+
+    let nonce = format!("{:032x}", rand::random::<u128>());
+    sign_with(key, &body, timestamp::now_ms(), &nonce).unwrap_or(body)
+}
+
+/// The verifier of the inbound messages, `None` if no key is configured.
+pub fn verifier() -> Option<Verifier> {
+    let max_age_ms = PARAMS.max_age_s.saturating_mul(1000) as i64;
+    key().map(|k| Verifier::new(k.to_vec(), max_age_ms))
+}
+
+fn sign_with(
+    key: &[u8],
+    body: &str,
+    ts: i64,
+    nonce: &str,
+) -> Option<String> {
+    let mut payload: Value = serde_json::from_str(body).ok()?;
+    let object = payload.as_object_mut()?;
+    object.remove("signature");
+
+    let mac = mac(key, ts, nonce, &canonical(&payload));
+    payload.as_object_mut()?.insert(
+        "signature".to_string(),
+        json!({ "ts": ts, "nonce": nonce, "mac": mac }),
+    );
+
+    Some(payload.to_string())
+}
+
+fn mac(key: &[u8], ts: i64, nonce: &str, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .expect("HMAC accepts a key of any size");
+    mac.update(format!("{}.{}.{}", ts, nonce, payload).as_bytes());
+
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compact JSON with the keys sorted, whatever the map order of
+/// `serde_json`.
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+
+            let fields: Vec<String> = keys.iter().map(|k| {
+                format!("{}:{}", Value::from(k.as_str()), canonical(&map[*k]))
+            }).collect();
+
+            format!("{{{}}}", fields.join(","))
+        },
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical).collect();
+            format!("[{}]", items.join(","))
+        },
+        _ => value.to_string(),
+    }
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    ts: i64,
+    nonce: String,
+    mac: String,
+}
+
+pub struct Verifier {
+    key: Vec<u8>,
+    max_age_ms: i64,
+
+    /// Nonce --> Signature timestamp
+    /// Seen within `max_age_ms`.
+    seen: HashMap<String, i64>,
+}
+
+impl Verifier {
+    fn new(key: Vec<u8>, max_age_ms: i64) -> Self {
+        Self {
+            key,
+            max_age_ms,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Check the signature of the message `body`.
+    pub fn verify(&mut self, body: &str) -> Result<(), String> {
+        self.verify_at(body, timestamp::now_ms())
+    }
+
+    fn verify_at(&mut self, body: &str, now: i64) -> Result<(), String> {
+        let mut payload: Value = serde_json::from_str(body)
+            .map_err(|e| e.to_string())?;
+        let signature = payload.as_object_mut()
+            .and_then(|o| o.remove("signature"))
+            .ok_or("No signature")?;
+        let signature: Signature = serde_json::from_value(signature)
+            .map_err(|e| format!("Invalid signature: {}", e))?;
+
+        if (now - signature.ts).abs() > self.max_age_ms {
+            return Err(format!("Signature expired: ts {}", signature.ts));
+        }
+
+        let max_age_ms = self.max_age_ms;
+        self.seen.retain(|_, ts| (now - *ts).abs() <= max_age_ms);
+        if self.seen.contains_key(&signature.nonce) {
+            return Err(format!("Replayed nonce {}", signature.nonce));
+        }
+
+        let expected = mac(
+            &self.key,
+            signature.ts,
+            &signature.nonce,
+            &canonical(&payload),
+        );
+        if !constant_time_eq(expected.as_bytes(), signature.mac.as_bytes()) {
+            return Err("Signature mismatch".to_string());
+        }
+
+        self.seen.insert(signature.nonce, signature.ts);
+        Ok(())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let body = r#"{"subject":"control","data":{"b":1,"a":[true]}}"#;
+        let signed = sign_with(b"key", body, 1000, "n1").unwrap();
+
+        let mut verifier = Verifier::new(b"key".to_vec(), 60000);
+        assert_eq!(verifier.verify_at(&signed, 2000), Ok(()));
+        // Replayed.
+        assert!(verifier.verify_at(&signed, 2000).is_err());
+
+        let mut verifier = Verifier::new(b"key".to_vec(), 60000);
+        // Expired.
+        assert!(verifier.verify_at(&signed, 100000).is_err());
+        // Tampered.
+        let tampered = signed.replace("\"b\":1", "\"b\":2");
+        assert!(verifier.verify_at(&tampered, 2000).is_err());
+        // Not signed.
+        assert!(verifier.verify_at(body, 2000).is_err());
+
+        let mut verifier = Verifier::new(b"other".to_vec(), 60000);
+        assert!(verifier.verify_at(&signed, 2000).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::{
-    center::{connector, message},
+    center::{connector, message, reporting::ChangeFilter},
     core::timestamp::*,
     transport::message::*,
     worker::task::TaskStatus,
@@ -17,6 +17,9 @@ pub struct TaskState<StateInfo> {
     started_at: Timestamp,
 
     pub info: StateInfo,
+
+    /// Status, info
+    report_filter: ChangeFilter<(TaskStatus, serde_json::Value)>,
 }
 
 impl<StateInfo> TaskState<StateInfo>
@@ -29,6 +32,7 @@ where
             status: TaskStatus::Unknown,
             started_at: now(),
             info: StateInfo::default(),
+            report_filter: ChangeFilter::new(),
         }
     }
 
@@ -36,14 +40,22 @@ where
         self.task_uuid = task_uuid;
         self.status = TaskStatus::Running;
         self.started_at = now();
+        self.report_filter.reset();
     }
 
-    pub fn send_report(&self) {
+    /// The report is not sent if neither the status nor the info has
+    /// changed since the last one.
+    pub fn send_report(&mut self) {
+        let info = json!(self.info);
+        if !self.report_filter.pass((self.status, info.clone())) {
+            return;
+        }
+
         let report = TaskStatusReport {
             task_uuid: self.task_uuid.clone(),
             status: self.status,
             started_at: self.started_at.clone(),
-            info: json!(self.info),
+            info,
         };
 
         let c_msg = message::create(
@@ -0,0 +1,388 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    sync::Arc,
+};
+
+use crate::{
+    center::send::send_control_msg,
+    control::{
+        command::*,
+        message::*,
+        registry,
+    },
+    core::{
+        env::{self, PATOKA_ROOT_DIR},
+        logger::create_logger,
+        monitor::*,
+        timestamp::{self, Timestamp},
+    },
+};
+
+lazy_static! {
+    static ref PARAMS: AuditParams =
+        env::load_opt("control.audit").unwrap_or_default();
+}
+
+/// `[control.audit]` configuration section.
+#[derive(Deserialize)]
+struct AuditParams {
+    #[serde(default)]
+    enabled: bool,
+
+    /// Append the entries to this file.
+    #[serde(default)]
+    path: Option<String>,
+
+    /// Entries kept in memory.
+    #[serde(default = "default_recent_size")]
+    recent_size: usize,
+
+    #[serde(default = "default_response_timeout_s")]
+    response_timeout_s: i64,
+}
+
+fn default_recent_size() -> usize { 1000 }
+
+fn default_response_timeout_s() -> i64 { 30 }
+
+impl Default for AuditParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            recent_size: default_recent_size(),
+            response_timeout_s: default_response_timeout_s(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub at: Timestamp,
+
+    /// `ControlMessage::uuid`
+    pub uuid: String,
+
+    /// `ControlMessage::orig_id`
+    pub requester: String,
+
+    pub role: Option<String>,
+    pub cmd: String,
+
+    /// `ControlMessage::dest_id`
+    pub target: String,
+
+    /// "ok", "error: <details>" or "no_response".
+    pub outcome: String,
+
+    pub responded_at: Option<Timestamp>,
+}
+
+impl AuditEntry {
+    fn new(msg: &ControlMessage) -> Self {
+        Self {
+            at: timestamp::now(),
+            uuid: msg.uuid.clone(),
+            requester: msg.orig_id.clone(),
+            role: msg.role.clone(),
+            cmd: msg.cmd.clone(),
+            target: msg.dest_id.clone(),
+            outcome: String::new(),
+            responded_at: None,
+        }
+    }
+
+    fn close(&mut self, outcome: String) {
+        self.outcome = outcome;
+        self.responded_at = Some(timestamp::now());
+    }
+}
+
+/// The outcome of a response, see `CommandResponse`.
+fn outcome(response: &ControlMessage) -> String {
+    match serde_json::from_value::<CommandResponse>(response.data.clone()) {
+        Ok(r) if r.result == "ok" => r.result,
+        Ok(r) => format!("{}: {}", r.result, r.details),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+/// A request received, responded right away if `outcome` is set.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct AuditRequest {
+    entry: AuditEntry,
+    outcome: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct AuditResponse {
+    uuid: String,
+    outcome: String,
+}
+
+/// `data` is optional.
+#[derive(Deserialize)]
+struct AuditLogCommand(Option<AuditQuery>);
+
+#[derive(Default, Deserialize)]
+struct AuditQuery {
+    /// The most recent entries, 100 by default.
+    #[serde(default)]
+    limit: Option<usize>,
+
+    #[serde(default)]
+    cmd: Option<String>,
+
+    #[serde(default)]
+    requester: Option<String>,
+}
+
+impl Command for AuditLogCommand {
+    const NAME: &'static str = "audit_log";
+    type Response = Vec<AuditEntry>;
+}
+
+/// The recent control requests and their outcome, also appended to `path`
+/// as JSON lines if configured.
+pub struct ControlAudit {
+    log: Logger,
+    commands: Arc<CommandRouter<Self>>,
+
+    /// Message UUID --> Entry
+    /// Not responded yet.
+    pending: HashMap<String, AuditEntry>,
+
+    /// Oldest first.
+    recent: VecDeque<AuditEntry>,
+
+    regular_check_timer: RegularCheckTimer,
+}
+
+impl ControlAudit {
+    fn record(&mut self, entry: AuditEntry) {
+        info!(
+            self.log,
+            "[AUDIT] {} by {} ({}) on {}: {}",
+            entry.cmd,
+            entry.requester,
+            entry.role.as_deref().unwrap_or("app"),
+            entry.target,
+            entry.outcome,
+        );
+
+        if let Some(ref path) = PARAMS.path {
+            if let Err(e) = append(path, &entry) {
+                error!(self.log, "Failed to append to {}: {}", path, e);
+            }
+        }
+
+        if self.recent.len() >= PARAMS.recent_size {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(entry);
+    }
+
+    /// Record the requests not responded within the timeout.
+    fn expire_pending(&mut self) {
+        let deadline = timestamp::now()
+            - chrono::Duration::seconds(PARAMS.response_timeout_s);
+
+        let expired: Vec<String> = self.pending.iter()
+            .filter(|(_, e)| e.at < deadline)
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+
+        for uuid in expired {
+            if let Some(mut entry) = self.pending.remove(&uuid) {
+                entry.outcome = "no_response".to_string();
+                self.record(entry);
+            }
+        }
+    }
+
+    fn query(&self, query: AuditQuery) -> Vec<AuditEntry> {
+        let mut entries: Vec<AuditEntry> = self.recent.iter().rev()
+            .filter(|e| query.cmd.as_ref().is_none_or(|c| *c == e.cmd))
+            .filter(|e| {
+                query.requester.as_ref().is_none_or(|r| *r == e.requester)
+            })
+            .take(query.limit.unwrap_or(100))
+            .cloned()
+            .collect();
+
+        entries.reverse();
+        entries
+    }
+
+    fn handle_control_message(
+        &mut self,
+        msg: ControlMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        debug!(self.log, "[CONTROL] {:?}", msg);
+
+        let commands = self.commands.clone();
+        send_control_msg(commands.route(self, msg, ctx));
+    }
+}
+
+fn append(path: &str, entry: &AuditEntry) -> io::Result<()> {
+    let path = env::full_path(path, "$PATOKA_ROOT_DIR", &PATOKA_ROOT_DIR);
+    if let Some(dir) = std::path::Path::new(&path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+impl Default for ControlAudit {
+    fn default() -> Self {
+        Self {
+            log: create_logger("control_audit"),
+            commands: Arc::new(
+                CommandRouter::new()
+                    .add::<AuditLogCommand>()
+            ),
+            pending: HashMap::new(),
+            recent: VecDeque::new(),
+            regular_check_timer: RegularCheckTimer::interval_s(5),
+        }
+    }
+}
+
+impl CommandHandler<AuditLogCommand> for ControlAudit {
+    fn handle_command(
+        &mut self,
+        args: AuditLogCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<Vec<AuditEntry>, CommandError> {
+        Ok(self.query(args.0.unwrap_or_default()))
+    }
+}
+
+impl Actor for ControlAudit {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Control Audit started.");
+
+        registry::register("audit".to_string(), ctx.address().recipient());
+        self.regular_check_timer.reset::<Self>(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Control Audit stopped.");
+    }
+}
+
+impl Supervised for ControlAudit {}
+
+impl SystemService for ControlAudit {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Control Audit system service started.")
+    }
+}
+
+impl Handler<AuditRequest> for ControlAudit {
+    type Result = ();
+
+    fn handle(&mut self, msg: AuditRequest, _ctx: &mut Self::Context) {
+        let mut entry = msg.entry;
+        match msg.outcome {
+            Some(outcome) => {
+                entry.close(outcome);
+                self.record(entry);
+            },
+            None => {
+                self.pending.insert(entry.uuid.clone(), entry);
+            },
+        }
+    }
+}
+
+impl Handler<AuditResponse> for ControlAudit {
+    type Result = ();
+
+    fn handle(&mut self, msg: AuditResponse, _ctx: &mut Self::Context) {
+        if let Some(mut entry) = self.pending.remove(&msg.uuid) {
+            entry.close(msg.outcome);
+            self.record(entry);
+        }
+    }
+}
+
+impl Handler<RegularCheckMessage> for ControlAudit {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: RegularCheckMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.expire_pending();
+    }
+}
+
+handler_impl_control_message!(ControlAudit);
+
+/// `control.audit.enabled`
+pub fn enabled() -> bool {
+    PARAMS.enabled
+}
+
+pub fn start() -> Addr<ControlAudit> {
+    ControlAudit::from_registry()
+}
+
+/// A request received by the registry. `outcome` is set if it has been
+/// responded right away, e.g. forbidden.
+pub fn request(msg: &ControlMessage, outcome: Option<String>) {
+    if !enabled() {
+        return;
+    }
+
+    start().do_send(AuditRequest {
+        entry: AuditEntry::new(msg),
+        outcome,
+    });
+}
+
+/// A response to a request recorded by `request`.
+pub fn response(msg: &ControlMessage) {
+    if !enabled() {
+        return;
+    }
+
+    start().do_send(AuditResponse {
+        uuid: msg.uuid.clone(),
+        outcome: outcome(msg),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn response_outcome() {
+        let request = ControlMessage::request("t1", "center", "stop_task");
+        let ok = request.clone().response(CommandResponse::ok(json!(null)));
+        assert_eq!(outcome(&ok), "ok");
+
+        let failed = request.response(CommandResponse::error(
+            &CommandError::Failed("Not found".into()),
+        ));
+        assert_eq!(outcome(&failed), "error: Not found");
+    }
+}
@@ -0,0 +1,100 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+
+use crate::{
+    control::{command::CommandError, message::*},
+    core::env,
+};
+
+/// Any command not listed.
+const ANY_COMMAND: &str = "*";
+
+lazy_static! {
+    static ref PARAMS: AuthzParams =
+        env::load_opt("control.authz").unwrap_or_default();
+}
+
+/// `[control.authz]` configuration section. The role claimed by a request
+/// is trusted only if the request is signed.
+#[derive(Deserialize)]
+struct AuthzParams {
+    #[serde(default = "default_role")]
+    default_role: String,
+
+    /// Command name or "*" --> Allowed roles
+    #[serde(default)]
+    commands: HashMap<String, Vec<String>>,
+}
+
+fn default_role() -> String { "admin".to_string() }
+
+impl Default for AuthzParams {
+    fn default() -> Self {
+        Self {
+            default_role: default_role(),
+            commands: HashMap::new(),
+        }
+    }
+}
+
+impl AuthzParams {
+    fn check(&self, msg: &ControlMessage) -> Result<(), CommandError> {
+        let role = match msg.role {
+            Some(ref r) if msg.type_ == Type::Request => r,
+            _ => return Ok(()),
+        };
+
+        let allowed = self.commands.get(&msg.cmd)
+            .or_else(|| self.commands.get(ANY_COMMAND));
+
+        match allowed {
+            Some(roles) if !roles.contains(role) => {
+                Err(CommandError::Forbidden {
+                    cmd: msg.cmd.clone(),
+                    role: role.clone(),
+                })
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The role of a request from the center.
+pub fn role(claimed: Option<String>, signed: bool) -> String {
+    match claimed {
+        Some(r) if signed => r,
+        _ => PARAMS.default_role.clone(),
+    }
+}
+
+/// Whether the requester is allowed to run `msg`. The requests without a
+/// role are from the app itself.
+pub fn check(msg: &ControlMessage) -> Result<(), CommandError> {
+    PARAMS.check(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_roles() {
+        let mut params = AuthzParams::default();
+        params.commands.insert("stop".into(), vec!["operator".into()]);
+        params.commands.insert("*".into(), vec!["operator".into()]);
+        params.commands.insert("status".into(), vec!["viewer".into()]);
+
+        let request = |cmd: &str, role: Option<&str>| {
+            let mut msg = ControlMessage::request("t1", "center", cmd);
+            msg.role = role.map(String::from);
+            msg
+        };
+
+        assert!(params.check(&request("stop", Some("operator"))).is_ok());
+        assert!(params.check(&request("stop", Some("viewer"))).is_err());
+        assert!(params.check(&request("restart", Some("viewer"))).is_err());
+        assert!(params.check(&request("stop", None)).is_ok());
+        assert!(params.check(&request("status", Some("viewer"))).is_ok());
+    }
+}
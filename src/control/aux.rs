@@ -18,5 +18,6 @@ pub fn update_task_params<P: serde::Serialize>(
         &task_definition.task_uuid,
         &task_definition,
         &task_definition.name,
+        task_definition.labels.clone(),
     );
 }
@@ -0,0 +1,178 @@
+use actix::prelude::*;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, fmt};
+
+use crate::control::message::ControlMessage;
+
+/// Typed arguments of a control command. Deserialized from
+/// `ControlMessage::data`.
+///
+/// Use `#[serde(transparent)]` on a single field struct to accept a plain
+/// value, e.g. a task UUID string.
+pub trait Command: DeserializeOwned {
+    /// `ControlMessage::cmd`
+    const NAME: &'static str;
+
+    type Response: serde::Serialize;
+}
+
+pub trait CommandHandler<C: Command>: Actor {
+    /// `msg` is the original request (e.g. to get `orig_id`).
+    fn handle_command(
+        &mut self,
+        args: C,
+        msg: &ControlMessage,
+        ctx: &mut Self::Context,
+    ) -> Result<C::Response, CommandError>;
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Unknown {
+        cmd: String,
+        supported: Vec<&'static str>,
+    },
+    InvalidArgs(String),
+    Failed(String),
+    Forbidden {
+        cmd: String,
+        role: String,
+    },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Unknown { cmd, supported } => write!(
+                f,
+                "Unknown command {}. Supported commands: {}",
+                cmd,
+                supported.join(", "),
+            ),
+            CommandError::InvalidArgs(e) => {
+                write!(f, "Invalid arguments: {}", e)
+            },
+            CommandError::Failed(e) => write!(f, "{}", e),
+            CommandError::Forbidden { cmd, role } => {
+                write!(f, "Command {} is not allowed for role {}", cmd, role)
+            },
+        }
+    }
+}
+
+/// `ControlMessage::data` of a response. `result` is "ok" or "error".
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommandResponse {
+    pub result: String,
+    pub details: String,
+
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+impl CommandResponse {
+    pub fn ok(data: serde_json::Value) -> Self {
+        Self {
+            result: "ok".to_string(),
+            details: String::new(),
+            data,
+        }
+    }
+
+    pub fn error(e: &CommandError) -> Self {
+        let data = match e {
+            CommandError::Unknown { supported, .. } => {
+                json!({ "supported": supported })
+            },
+            _ => serde_json::Value::default(),
+        };
+
+        Self {
+            result: "error".to_string(),
+            details: e.to_string(),
+            data,
+        }
+    }
+}
+
+type HandlerFn<A> = fn(
+    &mut A,
+    &ControlMessage,
+    &mut <A as Actor>::Context,
+) -> Result<serde_json::Value, CommandError>;
+
+/// Routes control requests to the typed `CommandHandler`s of actor `A`.
+pub struct CommandRouter<A: Actor> {
+    /// Command name --> Handler
+    handlers: BTreeMap<&'static str, HandlerFn<A>>,
+}
+
+impl<A: Actor> CommandRouter<A> {
+    pub fn new() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+        }
+    }
+
+    pub fn add<C>(mut self) -> Self
+    where
+        C: Command,
+        A: CommandHandler<C>,
+    {
+        self.handlers.insert(C::NAME, handle_typed::<A, C>);
+        self
+    }
+
+    pub fn supported(&self) -> Vec<&'static str> {
+        self.handlers.keys().copied().collect()
+    }
+
+    /// Handle the request and make a response to it.
+    pub fn route(
+        &self,
+        actor: &mut A,
+        msg: ControlMessage,
+        ctx: &mut A::Context,
+    ) -> ControlMessage {
+        let result = match self.handlers.get(msg.cmd.as_str()) {
+            Some(handler) => handler(actor, &msg, ctx),
+            None => Err(CommandError::Unknown {
+                cmd: msg.cmd.clone(),
+                supported: self.supported(),
+            }),
+        };
+
+        let response = match result {
+            Ok(data) => CommandResponse::ok(data),
+            Err(e) => CommandResponse::error(&e),
+        };
+
+        msg.response(response)
+    }
+}
+
+impl<A: Actor> Default for CommandRouter<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn handle_typed<A, C>(
+    actor: &mut A,
+    msg: &ControlMessage,
+    ctx: &mut A::Context,
+) -> Result<serde_json::Value, CommandError>
+where
+    C: Command,
+    A: CommandHandler<C>,
+{
+    let args: C = serde_json::from_value(msg.data.clone())
+        .map_err(|e| CommandError::InvalidArgs(e.to_string()))?;
+
+    let response = actor.handle_command(args, msg, ctx)?;
+
+    serde_json::to_value(response)
+        .map_err(|e| CommandError::Failed(e.to_string()))
+}
@@ -5,7 +5,10 @@ use serde_json::json;
 use std::fmt;
 use uuid::Uuid;
 
-use crate::transport::message::*;
+use crate::{
+    core::telemetry::{self, TraceContext},
+    transport::message::*,
+};
 
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -54,6 +57,15 @@ pub struct ControlMessage {
     pub cmd: String,
 
     pub data: serde_json::Value,
+
+    /// Of the requester. Set for the requests from the center, see
+    /// `control::authz`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+
+    /// The trace of the destination task, if any.
+    #[serde(flatten)]
+    pub trace: TraceContext,
 }
 
 impl Message for ControlMessage {
@@ -82,6 +94,8 @@ impl ControlMessage {
             orig_id: orig_id.into(),
             cmd: cmd.into(),
             data: serde_json::Value::default(),
+            role: None,
+            trace: telemetry::child_of_task(dest_id),
         }
     }
 
@@ -98,6 +112,8 @@ impl ControlMessage {
             orig_id: orig_id.into(),
             cmd: cmd.into(),
             data: json!(data),
+            role: None,
+            trace: telemetry::child_of_task(dest_id),
         }
     }
 
@@ -206,3 +222,70 @@ macro_rules! handler_impl_restart_task {
         }
     }
 }
+
+/// Restart the task with its `params` merge-patched (RFC 7396), e.g. to fix
+/// a bad URL. The task is not restarted if the patch does not apply.
+#[derive(Clone)]
+pub struct RestartTaskWith {
+    pub task_uuid: String,
+    pub params_patch: serde_json::Value,
+}
+
+impl Message for RestartTaskWith {
+    type Result = Result<(), String>;
+}
+
+/// Suspend the task until `ResumeTask`.
+#[derive(Clone)]
+pub struct PauseTask {
+    pub task_uuid: String,
+}
+
+impl Message for PauseTask {
+    type Result = ();
+}
+
+#[macro_export]
+macro_rules! handler_impl_pause_task {
+    ($x:ty) => {
+        impl Handler<PauseTask> for $x {
+            type Result = ();
+
+            fn handle(
+                &mut self,
+                msg: PauseTask,
+                ctx: &mut Self::Context
+            ) -> Self::Result {
+                info!(self.log, "Pause [TASK UUID] {}", msg.task_uuid);
+                self.handle_pause_task(msg, ctx);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ResumeTask {
+    pub task_uuid: String,
+}
+
+impl Message for ResumeTask {
+    type Result = ();
+}
+
+#[macro_export]
+macro_rules! handler_impl_resume_task {
+    ($x:ty) => {
+        impl Handler<ResumeTask> for $x {
+            type Result = ();
+
+            fn handle(
+                &mut self,
+                msg: ResumeTask,
+                ctx: &mut Self::Context
+            ) -> Self::Result {
+                info!(self.log, "Resume [TASK UUID] {}", msg.task_uuid);
+                self.handle_resume_task(msg, ctx);
+            }
+        }
+    }
+}
@@ -1,18 +1,30 @@
 use actix::prelude::*;
+use chrono::Duration as ChronoDuration;
 use serde_derive::{Deserialize, Serialize};
 use slog::Logger;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 
 use crate::{
     center::send::*,
     control::message::*,
     core::{
+        env,
         logger::create_logger,
         timestamp::{now, Timestamp},
     },
     worker::tracker::dismiss_task_question,
 };
 
+/// Requests sent by all the trackers and not responded yet.
+static OUTSTANDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Seconds.
+const DEFAULT_RESPONSE_TIMEOUT: u64 = 30;
+
 #[derive(Clone)]
 pub struct TrackerItem {
     pub request: ControlMessage,
@@ -48,6 +60,9 @@ pub struct ControlMessageTracker {
 
     /// Message UUID --> Item
     items: HashMap<String, TrackerItem>,
+
+    /// `control.response_timeout_s`
+    response_timeout: Duration,
 }
 
 impl ControlMessageTracker {
@@ -56,6 +71,7 @@ impl ControlMessageTracker {
         Self {
             log: create_logger(&format!("control_tracker_{}", task_uuid)),
             items: HashMap::new(),
+            response_timeout: response_timeout(),
         }
     }
 
@@ -72,6 +88,8 @@ impl ControlMessageTracker {
             panic!("Tried to send a command message multiple times.");
         }
 
+        OUTSTANDING.fetch_add(1, Ordering::Relaxed);
+
         if msg.cmd == "task_answer" {
             dismiss_task_question(msg.dest_id.clone());
         }
@@ -88,6 +106,8 @@ impl ControlMessageTracker {
 
         match self.items.remove(&msg.uuid) {
             Some(mut item) => {
+                OUTSTANDING.fetch_sub(1, Ordering::Relaxed);
+
                 let result: ResponseResult =
                     serde_json::from_value(msg.data.clone()).unwrap();
 
@@ -102,7 +122,94 @@ impl ControlMessageTracker {
         }
     }
 
-    pub fn clear_unresponded(&mut self) {
+    /// Remove the requests not responded in time. A failure response is
+    /// synthesized for each of them and sent to the center.
+    /// Returns the removed items to let the sender handle the failures.
+    pub fn clear_unresponded(&mut self) -> Vec<TrackerItem> {
+        let deadline = now()
+            - ChronoDuration::seconds(self.response_timeout.as_secs() as i64);
+
+        let expired: Vec<String> = self.items.iter()
+            .filter(|(_, item)| item.created_at < deadline)
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+
+        let mut items = vec![];
+        for uuid in expired {
+            let mut item = match self.items.remove(&uuid) {
+                Some(item) => item,
+                None => continue,
+            };
+
+            OUTSTANDING.fetch_sub(1, Ordering::Relaxed);
+
+            warn!(
+                self.log,
+                "No response in {:?} [CMD] {} [UUID] {}",
+                self.response_timeout,
+                item.request.cmd,
+                uuid,
+            );
+
+            let response = item.request.clone().response(ResponseResult {
+                result: "timeout".to_string(),
+                details: format!(
+                    "No response in {} seconds.",
+                    self.response_timeout.as_secs(),
+                ),
+            });
+
+            item.success = false;
+            item.response = Some(response.clone());
+            send_control_msg(response);
+            items.push(item);
+        }
+
+        items
+    }
+
+    /// Requests sent and not responded yet.
+    pub fn pending(&self) -> usize {
+        self.items.len()
+    }
+}
+
+/// The owner of a `ControlMessageTracker`.
+pub trait ControlRequester: Actor<Context=Context<Self>> {
+    fn control_tracker(&mut self) -> &mut ControlMessageTracker;
 
+    /// Called with the requests that have not been responded in time.
+    fn handle_unresponded(&mut self, _items: Vec<TrackerItem>) {
     }
 }
+
+/// Periodically clear the unresponded requests of the actor's tracker.
+pub fn start_sweeping<A: ControlRequester>(ctx: &mut Context<A>) {
+    ctx.run_interval(sweep_interval(), |act, _ctx| {
+        let items = act.control_tracker().clear_unresponded();
+        if !items.is_empty() {
+            act.handle_unresponded(items);
+        }
+    });
+}
+
+/// Requests sent by all the trackers and not responded yet.
+pub fn outstanding() -> usize {
+    OUTSTANDING.load(Ordering::Relaxed)
+}
+
+fn response_timeout() -> Duration {
+    let secs = env::get_opt_var("control.response_timeout_s")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_RESPONSE_TIMEOUT);
+
+    Duration::from_secs(secs)
+}
+
+fn sweep_interval() -> Duration {
+    let secs = env::get_opt_var("control.sweep_interval_s")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5);
+
+    Duration::from_secs(secs)
+}
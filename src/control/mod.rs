@@ -1,5 +1,9 @@
-pub mod aux;
 #[macro_use]
 pub mod message;
+
+pub mod audit;
+pub mod authz;
+pub mod aux;
+pub mod command;
 pub mod message_tracker;
 pub mod registry;
@@ -6,10 +6,17 @@ use crate::{
     center::{
         connector::{self, CenterConnector},
         message::*,
+        send::send_control_msg,
+    },
+    control::{
+        audit,
+        authz,
+        command::CommandResponse,
+        message::*,
     },
-    control::message::*,
     core::logger::create_logger,
     transport::message::*,
+    worker::question,
 };
 
 pub struct RegisterEntity {
@@ -81,7 +88,25 @@ impl Handler<ControlMessage> for ControlRegistry {
         msg: ControlMessage,
         _ctx: &mut Self::Context
     ) -> Self::Result {
-        self.send_to_entity(msg);
+        if msg.type_ == Type::Response {
+            audit::response(&msg);
+        }
+
+        if let Err(e) = authz::check(&msg) {
+            warn!(self.log, "[CONTROL] {:?}: {}", msg.uuid, e);
+            audit::request(&msg, Some(format!("error: {}", e)));
+            send_control_msg(msg.response(CommandResponse::error(&e)));
+            return;
+        }
+
+        if msg.type_ == Type::Request {
+            audit::request(&msg, None);
+        }
+
+        // Unless awaited by `question::ask`.
+        if let Some(msg) = question::answer(msg) {
+            self.send_to_entity(msg);
+        }
     }
 }
 
@@ -0,0 +1,454 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use lettre::{
+    message::header::ContentType,
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport,
+    AsyncTransport,
+    Message as Mail,
+    Tokio1Executor,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use slog::Logger;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::core::{
+    app_state,
+    env,
+    error_bus::{self, PatokaError, Severity},
+    http,
+    logger::create_logger,
+    timestamp::{now, Timestamp},
+};
+
+lazy_static! {
+    static ref PARAMS: AlertingParams =
+        env::load_opt("alerting").unwrap_or_default();
+
+    static ref SINKS: RwLock<Vec<Arc<dyn AlertSink>>> = RwLock::new(
+        PARAMS.sinks.iter()
+            .map(|s| Arc::new(s.clone()) as Arc<dyn AlertSink>)
+            .collect()
+    );
+}
+
+/// `[alerting]` configuration section. An alert repeated within
+/// `dedup_window_s` is suppressed, and no more than `max_alerts` are sent
+/// within `throttle_window_s`.
+#[derive(Deserialize)]
+struct AlertingParams {
+    #[serde(default)]
+    enabled: bool,
+
+    #[serde(default = "default_min_severity")]
+    min_severity: Severity,
+
+    #[serde(default = "default_dedup_window_s")]
+    dedup_window_s: u64,
+
+    #[serde(default = "default_throttle_window_s")]
+    throttle_window_s: u64,
+
+    #[serde(default = "default_max_alerts")]
+    max_alerts: usize,
+
+    #[serde(default = "default_heartbeat_lost")]
+    heartbeat_lost: bool,
+
+    #[serde(default)]
+    sinks: Vec<SinkConfig>,
+}
+
+fn default_min_severity() -> Severity { Severity::Critical }
+
+fn default_dedup_window_s() -> u64 { 300 }
+
+fn default_throttle_window_s() -> u64 { 3600 }
+
+fn default_max_alerts() -> usize { 20 }
+
+fn default_heartbeat_lost() -> bool { true }
+
+impl Default for AlertingParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_severity: default_min_severity(),
+            dedup_window_s: default_dedup_window_s(),
+            throttle_window_s: default_throttle_window_s(),
+            max_alerts: default_max_alerts(),
+            heartbeat_lost: default_heartbeat_lost(),
+            sinks: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SinkConfig {
+    Smtp {
+        /// E.g. "smtps://mail.example:465"
+        url: String,
+
+        from: String,
+        to: Vec<String>,
+
+        #[serde(default)]
+        username: Option<String>,
+
+        #[serde(default)]
+        password: Option<String>,
+    },
+
+    Http {
+        /// E.g. "https://events.pagerduty.com/v2/enqueue"
+        url: String,
+
+        #[serde(default)]
+        routing_key: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Message)]
+#[rtype(result = "()")]
+pub struct Alert {
+    /// Alerts with the same key are deduplicated.
+    pub key: String,
+
+    pub severity: Severity,
+    pub summary: String,
+
+    /// The module or the worker.
+    pub source: String,
+
+    pub task_uuid: String,
+    pub ts: Timestamp,
+}
+
+impl From<&PatokaError> for Alert {
+    fn from(e: &PatokaError) -> Self {
+        Self {
+            key: format!("{}:{}", e.module, e.details),
+            severity: e.severity,
+            summary: e.details.clone(),
+            source: e.module.clone(),
+            task_uuid: e.task_uuid.clone(),
+            ts: e.ts,
+        }
+    }
+}
+
+pub type SendFuture = Pin<Box<dyn Future<Output = Result<(), String>>>>;
+
+pub trait AlertSink: Send + Sync {
+    fn name(&self) -> String;
+
+    /// Polled in the arbiter of the alerting service.
+    fn send(&self, alert: &Alert) -> SendFuture;
+}
+
+/// Send the alerts to `sink` too.
+pub fn add_sink(sink: Arc<dyn AlertSink>) {
+    SINKS.write().unwrap().push(sink);
+}
+
+impl AlertSink for SinkConfig {
+    fn name(&self) -> String {
+        match self {
+            SinkConfig::Smtp { url, .. } => url.clone(),
+            SinkConfig::Http { url, .. } => url.clone(),
+        }
+    }
+
+    fn send(&self, alert: &Alert) -> SendFuture {
+        let alert = alert.clone();
+        match self.clone() {
+            SinkConfig::Smtp { url, from, to, username, password } => {
+                Box::pin(async move {
+                    let credentials = username.map(|u| {
+                        Credentials::new(u, password.unwrap_or_default())
+                    });
+                    send_mail(&url, &from, &to, credentials, &alert).await
+                })
+            },
+            SinkConfig::Http { url, routing_key } => Box::pin(async move {
+                let r = http::post(&url)
+                    .json(&trigger_event(&routing_key, &alert))
+                    .user_agent("patoka")
+                    .use_proxy(false)
+                    .rate_limit(false)
+                    .send()
+                    .await?;
+
+                if r.is_success() {
+                    Ok(())
+                } else {
+                    Err(format!("Status {}: {}", r.status, r.text()))
+                }
+            }),
+        }
+    }
+}
+
+fn subject(alert: &Alert) -> String {
+    let app = env::get_opt_var("general.name")
+        .unwrap_or_else(app_state::app_id);
+    format!("[{}] {:?} {}", app, alert.severity, alert.source)
+}
+
+fn email(from: &str, to: &[String], alert: &Alert)
+    -> Result<Mail, String>
+{
+    let mut builder = Mail::builder()
+        .from(from.parse().map_err(|e| format!("{}: {}", from, e))?)
+        .subject(subject(alert))
+        .header(ContentType::TEXT_PLAIN);
+
+    for rcpt in to {
+        builder = builder
+            .to(rcpt.parse().map_err(|e| format!("{}: {}", rcpt, e))?);
+    }
+
+    let mut body = format!("{}\r\n\r\n", alert.summary);
+    if !alert.task_uuid.is_empty() {
+        body += &format!("Task: {}\r\n", alert.task_uuid);
+    }
+    body += &format!("At: {}\r\n", alert.ts.to_rfc3339());
+
+    builder.body(body).map_err(|e| e.to_string())
+}
+
+async fn send_mail(
+    url: &str,
+    from: &str,
+    to: &[String],
+    credentials: Option<Credentials>,
+    alert: &Alert,
+) -> Result<(), String> {
+    let mail = email(from, to, alert)?;
+
+    let mut transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+        .map_err(|e| e.to_string())?
+        .timeout(Some(Duration::from_secs(60)));
+    if let Some(c) = credentials {
+        transport = transport.credentials(c);
+    }
+
+    transport.build().send(mail).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn trigger_event(routing_key: &str, alert: &Alert) -> serde_json::Value {
+    json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        "dedup_key": alert.key,
+        "payload": {
+            "summary": subject(alert) + ": " + &alert.summary,
+            "source": alert.source,
+            "severity": alert.severity.as_str(),
+            "timestamp": alert.ts.to_rfc3339(),
+            "custom_details": { "task_uuid": alert.task_uuid },
+        },
+    })
+}
+
+/// Deduplication and throttling of the alerts.
+struct Gate {
+    dedup_window: Duration,
+    throttle_window: Duration,
+    max_alerts: usize,
+
+    /// Key --> Last sent at
+    sent_by_key: HashMap<String, Instant>,
+
+    /// Within the throttle window, oldest first.
+    sent: VecDeque<Instant>,
+
+    /// Not sent since the last alert sent.
+    suppressed: usize,
+}
+
+impl Gate {
+    fn new(dedup_window: Duration, throttle_window: Duration, max: usize)
+        -> Self
+    {
+        Self {
+            dedup_window,
+            throttle_window,
+            max_alerts: max,
+            sent_by_key: HashMap::new(),
+            sent: VecDeque::new(),
+            suppressed: 0,
+        }
+    }
+
+    /// Whether an alert with `key` is to be sent at `now`.
+    fn pass(&mut self, key: &str, now: Instant) -> bool {
+        let dedup_window = self.dedup_window;
+        self.sent_by_key.retain(|_, at| now.duration_since(*at) < dedup_window);
+        while self.sent.front()
+            .is_some_and(|at| now.duration_since(*at) >= self.throttle_window)
+        {
+            self.sent.pop_front();
+        }
+
+        if self.sent_by_key.contains_key(key)
+            || self.sent.len() >= self.max_alerts
+        {
+            self.suppressed += 1;
+            return false;
+        }
+
+        self.sent_by_key.insert(key.to_string(), now);
+        self.sent.push_back(now);
+        true
+    }
+}
+
+pub struct Alerting {
+    log: Logger,
+    gate: Gate,
+}
+
+impl Alerting {
+    fn alert(&mut self, alert: Alert, ctx: &mut <Self as Actor>::Context) {
+        if !self.gate.pass(&alert.key, Instant::now()) {
+            debug!(self.log, "Suppressed [KEY] {}", alert.key);
+            return;
+        }
+
+        let suppressed = std::mem::take(&mut self.gate.suppressed);
+        if suppressed > 0 {
+            info!(self.log, "{} alerts suppressed meanwhile.", suppressed);
+        }
+
+        for sink in SINKS.read().unwrap().iter() {
+            let name = sink.name();
+            sink.send(&alert)
+                .into_actor(self)
+                .map(move |r, act, _| {
+                    if let Err(e) = r {
+                        warn!(act.log, "Failed to alert {}: {}", name, e);
+                    }
+                })
+                .spawn(ctx);
+        }
+    }
+}
+
+impl Default for Alerting {
+    fn default() -> Self {
+        Self {
+            log: create_logger("alerting"),
+            gate: Gate::new(
+                Duration::from_secs(PARAMS.dedup_window_s),
+                Duration::from_secs(PARAMS.throttle_window_s),
+                PARAMS.max_alerts,
+            ),
+        }
+    }
+}
+
+impl Actor for Alerting {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Alerting started.");
+
+        error_bus::subscribe(
+            "alerting".to_string(),
+            ctx.address().recipient(),
+        );
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Alerting stopped.");
+    }
+}
+
+impl Supervised for Alerting {}
+
+impl SystemService for Alerting {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Alerting system service started.")
+    }
+}
+
+impl Handler<PatokaError> for Alerting {
+    type Result = ();
+
+    fn handle(&mut self, msg: PatokaError, ctx: &mut Self::Context) {
+        if msg.severity >= PARAMS.min_severity {
+            self.alert(Alert::from(&msg), ctx);
+        }
+    }
+}
+
+impl Handler<Alert> for Alerting {
+    type Result = ();
+
+    fn handle(&mut self, msg: Alert, ctx: &mut Self::Context) {
+        self.alert(msg, ctx);
+    }
+}
+
+/// `alerting.enabled`
+pub fn enabled() -> bool {
+    PARAMS.enabled
+}
+
+pub fn start() -> Addr<Alerting> {
+    Alerting::from_registry()
+}
+
+/// Alert on the worker not answering the heartbeats.
+pub fn heartbeat_lost(worker_id: &str, timeout: Duration) {
+    if !enabled()
+        || !PARAMS.heartbeat_lost
+        || SINKS.read().unwrap().is_empty()
+    {
+        return;
+    }
+
+    start().do_send(Alert {
+        key: format!("heartbeat_lost:{}", worker_id),
+        severity: Severity::Critical,
+        summary: format!("No heartbeat response in {:?}", timeout),
+        source: worker_id.to_string(),
+        task_uuid: String::new(),
+        ts: now(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedup_and_throttle() {
+        let mut gate = Gate::new(
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+            2,
+        );
+        let now = Instant::now();
+
+        assert!(gate.pass("a", now));
+        assert!(!gate.pass("a", now + Duration::from_secs(5)));
+        assert!(gate.pass("b", now + Duration::from_secs(5)));
+
+        // Throttled.
+        assert!(!gate.pass("c", now + Duration::from_secs(20)));
+        assert_eq!(gate.suppressed, 2);
+
+        assert!(gate.pass("a", now + Duration::from_secs(61)));
+    }
+}
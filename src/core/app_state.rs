@@ -1,26 +1,61 @@
 use actix::prelude::*;
+use lazy_static::lazy_static;
 use serde_derive::{Deserialize, Serialize};
 use slog::Logger;
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU8, Ordering},
+    },
+};
 use uuid::Uuid;
 
 use crate::{
     center::{
-        connector::{self, CenterConnector},
+        connector::{self, CenterConnector, CenterStatus},
         message,
+        reporting::ChangeFilter,
+        send::send_control_msg,
+    },
+    control::{
+        command::{Command, CommandError, CommandHandler, CommandRouter},
+        message::*,
+        message_tracker,
+        registry,
     },
-    control::message::*,
     core::{
+        capabilities,
         env,
         logger::create_logger,
         monitor::*,
+        notifier::{self, EventKind},
         timestamp::*,
     },
     handler_impl_task_update,
     transport::message::RawMessage,
-    worker::tracker::*,
+    worker::{
+        node_registry::{self, NodeStatus},
+        processor::{self, ProcessHeldTasks},
+        slots::{self, ReservationStatus},
+        task::TaskStatus,
+        task_tree,
+        tracker::*,
+    },
 };
 
+lazy_static! {
+    static ref APP_ID: RwLock<String> = RwLock::new(
+        match env::get_opt_var("general.id") {
+            Some(id) => id,
+            None => {
+                // Generate "random" ID.
+                "app-".to_owned() + &Uuid::new_v4().to_string()
+            },
+        }
+    );
+}
+
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AppStatus {
@@ -30,12 +65,70 @@ pub enum AppStatus {
     Unknown,
 }
 
+impl AppStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppStatus::Running => "running",
+            AppStatus::Idle => "idle",
+            AppStatus::Error => "error",
+            AppStatus::Unknown => "unknown",
+        }
+    }
+}
+
+/// What the app accepts to run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppMode {
+    #[default]
+    Normal,
+
+    /// No new tasks are started, the running ones are let finish, e.g.
+    /// before a deploy. Their subtasks are still started.
+    Draining,
+
+    /// No tasks are started at all and the task readers do not send the
+    /// input again.
+    Maintenance,
+}
+
+impl AppMode {
+    /// A new task, `subtask` if of a running task, may be started.
+    pub fn accepts(self, subtask: bool) -> bool {
+        match self {
+            AppMode::Normal => true,
+            AppMode::Draining => subtask,
+            AppMode::Maintenance => false,
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => AppMode::Draining,
+            2 => AppMode::Maintenance,
+            _ => AppMode::Normal,
+        }
+    }
+}
+
+/// See `mode`.
+static MODE: AtomicU8 = AtomicU8::new(AppMode::Normal as u8);
+
+/// `data` is the mode, e.g. "draining".
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct SetModeCommand {
+    mode: AppMode,
+}
+
+impl Command for SetModeCommand {
+    const NAME: &'static str = "set_mode";
+    type Response = ();
+}
+
 pub struct AppState {
     log: Logger,
 
-    /// Application ID.
-    app_id: String,
-
     /// Application name.
     app_name: String,
 
@@ -51,10 +144,24 @@ pub struct AppState {
     /// Task is removed from the list when Closed.
     active_task_uuids: HashSet<String>,
 
+    /// Task UUID --> Summary
+    /// Of the active tasks.
+    tasks: HashMap<String, TaskSummary>,
+
+    /// Mailbox name --> Status
+    mailboxes: BTreeMap<String, MailboxStatus>,
+
+    process: ProcessMetrics,
+
     /// Periodically generate status report.
     report_status_timer: ReportStatusTimer,
 
+    /// Suppresses the unchanged reports.
+    report_filter: ChangeFilter<ReportMaterial>,
+
     center_connector_addr: Addr<CenterConnector>,
+
+    commands: Arc<CommandRouter<Self>>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -67,19 +174,66 @@ pub struct AppStatusReport {
 
     pub status: AppStatus,
 
+    #[serde(default)]
+    pub mode: AppMode,
+
     pub started_at: Timestamp,
 
     pub active_task_uuids: HashSet<String>,
+
+    /// The oldest active tasks, `status_report.max_tasks` at most.
+    #[serde(default)]
+    pub tasks: Vec<TaskSummary>,
+
+    /// There are more active tasks than `tasks`.
+    #[serde(default)]
+    pub tasks_truncated: bool,
+
+    /// Mailbox name --> Status
+    #[serde(default)]
+    pub mailboxes: BTreeMap<String, MailboxStatus>,
+
+    /// Control requests not responded yet.
+    #[serde(default)]
+    pub outstanding_control_requests: usize,
+
+    /// Connections to the centers.
+    #[serde(default)]
+    pub centers: Vec<CenterStatus>,
+
+    /// Remote worker nodes.
+    #[serde(default)]
+    pub nodes: Vec<NodeStatus>,
+
+    /// Controller ID --> Tasks the controller is reserved for
+    #[serde(default)]
+    pub reservations: BTreeMap<String, Vec<ReservationStatus>>,
+
+    /// Tasks closed for inactivity since the start.
+    #[serde(default)]
+    pub swept_tasks: usize,
+
+    /// Resource usage of the app and its worker processes.
+    #[serde(default)]
+    pub process: ProcessMetrics,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TaskSummary {
+    pub task_uuid: String,
+    pub name: String,
+    pub status: TaskStatus,
+    pub started_at: Timestamp,
+}
+
+/// `status_report.max_tasks`
+fn max_tasks() -> usize {
+    env::opt_var("status_report.max_tasks").ok().flatten().unwrap_or(100)
 }
 
 impl AppStatusReport {
     pub fn status_as_str(&self) -> &'static str {
-        match self.status {
-            AppStatus::Running => "running",
-            AppStatus::Idle => "idle",
-            AppStatus::Error => "error",
-            _  => "unknown",
-        }
+        self.status.as_str()
     }
 
     pub fn status_from_str(s: &str) -> AppStatus {
@@ -94,25 +248,102 @@ impl AppStatusReport {
     pub fn compare_attributes(&self, report: &Self) -> bool {
         self.app_name == report.app_name && self.url == report.url
     }
+
+    fn material(&self) -> ReportMaterial {
+        ReportMaterial {
+            status: self.status,
+            mode: self.mode,
+            active_task_uuids: self.active_task_uuids.clone(),
+            task_statuses: self.tasks.iter()
+                .map(|t| (t.task_uuid.clone(), t.status))
+                .collect(),
+            growing_mailboxes: self.mailboxes.iter()
+                .filter(|(_, s)| s.growing > 0)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            outstanding_control_requests: self.outstanding_control_requests,
+            centers: self.centers.iter()
+                .map(|c| (c.name.clone(), c.connected, c.alive))
+                .collect(),
+            nodes: self.nodes.iter()
+                .map(|n| (n.node_id.clone(), n.online, n.workers.len()))
+                .collect(),
+            reservations: self.reservations.clone(),
+        }
+    }
 }
 
-impl AppState {
-    fn generate_status_report(&self) {
-        //debug!(self.log, "Generate status report.");
+/// The part of `AppStatusReport` whose change is worth a report. Lags and
+/// counters are not.
+#[derive(PartialEq)]
+struct ReportMaterial {
+    status: AppStatus,
+    mode: AppMode,
+    active_task_uuids: HashSet<String>,
+
+    /// Of the reported tasks.
+    task_statuses: Vec<(String, TaskStatus)>,
+
+    growing_mailboxes: Vec<String>,
+    outstanding_control_requests: usize,
+
+    /// Name, connected, alive
+    centers: Vec<(String, bool, bool)>,
+
+    /// Node ID, online, number of workers
+    nodes: Vec<(String, bool, usize)>,
+
+    reservations: BTreeMap<String, Vec<ReservationStatus>>,
+}
 
-        let report = AppStatusReport {
-            app_id: self.app_id.clone(),
+impl AppState {
+    /// The report is not sent if nothing material has changed since the
+    /// last one.
+    fn status_report(&self) -> AppStatusReport {
+        let mut tasks: Vec<TaskSummary> = self.tasks.values()
+            .cloned()
+            .collect();
+        tasks.sort_by(|a, b| {
+            (a.started_at, &a.task_uuid).cmp(&(b.started_at, &b.task_uuid))
+        });
+
+        let max = max_tasks();
+        let tasks_truncated = tasks.len() > max;
+        tasks.truncate(max);
+
+        AppStatusReport {
+            app_id: app_id(),
             app_name: self.app_name.clone(),
             url: self.url.clone(),
             status: self.status,
+            mode: mode(),
             started_at: self.started_at.clone(),
             active_task_uuids: self.active_task_uuids.clone(),
-        };
+            tasks,
+            tasks_truncated,
+            mailboxes: self.mailboxes.clone(),
+            outstanding_control_requests: message_tracker::outstanding(),
+            centers: connector::status(),
+            nodes: node_registry::status(),
+            reservations: slots::status(),
+            swept_tasks: task_tree::swept_tasks(),
+            process: self.process.clone(),
+        }
+    }
+
+    fn generate_status_report(&mut self) {
+        //debug!(self.log, "Generate status report.");
+
+        let report = self.status_report();
+
+        if !self.report_filter.pass(report.material()) {
+            return;
+        }
 
         let c_msg = message::create(
             message::Dest::Center,
             message::Subject::AppStatusReport,
-            self.app_id.clone(),
+            app_id(),
             "status_report".to_string(),
             report,
         );
@@ -120,12 +351,47 @@ impl AppState {
         self.center_connector_addr.do_send(RawMessage::from(c_msg));
     }
 
+    fn handle_control_message(
+        &mut self,
+        msg: ControlMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        debug!(self.log, "[CONTROL] {:?}", msg);
+
+        let commands = self.commands.clone();
+        send_control_msg(commands.route(self, msg, ctx));
+    }
+
+    fn set_mode(&mut self, mode: AppMode, ctx: &mut <Self as Actor>::Context) {
+        let prev = AppMode::from_u8(MODE.swap(mode as u8, Ordering::SeqCst));
+        if prev == mode {
+            return;
+        }
+
+        info!(self.log, "App mode: {:?} --> {:?}", prev, mode);
+
+        // The tasks held in the previous mode.
+        processor::start().do_send(ProcessHeldTasks);
+
+        self.generate_status_report();
+        self.report_status_timer.reset::<Self>(ctx);
+    }
+
     fn determine_status(&mut self) {
+        let prev = self.status;
         if self.active_task_uuids.len() > 0 {
             self.status = AppStatus::Running;
         } else {
             self.status = AppStatus::Idle;
         }
+
+        if self.status != prev {
+            notifier::notify(
+                EventKind::AppStatusChanged,
+                &app_id(),
+                format!("{} --> {}", prev.as_str(), self.status.as_str()),
+            );
+        }
     }
 
     fn handle_task_update(
@@ -134,10 +400,19 @@ impl AppState {
         ctx: &mut <Self as Actor>::Context
     ) {
         if msg.tag != TaskUpdateTag::Started {
+            if let Some(task) = self.tasks.get_mut(&msg.task_uuid) {
+                task.status = msg.status;
+            }
             return;
         }
 
         self.active_task_uuids.insert(msg.task_uuid.clone());
+        self.tasks.insert(msg.task_uuid.clone(), TaskSummary {
+            task_uuid: msg.task_uuid.clone(),
+            name: msg.name.clone(),
+            status: msg.status,
+            started_at: now(),
+        });
 
         info!(
             self.log,
@@ -158,6 +433,7 @@ impl AppState {
         ctx: &mut <Self as Actor>::Context,
     ) {
         self.active_task_uuids.remove(&msg.task_uuid);
+        self.tasks.remove(&msg.task_uuid);
 
         info!(
             self.log,
@@ -174,14 +450,6 @@ impl AppState {
 
 impl Default for AppState {
     fn default() -> Self {
-        let app_id = match env::get_opt_var("general.id") {
-            Some(id) => id,
-            None => {
-                // Generate "random" ID.
-                "app-".to_owned() + &Uuid::new_v4().to_string()
-            },
-        };
-
         let app_name = if let Some(name) = env::get_opt_var("general.name") {
             name
         } else {
@@ -196,24 +464,49 @@ impl Default for AppState {
 
         Self {
             log: create_logger("app_state"),
-            app_id,
             app_name,
             url,
             status: AppStatus::Idle,
             started_at: now(),
             active_task_uuids: HashSet::new(),
-            report_status_timer: ReportStatusTimer::new_s(3),
+            tasks: HashMap::new(),
+            mailboxes: BTreeMap::new(),
+            process: ProcessMetrics::default(),
+            report_status_timer: ReportStatusTimer::interval_s(3),
+            report_filter: ChangeFilter::new(),
             center_connector_addr: connector::start(),
+            commands: Arc::new(
+                CommandRouter::new()
+                    .add::<SetModeCommand>()
+            ),
         }
     }
 }
 
+impl CommandHandler<SetModeCommand> for AppState {
+    fn handle_command(
+        &mut self,
+        args: SetModeCommand,
+        _msg: &ControlMessage,
+        ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.set_mode(args.mode, ctx);
+        Ok(())
+    }
+}
+
 impl Actor for AppState {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
         info!(self.log, "Application State started.");
 
+        registry::register(
+            "app_state".to_string(),
+            ctx.address().recipient(),
+        );
+
+        capabilities::report(&app_id(), &self.log);
         self.generate_status_report();
         self.report_status_timer.reset::<Self>(ctx);
     }
@@ -237,16 +530,74 @@ impl Handler<ReportStatusMessage> for AppState {
     fn handle(
         &mut self,
         _msg: ReportStatusMessage,
-        ctx: &mut Self::Context
+        _ctx: &mut Self::Context
     ) -> Self::Result {
         self.generate_status_report();
-        self.report_status_timer.reset::<Self>(ctx);
     }
 }
 
+/// The current status report, e.g. for the admin endpoint.
+pub struct GetStatusReport;
+
+impl Message for GetStatusReport {
+    type Result = AppStatusReport;
+}
+
+impl Handler<GetStatusReport> for AppState {
+    type Result = MessageResult<GetStatusReport>;
+
+    fn handle(
+        &mut self,
+        _msg: GetStatusReport,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        MessageResult(self.status_report())
+    }
+}
+
+impl Handler<MailboxReport> for AppState {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: MailboxReport,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.mailboxes = msg.statuses;
+    }
+}
+
+impl Handler<ProcessReport> for AppState {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: ProcessReport,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.process = msg.metrics;
+    }
+}
+
+/// `general.id` or a generated ID, unless assigned by the center.
+pub fn app_id() -> String {
+    APP_ID.read().unwrap().clone()
+}
+
+/// The ID assigned by the center on registration.
+pub fn set_app_id(id: &str) {
+    *APP_ID.write().unwrap() = id.to_string();
+}
+
 pub fn start() -> Addr<AppState> {
     AppState::from_registry()
 }
 
+/// Set by the `set_mode` control command.
+pub fn mode() -> AppMode {
+    AppMode::from_u8(MODE.load(Ordering::SeqCst))
+}
+
+handler_impl_control_message!(AppState);
 handler_impl_task_update!(AppState);
 handler_impl_close_task!(AppState);
@@ -1,19 +1,108 @@
 use actix::prelude::*;
 use lazy_static::lazy_static;
 use num_cpus;
+use serde_derive::Deserialize;
 use slog::Logger;
-use std::sync::Mutex;
+use std::{
+    cell::Cell,
+    sync::{
+        Arc,
+        Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::core::logger::create_logger;
+use crate::core::{env, logger::create_logger};
 
 lazy_static! {
+    static ref PARAMS: ArbiterPoolParams =
+        env::load_opt("arbiter_pool").unwrap_or_default();
+
     static ref ARBITER_POOL: Mutex<ArbiterPool> =
         Mutex::new(ArbiterPool::new());
+
+    /// Load of each arbiter of the pool, by index.
+    static ref LOADS: Mutex<Vec<Arc<ArbiterLoad>>> = Mutex::new(vec![]);
+}
+
+thread_local! {
+    /// Index of the pool arbiter running on this thread.
+    static ARBITER_INDEX: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// `[arbiter_pool]` configuration section.
+#[derive(Deserialize)]
+struct ArbiterPoolParams {
+    /// "least_loaded" (default) or "round_robin".
+    #[serde(default = "default_strategy")]
+    strategy: String,
+
+    /// How often the arbiters are probed for the scheduling delay.
+    #[serde(default = "default_probe_interval_ms")]
+    probe_interval_ms: u64,
+
+    /// The scheduling delay counted as much as a single active actor.
+    #[serde(default = "default_busy_weight_us")]
+    busy_weight_us: u64,
+}
+
+fn default_strategy() -> String { "least_loaded".to_string() }
+
+fn default_probe_interval_ms() -> u64 { 500 }
+
+fn default_busy_weight_us() -> u64 { 1000 }
+
+impl Default for ArbiterPoolParams {
+    fn default() -> Self {
+        Self {
+            strategy: default_strategy(),
+            probe_interval_ms: default_probe_interval_ms(),
+            busy_weight_us: default_busy_weight_us(),
+        }
+    }
+}
+
+type IsAlive = Box<dyn Fn() -> bool + Send>;
+
+#[derive(Default)]
+struct ArbiterLoad {
+    /// Live actors started in the arbiter with `track`.
+    active: AtomicUsize,
+
+    /// Moving average of the time a future spawned in the arbiter waits
+    /// before it is run, i.e. how busy the arbiter has recently been.
+    busy_us: AtomicU64,
+
+    actors: Mutex<Vec<IsAlive>>,
+}
+
+impl ArbiterLoad {
+    fn update_active(&self) {
+        let mut actors = self.actors.lock().unwrap();
+        actors.retain(|is_alive| is_alive());
+        self.active.store(actors.len(), Ordering::Relaxed);
+    }
+
+    fn update_busy(&self, delay: Duration) {
+        let delay = delay.as_micros() as u64;
+        let prev = self.busy_us.load(Ordering::Relaxed);
+        self.busy_us.store((prev * 3 + delay) / 4, Ordering::Relaxed);
+    }
+
+    fn score(&self) -> u64 {
+        let active = self.active.load(Ordering::Relaxed) as u64;
+        let busy = self.busy_us.load(Ordering::Relaxed);
+
+        active + busy / PARAMS.busy_weight_us.max(1)
+    }
 }
 
 struct ArbiterPool {
     arbiters: Vec<Arbiter>,
     next_to_use: usize,
+    least_loaded: bool,
     log: Logger,
 }
 
@@ -22,36 +111,150 @@ impl ArbiterPool {
         let mut arbiter_pool = ArbiterPool {
             arbiters: Vec::new(),
             next_to_use: 0,
+            least_loaded: is_least_loaded(),
             log: create_logger("arbiter_pool"),
         };
 
         arbiter_pool.launch(num_cpus::get());
 
+        if arbiter_pool.least_loaded {
+            arbiter_pool.start_probing();
+        }
+
         arbiter_pool
     }
 
     pub fn launch(&mut self, size: usize) {
+        let mut loads = LOADS.lock().unwrap();
+
         for _i in 0..size {
             let addr = Arbiter::new();
+
+            let index = self.arbiters.len();
+            addr.spawn_fn(move || {
+                ARBITER_INDEX.with(|i| i.set(Some(index)));
+            });
+
             self.arbiters.push(addr);
+            loads.push(Arc::new(ArbiterLoad::default()));
         }
 
         info!(self.log, "Created {} arbiters.", self.arbiters.len());
     }
 
+    /// Measure the scheduling delay of each arbiter and forget the stopped
+    /// actors in a background thread.
+    fn start_probing(&self) {
+        let handles: Vec<ArbiterHandle> = self.arbiters.iter()
+            .map(|a| a.handle())
+            .collect();
+        let interval = Duration::from_millis(PARAMS.probe_interval_ms.max(1));
+
+        thread::spawn(move || loop {
+            let loads = LOADS.lock().unwrap().clone();
+
+            for (handle, load) in handles.iter().zip(loads) {
+                load.update_active();
+
+                let sent_at = Instant::now();
+                handle.spawn(async move {
+                    load.update_busy(sent_at.elapsed());
+                });
+            }
+
+            thread::sleep(interval);
+        });
+    }
+
     pub fn next(&mut self) -> ArbiterHandle {
-        let arb = &self.arbiters[self.next_to_use];
+        let len = self.arbiters.len();
+
+        let index = if self.least_loaded {
+            let scores: Vec<u64> = LOADS.lock().unwrap().iter()
+                .map(|l| l.score())
+                .collect();
+            least_loaded(&scores, self.next_to_use)
+        } else {
+            self.next_to_use
+        };
+
+        self.next_to_use = (index + 1) % len;
 
-        self.next_to_use += 1;
-        if self.next_to_use >= self.arbiters.len() {
-            self.next_to_use = 0;
+        // Count the actor to be started right away, so that a burst of
+        // actors is spread before `track` and the probes catch up.
+        if self.least_loaded {
+            if let Some(load) = LOADS.lock().unwrap().get(index) {
+                load.active.fetch_add(1, Ordering::Relaxed);
+            }
         }
 
-        arb.handle()
+        self.arbiters[index].handle()
     }
 }
 
+fn is_least_loaded() -> bool {
+    PARAMS.strategy != "round_robin"
+}
+
+/// Index of the lowest score, the first one from `start` among the equal.
+fn least_loaded(scores: &[u64], start: usize) -> usize {
+    let len = scores.len();
+
+    (0..len)
+        .map(|i| (start + i) % len)
+        .min_by_key(|&i| scores[i])
+        .unwrap_or(0)
+}
+
+/// Number of arbiters in the pool.
+pub fn size() -> usize {
+    let arbiter_pool = ARBITER_POOL.lock().unwrap();
+    arbiter_pool.arbiters.len()
+}
+
+/// The least loaded arbiter, or the next one in turn with the
+/// `arbiter_pool.strategy = "round_robin"`.
 pub fn next() -> ArbiterHandle {
     let mut arbiter_pool = ARBITER_POOL.lock().unwrap();
     arbiter_pool.next()
 }
+
+/// Count the actor as active in its arbiter until it stops. To be called
+/// from the arbiter, e.g. in the `start_in_arbiter` closure. Does nothing
+/// outside the pool arbiters.
+pub fn track<A>(addr: &Addr<A>)
+where
+    A: Actor,
+{
+    if !is_least_loaded() {
+        return;
+    }
+
+    let index = match ARBITER_INDEX.with(|i| i.get()) {
+        Some(index) => index,
+        None => return,
+    };
+
+    let load = match LOADS.lock().unwrap().get(index) {
+        Some(load) => load.clone(),
+        None => return,
+    };
+
+    let weak = addr.downgrade();
+    load.actors.lock().unwrap().push(Box::new(move || {
+        weak.upgrade().is_some_and(|a| a.connected())
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_least_loaded() {
+        assert_eq!(least_loaded(&[3, 1, 2], 0), 1);
+        assert_eq!(least_loaded(&[1, 1, 1], 2), 2);
+        assert_eq!(least_loaded(&[0, 5, 0], 1), 2);
+        assert_eq!(least_loaded(&[], 0), 0);
+    }
+}
@@ -0,0 +1,382 @@
+use actix::{dev::ToEnvelope, prelude::*};
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::core::{
+    env,
+    error_bus::{self, PatokaError},
+};
+
+/// Module name used to publish errors.
+const MODULE: &str = "backpressure";
+
+/// The buffered messages are sent again that often.
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+lazy_static! {
+    static ref PARAMS: BackpressureParams =
+        env::load_opt("backpressure").unwrap_or_default();
+
+    /// Call site --> Counters
+    static ref STATS: Mutex<BTreeMap<&'static str, SiteStats>> =
+        Mutex::new(BTreeMap::new());
+
+    static ref BUFFERS: Mutex<Buffers> = Mutex::new(Buffers::default());
+}
+
+/// `[backpressure]` configuration section.
+#[derive(Deserialize)]
+struct BackpressureParams {
+    /// Messages buffered per call site and mailbox while the mailbox is
+    /// full. More are dropped.
+    #[serde(default = "default_max_buffered")]
+    max_buffered: usize,
+}
+
+fn default_max_buffered() -> usize { 1000 }
+
+impl Default for BackpressureParams {
+    fn default() -> Self {
+        Self {
+            max_buffered: default_max_buffered(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SiteStats {
+    /// Found the mailbox full, the message is buffered.
+    pub full: u64,
+
+    /// Found the buffer full too, the message is lost.
+    pub dropped: u64,
+
+    /// Found the mailbox closed, the message is lost.
+    pub closed: u64,
+
+    /// Buffered now.
+    pub buffered: u64,
+}
+
+/// Of a buffered message sent again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Sent,
+    Full,
+    Closed,
+}
+
+/// Of a message given to `do_send`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fate {
+    Sent,
+    Buffered,
+    Dropped,
+    Closed,
+}
+
+/// A buffered message, sent again until the mailbox accepts it.
+type Pending = Box<dyn FnMut() -> Outcome + Send>;
+
+/// Call site, Mailbox
+type Key = (&'static str, u64);
+
+#[derive(Default)]
+struct Buffers {
+    /// Key --> Messages, oldest first
+    pending: HashMap<Key, VecDeque<Pending>>,
+
+    /// The retries have been scheduled.
+    retrying: bool,
+}
+
+impl Buffers {
+    /// Send the buffered messages of `key` in order until the mailbox is
+    /// full again. Returns the number sent and lost.
+    fn flush(&mut self, key: &Key) -> (u64, u64) {
+        let buffer = match self.pending.get_mut(key) {
+            Some(b) => b,
+            None => return (0, 0),
+        };
+
+        let (mut sent, mut lost) = (0, 0);
+        while let Some(send) = buffer.front_mut() {
+            match send() {
+                Outcome::Sent => sent += 1,
+                Outcome::Full => break,
+                Outcome::Closed => {
+                    lost = buffer.len() as u64;
+                    buffer.clear();
+                    break;
+                },
+            }
+            buffer.pop_front();
+        }
+
+        if buffer.is_empty() {
+            self.pending.remove(key);
+        }
+
+        (sent, lost)
+    }
+
+    fn len(&self, key: &Key) -> usize {
+        self.pending.get(key).map_or(0, |b| b.len())
+    }
+
+    fn push(&mut self, key: Key, msg: Pending) {
+        self.pending.entry(key).or_default().push_back(msg);
+
+        if !self.retrying {
+            self.retrying = true;
+            actix::spawn(async {
+                loop {
+                    actix::clock::sleep(RETRY_INTERVAL).await;
+                    if !retry() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Send the buffered messages again. `False` once none is left.
+fn retry() -> bool {
+    let mut buffers = BUFFERS.lock().unwrap();
+    let keys: Vec<Key> = buffers.pending.keys().copied().collect();
+    let flushed: Vec<_> = keys.iter()
+        .map(|key| (key.0, buffers.flush(key)))
+        .collect();
+
+    buffers.retrying = !buffers.pending.is_empty();
+    let left = buffers.retrying;
+    drop(buffers);
+
+    for (site, (sent, lost)) in flushed {
+        count(site, sent, lost, None);
+    }
+
+    left
+}
+
+/// A mailbox `do_send` sends `M` to.
+pub trait Mailbox<M>: Clone + Hash + Send + 'static
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    fn try_send(&self, msg: M) -> Result<(), SendError<M>>;
+}
+
+impl<A, M> Mailbox<M> for Addr<A>
+where
+    A: Actor + Handler<M>,
+    A::Context: ToEnvelope<A, M>,
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    fn try_send(&self, msg: M) -> Result<(), SendError<M>> {
+        Addr::try_send(self, msg)
+    }
+}
+
+impl<M> Mailbox<M> for Recipient<M>
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    fn try_send(&self, msg: M) -> Result<(), SendError<M>> {
+        Recipient::try_send(self, msg)
+    }
+}
+
+fn pending<M, T>(mailbox: T, msg: M) -> Pending
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+    T: Mailbox<M>,
+{
+    let mut msg = Some(msg);
+    Box::new(move || {
+        let m = match msg.take() {
+            Some(m) => m,
+            None => return Outcome::Sent,
+        };
+
+        match mailbox.try_send(m) {
+            Ok(()) => Outcome::Sent,
+            Err(SendError::Full(m)) => {
+                msg = Some(m);
+                Outcome::Full
+            },
+            Err(SendError::Closed(_)) => Outcome::Closed,
+        }
+    })
+}
+
+/// `do_send` of the hot paths, counting the full and closed mailboxes by
+/// call site, e.g. "tracker.task_tree". A message to a full mailbox is
+/// buffered and sent again in order, up to `backpressure.max_buffered` per
+/// mailbox, then dropped. `False` if the message is lost.
+pub fn do_send<M, T>(site: &'static str, mailbox: &T, msg: M) -> bool
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+    T: Mailbox<M>,
+{
+    let mut hasher = DefaultHasher::new();
+    mailbox.hash(&mut hasher);
+    let key = (site, hasher.finish());
+
+    let mut buffers = BUFFERS.lock().unwrap();
+
+    // The messages buffered for the mailbox go first.
+    let (sent, lost) = buffers.flush(&key);
+    let buffered = buffers.len(&key);
+    let result = if buffered > 0 {
+        Err(SendError::Full(msg))
+    } else {
+        mailbox.try_send(msg)
+    };
+
+    let fate = match result {
+        Ok(()) => Fate::Sent,
+        Err(SendError::Full(_)) if buffered >= PARAMS.max_buffered => {
+            Fate::Dropped
+        },
+        Err(SendError::Full(msg)) => {
+            buffers.push(key, pending(mailbox.clone(), msg));
+            Fate::Buffered
+        },
+        Err(SendError::Closed(_)) => Fate::Closed,
+    };
+    drop(buffers);
+
+    count(site, sent, lost, Some(fate));
+    matches!(fate, Fate::Sent | Fate::Buffered)
+}
+
+/// Count the buffered messages sent and lost, and the fate of a new one.
+fn count(site: &'static str, sent: u64, lost: u64, fate: Option<Fate>) {
+    if sent == 0 && lost == 0 && matches!(fate, None | Some(Fate::Sent)) {
+        return;
+    }
+
+    let mut stats = STATS.lock().unwrap();
+    let s = stats.entry(site).or_default();
+    s.buffered -= sent + lost;
+    s.closed += lost;
+
+    let (what, n) = match fate {
+        Some(Fate::Buffered) => {
+            s.buffered += 1;
+            s.full += 1;
+            ("full", s.full)
+        },
+        Some(Fate::Dropped) => {
+            s.dropped += 1;
+            ("full, dropped", s.dropped)
+        },
+        Some(Fate::Closed) => {
+            s.closed += 1;
+            ("closed", s.closed)
+        },
+        None | Some(Fate::Sent) => return,
+    };
+    drop(stats);
+
+    report(site, what, n);
+}
+
+/// Publish the first time and then every 100th.
+fn report(site: &'static str, what: &str, n: u64) {
+    if n % 100 == 1 {
+        error_bus::publish(PatokaError::warning(
+            MODULE,
+            format!("Mailbox {} [SITE] {} [COUNT] {}", what, site, n),
+        ));
+    }
+}
+
+/// Call site --> Counters, of the call sites having found a mailbox full or
+/// closed.
+pub fn stats() -> BTreeMap<String, SiteStats> {
+    STATS.lock().unwrap().iter()
+        .map(|(site, stats)| (site.to_string(), stats.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Slow {
+        received: Vec<u32>,
+    }
+
+    impl Actor for Slow {
+        type Context = Context<Self>;
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Number(u32);
+
+    impl Handler<Number> for Slow {
+        type Result = ();
+
+        fn handle(&mut self, msg: Number, _ctx: &mut Self::Context) {
+            self.received.push(msg.0);
+        }
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "Vec<u32>")]
+    struct Received;
+
+    impl Handler<Received> for Slow {
+        type Result = MessageResult<Received>;
+
+        fn handle(
+            &mut self,
+            _msg: Received,
+            _ctx: &mut Self::Context,
+        ) -> Self::Result {
+            MessageResult(self.received.clone())
+        }
+    }
+
+    #[actix::test]
+    async fn buffers_when_full() {
+        let addr = Slow::create(|ctx| {
+            ctx.set_mailbox_capacity(1);
+            Slow { received: vec![] }
+        });
+        for n in 0..20 {
+            assert!(do_send("test.slow", &addr, Number(n)));
+        }
+
+        let mut received = vec![];
+        for _ in 0..100 {
+            actix::clock::sleep(RETRY_INTERVAL).await;
+            received = addr.send(Received).await.unwrap();
+            if received.len() == 20 {
+                break;
+            }
+        }
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+
+        let stats = &stats()["test.slow"];
+        assert!(stats.full > 0);
+        assert_eq!(stats.buffered, 0);
+        assert_eq!(stats.dropped, 0);
+        assert_eq!(stats.closed, 0);
+    }
+}
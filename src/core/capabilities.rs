@@ -0,0 +1,175 @@
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::collections::BTreeMap;
+
+use crate::{
+    center::{connector, message},
+    core::{arbiter_pool, env, proxy},
+    storage::{backend, db_executor},
+    transport::message::RawMessage,
+    worker::{
+        controller_pool,
+        params_schema,
+        plugin::{self, WorkerPlugin},
+        processor,
+        router,
+    },
+};
+
+/// What a running instance has been deployed with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapabilityReport {
+    pub app_id: String,
+
+    /// Crate version.
+    pub version: String,
+
+    /// Subsystem --> Enabled
+    pub subsystems: BTreeMap<String, bool>,
+
+    /// The plugins installed on this node.
+    pub plugins: Vec<String>,
+
+    /// Pool --> Size
+    pub pools: BTreeMap<String, usize>,
+
+    /// Endpoint name --> Address
+    pub endpoints: BTreeMap<String, String>,
+
+    pub storage: Vec<String>,
+
+    /// Task Name --> JSON Schema of the params. See `params_schema`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params_schemas: BTreeMap<String, serde_json::Value>,
+}
+
+impl CapabilityReport {
+    pub fn collect(app_id: &str) -> Self {
+        let center_address =
+            env::get_opt_var("center.address").unwrap_or_default();
+
+        let mut subsystems = BTreeMap::new();
+        subsystems.insert("center".into(), !center_address.is_empty());
+        subsystems.insert(
+            "external_worker".into(),
+            is_true("general.external_worker"),
+        );
+        subsystems.insert(
+            "simple_protocol".into(),
+            is_true("general.simple_protocol"),
+        );
+        subsystems.insert("proxy".into(), !proxy::no_proxy());
+        subsystems.insert("task_readers".into(), is_configured("task_readers"));
+        subsystems.insert("task_writers".into(), is_configured("task_writers"));
+
+        let plugins = plugin::installed()
+            .into_iter()
+            .map(|p| WorkerPlugin::as_str(p).to_string())
+            .collect();
+
+        let mut pools = BTreeMap::new();
+        pools.insert("arbiters".into(), arbiter_pool::size());
+        pools.insert(
+            "controllers".into(),
+            processor::controller_pool_capacity(),
+        );
+        for (name, params) in controller_pool::queue_params() {
+            pools.insert(format!("controllers.{}", name), params.capacity());
+        }
+
+        let mut endpoints = BTreeMap::new();
+        for r in router::routers() {
+            let (fe, be) = if r.name == router::DEFAULT_ROUTER {
+                ("worker_router".to_string(), "worker_backend".to_string())
+            } else {
+                (
+                    format!("worker_router_{}", r.name),
+                    format!("worker_backend_{}", r.name),
+                )
+            };
+
+            endpoints.insert(fe, r.bind.clone());
+            endpoints.insert(be, r.backend.clone());
+        }
+        if !center_address.is_empty() {
+            endpoints.insert("center".into(), center_address);
+        }
+        endpoints.insert(
+            "center_backend".into(),
+            "inproc://center_router".into(),
+        );
+
+        let mut storage = vec![];
+        if let Some(name) = storage_backend() {
+            storage.push(name.to_string());
+        }
+
+        Self {
+            app_id: app_id.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            subsystems,
+            plugins,
+            pools,
+            endpoints,
+            storage,
+            params_schemas: params_schema::schemas(),
+        }
+    }
+
+    pub fn log_banner(&self, log: &Logger) {
+        let enabled: Vec<&str> = self.subsystems.iter()
+            .filter(|(_, enabled)| **enabled)
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        info!(log, "Patoka {} [APP ID] {}", self.version, self.app_id);
+        info!(log, "  Subsystems: {}", enabled.join(", "));
+        info!(log, "  Plugins: {}", self.plugins.join(", "));
+        for (name, size) in &self.pools {
+            info!(log, "  Pool {}: {}", name, size);
+        }
+        for (name, address) in &self.endpoints {
+            info!(log, "  Endpoint {}: {}", name, address);
+        }
+        if self.storage.is_empty() {
+            info!(log, "  Storage: none");
+        } else {
+            info!(log, "  Storage: {}", self.storage.join(", "));
+        }
+    }
+
+    pub fn send_to_center(&self) {
+        let c_msg = message::create(
+            message::Dest::Center,
+            message::Subject::CapabilityReport,
+            self.app_id.clone(),
+            "capability_report".to_string(),
+            self,
+        );
+
+        connector::start().do_send(RawMessage::from(c_msg));
+    }
+}
+
+fn is_true(key: &str) -> bool {
+    env::get_opt_var(key).as_deref() == Some("true")
+}
+
+fn is_configured(group_name: &str) -> bool {
+    env::load_opt::<serde_json::Value>(group_name).is_some()
+}
+
+/// The backend connected to, else the one `app.db` selects.
+fn storage_backend() -> Option<&'static str> {
+    match db_executor::backend() {
+        Some(b) => Some(b.name()),
+        None => env::get_opt_var("app.db").map(|url| backend::name_of(&url)),
+    }
+}
+
+/// Log the startup banner and send the report to the center.
+pub fn report(app_id: &str, log: &Logger) {
+    let report = CapabilityReport::collect(app_id);
+    report.log_banner(log);
+    report.send_to_center();
+}
@@ -0,0 +1,291 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{
+    core::{
+        env::{self, PATOKA_ROOT_DIR},
+        error_bus::{self, PatokaError},
+        logger::create_logger,
+        monitor::*,
+    },
+    worker::processor::{self, ProcessHeldTasks},
+};
+
+lazy_static! {
+    static ref PARAMS: DiskGuardParams =
+        env::load_opt("disk_guard").unwrap_or_default();
+}
+
+/// See `level`.
+static LEVEL: AtomicU8 = AtomicU8::new(DiskLevel::Ok as u8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiskLevel {
+    Ok,
+    Low,
+    Critical,
+}
+
+impl DiskLevel {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => DiskLevel::Low,
+            2 => DiskLevel::Critical,
+            _ => DiskLevel::Ok,
+        }
+    }
+}
+
+/// What to do once the space is critical.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskAction {
+    /// The task writers keep the records in memory.
+    #[default]
+    PauseWriters,
+
+    /// No new tasks are started.
+    HoldTasks,
+}
+
+/// `[disk_guard]` configuration section. A warning is published below
+/// `warn_free_mb`, `action` is taken below `critical_free_mb`.
+#[derive(Deserialize)]
+struct DiskGuardParams {
+    #[serde(default)]
+    enabled: bool,
+
+    #[serde(default = "default_paths")]
+    paths: Vec<String>,
+
+    #[serde(default = "default_warn_free_mb")]
+    warn_free_mb: u64,
+
+    #[serde(default = "default_critical_free_mb")]
+    critical_free_mb: u64,
+
+    #[serde(default)]
+    action: DiskAction,
+
+    #[serde(default = "default_check_interval_s")]
+    check_interval_s: u64,
+}
+
+fn default_paths() -> Vec<String> { vec!["$PATOKA_ROOT_DIR/data".to_string()] }
+
+fn default_warn_free_mb() -> u64 { 1024 }
+
+fn default_critical_free_mb() -> u64 { 256 }
+
+fn default_check_interval_s() -> u64 { 30 }
+
+impl Default for DiskGuardParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            paths: default_paths(),
+            warn_free_mb: default_warn_free_mb(),
+            critical_free_mb: default_critical_free_mb(),
+            action: DiskAction::default(),
+            check_interval_s: default_check_interval_s(),
+        }
+    }
+}
+
+impl DiskGuardParams {
+    fn level(&self, free_mb: u64) -> DiskLevel {
+        if free_mb < self.critical_free_mb {
+            DiskLevel::Critical
+        } else if free_mb < self.warn_free_mb {
+            DiskLevel::Low
+        } else {
+            DiskLevel::Ok
+        }
+    }
+}
+
+/// The lowest of the paths, as of the last check.
+pub fn level() -> DiskLevel {
+    DiskLevel::from_u8(LEVEL.load(Ordering::SeqCst))
+}
+
+/// The task writers keep the records in memory.
+pub fn writes_paused() -> bool {
+    level() == DiskLevel::Critical && PARAMS.action == DiskAction::PauseWriters
+}
+
+/// No new tasks are started.
+pub fn holds_tasks() -> bool {
+    level() == DiskLevel::Critical && PARAMS.action == DiskAction::HoldTasks
+}
+
+/// Free MB on the file system of `path`. The closest existing parent is
+/// checked if `path` does not exist yet.
+fn free_mb(path: &str) -> Result<u64, String> {
+    let mut path = Path::new(path);
+    while !path.exists() {
+        path = path.parent().ok_or("No existing parent")?;
+    }
+
+    available_bytes(path).map(|bytes| bytes / (1024 * 1024))
+}
+
+/// The space available to an unprivileged user on the file system of
+/// `path`, like "Available" of `df`.
+#[cfg(unix)]
+fn available_bytes(path: &Path) -> Result<u64, String> {
+    use std::{ffi::CString, mem, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_bytes(_path: &Path) -> Result<u64, String> {
+    Err("Not supported on this platform".to_string())
+}
+
+pub struct DiskGuard {
+    log: Logger,
+    regular_check_timer: RegularCheckTimer,
+}
+
+impl DiskGuard {
+    fn check(&mut self) {
+        // Level, Path, Free MB
+        let mut lowest = (DiskLevel::Ok, String::new(), u64::MAX);
+        for p in &PARAMS.paths {
+            let path = env::full_path(p, "$PATOKA_ROOT_DIR", &PATOKA_ROOT_DIR);
+            match free_mb(&path) {
+                Ok(mb) if mb < lowest.2 => {
+                    lowest = (PARAMS.level(mb), path, mb);
+                },
+                Ok(_) => {},
+                Err(e) => warn!(self.log, "Failed to check {}: {}", path, e),
+            }
+        }
+
+        let (level, path, mb) = lowest;
+        let prev = LEVEL.swap(level as u8, Ordering::SeqCst);
+        let prev = DiskLevel::from_u8(prev);
+        if prev == level {
+            return;
+        }
+
+        let details = format!(
+            "Disk space {:?} --> {:?}: {} MB free on {}",
+            prev,
+            level,
+            mb,
+            path,
+        );
+
+        match level {
+            DiskLevel::Ok => info!(self.log, "{}", details),
+            DiskLevel::Low => warn!(self.log, "{}", details),
+            DiskLevel::Critical => {
+                error!(self.log, "{}. {:?}", details, PARAMS.action);
+            },
+        }
+
+        // Entering and leaving the critical level is forwarded to the center.
+        if level == DiskLevel::Critical || prev == DiskLevel::Critical {
+            error_bus::publish(PatokaError::critical("disk_guard", details));
+        } else {
+            error_bus::publish(PatokaError::warning("disk_guard", details));
+        }
+
+        if prev == DiskLevel::Critical {
+            // The writers catch up on their next flush.
+            processor::start().do_send(ProcessHeldTasks);
+        }
+    }
+}
+
+impl Default for DiskGuard {
+    fn default() -> Self {
+        Self {
+            log: create_logger("disk_guard"),
+            regular_check_timer: RegularCheckTimer::interval_s(
+                PARAMS.check_interval_s.max(1),
+            ),
+        }
+    }
+}
+
+impl Actor for DiskGuard {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Disk Guard started.");
+
+        if PARAMS.enabled {
+            self.check();
+            self.regular_check_timer.reset::<Self>(ctx);
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Disk Guard stopped.");
+    }
+}
+
+impl Supervised for DiskGuard {}
+
+impl SystemService for DiskGuard {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Disk Guard system service started.")
+    }
+}
+
+impl Handler<RegularCheckMessage> for DiskGuard {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: RegularCheckMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.check();
+    }
+}
+
+/// `disk_guard.enabled`
+pub fn enabled() -> bool {
+    PARAMS.enabled
+}
+
+pub fn start() -> Addr<DiskGuard> {
+    DiskGuard::from_registry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_space() {
+        assert!(free_mb("/").is_ok());
+        assert!(free_mb("/no/such/dir").is_ok());
+
+        let params = DiskGuardParams::default();
+        assert_eq!(params.level(2048), DiskLevel::Ok);
+        assert_eq!(params.level(512), DiskLevel::Low);
+        assert_eq!(params.level(100), DiskLevel::Critical);
+    }
+}
@@ -1,14 +1,106 @@
-use config::{Config, File, ConfigError, Source};
+use config::{Config, Environment, File, ConfigError, Value};
 use lazy_static::lazy_static;
 use serde;
 use serde_json::json;
-use std::{env, sync::RwLock};
+use std::{collections::HashMap, env, path::Path, sync::RwLock};
+
+use crate::core::secrets;
+
+/// `PATOKA__CENTER__ADDRESS` overrides `center.address`.
+const ENV_PREFIX: &str = "PATOKA";
+const ENV_SEPARATOR: &str = "__";
+
+/// The name of the environment, e.g. "production", to load the overlays of
+/// the configuration files for. See `overlay_path`.
+const ENV_NAME_VAR: &str = "PATOKA_ENV";
 
 lazy_static! {
     pub static ref PATOKA_ROOT_DIR: String = make_dir_path("PATOKA_ROOT_DIR");
     pub static ref PATOKA_X_DIR: String = make_dir_path("PATOKA_X_DIR");
 
     static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+
+    /// The layers the configuration is built of, in order of precedence.
+    static ref LAYERS: RwLock<Layers> = RwLock::new(Layers::default());
+}
+
+#[derive(Default)]
+struct Layers {
+    /// Key --> Value
+    defaults: Vec<(String, Value)>,
+
+    /// In order of loading: a later file overrides an earlier one.
+    files: Vec<String>,
+}
+
+impl Layers {
+    /// The defaults, the files with their overlays, then the environment
+    /// variables. The `secret://` references are resolved.
+    fn build(&self) -> Result<Config, ConfigError> {
+        let mut builder = Config::builder();
+
+        for (key, value) in &self.defaults {
+            builder = builder.set_default(key.as_str(), value.clone())?;
+        }
+
+        let env_name = env::var(ENV_NAME_VAR).ok()
+            .filter(|name| !name.is_empty());
+
+        for file in &self.files {
+            builder = builder.add_source(File::with_name(file));
+
+            if let Some(ref env_name) = env_name {
+                let overlay = overlay_path(file, env_name);
+                builder = builder.add_source(
+                    File::with_name(&overlay).required(false)
+                );
+            }
+        }
+
+        builder = builder.add_source(
+            Environment::with_prefix(ENV_PREFIX)
+                .separator(ENV_SEPARATOR)
+                .try_parsing(true)
+        );
+
+        let config = builder.build_cloned()?;
+        let references: Vec<(String, String)> = flatten(&config)
+            .map_err(ConfigError::Message)?
+            .into_iter()
+            .filter_map(|(key, v)| match v {
+                serde_json::Value::String(s) if secrets::is_reference(&s) => {
+                    Some((key, s))
+                },
+                _ => None,
+            })
+            .collect();
+
+        if references.is_empty() {
+            return Ok(config);
+        }
+
+        for (key, reference) in references {
+            let secret = secrets::resolve(&reference)
+                .map_err(|e| ConfigError::Message(format!("{}: {}", key, e)))?;
+            builder = builder.set_override(key.as_str(), secret)?;
+        }
+
+        builder.build()
+    }
+}
+
+/// `cfg/patoka.toml` --> `cfg/patoka.{env_name}.toml`
+fn overlay_path(file: &str, env_name: &str) -> String {
+    let path = Path::new(file);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => path.with_file_name(format!(
+            "{}.{}.{}",
+            stem.to_string_lossy(),
+            env_name,
+            ext.to_string_lossy(),
+        )).to_string_lossy().into_owned(),
+        _ => format!("{}.{}", file, env_name),
+    }
 }
 
 pub fn full_path_curr_dir(relative_path: &str) -> String {
@@ -31,10 +123,32 @@ pub fn full_path(
     }
 }
 
-/// Get a mandatory variable value.
+/// Get a mandatory variable value. Panics if there is none, see `var` to
+/// handle the error.
 pub fn get_var(key: &str) -> String {
+    match var(key) {
+        Ok(v) => v,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Get a variable value of type `T`.
+pub fn var<T: serde::de::DeserializeOwned>(key: &str) -> Result<T, String> {
     let config = CONFIG.read().unwrap();
-    config.get_string(key).unwrap()
+    config.get::<T>(key).map_err(|e| format!("{}: {}", key, e))
+}
+
+/// Get an optional variable value of type `T`: `Ok(None)` if not set, an
+/// error if set but not a `T`.
+pub fn opt_var<T: serde::de::DeserializeOwned>(
+    key: &str,
+) -> Result<Option<T>, String> {
+    let config = CONFIG.read().unwrap();
+    match config.get::<T>(key) {
+        Ok(v) => Ok(Some(v)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(format!("{}: {}", key, e)),
+    }
 }
 
 /// Get an optional variable value.
@@ -50,22 +164,91 @@ pub fn get_config() -> &'static RwLock<Config> {
     &CONFIG
 }
 
-pub fn load(config_file: &str) -> Result<(), ConfigError> {
-    let mut config = CONFIG.write().unwrap();
-    if let Err(e) = config.merge(File::with_name(config_file)) {
-        println!(
-            "Failed to load configuration from file {}: {}",
-            config_file,
-            e
-        );
-        return Err(e);
+/// Re-read the configuration files and the environment. Returns the keys
+/// changed, added or removed, e.g. `logging.level`; an array is a single
+/// key.
+pub fn reload() -> Result<Vec<String>, String> {
+    let layers = LAYERS.read().unwrap();
+    let config = layers.build().map_err(|e| e.to_string())?;
+
+    let mut config_lock = CONFIG.write().unwrap();
+    let old = flatten(&config_lock)?;
+    let new = flatten(&config)?;
+    *config_lock = config;
+
+    let mut changed: Vec<String> = old.keys()
+        .chain(new.keys())
+        .filter(|k| old.get(*k) != new.get(*k))
+        .cloned()
+        .collect();
+    changed.sort();
+    changed.dedup();
+
+    Ok(changed)
+}
+
+/// Dotted key --> Value
+fn flatten(
+    config: &Config,
+) -> Result<HashMap<String, serde_json::Value>, String> {
+    fn walk(
+        prefix: &str,
+        v: serde_json::Value,
+        out: &mut HashMap<String, serde_json::Value>,
+    ) {
+        match v {
+            serde_json::Value::Object(o) => {
+                for (k, v) in o {
+                    let key = if prefix.is_empty() {
+                        k
+                    } else {
+                        format!("{}.{}", prefix, k)
+                    };
+                    walk(&key, v, out);
+                }
+            },
+            v => {
+                out.insert(prefix.to_string(), v);
+            },
+        }
     }
 
-    /*if let Ok(c) = config.collect() {
-        println!("Configuration: {:#?}", c);
-    }*/
+    let v = config.clone().try_deserialize::<serde_json::Value>()
+        .map_err(|e| e.to_string())?;
 
-    Ok(())
+    let mut out = HashMap::new();
+    walk("", v, &mut out);
+    Ok(out)
+}
+
+/// Set the value of `key` unless set by a file or the environment. Takes
+/// effect on the next `load`.
+pub fn set_default<T: Into<Value>>(key: &str, value: T) {
+    LAYERS.write().unwrap().defaults.push((key.to_string(), value.into()));
+}
+
+/// Load the file over those loaded before, with its overlay for the
+/// environment `PATOKA_ENV` if any, e.g. `cfg/patoka.production.toml`.
+/// The `PATOKA__SECTION__KEY` environment variables override the files.
+pub fn load(config_file: &str) -> Result<(), ConfigError> {
+    let mut layers = LAYERS.write().unwrap();
+    layers.files.push(config_file.to_string());
+
+    match layers.build() {
+        Ok(config) => {
+            *CONFIG.write().unwrap() = config;
+            Ok(())
+        },
+        Err(e) => {
+            println!(
+                "Failed to load configuration from file {}: {}",
+                config_file,
+                e
+            );
+            layers.files.pop();
+            Err(e)
+        },
+    }
 }
 
 pub fn load_params<P: serde::de::DeserializeOwned>(group_name: &str) -> P {
@@ -137,3 +320,17 @@ pub fn is_enabled(name: &str) -> bool {
 
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_paths() {
+        assert_eq!(
+            overlay_path("cfg/patoka.toml", "prod"),
+            "cfg/patoka.prod.toml",
+        );
+        assert_eq!(overlay_path("cfg/patoka", "prod"), "cfg/patoka.prod");
+    }
+}
@@ -0,0 +1,255 @@
+use actix::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    center::{connector, message},
+    core::{
+        logger::create_logger,
+        timestamp::{now, Timestamp},
+    },
+    transport::message::RawMessage,
+    worker::attachment::Attachment,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+
+    /// Forwarded to the center.
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Debug for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// Structured error event published by the modules.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PatokaError {
+    /// Module (usually the logger name) that published the error.
+    pub module: String,
+
+    pub severity: Severity,
+
+    /// Empty if the error is not related to a particular task.
+    pub task_uuid: String,
+
+    pub details: String,
+
+    /// E.g. the screenshot of a failed task.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+
+    pub ts: Timestamp,
+}
+
+impl Message for PatokaError {
+    type Result = ();
+}
+
+impl PatokaError {
+    pub fn new(module: &str, severity: Severity, details: String) -> Self {
+        Self {
+            module: module.to_string(),
+            severity,
+            task_uuid: String::new(),
+            details,
+            attachments: Vec::new(),
+            ts: now(),
+        }
+    }
+
+    pub fn warning(module: &str, details: String) -> Self {
+        Self::new(module, Severity::Warning, details)
+    }
+
+    pub fn error(module: &str, details: String) -> Self {
+        Self::new(module, Severity::Error, details)
+    }
+
+    pub fn critical(module: &str, details: String) -> Self {
+        Self::new(module, Severity::Critical, details)
+    }
+
+    pub fn task(mut self, task_uuid: &str) -> Self {
+        self.task_uuid = task_uuid.to_string();
+        self
+    }
+
+    pub fn attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = attachments;
+        self
+    }
+}
+
+impl fmt::Display for PatokaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[MODULE] {} [SEVERITY] {:?} [TASK UUID] {} {}",
+            self.module,
+            self.severity,
+            self.task_uuid,
+            self.details,
+        )
+    }
+}
+
+type ErrorSubscriber = Recipient<PatokaError>;
+
+pub struct ErrorBus {
+    log: Logger,
+
+    /// Subscriber ID --> Subscriber
+    subscribers: HashMap<String, ErrorSubscriber>,
+}
+
+impl ErrorBus {
+    fn handle_error(&mut self, msg: PatokaError) {
+        match msg.severity {
+            Severity::Warning => warn!(self.log, "{}", msg),
+            _ => error!(self.log, "{}", msg),
+        }
+
+        for s in self.subscribers.values() {
+            s.do_send(msg.clone());
+        }
+
+        if msg.severity == Severity::Critical {
+            send_to_center(msg);
+        }
+    }
+}
+
+fn send_to_center(msg: PatokaError) {
+    let entity_id = if msg.task_uuid.is_empty() {
+        msg.module.clone()
+    } else {
+        msg.task_uuid.clone()
+    };
+
+    let c_msg = message::create(
+        message::Dest::Center,
+        message::Subject::Error,
+        entity_id,
+        msg.severity.as_str().to_string(),
+        msg,
+    );
+
+    connector::start().do_send(RawMessage::from(c_msg));
+}
+
+impl Default for ErrorBus {
+    fn default() -> Self {
+        Self {
+            log: create_logger("error_bus"),
+            subscribers: HashMap::new(),
+        }
+    }
+}
+
+impl Actor for ErrorBus {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Error Bus started.");
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Error Bus stopped.");
+    }
+}
+
+impl Supervised for ErrorBus {}
+
+impl SystemService for ErrorBus {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Error Bus system service started.")
+    }
+}
+
+impl Handler<PatokaError> for ErrorBus {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: PatokaError,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.handle_error(msg);
+    }
+}
+
+struct ErrorSubscription {
+    id: String,
+
+    /// `None` to unsubscribe.
+    subscriber: Option<ErrorSubscriber>,
+}
+
+impl Message for ErrorSubscription {
+    type Result = ();
+}
+
+impl Handler<ErrorSubscription> for ErrorBus {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: ErrorSubscription,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        match msg.subscriber {
+            Some(s) => {
+                debug!(self.log, "Subscribed [ID] {}", msg.id);
+                self.subscribers.insert(msg.id, s);
+            },
+            None => {
+                debug!(self.log, "Unsubscribed [ID] {}", msg.id);
+                self.subscribers.remove(&msg.id);
+            },
+        }
+    }
+}
+
+/// Publish `error` to the bus.
+pub fn publish(error: PatokaError) {
+    start().do_send(error);
+}
+
+/// Publish the error and send it to the center whatever its severity.
+pub fn report(error: PatokaError) {
+    if error.severity < Severity::Critical {
+        send_to_center(error.clone());
+    }
+    publish(error);
+}
+
+/// Receive all the errors published to the bus.
+pub fn subscribe(id: String, subscriber: Recipient<PatokaError>) {
+    start().do_send(ErrorSubscription { id, subscriber: Some(subscriber) });
+}
+
+pub fn unsubscribe(id: String) {
+    start().do_send(ErrorSubscription { id, subscriber: None });
+}
+
+pub fn start() -> Addr<ErrorBus> {
+    ErrorBus::from_registry()
+}
@@ -0,0 +1,311 @@
+use awc::{
+    error::PayloadError,
+    http::{header, Method},
+};
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::time::Duration;
+
+use crate::core::{
+    env,
+    proxy::{self, Proxy},
+    ratelimit,
+    user_agent::random_ua,
+};
+
+lazy_static! {
+    static ref PARAMS: HttpParams = env::load_opt("http").unwrap_or_default();
+}
+
+/// `[http]` configuration section of the plain HTTP requests of the tasks
+/// that do not need a worker.
+#[derive(Deserialize)]
+struct HttpParams {
+    #[serde(default = "default_timeout_s")]
+    timeout_s: u64,
+
+    /// Attempts after the first one.
+    #[serde(default = "default_retries")]
+    retries: u32,
+
+    /// Doubled on each retry.
+    #[serde(default = "default_backoff_ms")]
+    backoff_ms: u64,
+
+    #[serde(default = "default_max_body_bytes")]
+    max_body_bytes: u64,
+
+    #[serde(default = "default_true")]
+    use_proxy: bool,
+
+    #[serde(default = "default_true")]
+    random_user_agent: bool,
+
+    #[serde(default = "default_true")]
+    rate_limit: bool,
+
+    #[serde(default = "default_retry_statuses")]
+    retry_statuses: Vec<u16>,
+}
+
+fn default_timeout_s() -> u64 { 30 }
+
+fn default_retries() -> u32 { 2 }
+
+fn default_backoff_ms() -> u64 { 1000 }
+
+fn default_max_body_bytes() -> u64 { 10 * 1024 * 1024 }
+
+fn default_true() -> bool { true }
+
+fn default_retry_statuses() -> Vec<u16> { vec![429, 500, 502, 503, 504] }
+
+impl Default for HttpParams {
+    fn default() -> Self {
+        Self {
+            timeout_s: default_timeout_s(),
+            retries: default_retries(),
+            backoff_ms: default_backoff_ms(),
+            max_body_bytes: default_max_body_bytes(),
+            use_proxy: true,
+            random_user_agent: true,
+            rate_limit: true,
+            retry_statuses: default_retry_statuses(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+
+    /// Of the last response if redirected. Names are lowercase.
+    pub headers: Vec<(String, String)>,
+
+    pub body: Vec<u8>,
+
+    /// The proxy the response has been received through.
+    pub proxy: Option<Proxy>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_slice(&self.body).map_err(|e| e.to_string())
+    }
+}
+
+/// A request to `send`, see `get` and `post`.
+#[derive(Clone, Debug)]
+pub struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+
+    /// A random one if not set and `random_user_agent`.
+    user_agent: Option<String>,
+
+    timeout: Duration,
+    retries: u32,
+    max_body_bytes: u64,
+    use_proxy: bool,
+    rate_limit: bool,
+}
+
+pub fn get(url: &str) -> Request {
+    Request::new("GET", url)
+}
+
+pub fn post(url: &str) -> Request {
+    Request::new("POST", url)
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Self {
+        Self {
+            method: method.to_uppercase(),
+            url: url.to_string(),
+            headers: vec![],
+            body: None,
+            user_agent: None,
+            timeout: Duration::from_secs(PARAMS.timeout_s),
+            retries: PARAMS.retries,
+            max_body_bytes: PARAMS.max_body_bytes,
+            use_proxy: PARAMS.use_proxy,
+            rate_limit: PARAMS.rate_limit,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    pub fn json<T: serde::Serialize>(self, body: &T) -> Self {
+        let body = serde_json::to_vec(body).unwrap_or_default();
+        self.header("Content-Type", "application/json").body(body)
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn max_body_bytes(mut self, max: u64) -> Self {
+        self.max_body_bytes = max;
+        self
+    }
+
+    pub fn use_proxy(mut self, use_proxy: bool) -> Self {
+        self.use_proxy = use_proxy;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: bool) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub async fn send(self) -> Result<Response, String> {
+        let domain = ratelimit::domain_of(&self.url);
+        let mut attempt = 0;
+
+        loop {
+            if let (true, Some(domain)) = (self.rate_limit, &domain) {
+                ratelimit::acquire(domain).await;
+            }
+
+            let proxy = if self.use_proxy { proxy::next() } else { None };
+            let result = self.transfer(proxy.as_ref()).await;
+
+            let retry = match &result {
+                Ok(r) => PARAMS.retry_statuses.contains(&r.status),
+                Err(Failure::Proxy(_)) => {
+                    // Skipped eventually.
+                    if let Some(ref p) = proxy {
+                        proxy::mark_blocked(p);
+                    }
+                    true
+                },
+                Err(Failure::Other(_)) => true,
+            };
+
+            if !retry || attempt >= self.retries {
+                return match result {
+                    Ok(mut r) => {
+                        r.proxy = proxy;
+                        Ok(r)
+                    },
+                    Err(Failure::Proxy(e) | Failure::Other(e)) => Err(e),
+                };
+            }
+
+            actix::clock::sleep(backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    async fn transfer(&self, proxy: Option<&Proxy>)
+        -> Result<Response, Failure>
+    {
+        let user_agent = match self.user_agent {
+            Some(ref ua) => ua.clone(),
+            None if PARAMS.random_user_agent => random_ua(),
+            None => String::new(),
+        };
+
+        let method = Method::from_bytes(self.method.as_bytes())
+            .map_err(|e| Failure::Other(e.to_string()))?;
+
+        let mut request = proxy::client(proxy, self.timeout)
+            .request(method, &self.url);
+
+        if !user_agent.is_empty() {
+            request = request.insert_header((header::USER_AGENT, user_agent));
+        }
+
+        for (name, value) in &self.headers {
+            request = request.append_header((name.as_str(), value.as_str()));
+        }
+
+        let sent = match self.body {
+            Some(ref body) => request.send_body(body.clone()).await,
+            None => request.send().await,
+        };
+
+        let mut response = sent.map_err(|e| {
+            let error = format!("{} {}: {}", self.method, self.url, e);
+            if proxy.is_some() && proxy::is_proxy_error(&e) {
+                Failure::Proxy(error)
+            } else {
+                Failure::Other(error)
+            }
+        })?;
+
+        let max = usize::try_from(self.max_body_bytes).unwrap_or(usize::MAX);
+        let body = response.body().limit(max).await.map_err(|e| match e {
+            PayloadError::Overflow => Failure::Other(format!(
+                "The response is larger than {} bytes",
+                self.max_body_bytes,
+            )),
+            e => Failure::Other(e.to_string()),
+        })?;
+
+        let headers = response.headers().iter()
+            .map(|(n, v)| {
+                let v = String::from_utf8_lossy(v.as_bytes()).into_owned();
+                (n.as_str().to_string(), v)
+            })
+            .collect();
+
+        Ok(Response {
+            status: response.status().as_u16(),
+            headers,
+            body: body.to_vec(),
+            proxy: None,
+        })
+    }
+}
+
+/// Why `transfer` has failed.
+enum Failure {
+    /// Of the proxy rather than of the host.
+    Proxy(String),
+
+    Other(String),
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let factor = 1u64 << attempt.min(16);
+    Duration::from_millis(PARAMS.backoff_ms.saturating_mul(factor))
+}
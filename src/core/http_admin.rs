@@ -0,0 +1,166 @@
+use slog::Logger;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+use crate::{
+    core::{
+        app_state::{self, GetStatusReport},
+        logger::create_logger,
+    },
+    worker::{
+        task_history,
+        task_stats::{self, GetTaskStats},
+    },
+};
+
+/// A larger request head is rejected.
+const MAX_HEAD_SIZE: usize = 8192;
+
+/// A client not sending the request head in that long is disconnected.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Listen on `addr`, e.g. "127.0.0.1:8081", in the current system.
+pub async fn start(addr: &str) -> Result<(), String> {
+    let listener = TcpListener::bind(addr).await
+        .map_err(|e| format!("HTTP admin {}: {}", addr, e))?;
+
+    let log = create_logger("http_admin");
+    info!(log, "Listening on {}", addr);
+
+    actix::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    actix::spawn(serve(stream, log.clone()));
+                },
+                Err(e) => error!(log, "Failed to accept: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn serve(mut stream: TcpStream, log: Logger) {
+    let head = match time::timeout(READ_TIMEOUT, read_head(&mut stream)).await
+    {
+        Ok(Some(head)) => head,
+        Ok(None) => return,
+        Err(_) => {
+            debug!(log, "Timed out reading the request head.");
+            return;
+        },
+    };
+
+    let (status, body) = if head.len() > MAX_HEAD_SIZE {
+        (
+            "431 Request Header Fields Too Large",
+            error("The request head is too large"),
+        )
+    } else {
+        let head = String::from_utf8_lossy(&head);
+        let mut request_line = head.lines().next().unwrap_or_default()
+            .split_whitespace();
+        let method = request_line.next().unwrap_or_default();
+        let path = request_line.next().unwrap_or_default();
+
+        respond(method, path).await
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        debug!(log, "Failed to respond: {}", e);
+    }
+}
+
+/// Read up to the end of the head, no further than `MAX_HEAD_SIZE`. `None`
+/// if the connection has been closed meanwhile.
+async fn read_head(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+
+        if head.len() > MAX_HEAD_SIZE {
+            break;
+        }
+    }
+
+    Some(head)
+}
+
+/// `/health`, `/status`, `/metrics` and `/history/<task uuid>`.
+async fn respond(method: &str, path: &str) -> (&'static str, String) {
+    if method != "GET" {
+        return ("405 Method Not Allowed", error("Only GET is supported"));
+    }
+
+    let path = path.split('?').next().unwrap_or_default();
+    if let Some(task_uuid) = path.strip_prefix("/history/") {
+        return match task_history::history(task_uuid).await {
+            Ok(events) => (
+                "200 OK",
+                serde_json::to_string(&events).unwrap_or_default(),
+            ),
+            Err(e) => ("503 Service Unavailable", error(&e)),
+        };
+    }
+
+    match path {
+        "/health" => ("200 OK", r#"{"status":"ok"}"#.to_string()),
+        "/status" => match app_state::start().send(GetStatusReport).await {
+            Ok(report) => (
+                "200 OK",
+                serde_json::to_string(&report).unwrap_or_default(),
+            ),
+            Err(e) => ("503 Service Unavailable", error(&e.to_string())),
+        },
+        "/metrics" if !task_stats::enabled() => {
+            ("404 Not Found", error("Task stats are disabled"))
+        },
+        "/metrics" => match task_stats::start().send(GetTaskStats).await {
+            Ok(stats) => (
+                "200 OK",
+                serde_json::to_string(&stats).unwrap_or_default(),
+            ),
+            Err(e) => ("503 Service Unavailable", error(&e.to_string())),
+        },
+        _ => ("404 Not Found", error("Unknown path")),
+    }
+}
+
+fn error(e: &str) -> String {
+    serde_json::json!({ "error": e }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix::test]
+    async fn routes() {
+        assert_eq!(respond("GET", "/health?v=1").await.0, "200 OK");
+        assert_eq!(respond("GET", "/nope").await.0, "404 Not Found");
+        assert_eq!(
+            respond("POST", "/health").await.0,
+            "405 Method Not Allowed",
+        );
+    }
+}
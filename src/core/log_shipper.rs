@@ -0,0 +1,207 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use slog::{Drain, Level, Logger, Never, OwnedKVList, Record};
+use std::{mem, sync::Mutex};
+
+use crate::{
+    center::{connector, message},
+    core::{
+        app_state,
+        env,
+        logger::create_logger,
+        monitor::*,
+        timestamp::{now, Timestamp},
+    },
+    transport::message::RawMessage,
+};
+
+lazy_static! {
+    static ref PARAMS: ShippingParams =
+        env::load_opt("logging.shipping").unwrap_or_default();
+
+    /// Records waiting to be sent to the center.
+    static ref PENDING: Mutex<Vec<LogRecord>> = Mutex::new(vec![]);
+}
+
+/// `[logging.shipping]` configuration section.
+#[derive(Deserialize)]
+struct ShippingParams {
+    #[serde(default)]
+    enabled: bool,
+
+    /// The records of this level and above are shipped.
+    #[serde(default = "default_level")]
+    level: String,
+
+    #[serde(default = "default_interval_s")]
+    interval_s: u64,
+
+    /// Maximum number of records sent in a single message.
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+
+    /// The newer records are dropped when there are that many records
+    /// waiting to be sent, e.g. when the center is not reachable.
+    #[serde(default = "default_max_pending")]
+    max_pending: usize,
+}
+
+fn default_level() -> String { "warning".to_string() }
+
+fn default_interval_s() -> u64 { 5 }
+
+fn default_batch_size() -> usize { 100 }
+
+fn default_max_pending() -> usize { 10000 }
+
+impl Default for ShippingParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: default_level(),
+            interval_s: default_interval_s(),
+            batch_size: default_batch_size(),
+            max_pending: default_max_pending(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub ts: Timestamp,
+    pub level: String,
+
+    /// Logger name.
+    pub module: String,
+
+    pub message: String,
+}
+
+/// Collects the records to be shipped to the center by `LogShipper`.
+pub struct ShipDrain {
+    module: String,
+    level: Level,
+}
+
+impl ShipDrain {
+    pub fn new(module: &str) -> Self {
+        Self {
+            module: module.to_string(),
+            level: PARAMS.level.parse().unwrap_or(Level::Warning),
+        }
+    }
+}
+
+impl Drain for ShipDrain {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(
+        &self,
+        record: &Record,
+        _values: &OwnedKVList
+    ) -> Result<Self::Ok, Self::Err> {
+        if !record.level().is_at_least(self.level) {
+            return Ok(());
+        }
+
+        let mut pending = PENDING.lock().unwrap();
+        if pending.len() < PARAMS.max_pending {
+            pending.push(LogRecord {
+                ts: now(),
+                level: record.level().as_str().to_string(),
+                module: self.module.clone(),
+                message: record.msg().to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Periodically sends the collected records to the center in batches.
+pub struct LogShipper {
+    log: Logger,
+    regular_check_timer: RegularCheckTimer,
+}
+
+impl LogShipper {
+    fn ship(&self) {
+        let records = mem::take(&mut *PENDING.lock().unwrap());
+        if records.is_empty() {
+            return;
+        }
+
+        let app_id = app_state::app_id();
+        for batch in records.chunks(PARAMS.batch_size.max(1)) {
+            let c_msg = message::create(
+                message::Dest::Center,
+                message::Subject::LogRecord,
+                app_id.clone(),
+                "log_records".to_string(),
+                batch,
+            );
+
+            connector::start().do_send(RawMessage::from(c_msg));
+        }
+    }
+}
+
+impl Default for LogShipper {
+    fn default() -> Self {
+        Self {
+            log: create_logger("log_shipper"),
+            regular_check_timer: RegularCheckTimer::new_s(PARAMS.interval_s),
+        }
+    }
+}
+
+impl Actor for LogShipper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Log Shipper started.");
+
+        self.regular_check_timer.reset::<Self>(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Log Shipper stopped.");
+    }
+}
+
+impl Supervised for LogShipper {}
+
+impl SystemService for LogShipper {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Log Shipper system service started.")
+    }
+}
+
+impl Handler<RegularCheckMessage> for LogShipper {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: RegularCheckMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.ship();
+        self.regular_check_timer.reset::<Self>(ctx);
+    }
+}
+
+/// `logging.shipping.enabled`
+pub fn enabled() -> bool {
+    PARAMS.enabled
+}
+
+/// Start shipping the records if enabled.
+pub fn start() -> Option<Addr<LogShipper>> {
+    if enabled() {
+        Some(LogShipper::from_registry())
+    } else {
+        None
+    }
+}
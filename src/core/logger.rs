@@ -1,30 +1,454 @@
 extern crate slog_term;
 extern crate chrono;
 
-use std::{io, thread};
-use slog::{Logger, Drain};
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use slog::{
+    Logger,
+    Drain,
+    Level,
+    Never,
+    OwnedKVList,
+    Record,
+    SendSyncRefUnwindSafeDrain,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+        Mutex,
+        Weak,
+    },
+    thread,
+};
+
+use crate::core::{
+    env::{self, PATOKA_ROOT_DIR},
+    log_shipper::{self, ShipDrain},
+};
 
 const TIMESTAMP_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S%.3f";
 
+lazy_static! {
+    /// Loaded on the first logger creation, so the configuration must be
+    /// loaded by then.
+    static ref PARAMS: LoggingParams =
+        env::load_opt("logging").unwrap_or_default();
+
+    /// Shared by all the loggers.
+    static ref OUTPUT: Arc<Output> = Arc::new(Output::create(&PARAMS));
+
+    /// Logger name --> Level, see `reload_levels`.
+    static ref LEVELS: Mutex<Vec<(String, Weak<AtomicUsize>)>> =
+        Mutex::new(Vec::new());
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogFormat {
+    Term,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Overflow {
+    /// Wait until there is a room in the channel.
+    Block,
+
+    /// Drop the record.
+    Drop,
+}
+
+/// `[logging]` configuration section.
+#[derive(Deserialize)]
+struct LoggingParams {
+    /// Default level: "critical", "error", "warning", "info", "debug" or
+    /// "trace".
+    #[serde(default = "default_level")]
+    level: String,
+
+    /// Logger name prefix --> Level
+    /// The longest matching prefix wins, e.g. `worker_controller = "debug"`
+    /// applies to all `worker_controller_{id}` loggers.
+    #[serde(default)]
+    modules: HashMap<String, String>,
+
+    #[serde(default = "default_format")]
+    format: LogFormat,
+
+    /// Write to the file instead of stdout.
+    #[serde(default)]
+    file: Option<String>,
+
+    /// The file is rotated when it exceeds the size. 0 to never rotate.
+    #[serde(default = "default_max_size_mb")]
+    max_size_mb: u64,
+
+    /// Rotated files to keep: `{file}.1` (the newest) ... `{file}.{N}`.
+    #[serde(default = "default_max_files")]
+    max_files: usize,
+
+    /// Write the records from a separate thread.
+    #[serde(default, rename = "async")]
+    async_: bool,
+
+    /// Records waiting to be written in async mode.
+    #[serde(default = "default_chan_size")]
+    chan_size: usize,
+
+    /// What to do when the channel is full in async mode.
+    #[serde(default = "default_overflow")]
+    overflow: Overflow,
+}
+
+fn default_level() -> String { "trace".to_string() }
+
+fn default_format() -> LogFormat { LogFormat::Term }
+
+fn default_max_size_mb() -> u64 { 100 }
+
+fn default_max_files() -> usize { 5 }
+
+fn default_chan_size() -> usize { 4096 }
+
+fn default_overflow() -> Overflow { Overflow::Block }
+
+impl Default for LoggingParams {
+    fn default() -> Self {
+        Self {
+            level: default_level(),
+            modules: HashMap::new(),
+            format: default_format(),
+            file: None,
+            max_size_mb: default_max_size_mb(),
+            max_files: default_max_files(),
+            async_: false,
+            chan_size: default_chan_size(),
+            overflow: default_overflow(),
+        }
+    }
+}
+
+impl LoggingParams {
+    fn level(&self, name: &str) -> Level {
+        let level = self.modules.iter()
+            .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(&self.level, |(_, level)| level);
+
+        match level.parse::<Level>() {
+            Ok(l) => l,
+            Err(_) => {
+                eprintln!("Invalid log level {} for {}", level, name);
+                Level::Trace
+            }
+        }
+    }
+}
+
+/// A file rotated when it exceeds `max_size` bytes.
+struct RotatingFile {
+    path: String,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &str, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_string(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn write_record(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.max_size > 0 && self.size > 0
+            && self.size + buf.len() as u64 > self.max_size
+        {
+            self.rotate()?;
+        }
+
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..self.max_files).rev() {
+            let from = format!("{}.{}", self.path, i);
+            if fs::metadata(&from).is_ok() {
+                fs::rename(&from, format!("{}.{}", self.path, i + 1))?;
+            }
+        }
+
+        if self.max_files > 0 {
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Where the formatted records are written to.
+enum Output {
+    Stdout,
+    File(Mutex<RotatingFile>),
+
+    /// Written to the sync output from a separate thread.
+    Async {
+        tx: SyncSender<Vec<u8>>,
+        overflow: Overflow,
+    },
+}
+
+impl Output {
+    fn create(params: &LoggingParams) -> Self {
+        let output = Self::create_sync(params);
+        if !params.async_ {
+            return output;
+        }
+
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(params.chan_size);
+        thread::spawn(move || {
+            for record in rx {
+                if let Err(e) = output.write_record(record) {
+                    eprintln!("Failed to write a log record: {}", e);
+                }
+            }
+        });
+
+        Output::Async { tx, overflow: params.overflow }
+    }
+
+    fn create_sync(params: &LoggingParams) -> Self {
+        let file = match params.file {
+            Some(ref f) => f,
+            None => return Output::Stdout,
+        };
+
+        let path = env::full_path(file, "$PATOKA_ROOT_DIR", &PATOKA_ROOT_DIR);
+        let max_size = params.max_size_mb * 1024 * 1024;
+        match RotatingFile::open(&path, max_size, params.max_files) {
+            Ok(f) => Output::File(Mutex::new(f)),
+            Err(e) => {
+                eprintln!(
+                    "Failed to open log file {}: {}. Will log to stdout.",
+                    path,
+                    e,
+                );
+                Output::Stdout
+            }
+        }
+    }
+
+    fn write_record(&self, record: Vec<u8>) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().lock().write_all(&record),
+            Output::File(f) => f.lock().unwrap().write_record(&record),
+            Output::Async { tx, overflow } => {
+                let res = match overflow {
+                    Overflow::Block => tx.send(record).map_err(|_| ()),
+                    Overflow::Drop => match tx.try_send(record) {
+                        Err(TrySendError::Disconnected(_)) => Err(()),
+                        _ => Ok(()),
+                    },
+                };
+
+                res.map_err(|_| io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "log writer thread has stopped",
+                ))
+            },
+        }
+    }
+}
+
+/// Collects a formatted record and writes it to the shared output on flush,
+/// so the records of different loggers are not interleaved.
+struct RecordWriter {
+    output: Arc<Output>,
+    buf: Vec<u8>,
+}
+
+impl RecordWriter {
+    fn new() -> Self {
+        Self {
+            output: OUTPUT.clone(),
+            buf: vec![],
+        }
+    }
+}
+
+impl io::Write for RecordWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        self.output.write_record(std::mem::take(&mut self.buf))
+    }
+}
+
+/// Passes the records of the level, changed by `reload_levels`.
+struct LevelFilter<D> {
+    drain: D,
+    level: Arc<AtomicUsize>,
+}
+
+impl<D: Drain> Drain for LevelFilter<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        if record.level().as_usize() <= self.level.load(Ordering::Relaxed) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn level_filter<D: Drain>(drain: D, name: &str) -> LevelFilter<D> {
+    let level = Arc::new(AtomicUsize::new(PARAMS.level(name).as_usize()));
+
+    let mut levels = LEVELS.lock().unwrap();
+    // Forget the dropped loggers from time to time.
+    if levels.len() >= 64 && levels.len().is_power_of_two() {
+        levels.retain(|(_, l)| l.strong_count() > 0);
+    }
+    levels.push((name.to_string(), Arc::downgrade(&level)));
+
+    LevelFilter { drain, level }
+}
+
+/// Apply `logging.level` and `logging.modules` of the configuration to the
+/// existing loggers. The other parameters require a restart.
+pub fn reload_levels() -> Result<(), String> {
+    let params: LoggingParams = env::load_opt("logging").unwrap_or_default();
+
+    let mut levels = LEVELS.lock().unwrap();
+    levels.retain(|(name, level)| match level.upgrade() {
+        Some(level) => {
+            level.store(params.level(name).as_usize(), Ordering::Relaxed);
+            true
+        },
+        None => false,
+    });
+
+    Ok(())
+}
+
 pub fn create_logger(name: &str) -> Logger {
-    let logger_name = name.to_string();
-    let custom_format = move |io: &mut dyn io::Write| -> io::Result<()> {
-        write!(io,
-            "{} {:?} {}",
-            chrono::Utc::now().format(TIMESTAMP_FORMAT),
-            thread::current().id(),
-            logger_name,
-        )
-    };
+    match PARAMS.format {
+        LogFormat::Term => {
+            let logger_name = name.to_string();
+            let custom_format = move |io: &mut dyn io::Write| {
+                write!(io,
+                    "{} {:?} {}",
+                    chrono::Utc::now().format(TIMESTAMP_FORMAT),
+                    thread::current().id(),
+                    logger_name,
+                )
+            };
 
-    let decorator = slog_term::PlainSyncDecorator::new(io::stdout());
-    let drain = slog_term::FullFormat::new(decorator)
-        .use_custom_timestamp(custom_format)
-        .build()
-        .fuse();
+            let decorator =
+                slog_term::PlainSyncDecorator::new(RecordWriter::new());
+            let drain = slog_term::FullFormat::new(decorator)
+                .use_custom_timestamp(custom_format)
+                .build();
+            let drain = level_filter(drain, name).fuse();
 
-    let logger = Logger::root(drain, o!());
-    logger
+            Logger::root(with_shipping(drain, name), o!())
+        },
+        LogFormat::Json => {
+            let drain = slog_json::Json::new(RecordWriter::new())
+                .set_flush(true)
+                .add_default_keys()
+                .build();
+            let drain = level_filter(Mutex::new(drain), name).fuse();
+
+            Logger::root(
+                with_shipping(drain, name),
+                o!("module" => name.to_string()),
+            )
+        },
+    }
 }
 
+type SharedDrain = Arc<dyn SendSyncRefUnwindSafeDrain<Ok=(), Err=Never>>;
+
+/// Duplicate the records to the center if log shipping is enabled.
+fn with_shipping<D>(drain: D, name: &str) -> SharedDrain
+where
+    D: SendSyncRefUnwindSafeDrain<Ok=(), Err=Never> + 'static,
+{
+    if log_shipper::enabled() {
+        Arc::new(slog::Duplicate::new(drain, ShipDrain::new(name)).fuse())
+    } else {
+        Arc::new(drain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate() {
+        let dir = std::env::temp_dir().join("patoka_logger_rotate");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.log").to_str().unwrap().to_string();
 
+        let mut f = RotatingFile::open(&path, 10, 2).unwrap();
+        for record in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            f.write_record(record.as_bytes()).unwrap();
+        }
+
+        let read = |p: &str| fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "dddddddd\n");
+        assert_eq!(read(&(path.clone() + ".1")), "cccccccc\n");
+        assert_eq!(read(&(path.clone() + ".2")), "bbbbbbbb\n");
+        assert!(fs::metadata(path.clone() + ".3").is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn module_level() {
+        let mut params = LoggingParams {
+            level: "info".to_string(),
+            ..Default::default()
+        };
+        params.modules.insert("worker".into(), "warn".into());
+        params.modules.insert("worker_controller".into(), "debug".into());
+
+        assert_eq!(params.level("task_tree"), Level::Info);
+        assert_eq!(params.level("worker_router"), Level::Warning);
+        assert_eq!(params.level("worker_controller_1"), Level::Debug);
+    }
+}
@@ -1,10 +1,25 @@
+pub mod alerting;
 pub mod app_state;
 pub mod arbiter_pool;
+pub mod backpressure;
+pub mod capabilities;
+pub mod disk_guard;
 pub mod env;
+pub mod error_bus;
+pub mod http;
+pub mod http_admin;
+pub mod log_shipper;
 pub mod logger;
 pub mod monitor;
+pub mod notifier;
 pub mod proxy;
+pub mod ratelimit;
 pub mod recipient_group;
+pub mod reload;
+pub mod robots;
+pub mod secrets;
+pub mod status_aggregator;
+pub mod telemetry;
 pub mod timer;
 pub mod timestamp;
 pub mod user_agent;
@@ -1,5 +1,19 @@
 use actix::prelude::*;
-use crate::core::timer::Timer;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    time::Instant,
+};
+
+use crate::core::{
+    app_state,
+    env,
+    error_bus::{self, PatokaError},
+    logger::create_logger,
+    timer::Timer,
+};
 
 /// Used in conjunction with `ReportStatusTimer` to notify
 /// `Handler<ReportStatusMessage>` to submit its status report.
@@ -24,3 +38,463 @@ impl Message for RegularCheckMessage {
 }
 
 pub type RegularCheckTimer = Timer<RegularCheckMessage>;
+
+#[macro_export]
+macro_rules! handler_impl_mailbox_probe {
+    ($x:ty) => {
+        impl Handler<MailboxProbe> for $x {
+            type Result = ();
+
+            fn handle(
+                &mut self,
+                _msg: MailboxProbe,
+                _ctx: &mut Self::Context
+            ) -> Self::Result {
+            }
+        }
+    }
+}
+
+/// Sent periodically to the watched actors. The time it takes the probe to
+/// get through the mailbox is the mailbox lag.
+#[derive(Clone, Default)]
+pub struct MailboxProbe {
+}
+
+impl Message for MailboxProbe {
+    type Result = ();
+}
+
+#[derive(Clone, Deserialize)]
+struct MailboxMonitorParams {
+    #[serde(default = "default_probe_interval_s")]
+    probe_interval_s: u64,
+
+    /// Lag considered to be a problem.
+    #[serde(default = "default_lag_threshold_ms")]
+    lag_threshold_ms: u64,
+
+    /// Raise an alert after the lag has been growing over the threshold for
+    /// that many consecutive samples.
+    #[serde(default = "default_alert_after")]
+    alert_after: usize,
+}
+
+fn default_probe_interval_s() -> u64 { 5 }
+
+fn default_lag_threshold_ms() -> u64 { 1000 }
+
+fn default_alert_after() -> usize { 3 }
+
+impl Default for MailboxMonitorParams {
+    fn default() -> Self {
+        Self {
+            probe_interval_s: default_probe_interval_s(),
+            lag_threshold_ms: default_lag_threshold_ms(),
+            alert_after: default_alert_after(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MailboxStatus {
+    /// The last measured lag. A probe still waiting in the mailbox is
+    /// accounted as well.
+    pub lag_ms: u64,
+
+    pub max_lag_ms: u64,
+
+    /// The number of consecutive samples the lag has been growing over the
+    /// threshold.
+    pub growing: usize,
+}
+
+struct WatchedMailbox {
+    recipient: Recipient<MailboxProbe>,
+
+    /// Send time of the probe still in the mailbox.
+    oldest_probe: Option<Instant>,
+
+    status: MailboxStatus,
+
+    alerted: bool,
+}
+
+pub struct MailboxMonitor {
+    log: Logger,
+
+    params: MailboxMonitorParams,
+
+    /// Name --> Mailbox
+    mailboxes: HashMap<String, WatchedMailbox>,
+
+    regular_check_timer: RegularCheckTimer,
+
+    report_status_timer: ReportStatusTimer,
+
+    process_sampler: ProcessSampler,
+}
+
+impl MailboxMonitor {
+    fn probe(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let now = Instant::now();
+        let mut requests = vec![];
+        for (name, m) in self.mailboxes.iter_mut() {
+            if let Some(sent_at) = m.oldest_probe {
+                // The previous probe is still in the mailbox.
+                let lag_ms = now.duration_since(sent_at).as_millis() as u64;
+                m.update_lag(lag_ms, self.params.lag_threshold_ms);
+                continue;
+            }
+
+            m.oldest_probe = Some(now);
+
+            requests.push((
+                name.clone(),
+                m.recipient.send(MailboxProbe::default()),
+            ));
+        }
+
+        for (name, request) in requests {
+            request
+                .into_actor(self)
+                .map(move |res, act, _ctx| {
+                    act.handle_probe_result(&name, now, res.is_ok());
+                })
+                .spawn(ctx);
+        }
+
+        self.check_alerts();
+    }
+
+    fn handle_probe_result(&mut self, name: &str, sent_at: Instant, ok: bool) {
+        let threshold_ms = self.params.lag_threshold_ms;
+        let m = match self.mailboxes.get_mut(name) {
+            Some(m) => m,
+            None => return,
+        };
+
+        m.oldest_probe = None;
+
+        if ok {
+            let lag_ms = sent_at.elapsed().as_millis() as u64;
+            m.update_lag(lag_ms, threshold_ms);
+        } else {
+            warn!(self.log, "Mailbox {} is not reachable.", name);
+        }
+    }
+
+    fn check_alerts(&mut self) {
+        for (name, m) in self.mailboxes.iter_mut() {
+            if m.status.growing < self.params.alert_after {
+                m.alerted = false;
+                continue;
+            }
+
+            if m.alerted {
+                continue;
+            }
+
+            m.alerted = true;
+            error_bus::publish(PatokaError::critical(
+                "mailbox_monitor",
+                format!(
+                    "Mailbox {} keeps growing. [LAG MS] {}",
+                    name,
+                    m.status.lag_ms,
+                ),
+            ));
+        }
+    }
+
+    fn report_status(&mut self) {
+        let statuses: BTreeMap<String, MailboxStatus> = self.mailboxes.iter()
+            .map(|(name, m)| (name.clone(), m.status.clone()))
+            .collect();
+
+        for (name, s) in &statuses {
+            debug!(
+                self.log,
+                "[STATUS] Mailbox {} [LAG MS] {} [MAX LAG MS] {}",
+                name,
+                s.lag_ms,
+                s.max_lag_ms,
+            );
+        }
+
+        app_state::start().do_send(MailboxReport { statuses });
+
+        let metrics = self.process_sampler.sample();
+        debug!(
+            self.log,
+            "[STATUS] Process [RSS] {} [CPU %] {:.1} [FDS] {} [WORKERS] {}",
+            metrics.rss_bytes,
+            metrics.cpu_percent,
+            metrics.open_fds,
+            metrics.worker_processes,
+        );
+        app_state::start().do_send(ProcessReport { metrics });
+    }
+}
+
+impl WatchedMailbox {
+    fn update_lag(&mut self, lag_ms: u64, threshold_ms: u64) {
+        if lag_ms > threshold_ms && lag_ms > self.status.lag_ms {
+            self.status.growing += 1;
+        } else if lag_ms <= threshold_ms {
+            self.status.growing = 0;
+        }
+
+        self.status.lag_ms = lag_ms;
+        self.status.max_lag_ms = self.status.max_lag_ms.max(lag_ms);
+    }
+}
+
+impl Default for MailboxMonitor {
+    fn default() -> Self {
+        let params = env::load_opt::<MailboxMonitorParams>("monitor")
+            .unwrap_or_default();
+
+        Self {
+            log: create_logger("mailbox_monitor"),
+            regular_check_timer: RegularCheckTimer::new_s(
+                params.probe_interval_s,
+            ),
+            params,
+            mailboxes: HashMap::new(),
+            report_status_timer: ReportStatusTimer::interval_s(5),
+            process_sampler: ProcessSampler::default(),
+        }
+    }
+}
+
+impl Actor for MailboxMonitor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Mailbox Monitor started.");
+
+        self.regular_check_timer.reset::<Self>(ctx);
+        self.report_status_timer.reset::<Self>(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Mailbox Monitor stopped.");
+    }
+}
+
+impl Supervised for MailboxMonitor {}
+
+impl SystemService for MailboxMonitor {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Mailbox Monitor system service started.")
+    }
+}
+
+impl Handler<RegularCheckMessage> for MailboxMonitor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: RegularCheckMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.probe(ctx);
+        self.regular_check_timer.reset::<Self>(ctx);
+    }
+}
+
+impl Handler<ReportStatusMessage> for MailboxMonitor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: ReportStatusMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.report_status();
+    }
+}
+
+struct WatchMailbox {
+    name: String,
+    recipient: Recipient<MailboxProbe>,
+}
+
+impl Message for WatchMailbox {
+    type Result = ();
+}
+
+impl Handler<WatchMailbox> for MailboxMonitor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: WatchMailbox,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        debug!(self.log, "Watch mailbox {}", msg.name);
+
+        self.mailboxes.insert(msg.name, WatchedMailbox {
+            recipient: msg.recipient,
+            oldest_probe: None,
+            status: MailboxStatus::default(),
+            alerted: false,
+        });
+    }
+}
+
+/// Mailbox name --> Status
+/// Sent to the application state to be included to its status report.
+pub struct MailboxReport {
+    pub statuses: BTreeMap<String, MailboxStatus>,
+}
+
+impl Message for MailboxReport {
+    type Result = ();
+}
+
+/// Periodically measure the lag of the actor's mailbox. The actor must
+/// handle `MailboxProbe` (see `handler_impl_mailbox_probe`).
+pub fn watch_mailbox(name: &str, recipient: Recipient<MailboxProbe>) {
+    MailboxMonitor::from_registry().do_send(WatchMailbox {
+        name: name.to_string(),
+        recipient,
+    });
+}
+
+/// Resource usage of the app process and its worker processes, read from
+/// `/proc`. Zero where not available.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessMetrics {
+    pub rss_bytes: u64,
+
+    /// Since the previous sample, 100 per busy CPU.
+    pub cpu_percent: f64,
+
+    pub open_fds: usize,
+
+    /// The child processes, i.e. the worker processes.
+    pub worker_processes: usize,
+
+    /// Of all the worker processes.
+    pub workers_rss_bytes: u64,
+}
+
+/// Sent to the application state to be included to its status report.
+pub struct ProcessReport {
+    pub metrics: ProcessMetrics,
+}
+
+impl Message for ProcessReport {
+    type Result = ();
+}
+
+/// `USER_HZ`, the unit of the CPU times in `/proc/<pid>/stat`.
+const CLOCK_TICKS_PER_S: f64 = 100.0;
+
+/// Keeps the CPU time of the previous sample.
+#[derive(Default)]
+struct ProcessSampler {
+    /// CPU ticks, Time
+    last: Option<(u64, Instant)>,
+}
+
+impl ProcessSampler {
+    fn sample(&mut self) -> ProcessMetrics {
+        let pid = std::process::id();
+        let mut metrics = ProcessMetrics {
+            rss_bytes: rss_bytes("self").unwrap_or(0),
+            open_fds: fs::read_dir("/proc/self/fd")
+                .map(|d| d.count())
+                .unwrap_or(0),
+            ..ProcessMetrics::default()
+        };
+
+        let now = Instant::now();
+        if let Some(stat) = read_stat("self") {
+            if let Some((ticks, at)) = self.last {
+                let elapsed_s = now.duration_since(at).as_secs_f64();
+                if elapsed_s > 0.0 {
+                    let cpu_s = stat.cpu_ticks.saturating_sub(ticks) as f64
+                        / CLOCK_TICKS_PER_S;
+                    metrics.cpu_percent = 100.0 * cpu_s / elapsed_s;
+                }
+            }
+            self.last = Some((stat.cpu_ticks, now));
+        }
+
+        let entries = match fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return metrics,
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(n) if n.bytes().all(|b| b.is_ascii_digit()) => n,
+                _ => continue,
+            };
+
+            if read_stat(name).is_some_and(|s| s.ppid == pid) {
+                metrics.worker_processes += 1;
+                metrics.workers_rss_bytes += rss_bytes(name).unwrap_or(0);
+            }
+        }
+
+        metrics
+    }
+}
+
+struct ProcStat {
+    ppid: u32,
+
+    /// User and system.
+    cpu_ticks: u64,
+}
+
+fn read_stat(pid: &str) -> Option<ProcStat> {
+    parse_stat(&fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+/// "1234 (name) S 1 ... utime stime ...": the name may contain spaces and
+/// parentheses.
+fn parse_stat(stat: &str) -> Option<ProcStat> {
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..]
+        .split_whitespace()
+        .collect();
+
+    Some(ProcStat {
+        ppid: fields.get(1)?.parse().ok()?,
+        cpu_ticks: fields.get(11)?.parse::<u64>().ok()?
+            + fields.get(12)?.parse::<u64>().ok()?,
+    })
+}
+
+fn rss_bytes(pid: &str) -> Option<u64> {
+    parse_rss(&fs::read_to_string(format!("/proc/{}/status", pid)).ok()?)
+}
+
+/// "VmRSS:     1024 kB" of `/proc/<pid>/status`.
+fn parse_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_files() {
+        let stat = parse_stat(
+            "42 (a (b) c) S 7 42 42 0 -1 4194560 100 0 0 0 15 5 0 0 20 0",
+        ).unwrap();
+        assert_eq!(stat.ppid, 7);
+        assert_eq!(stat.cpu_ticks, 20);
+
+        assert_eq!(parse_rss("Name:\tx\nVmRSS:\t  1024 kB\n"), Some(1 << 20));
+        assert_eq!(parse_rss("Name:\tx\n"), None);
+    }
+}
@@ -0,0 +1,301 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+
+use crate::core::{
+    app_state,
+    env,
+    http,
+    logger::create_logger,
+    timestamp::{now, Timestamp},
+};
+
+lazy_static! {
+    static ref PARAMS: NotifierParams =
+        env::load_opt("notifier").unwrap_or_default();
+}
+
+const DEFAULT_SLACK_TEMPLATE: &str =
+    "[{{app_name}}] {{event}} {{subject}}: {{details}}";
+
+/// `[notifier]` configuration section.
+#[derive(Default, Deserialize)]
+struct NotifierParams {
+    #[serde(default)]
+    webhooks: Vec<Webhook>,
+
+    /// Delivery attempts after the first one, `[http]` retries if not set.
+    #[serde(default)]
+    retries: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WebhookKind {
+    Slack,
+
+    #[default]
+    Json,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Webhook {
+    url: String,
+
+    #[serde(default)]
+    kind: WebhookKind,
+
+    /// All if empty.
+    #[serde(default)]
+    events: Vec<EventKind>,
+
+    /// With `{{event}}`, `{{subject}}`, `{{details}}`, `{{ts}}`, `{{app_id}}`
+    /// and `{{app_name}}`. The event itself is posted if not set.
+    #[serde(default)]
+    template: Option<String>,
+}
+
+impl Webhook {
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    fn payload(&self, event: &Event) -> Result<String, String> {
+        let body = match (self.kind, &self.template) {
+            (WebhookKind::Slack, template) => {
+                let template = template.as_deref()
+                    .unwrap_or(DEFAULT_SLACK_TEMPLATE);
+                serde_json::json!({ "text": render(template, event, false) })
+                    .to_string()
+            },
+            (WebhookKind::Json, Some(template)) => {
+                render(template, event, true)
+            },
+            (WebhookKind::Json, None) => {
+                serde_json::to_string(event).map_err(|e| e.to_string())?
+            },
+        };
+
+        Ok(body)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    TaskFailed,
+    WorkerRecovered,
+    WorkerRecycled,
+    HeartbeatLost,
+    AppStatusChanged,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::TaskFailed => "task_failed",
+            EventKind::WorkerRecovered => "worker_recovered",
+            EventKind::WorkerRecycled => "worker_recycled",
+            EventKind::HeartbeatLost => "heartbeat_lost",
+            EventKind::AppStatusChanged => "app_status_changed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Message)]
+#[rtype(result = "()")]
+pub struct Event {
+    pub event: EventKind,
+
+    /// Task UUID, worker ID or app ID.
+    pub subject: String,
+
+    pub details: String,
+    pub ts: Timestamp,
+    pub app_id: String,
+    pub app_name: String,
+}
+
+impl Event {
+    pub fn new(event: EventKind, subject: &str, details: String) -> Self {
+        Self {
+            event,
+            subject: subject.to_string(),
+            details,
+            ts: now(),
+            app_id: app_state::app_id(),
+            app_name: env::get_opt_var("general.name").unwrap_or_default(),
+        }
+    }
+}
+
+/// Substitute the placeholders of `template`, JSON escaping the values if
+/// `escape` is set.
+fn render(template: &str, event: &Event, escape: bool) -> String {
+    let ts = event.ts.to_rfc3339();
+    let values = [
+        ("event", event.event.as_str()),
+        ("subject", event.subject.as_str()),
+        ("details", event.details.as_str()),
+        ("ts", ts.as_str()),
+        ("app_id", event.app_id.as_str()),
+        ("app_name", event.app_name.as_str()),
+    ];
+
+    values.iter().fold(template.to_string(), |text, (name, value)| {
+        let value = if escape {
+            let quoted = serde_json::to_string(value).unwrap_or_default();
+            quoted[1..quoted.len() - 1].to_string()
+        } else {
+            value.to_string()
+        };
+        text.replace(&format!("{{{{{}}}}}", name), &value)
+    })
+}
+
+pub struct Notifier {
+    log: Logger,
+}
+
+impl Notifier {
+    fn deliver(
+        &mut self,
+        webhook: &Webhook,
+        event: &Event,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let body = match webhook.payload(event) {
+            Ok(b) => b,
+            Err(e) => {
+                error!(self.log, "Failed to render {:?}: {}", event.event, e);
+                return;
+            },
+        };
+
+        let mut request = http::post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .user_agent("patoka")
+            .use_proxy(false)
+            .rate_limit(false);
+        if let Some(retries) = PARAMS.retries {
+            request = request.retries(retries);
+        }
+
+        let url = webhook.url.clone();
+        let kind = event.event;
+        request.send()
+            .into_actor(self)
+            .map(move |r, act, _| match r {
+                Ok(r) if r.is_success() => {
+                    debug!(act.log, "Delivered {:?} to {}", kind, url);
+                },
+                Ok(r) => error!(
+                    act.log,
+                    "Failed to deliver {:?} to {}: status {}",
+                    kind,
+                    url,
+                    r.status,
+                ),
+                Err(e) => error!(
+                    act.log,
+                    "Failed to deliver {:?} to {}: {}",
+                    kind,
+                    url,
+                    e,
+                ),
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self {
+            log: create_logger("notifier"),
+        }
+    }
+}
+
+impl Actor for Notifier {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Notifier started.");
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Notifier stopped.");
+    }
+}
+
+impl Supervised for Notifier {}
+
+impl SystemService for Notifier {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Notifier system service started.")
+    }
+}
+
+impl Handler<Event> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, msg: Event, ctx: &mut Self::Context) {
+        debug!(self.log, "{:?} [SUBJECT] {}", msg.event, msg.subject);
+
+        for webhook in PARAMS.webhooks.iter().filter(|w| w.accepts(msg.event)) {
+            self.deliver(webhook, &msg, ctx);
+        }
+    }
+}
+
+pub fn start() -> Addr<Notifier> {
+    Notifier::from_registry()
+}
+
+/// Notify the webhooks subscribed to the event, if any.
+pub fn notify(kind: EventKind, subject: &str, details: String) {
+    if !PARAMS.webhooks.iter().any(|w| w.accepts(kind)) {
+        return;
+    }
+
+    start().do_send(Event::new(kind, subject, details));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads() {
+        let mut event = Event::new(
+            EventKind::TaskFailed,
+            "t1",
+            "Failed \"fetch\"".to_string(),
+        );
+        event.app_name = "app".to_string();
+
+        let slack = Webhook {
+            url: String::new(),
+            kind: WebhookKind::Slack,
+            events: vec![EventKind::TaskFailed],
+            template: None,
+        };
+        assert!(!slack.accepts(EventKind::HeartbeatLost));
+        assert_eq!(
+            slack.payload(&event).unwrap(),
+            r#"{"text":"[app] task_failed t1: Failed \"fetch\""}"#,
+        );
+
+        let json = Webhook {
+            kind: WebhookKind::Json,
+            template: Some(r#"{"msg": "{{subject}} {{details}}"}"#.into()),
+            ..slack
+        };
+        assert_eq!(
+            json.payload(&event).unwrap(),
+            r#"{"msg": "t1 Failed \"fetch\""}"#,
+        );
+    }
+}
@@ -1,6 +1,31 @@
+use actix_service::Service;
+use actix_tls::connect::{
+    ConnectError as TcpConnectError,
+    ConnectInfo,
+    Connection,
+};
+use awc::{
+    error::{ConnectError, SendRequestError},
+    http::Uri,
+    Connector,
+};
 use lazy_static::lazy_static;
 use serde_derive::{Deserialize};
-use std::{error::Error, fs::File, sync::RwLock};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::File,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
 
 use crate::{
     core::env::{self, *},
@@ -27,12 +52,24 @@ pub fn next() -> Option<Proxy> {
     }
 
     let mut proxies = PROXIES.write().unwrap();
-    let idx = proxies.next_to_use;
-    proxies.next_to_use += 1;
-    if proxies.next_to_use >= proxies.proxies.len() {
-        proxies.next_to_use = 0;
+    Some(proxies.next_proxy())
+}
+
+/// Register a blocked/denied response received through `proxy`.
+/// Proxies blocked too many times are skipped by `next()`.
+pub fn mark_blocked(proxy: &Proxy) {
+    if *NO_PROXY {
+        return;
     }
-    Some(proxies.proxies[idx].clone())
+
+    let mut proxies = PROXIES.write().unwrap();
+    proxies.mark_blocked(&proxy.address);
+}
+
+/// Number of times `proxy` has been reported as blocked.
+pub fn blocked_count(proxy: &Proxy) -> usize {
+    let proxies = PROXIES.read().unwrap();
+    proxies.blocked_count(&proxy.address)
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -48,6 +85,25 @@ pub struct Proxy {
 pub struct Proxies {
     pub proxies: Vec<Proxy>,
     pub next_to_use: usize,
+
+    /// Proxy Address --> Blocked responses.
+    pub blocked: HashMap<String, Blocked>,
+
+    /// A proxy is skipped once it has been blocked this many times.
+    /// 0 disables skipping.
+    pub max_blocked: usize,
+
+    /// The blocked count of a proxy is reset once it has not been blocked
+    /// for this long. 0 keeps the counts.
+    pub blocked_cooldown: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Blocked {
+    pub count: usize,
+
+    /// The last blocked response.
+    pub at: Instant,
 }
 
 impl Proxies {
@@ -56,25 +112,78 @@ impl Proxies {
         Self {
             proxies,
             next_to_use: 0,
+            blocked: HashMap::new(),
+            max_blocked: 3,
+            blocked_cooldown: Duration::from_secs(600),
         }
     }
-}
 
-fn load() -> Proxies {
-    if *NO_PROXY {
-        return Proxies::default();
+    /// Round robin over the proxies that are not considered blocked.
+    /// If all of them are blocked, fall back to plain round robin.
+    pub fn next_proxy(&mut self) -> Proxy {
+        let len = self.proxies.len();
+        let mut idx = self.next_to_use;
+        for i in 0..len {
+            let candidate = (self.next_to_use + i) % len;
+            if !self.is_blocked(&self.proxies[candidate].address) {
+                idx = candidate;
+                break;
+            }
+        }
+
+        self.next_to_use = idx + 1;
+        if self.next_to_use >= len {
+            self.next_to_use = 0;
+        }
+
+        self.proxies[idx].clone()
     }
 
+    pub fn mark_blocked(&mut self, address: &str) {
+        let count = self.blocked_count(address) + 1;
+        self.blocked.insert(
+            address.to_string(),
+            Blocked { count, at: Instant::now() },
+        );
+    }
+
+    /// 0 once the cool-down has passed since the last blocked response.
+    pub fn blocked_count(&self, address: &str) -> usize {
+        match self.blocked.get(address) {
+            Some(b) if !self.cooled_down(b) => b.count,
+            _ => 0,
+        }
+    }
+
+    fn cooled_down(&self, blocked: &Blocked) -> bool {
+        !self.blocked_cooldown.is_zero()
+            && blocked.at.elapsed() >= self.blocked_cooldown
+    }
+
+    fn is_blocked(&self, address: &str) -> bool {
+        self.max_blocked > 0 && self.blocked_count(address) >= self.max_blocked
+    }
+}
+
+fn list_path() -> String {
     let proxies_file = match env::get_opt_var("proxy.list") {
         Some(f) => f,
         None => "$PATOKA_ROOT_DIR/cfg/proxies.csv".to_string(),
     };
 
-    let path = env::full_path(
+    env::full_path(
         &proxies_file,
         "$PATOKA_ROOT_DIR",
         &PATOKA_ROOT_DIR
-    );
+    )
+}
+
+fn load() -> Proxies {
+    if *NO_PROXY {
+        return Proxies::default();
+    }
+
+    let path = list_path();
 
     match load_from_file(&path) {
         Ok(proxies) => {
@@ -92,18 +201,293 @@ fn load() -> Proxies {
     }
 }
 
+/// Re-read `proxy.list`, `proxy.max_blocked` and `proxy.blocked_cooldown_s`.
+/// The blocked counts of the proxies still listed are kept.
+pub fn reload() -> Result<(), String> {
+    if *NO_PROXY {
+        return Ok(());
+    }
+
+    let path = list_path();
+    let mut reloaded = load_from_file(&path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    if reloaded.proxies.is_empty() {
+        return Err(format!("No proxies in {}", path));
+    }
+
+    let mut proxies = PROXIES.write().unwrap();
+    reloaded.blocked = std::mem::take(&mut proxies.blocked).into_iter()
+        .filter(|(a, _)| reloaded.proxies.iter().any(|p| &p.address == a))
+        .collect();
+    *proxies = reloaded;
+
+    Ok(())
+}
+
 fn load_from_file(path: &str) -> Result<Proxies, Box<dyn Error>> {
-    let proxies = csv::load_from_file::<Proxy>(path)?;
+    let mut proxies = Proxies::new(csv::load_from_file::<Proxy>(path)?);
 
-    Ok(Proxies::new(proxies))
+    if let Some(n) = opt_number("proxy.max_blocked")? {
+        proxies.max_blocked = n as usize;
+    }
+    if let Some(s) = opt_number("proxy.blocked_cooldown_s")? {
+        proxies.blocked_cooldown = Duration::from_secs(s);
+    }
+
+    Ok(proxies)
+}
+
+fn opt_number(name: &str) -> Result<Option<u64>, String> {
+    match env::get_opt_var(name) {
+        Some(v) => v.parse().map(Some)
+            .map_err(|_| format!("Invalid {}: {}", name, v)),
+        None => Ok(None),
+    }
+}
+
+/// Connects `awc` to the hosts through `proxy`, by HTTP CONNECT or SOCKS5.
+#[derive(Clone)]
+pub struct ProxyConnector {
+    proxy: Proxy,
+}
+
+/// A failure of the proxy itself rather than of the host behind it.
+#[derive(Debug)]
+struct ProxyError(String);
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ProxyError {}
+
+fn proxy_error<E: fmt::Display>(e: E) -> io::Error {
+    io::Error::other(ProxyError(e.to_string()))
+}
+
+fn unreachable(target: &str, reply: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::HostUnreachable,
+        format!("{} through the proxy: {}", target, reply),
+    )
+}
+
+type ConnectResult = Result<Connection<Uri, TcpStream>, TcpConnectError>;
+
+impl Service<ConnectInfo<Uri>> for ProxyConnector {
+    type Response = Connection<Uri, TcpStream>;
+    type Error = TcpConnectError;
+    type Future = Pin<Box<dyn Future<Output = ConnectResult>>>;
+
+    actix_service::always_ready!();
+
+    fn call(&self, req: ConnectInfo<Uri>) -> Self::Future {
+        let proxy = self.proxy.clone();
+        Box::pin(async move {
+            let (host, port) = (req.hostname(), req.port());
+            let handshake = async {
+                let mut stream = TcpStream::connect(&proxy.address).await
+                    .map_err(proxy_error)?;
+                if proxy.type_ == "socks5" {
+                    socks5_connect(&mut stream, host, port).await?;
+                } else {
+                    http_connect(&mut stream, &format!("{}:{}", host, port))
+                        .await?;
+                }
+                Ok(stream)
+            };
+
+            let stream = handshake.await.map_err(|e: io::Error| {
+                TcpConnectError::Io(match e.kind() {
+                    io::ErrorKind::HostUnreachable => e,
+                    _ => proxy_error(format!("{}: {}", proxy.address, e)),
+                })
+            })?;
+
+            Ok(Connection::new(req.request().clone(), stream))
+        })
+    }
+}
+
+/// The host is reachable through the proxy once done.
+async fn http_connect<S>(stream: &mut S, target: &str) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut head = vec![];
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > 16 * 1024 {
+            return Err(proxy_error("CONNECT response headers too long"));
+        }
+        if stream.read(&mut byte).await? == 0 {
+            return Err(proxy_error("Closed before the CONNECT response"));
+        }
+        head.push(byte[0]);
+    }
+
+    let status: u16 = String::from_utf8_lossy(&head)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| proxy_error("Invalid CONNECT response"))?;
+
+    match status {
+        200..=299 => Ok(()),
+        502 | 504 => Err(unreachable(target, &status.to_string())),
+        _ => Err(proxy_error(format!("CONNECT {}: {}", target, status))),
+    }
+}
+
+/// The host is reachable through the proxy once done.
+async fn socks5_connect<S>(stream: &mut S, host: &str, port: u16)
+    -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // No authentication.
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [5, 0] {
+        return Err(proxy_error("SOCKS5 authentication required"));
+    }
+
+    let len = u8::try_from(host.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, host))?;
+    let mut request = vec![5, 1, 0, 3, len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    let target = format!("{}:{}", host, port);
+    match reply[1] {
+        0 => {},
+        // Network or host unreachable, connection refused, TTL expired.
+        3..=6 => return Err(unreachable(&target, &reply[1].to_string())),
+        r => return Err(proxy_error(format!("SOCKS5 {}: {}", target, r))),
+    }
+
+    // The bound address and port.
+    let len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0u8];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        },
+        a => return Err(proxy_error(format!("SOCKS5 address type {}", a))),
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// An `awc` client connecting through `proxy` if any.
+pub fn client(proxy: Option<&Proxy>, timeout: Duration) -> awc::Client {
+    let builder = awc::Client::builder().timeout(timeout);
+    let connector = Connector::new().timeout(timeout);
+    match proxy {
+        Some(p) => builder
+            .connector(connector.connector(ProxyConnector { proxy: p.clone() }))
+            .finish(),
+        None => builder.connector(connector).finish(),
+    }
+}
+
+/// Whether a request sent by a `client` through a proxy has failed because
+/// of the proxy rather than of the host.
+pub fn is_proxy_error(e: &SendRequestError) -> bool {
+    match e {
+        SendRequestError::Connect(ConnectError::Timeout) => true,
+        SendRequestError::Connect(ConnectError::Io(e)) => {
+            e.get_ref().is_some_and(|e| e.is::<ProxyError>())
+        },
+        _ => false,
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn next() {
         let proxy = super::next().unwrap();
         assert!(proxy.type_ == "http" || proxy.type_ == "socks5");
         assert!(!proxy.address.is_empty());
     }
+
+    #[test]
+    fn skip_blocked() {
+        let proxy = |address: &str| Proxy {
+            type_: "http".to_string(),
+            address: address.to_string(),
+        };
+
+        let mut proxies = Proxies::new(vec![proxy("a:1"), proxy("b:2")]);
+        proxies.max_blocked = 1;
+        proxies.mark_blocked("a:1");
+
+        assert_eq!(proxies.next_proxy().address, "b:2");
+        assert_eq!(proxies.next_proxy().address, "b:2");
+
+        // All proxies are blocked: plain round robin.
+        proxies.mark_blocked("b:2");
+        assert_eq!(proxies.next_proxy().address, "a:1");
+        assert_eq!(proxies.next_proxy().address, "b:2");
+
+        // The cool-down of "a:1" has passed.
+        proxies.blocked.get_mut("a:1").unwrap().at -=
+            proxies.blocked_cooldown;
+        assert_eq!(proxies.blocked_count("a:1"), 0);
+        assert_eq!(proxies.next_proxy().address, "a:1");
+        assert_eq!(proxies.next_proxy().address, "a:1");
+
+        proxies.mark_blocked("a:1");
+        assert_eq!(proxies.blocked_count("a:1"), 1);
+    }
+
+    #[actix::test]
+    async fn connect_handshakes() {
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0u8; 64];
+            let n = proxy.read(&mut request).await.unwrap();
+            assert!(request[..n].starts_with(b"CONNECT a.example:443 "));
+            proxy.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+        });
+        http_connect(&mut client, "a.example:443").await.unwrap();
+
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0u8; 64];
+            let _ = proxy.read(&mut request).await.unwrap();
+            proxy.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await.unwrap();
+        });
+        let e = http_connect(&mut client, "a.example:443").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::HostUnreachable);
+
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            proxy.write_all(&[5, 0]).await.unwrap();
+
+            let mut request = [0u8; 20];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[5..18], b"b.example.org");
+            proxy.write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0, 80]).await.unwrap();
+        });
+        socks5_connect(&mut client, "b.example.org", 80).await.unwrap();
+    }
 }
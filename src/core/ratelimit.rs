@@ -0,0 +1,225 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::core::env;
+
+/// The full buckets are forgotten beyond that many.
+const MAX_IDLE_BUCKETS: usize = 10000;
+
+lazy_static! {
+    static ref PARAMS: RateLimitParams =
+        env::load_opt("ratelimit").unwrap_or_default();
+
+    /// Key --> Bucket
+    static ref BUCKETS: Mutex<HashMap<String, Bucket>> =
+        Mutex::new(HashMap::new());
+
+    /// Key --> Limit
+    /// Set at runtime, see `set_limit`.
+    static ref LIMITS: RwLock<HashMap<String, Limit>> =
+        RwLock::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Deserialize)]
+struct Limit {
+    /// Tokens per second.
+    rate: f64,
+
+    #[serde(default = "default_burst")]
+    burst: u32,
+}
+
+fn default_rate() -> f64 { 1.0 }
+
+fn default_burst() -> u32 { 1 }
+
+/// `[ratelimit]` configuration section of the token buckets keyed by e.g.
+/// the domains the tasks request.
+#[derive(Deserialize)]
+struct RateLimitParams {
+    #[serde(default = "default_rate")]
+    rate: f64,
+
+    #[serde(default = "default_burst")]
+    burst: u32,
+
+    /// Key --> Limit
+    #[serde(default)]
+    keys: HashMap<String, Limit>,
+
+    /// Throttle the tasks by the domain of their params.
+    #[serde(default)]
+    auto: bool,
+
+    /// Params holding the URL, e.g. "url" or "target.url".
+    #[serde(default = "default_url_fields")]
+    url_fields: Vec<String>,
+}
+
+fn default_url_fields() -> Vec<String> { vec!["url".to_string()] }
+
+impl Default for RateLimitParams {
+    fn default() -> Self {
+        Self {
+            rate: default_rate(),
+            burst: default_burst(),
+            keys: HashMap::new(),
+            auto: false,
+            url_fields: default_url_fields(),
+        }
+    }
+}
+
+impl RateLimitParams {
+    fn limit(&self, key: &str) -> Limit {
+        if let Some(limit) = LIMITS.read().unwrap().get(key) {
+            return *limit;
+        }
+
+        self.keys.get(key).copied().unwrap_or(Limit {
+            rate: self.rate,
+            burst: self.burst,
+        })
+    }
+}
+
+struct Bucket {
+    limit: Limit,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst.max(1) as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        let burst = self.limit.burst.max(1) as f64;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.rate)
+            .min(burst);
+        self.updated_at = now;
+    }
+
+    /// Take a token, or tell how long until there is one.
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if self.limit.rate <= 0.0 {
+            return Err(Duration::from_secs(1));
+        }
+
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / self.limit.rate))
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.limit.burst.max(1) as f64
+    }
+}
+
+/// Take a token of `key`, or tell how long to wait before trying again.
+pub fn try_acquire(key: &str) -> Result<(), Duration> {
+    let now = Instant::now();
+    let mut buckets = BUCKETS.lock().unwrap();
+
+    if buckets.len() > MAX_IDLE_BUCKETS {
+        buckets.retain(|_, b| !b.is_full(now));
+    }
+
+    buckets.entry(key.to_string())
+        .or_insert_with(|| Bucket::new(PARAMS.limit(key), now))
+        .take(now)
+}
+
+/// Replace the limit of `key`, e.g. by the crawl delay of a domain.
+pub fn set_limit(key: &str, rate: f64, burst: u32) {
+    let limit = Limit { rate, burst };
+    LIMITS.write().unwrap().insert(key.to_string(), limit);
+
+    if let Some(bucket) = BUCKETS.lock().unwrap().get_mut(key) {
+        bucket.refill(Instant::now());
+        bucket.limit = limit;
+    }
+}
+
+/// Wait for a token of `key`.
+pub async fn acquire(key: &str) {
+    while let Err(wait) = try_acquire(key) {
+        actix::clock::sleep(wait).await;
+    }
+}
+
+/// The lowercase host of `url` without the port, e.g. "a.example" of
+/// "https://user@A.example:8080/path".
+pub fn domain_of(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, r)| r);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    let host = match host.strip_prefix('[') {
+        // IPv6
+        Some(h) => h.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+/// The key to throttle a task with `params` by, if `auto` is set.
+pub fn task_key(params: &serde_json::Value) -> Option<String> {
+    if !PARAMS.auto {
+        return None;
+    }
+
+    PARAMS.url_fields.iter().find_map(|field| {
+        field.split('.')
+            .try_fold(params, |v, name| v.get(name))
+            .and_then(|v| v.as_str())
+            .and_then(domain_of)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(Limit { rate: 2.0, burst: 2 }, now);
+        assert!(bucket.take(now).is_ok());
+        assert!(bucket.take(now).is_ok());
+        assert_eq!(bucket.take(now), Err(Duration::from_millis(500)));
+
+        let later = now + Duration::from_millis(500);
+        assert!(bucket.take(later).is_ok());
+        assert!(bucket.take(later).is_err());
+        assert!(bucket.is_full(later + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn domains() {
+        assert_eq!(
+            domain_of("https://user@A.example:8080/p?q").as_deref(),
+            Some("a.example"),
+        );
+        assert_eq!(domain_of("a.example/p").as_deref(), Some("a.example"));
+        assert_eq!(domain_of("http://[::1]:80/").as_deref(), Some("::1"));
+        assert_eq!(domain_of("https:///p"), None);
+    }
+}
@@ -0,0 +1,258 @@
+use actix::prelude::*;
+use serde::de::IgnoredAny;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::sync::Arc;
+
+use crate::{
+    center::send::send_control_msg,
+    control::{
+        command::{Command, CommandError, CommandHandler, CommandRouter},
+        message::*,
+        registry,
+    },
+    core::{env, logger::{self, create_logger}, proxy},
+    worker::{processor, task_reader, task_writer},
+};
+
+type ApplyFn = fn() -> Result<(), String>;
+
+/// Key prefix --> What applies the key, `None` if read on use.
+const RELOADABLE: &[(&str, Option<ApplyFn>)] = &[
+    ("logging.level", Some(logger::reload_levels)),
+    ("logging.modules", Some(logger::reload_levels)),
+    ("task_readers", Some(task_reader::reload_settings)),
+    ("task_writers", Some(task_writer::reload_settings)),
+    ("proxy.list", Some(proxy::reload)),
+    ("proxy.max_blocked", Some(proxy::reload)),
+    ("proxy.blocked_cooldown_s", Some(proxy::reload)),
+    ("general.number_of_workers", Some(processor::reload_pool_capacity)),
+    ("queues", Some(processor::reload_pool_capacity)),
+    ("general.heartbeat_interval_s", None),
+    ("general.heartbeat_timeout_s", None),
+    ("general.worker_stop_grace_ms", None),
+    ("general.worker_term_grace_ms", None),
+    ("general.fair_scheduling", None),
+    ("worker_nodes.heartbeat_timeout_s", None),
+];
+
+/// The index in `RELOADABLE` of `key`, e.g. `logging.modules.tracker`.
+fn reloadable(key: &str) -> Option<usize> {
+    RELOADABLE.iter().position(|(prefix, _)| {
+        key == *prefix
+            || key.strip_prefix(prefix).is_some_and(|k| k.starts_with('.'))
+    })
+}
+
+/// The changed keys by their outcome.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+
+    /// Key --> Error
+    pub failed: Vec<(String, String)>,
+}
+
+/// Reload the configuration now.
+pub fn reload() -> Result<ReloadReport, String> {
+    let changed = env::reload()?;
+    let mut report = ReloadReport::default();
+
+    // Each component is applied once for all its keys.
+    let mut results: Vec<(ApplyFn, Result<(), String>)> = Vec::new();
+
+    for key in changed {
+        let apply = match reloadable(&key) {
+            Some(i) => RELOADABLE[i].1,
+            None => {
+                report.restart_required.push(key);
+                continue;
+            },
+        };
+
+        let result = match apply {
+            None => Ok(()),
+            Some(f) => {
+                let done = results.iter()
+                    .find(|(g, _)| std::ptr::fn_addr_eq(*g, f));
+                match done {
+                    Some((_, r)) => r.clone(),
+                    None => {
+                        let r = f();
+                        results.push((f, r.clone()));
+                        r
+                    },
+                }
+            },
+        };
+
+        match result {
+            Ok(()) => report.applied.push(key),
+            Err(e) => report.failed.push((key, e)),
+        }
+    }
+
+    Ok(report)
+}
+
+/// `data` is ignored.
+#[derive(Deserialize)]
+struct ReloadConfigCommand(IgnoredAny);
+
+impl Command for ReloadConfigCommand {
+    const NAME: &'static str = "reload_config";
+    type Response = ReloadReport;
+}
+
+/// Sent on SIGHUP.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ReloadConfig;
+
+/// Re-reads the configuration on SIGHUP or `reload_config`. The keys not in
+/// `RELOADABLE` take effect on the next restart.
+pub struct ConfigReloader {
+    log: Logger,
+    commands: Arc<CommandRouter<Self>>,
+}
+
+impl ConfigReloader {
+    fn reload(&self) -> Result<ReloadReport, String> {
+        let report = match reload() {
+            Ok(r) => r,
+            Err(e) => {
+                error!(self.log, "Failed to reload the configuration: {}", e);
+                return Err(e);
+            },
+        };
+
+        info!(
+            self.log,
+            "Configuration reloaded. Applied: {:?}. Restart required: {:?}",
+            report.applied,
+            report.restart_required,
+        );
+
+        for (key, e) in &report.failed {
+            error!(self.log, "Failed to apply {}: {}", key, e);
+        }
+
+        Ok(report)
+    }
+
+    fn handle_control_message(
+        &mut self,
+        msg: ControlMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        debug!(self.log, "[CONTROL] {:?}", msg);
+
+        let commands = self.commands.clone();
+        send_control_msg(commands.route(self, msg, ctx));
+    }
+
+    #[cfg(unix)]
+    fn listen_to_sighup(&self, ctx: &mut <Self as Actor>::Context) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                error!(self.log, "Failed to listen to SIGHUP: {}", e);
+                return;
+            },
+        };
+
+        let addr = ctx.address();
+        actix::spawn(async move {
+            while hangups.recv().await.is_some() {
+                addr.do_send(ReloadConfig);
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    fn listen_to_sighup(&self, _ctx: &mut <Self as Actor>::Context) {}
+}
+
+impl Default for ConfigReloader {
+    fn default() -> Self {
+        Self {
+            log: create_logger("config_reloader"),
+            commands: Arc::new(
+                CommandRouter::new()
+                    .add::<ReloadConfigCommand>()
+            ),
+        }
+    }
+}
+
+impl CommandHandler<ReloadConfigCommand> for ConfigReloader {
+    fn handle_command(
+        &mut self,
+        _args: ReloadConfigCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<ReloadReport, CommandError> {
+        self.reload().map_err(CommandError::Failed)
+    }
+}
+
+impl Actor for ConfigReloader {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Config Reloader started.");
+
+        registry::register("config".to_string(), ctx.address().recipient());
+        self.listen_to_sighup(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Config Reloader stopped.");
+    }
+}
+
+impl Supervised for ConfigReloader {}
+
+impl SystemService for ConfigReloader {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Config Reloader system service started.")
+    }
+}
+
+impl Handler<ReloadConfig> for ConfigReloader {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ReloadConfig, _ctx: &mut Self::Context) {
+        let _ = self.reload();
+    }
+}
+
+handler_impl_control_message!(ConfigReloader);
+
+/// `general.config_reload`: reload on SIGHUP and on the `reload_config`
+/// command.
+pub fn enabled() -> bool {
+    env::get_opt_var("general.config_reload").as_deref() == Some("true")
+}
+
+pub fn start() -> Addr<ConfigReloader> {
+    ConfigReloader::from_registry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloadable_keys() {
+        assert!(reloadable("logging.level").is_some());
+        assert!(reloadable("logging.modules.tracker").is_some());
+        assert!(reloadable("task_readers.default.batch_size").is_some());
+        assert!(reloadable("logging.levels").is_none());
+        assert!(reloadable("logging.format").is_none());
+        assert!(reloadable("center.address").is_none());
+    }
+}
@@ -0,0 +1,369 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::core::{env, http, ratelimit};
+
+/// Larger files are considered unreachable.
+const MAX_BYTES: u64 = 512 * 1024;
+
+lazy_static! {
+    static ref PARAMS: RobotsParams =
+        env::load_opt("robots").unwrap_or_default();
+
+    /// Origin --> Cached robots.txt
+    static ref CACHE: Mutex<HashMap<String, Cached>> =
+        Mutex::new(HashMap::new());
+}
+
+/// `[robots]` configuration section. Per RFC 9309, a missing file allows
+/// all, an unreachable one disallows all until `error_ttl_s`.
+#[derive(Deserialize)]
+struct RobotsParams {
+    /// Of the fetch requests and the crawl delay.
+    #[serde(default = "default_user_agent")]
+    user_agent: String,
+
+    #[serde(default = "default_ttl_s")]
+    ttl_s: u64,
+
+    #[serde(default = "default_error_ttl_s")]
+    error_ttl_s: u64,
+
+    #[serde(default = "default_timeout_s")]
+    timeout_s: u64,
+
+    #[serde(default)]
+    use_proxy: bool,
+
+    #[serde(default = "default_apply_crawl_delay")]
+    apply_crawl_delay: bool,
+}
+
+fn default_user_agent() -> String { "patoka".to_string() }
+
+fn default_ttl_s() -> u64 { 86400 }
+
+fn default_error_ttl_s() -> u64 { 600 }
+
+fn default_timeout_s() -> u64 { 10 }
+
+fn default_apply_crawl_delay() -> bool { true }
+
+impl Default for RobotsParams {
+    fn default() -> Self {
+        Self {
+            user_agent: default_user_agent(),
+            ttl_s: default_ttl_s(),
+            error_ttl_s: default_error_ttl_s(),
+            timeout_s: default_timeout_s(),
+            use_proxy: false,
+            apply_crawl_delay: default_apply_crawl_delay(),
+        }
+    }
+}
+
+struct Cached {
+    robots: Arc<Robots>,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    /// Lowercase.
+    agents: Vec<String>,
+
+    /// (Allow, Path pattern)
+    rules: Vec<(bool, String)>,
+
+    crawl_delay: Option<f64>,
+}
+
+/// Parsed robots.txt.
+#[derive(Debug, Default)]
+pub struct Robots {
+    groups: Vec<Group>,
+
+    /// Unreachable: nothing is allowed.
+    disallow_all: bool,
+}
+
+impl Robots {
+    pub fn parse(text: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+
+        // The last line has been a user agent line.
+        let mut in_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (field, value) = match line.split_once(':') {
+                Some((f, v)) => (f.trim().to_lowercase(), v.trim()),
+                None => continue,
+            };
+
+            if field == "user-agent" {
+                if !in_agents {
+                    groups.push(Group::default());
+                }
+                in_agents = true;
+
+                if let Some(g) = groups.last_mut() {
+                    g.agents.push(value.to_lowercase());
+                }
+                continue;
+            }
+
+            in_agents = false;
+            let group = match groups.last_mut() {
+                Some(g) => g,
+                None => continue,
+            };
+
+            match field.as_str() {
+                "allow" | "disallow" if !value.is_empty() => {
+                    group.rules.push((field == "allow", value.to_string()));
+                },
+                "crawl-delay" => group.crawl_delay = value.parse().ok(),
+                _ => {},
+            }
+        }
+
+        Self {
+            groups,
+            disallow_all: false,
+        }
+    }
+
+    fn disallow_all() -> Self {
+        Self {
+            groups: vec![],
+            disallow_all: true,
+        }
+    }
+
+    /// The groups of `user_agent`: the ones naming it, else "*".
+    fn groups_of(&self, user_agent: &str) -> Vec<&Group> {
+        let user_agent = user_agent.to_lowercase();
+        let named: Vec<&Group> = self.groups.iter()
+            .filter(|g| g.agents.iter().any(|a| {
+                a != "*" && user_agent.contains(a.as_str())
+            }))
+            .collect();
+
+        if !named.is_empty() {
+            return named;
+        }
+
+        self.groups.iter()
+            .filter(|g| g.agents.iter().any(|a| a == "*"))
+            .collect()
+    }
+
+    /// Whether `path`, with the query, may be crawled. The longest matching
+    /// rule wins, "allow" on a tie.
+    pub fn is_allowed(&self, path: &str, user_agent: &str) -> bool {
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        if self.disallow_all {
+            return false;
+        }
+
+        let mut best: Option<(usize, bool)> = None;
+        for group in self.groups_of(user_agent) {
+            for (allow, pattern) in &group.rules {
+                if !pattern_matches(pattern, path) {
+                    continue;
+                }
+
+                let len = pattern.len();
+                let better = match best {
+                    None => true,
+                    Some((l, a)) => len > l || (len == l && *allow && !a),
+                };
+                if better {
+                    best = Some((len, *allow));
+                }
+            }
+        }
+
+        best.is_none_or(|(_, allow)| allow)
+    }
+
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.groups_of(user_agent).iter()
+            .find_map(|g| g.crawl_delay)
+            .filter(|d| d.is_finite() && *d > 0.0)
+            .map(Duration::from_secs_f64)
+    }
+}
+
+/// `*` matches any characters, a trailing `$` the end of the path.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match path.strip_prefix(first) {
+        Some(r) => r,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+        if last && anchored {
+            return rest.ends_with(part);
+        }
+
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// "https://a.example:8080" and "/path?query" of `url`.
+fn split_url(url: &str) -> Option<(String, String)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    let path = path.split('#').next().unwrap_or("");
+
+    let path = match path {
+        "" => "/".to_string(),
+        p if p.starts_with('?') => format!("/{}", p),
+        p => p.to_string(),
+    };
+
+    Some((format!("{}://{}", scheme.to_lowercase(), authority), path))
+}
+
+async fn load(origin: String) -> Robots {
+    let fetched = http::get(&format!("{}/robots.txt", origin))
+        .user_agent(&PARAMS.user_agent)
+        .timeout(Duration::from_secs(PARAMS.timeout_s))
+        .retries(0)
+        .max_body_bytes(MAX_BYTES)
+        .use_proxy(PARAMS.use_proxy)
+        .rate_limit(false)
+        .send()
+        .await;
+
+    match fetched {
+        Ok(r) if r.is_success() => Robots::parse(&r.text()),
+        Ok(r) if (400..500).contains(&r.status) => Robots::default(),
+        _ => Robots::disallow_all(),
+    }
+}
+
+/// The robots.txt of the origin of `url`, fetched if not cached.
+pub async fn robots(url: &str) -> Result<Arc<Robots>, String> {
+    let (origin, _) = split_url(url).ok_or("Not an absolute URL")?;
+
+    let cached = CACHE.lock().unwrap().get(&origin)
+        .filter(|c| Instant::now() < c.expires_at)
+        .map(|c| c.robots.clone());
+    if let Some(robots) = cached {
+        return Ok(robots);
+    }
+
+    let robots = Arc::new(load(origin.clone()).await);
+    let ttl = if robots.disallow_all {
+        PARAMS.error_ttl_s
+    } else {
+        PARAMS.ttl_s
+    };
+
+    if PARAMS.apply_crawl_delay {
+        let delay = robots.crawl_delay(&PARAMS.user_agent);
+        if let (Some(d), Some(domain)) = (delay, ratelimit::domain_of(url)) {
+            ratelimit::set_limit(&domain, 1.0 / d.as_secs_f64(), 1);
+        }
+    }
+
+    CACHE.lock().unwrap().insert(origin, Cached {
+        robots: robots.clone(),
+        expires_at: Instant::now() + Duration::from_secs(ttl),
+    });
+
+    Ok(robots)
+}
+
+/// Whether `user_agent` may crawl `url`. Not for an invalid URL.
+pub async fn is_allowed(url: &str, user_agent: &str) -> bool {
+    let path = match split_url(url) {
+        Some((_, path)) => path,
+        None => return false,
+    };
+
+    match robots(url).await {
+        Ok(r) => r.is_allowed(&path, user_agent),
+        Err(_) => false,
+    }
+}
+
+/// The crawl delay of the site of `url` for `user_agent`.
+pub async fn crawl_delay(url: &str, user_agent: &str) -> Option<Duration> {
+    robots(url).await.ok()?.crawl_delay(user_agent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "\
+        User-agent: *\n\
+        Disallow: /private/\n\
+        Allow: /private/open$\n\
+        Crawl-delay: 2\n\
+        \n\
+        User-agent: patoka\n\
+        User-agent: other\n\
+        Disallow: /*.pdf$\n\
+        Disallow: /tmp # comment\n";
+
+    #[test]
+    fn rules() {
+        let robots = Robots::parse(ROBOTS);
+
+        assert!(!robots.is_allowed("/private/a", "Mozilla"));
+        assert!(robots.is_allowed("/private/open", "Mozilla"));
+        assert!(!robots.is_allowed("/private/open/b", "Mozilla"));
+        assert!(robots.is_allowed("/a.pdf", "Mozilla"));
+        assert_eq!(
+            robots.crawl_delay("Mozilla"),
+            Some(Duration::from_secs(2)),
+        );
+
+        assert!(robots.is_allowed("/private/a", "Patoka/1.0"));
+        assert!(!robots.is_allowed("/docs/a.pdf", "Patoka/1.0"));
+        assert!(robots.is_allowed("/docs/a.pdf?x", "Patoka/1.0"));
+        assert!(!robots.is_allowed("/tmp/x", "Patoka/1.0"));
+        assert_eq!(robots.crawl_delay("Patoka/1.0"), None);
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            split_url("HTTPS://a.example:8080?q#f"),
+            Some(("https://a.example:8080".into(), "/?q".into())),
+        );
+        assert_eq!(
+            split_url("http://a.example/p/q?x=1"),
+            Some(("http://a.example".into(), "/p/q?x=1".into())),
+        );
+        assert_eq!(split_url("a.example/p"), None);
+    }
+}
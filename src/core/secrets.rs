@@ -0,0 +1,115 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, RwLock},
+};
+
+use crate::core::env::{self, PATOKA_ROOT_DIR};
+
+/// `secret://<provider>/<key>`, e.g. `secret://env/DB_PASSWORD`, resolved
+/// as the configuration is loaded.
+pub const SCHEME: &str = "secret://";
+
+lazy_static! {
+    /// Provider name --> Provider
+    static ref PROVIDERS: RwLock<HashMap<String, Arc<dyn SecretProvider>>> =
+        RwLock::new(builtin());
+}
+
+pub trait SecretProvider: Send + Sync {
+    /// The secret of `key`, the rest of the reference after the provider
+    /// name.
+    fn get(&self, key: &str) -> Result<String, String>;
+}
+
+struct EnvProvider;
+
+impl SecretProvider for EnvProvider {
+    fn get(&self, key: &str) -> Result<String, String> {
+        std::env::var(key).map_err(|e| format!("{}: {}", key, e))
+    }
+}
+
+struct FileProvider;
+
+impl SecretProvider for FileProvider {
+    fn get(&self, key: &str) -> Result<String, String> {
+        let path = if key.starts_with("$PATOKA_ROOT_DIR") {
+            env::full_path(key, "$PATOKA_ROOT_DIR", &PATOKA_ROOT_DIR)
+        } else {
+            key.to_string()
+        };
+        let secret = fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+fn builtin() -> HashMap<String, Arc<dyn SecretProvider>> {
+    let mut providers: HashMap<String, Arc<dyn SecretProvider>> =
+        HashMap::new();
+    providers.insert("env".to_string(), Arc::new(EnvProvider));
+    providers.insert("file".to_string(), Arc::new(FileProvider));
+    providers
+}
+
+/// Resolve `secret://<name>/...` with `provider`, replacing the one of the
+/// name if any.
+pub fn register<P: SecretProvider + 'static>(name: &str, provider: P) {
+    PROVIDERS.write().unwrap().insert(name.to_string(), Arc::new(provider));
+}
+
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(SCHEME)
+}
+
+/// The secret `value` refers to, `value` itself if not a reference.
+pub fn resolve(value: &str) -> Result<String, String> {
+    let reference = match value.strip_prefix(SCHEME) {
+        Some(r) => r,
+        None => return Ok(value.to_string()),
+    };
+
+    let (name, key) = reference.split_once('/')
+        .ok_or_else(|| format!("{}: no secret key", value))?;
+
+    let provider = PROVIDERS.read().unwrap().get(name).cloned()
+        .ok_or_else(|| format!("{}: no secret provider {}", value, name))?;
+
+    provider.get(key).map_err(|e| format!("{}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed;
+
+    impl SecretProvider for Fixed {
+        fn get(&self, key: &str) -> Result<String, String> {
+            Ok(format!("fixed-{}", key))
+        }
+    }
+
+    #[test]
+    fn resolves_references() {
+        assert_eq!(resolve("plain").unwrap(), "plain");
+
+        std::env::set_var("PATOKA_TEST_SECRET", "s3cret");
+        let secret = resolve("secret://env/PATOKA_TEST_SECRET").unwrap();
+        assert_eq!(secret, "s3cret");
+
+        let path = std::env::temp_dir().join("patoka_test_secret");
+        fs::write(&path, "from-file\n").unwrap();
+        let reference = format!("secret://file/{}", path.display());
+        assert_eq!(resolve(&reference).unwrap(), "from-file");
+
+        register("fixed", Fixed);
+        assert_eq!(resolve("secret://fixed/db/pw").unwrap(), "fixed-db/pw");
+
+        let e = resolve("secret://vault/db").unwrap_err();
+        assert_eq!(e, "secret://vault/db: no secret provider vault");
+        assert!(resolve("secret://env").is_err());
+    }
+}
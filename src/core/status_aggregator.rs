@@ -0,0 +1,280 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use slog::Logger;
+use std::collections::BTreeMap;
+
+use crate::{
+    center::{connector, message},
+    core::{
+        app_state,
+        backpressure,
+        env,
+        logger::create_logger,
+        monitor::*,
+        timestamp::{self, Timestamp},
+    },
+    transport::message::RawMessage,
+};
+
+lazy_static! {
+    static ref PARAMS: StatusParams =
+        env::load_opt("status").unwrap_or_default();
+}
+
+/// `[status]` configuration section.
+#[derive(Deserialize)]
+struct StatusParams {
+    #[serde(default)]
+    enabled: bool,
+
+    #[serde(default = "default_interval_s")]
+    interval_s: u64,
+
+    /// Send the report to the center.
+    #[serde(default = "default_to_center")]
+    to_center: bool,
+}
+
+fn default_interval_s() -> u64 { 10 }
+
+fn default_to_center() -> bool { true }
+
+impl Default for StatusParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_s: default_interval_s(),
+            to_center: default_to_center(),
+        }
+    }
+}
+
+/// The status of a component, e.g. `{ "active_clients": 2 }`.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct StatusSnapshot {
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl StatusSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<V: Into<Value>>(mut self, key: &str, value: V) -> Self {
+        self.fields.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+/// "active_clients=2 tasks=3"
+impl std::fmt::Display for StatusSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut sep = "";
+        for (key, value) in &self.fields {
+            write!(f, "{}{}={}", sep, key, value)?;
+            sep = " ";
+        }
+        Ok(())
+    }
+}
+
+/// Sent to the registered components by the aggregator.
+pub struct GetStatusSnapshot;
+
+impl Message for GetStatusSnapshot {
+    type Result = StatusSnapshot;
+}
+
+/// Component name --> Snapshot
+#[derive(Clone, Serialize)]
+pub struct StatusReport {
+    pub app_id: String,
+    pub at: Timestamp,
+    pub components: BTreeMap<String, StatusSnapshot>,
+}
+
+/// A component stopped is forgotten once it no longer answers.
+pub struct RegisterComponent {
+    pub name: String,
+    pub recipient: Recipient<GetStatusSnapshot>,
+}
+
+impl Message for RegisterComponent {
+    type Result = ();
+}
+
+/// Collects the status of the registered components every `interval_s` for
+/// the log and the center.
+pub struct StatusAggregator {
+    log: Logger,
+
+    /// Component name --> Recipient
+    components: BTreeMap<String, Recipient<GetStatusSnapshot>>,
+
+    report_status_timer: ReportStatusTimer,
+}
+
+impl StatusAggregator {
+    fn collect(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let components = self.components.clone();
+
+        async move {
+            let mut snapshots = BTreeMap::new();
+            let mut gone = vec![];
+            for (name, recipient) in components {
+                match recipient.send(GetStatusSnapshot).await {
+                    Ok(s) => {
+                        snapshots.insert(name, s);
+                    },
+                    Err(_) => gone.push(name),
+                }
+            }
+
+            let backpressure = backpressure::stats();
+            if !backpressure.is_empty() {
+                let snapshot = backpressure.into_iter()
+                    .fold(StatusSnapshot::new(), |s, (site, stats)| {
+                        s.with(&site, serde_json::json!(stats))
+                    });
+                snapshots.insert("backpressure".to_string(), snapshot);
+            }
+
+            (snapshots, gone)
+        }
+        .into_actor(self)
+        .map(|(snapshots, gone), act, _| {
+            for name in gone {
+                act.components.remove(&name);
+            }
+
+            act.report(StatusReport {
+                app_id: app_state::app_id(),
+                at: timestamp::now(),
+                components: snapshots,
+            });
+        })
+        .spawn(ctx);
+    }
+
+    fn report(&self, report: StatusReport) {
+        if report.components.is_empty() {
+            return;
+        }
+
+        for (name, snapshot) in &report.components {
+            info!(self.log, "[STATUS] {}: {}", name, snapshot);
+        }
+
+        if !PARAMS.to_center {
+            return;
+        }
+
+        let c_msg = message::create(
+            message::Dest::Center,
+            message::Subject::StatusReport,
+            report.app_id.clone(),
+            "status_report".to_string(),
+            report,
+        );
+
+        connector::start().do_send(RawMessage::from(c_msg));
+    }
+}
+
+impl Default for StatusAggregator {
+    fn default() -> Self {
+        Self {
+            log: create_logger("status_aggregator"),
+            components: BTreeMap::new(),
+            report_status_timer: ReportStatusTimer::interval_s(
+                PARAMS.interval_s,
+            ),
+        }
+    }
+}
+
+impl Actor for StatusAggregator {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Status Aggregator started.");
+
+        if PARAMS.interval_s > 0 {
+            self.report_status_timer.reset::<Self>(ctx);
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Status Aggregator stopped.");
+    }
+}
+
+impl Supervised for StatusAggregator {}
+
+impl SystemService for StatusAggregator {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Status Aggregator system service started.")
+    }
+}
+
+impl Handler<RegisterComponent> for StatusAggregator {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: RegisterComponent,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.components.insert(msg.name, msg.recipient);
+    }
+}
+
+impl Handler<ReportStatusMessage> for StatusAggregator {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: ReportStatusMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.collect(ctx);
+    }
+}
+
+/// `status.enabled`
+pub fn enabled() -> bool {
+    PARAMS.enabled
+}
+
+pub fn start() -> Addr<StatusAggregator> {
+    StatusAggregator::from_registry()
+}
+
+/// Include the status of the component in the report, e.g. "tracker" or
+/// "controller.0".
+pub fn register(name: &str, recipient: Recipient<GetStatusSnapshot>) {
+    if !enabled() {
+        return;
+    }
+
+    start().do_send(RegisterComponent {
+        name: name.to_string(),
+        recipient,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_line() {
+        let s = StatusSnapshot::new()
+            .with("tasks", 3)
+            .with("active_clients", 2);
+        assert_eq!(s.to_string(), "active_clients=2 tasks=3");
+    }
+}
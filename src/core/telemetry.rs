@@ -0,0 +1,439 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use slog::Logger;
+use std::{
+    collections::HashMap,
+    mem,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::core::{
+    env,
+    logger::create_logger,
+    monitor::*,
+};
+
+lazy_static! {
+    static ref PARAMS: TelemetryParams =
+        env::load_opt("telemetry").unwrap_or_default();
+
+    /// Task UUID --> Context of the task span
+    static ref TASK_TRACES: Mutex<HashMap<String, OpenSpan>> =
+        Mutex::new(HashMap::new());
+
+    /// `name:key` --> The span begun but not ended yet
+    static ref OPEN_SPANS: Mutex<HashMap<String, OpenSpan>> =
+        Mutex::new(HashMap::new());
+
+    /// Ended spans waiting to be exported.
+    static ref PENDING: Mutex<Vec<Span>> = Mutex::new(vec![]);
+}
+
+/// `[telemetry]` configuration section.
+#[derive(Deserialize)]
+struct TelemetryParams {
+    #[serde(default)]
+    enabled: bool,
+
+    /// OTLP/HTTP traces endpoint accepting JSON.
+    #[serde(default = "default_otlp_endpoint")]
+    otlp_endpoint: String,
+
+    #[serde(default = "default_service_name")]
+    service_name: String,
+
+    #[serde(default = "default_interval_s")]
+    interval_s: u64,
+
+    /// Maximum number of spans exported in a single request.
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+
+    /// The newer spans are dropped when there are that many spans waiting
+    /// to be exported, e.g. when the collector is not reachable.
+    #[serde(default = "default_max_pending")]
+    max_pending: usize,
+
+    #[serde(default = "default_timeout_s")]
+    timeout_s: u64,
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://127.0.0.1:4318/v1/traces".to_string()
+}
+
+fn default_service_name() -> String { "patoka".to_string() }
+
+fn default_interval_s() -> u64 { 5 }
+
+fn default_batch_size() -> usize { 512 }
+
+fn default_max_pending() -> usize { 10000 }
+
+fn default_timeout_s() -> u64 { 10 }
+
+impl Default for TelemetryParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            otlp_endpoint: default_otlp_endpoint(),
+            service_name: default_service_name(),
+            interval_s: default_interval_s(),
+            batch_size: default_batch_size(),
+            max_pending: default_max_pending(),
+            timeout_s: default_timeout_s(),
+        }
+    }
+}
+
+/// Trace and span IDs carried by the worker, center and control messages.
+/// Flattened into the message, so the router tracer reuses `trace_id`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TraceContext {
+    /// 32 hex digits.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub trace_id: String,
+
+    /// 16 hex digits.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub span_id: String,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub parent_span_id: String,
+}
+
+impl TraceContext {
+    pub fn is_empty(&self) -> bool {
+        self.trace_id.is_empty()
+    }
+
+    /// A new trace.
+    pub fn root() -> Self {
+        Self {
+            trace_id: format!("{:032x}", rand::random::<u128>().max(1)),
+            span_id: new_span_id(),
+            parent_span_id: String::new(),
+        }
+    }
+
+    /// A new span of the same trace under this one.
+    pub fn child(&self) -> Self {
+        if self.is_empty() {
+            return Self::default();
+        }
+
+        Self {
+            trace_id: self.trace_id.clone(),
+            span_id: new_span_id(),
+            parent_span_id: self.span_id.clone(),
+        }
+    }
+}
+
+fn new_span_id() -> String {
+    format!("{:016x}", rand::random::<u64>().max(1))
+}
+
+fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+struct OpenSpan {
+    ctx: TraceContext,
+    start_ns: u64,
+}
+
+impl OpenSpan {
+    fn new(ctx: TraceContext) -> Self {
+        Self { ctx, start_ns: now_ns() }
+    }
+
+    fn end(self, name: String, attributes: Vec<(String, String)>) -> Span {
+        Span {
+            ctx: self.ctx,
+            name,
+            start_ns: self.start_ns,
+            end_ns: now_ns(),
+            attributes,
+        }
+    }
+}
+
+struct Span {
+    ctx: TraceContext,
+    name: String,
+    start_ns: u64,
+    end_ns: u64,
+    attributes: Vec<(String, String)>,
+}
+
+impl Span {
+    fn to_otlp(&self) -> serde_json::Value {
+        let attributes: Vec<_> = self.attributes.iter()
+            .map(|(k, v)| json!({ "key": k, "value": { "stringValue": v } }))
+            .collect();
+
+        json!({
+            "traceId": self.ctx.trace_id,
+            "spanId": self.ctx.span_id,
+            "parentSpanId": self.ctx.parent_span_id,
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": self.start_ns.to_string(),
+            "endTimeUnixNano": self.end_ns.to_string(),
+            "attributes": attributes,
+        })
+    }
+}
+
+fn record(span: Span) {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() < PARAMS.max_pending {
+        pending.push(span);
+    }
+}
+
+/// OTLP/HTTP JSON request body.
+fn export_request(service_name: &str, spans: &[Span]) -> serde_json::Value {
+    let spans: Vec<_> = spans.iter().map(Span::to_otlp).collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": service_name },
+                }],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "patoka" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// `telemetry.enabled`
+pub fn enabled() -> bool {
+    PARAMS.enabled
+}
+
+/// Begin the span of the task. A subtask joins the trace of its parent, so
+/// a whole job is a single trace.
+pub fn start_task(task_uuid: &str, parent_task_uuid: &str) {
+    if !enabled() {
+        return;
+    }
+
+    let mut traces = TASK_TRACES.lock().unwrap();
+    if traces.contains_key(task_uuid) {
+        return;
+    }
+
+    let ctx = traces.get(parent_task_uuid)
+        .map(|p| p.ctx.child())
+        .unwrap_or_else(TraceContext::root);
+
+    traces.insert(task_uuid.to_string(), OpenSpan::new(ctx));
+}
+
+/// End the span of the task.
+pub fn finish_task(task_uuid: &str) {
+    if !enabled() {
+        return;
+    }
+
+    let open = TASK_TRACES.lock().unwrap().remove(task_uuid);
+    if let Some(open) = open {
+        record(open.end(
+            "task".to_string(),
+            vec![("task_uuid".to_string(), task_uuid.to_string())],
+        ));
+    }
+}
+
+/// A new span under the span of the task. Empty if the task is not traced.
+pub fn child_of_task(task_uuid: &str) -> TraceContext {
+    if !enabled() {
+        return TraceContext::default();
+    }
+
+    TASK_TRACES.lock().unwrap()
+        .get(task_uuid)
+        .map(|open| open.ctx.child())
+        .unwrap_or_default()
+}
+
+/// Context of a message to the center about `entity_id`. The message itself
+/// is recorded as an instant span.
+pub fn center_message(entity_id: &str, subject: &str) -> TraceContext {
+    let ctx = child_of_task(entity_id);
+    if !ctx.is_empty() {
+        record(OpenSpan::new(ctx.clone()).end(
+            format!("center.{}", subject),
+            vec![("task_uuid".to_string(), entity_id.to_string())],
+        ));
+    }
+
+    ctx
+}
+
+/// Begin the span `name` identified by `key`, e.g. a task UUID, unless it
+/// has been begun already.
+pub fn begin_span(name: &str, key: &str, ctx: &TraceContext) {
+    if !enabled() || ctx.is_empty() {
+        return;
+    }
+
+    OPEN_SPANS.lock().unwrap()
+        .entry(format!("{}:{}", name, key))
+        .or_insert_with(|| OpenSpan::new(ctx.clone()));
+}
+
+pub fn end_span(name: &str, key: &str, attributes: Vec<(String, String)>) {
+    if !enabled() {
+        return;
+    }
+
+    let open = OPEN_SPANS.lock().unwrap().remove(&format!("{}:{}", name, key));
+    if let Some(open) = open {
+        record(open.end(name.to_string(), attributes));
+    }
+}
+
+/// Periodically exports the ended spans to the OTLP collector in batches.
+pub struct TelemetryExporter {
+    log: Logger,
+    client: awc::Client,
+    regular_check_timer: RegularCheckTimer,
+}
+
+impl TelemetryExporter {
+    fn export(&self) {
+        let spans = mem::take(&mut *PENDING.lock().unwrap());
+        if spans.is_empty() {
+            return;
+        }
+
+        for batch in spans.chunks(PARAMS.batch_size.max(1)) {
+            let body = export_request(&PARAMS.service_name, batch);
+            let request = self.client.post(&PARAMS.otlp_endpoint);
+            let count = batch.len();
+            let log = self.log.clone();
+
+            actix::spawn(async move {
+                match request.send_json(&body).await {
+                    Ok(r) if r.status().is_success() => {},
+                    Ok(r) => error!(
+                        log,
+                        "Failed to export {} spans: {}",
+                        count,
+                        r.status(),
+                    ),
+                    Err(e) => error!(
+                        log,
+                        "Failed to export {} spans: {}",
+                        count,
+                        e,
+                    ),
+                }
+            });
+        }
+    }
+}
+
+impl Default for TelemetryExporter {
+    fn default() -> Self {
+        Self {
+            log: create_logger("telemetry"),
+            client: awc::Client::builder()
+                .timeout(Duration::from_secs(PARAMS.timeout_s))
+                .finish(),
+            regular_check_timer: RegularCheckTimer::new_s(PARAMS.interval_s),
+        }
+    }
+}
+
+impl Actor for TelemetryExporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(
+            self.log,
+            "Telemetry Exporter started: {}",
+            PARAMS.otlp_endpoint,
+        );
+
+        self.regular_check_timer.reset::<Self>(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Telemetry Exporter stopped.");
+    }
+}
+
+impl Supervised for TelemetryExporter {}
+
+impl SystemService for TelemetryExporter {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Telemetry Exporter system service started.")
+    }
+}
+
+impl Handler<RegularCheckMessage> for TelemetryExporter {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: RegularCheckMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.export();
+        self.regular_check_timer.reset::<Self>(ctx);
+    }
+}
+
+/// Start exporting the spans if enabled.
+pub fn start() -> Option<Addr<TelemetryExporter>> {
+    if enabled() {
+        Some(TelemetryExporter::from_registry())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn child_context_and_export() {
+        let root = TraceContext::root();
+        assert_eq!(root.trace_id.len(), 32);
+        assert_eq!(root.span_id.len(), 16);
+
+        let child = root.child();
+        assert_eq!(child.trace_id, root.trace_id);
+        assert_eq!(child.parent_span_id, root.span_id);
+        assert_ne!(child.span_id, root.span_id);
+        assert!(TraceContext::default().child().is_empty());
+
+        let body = serde_json::to_value(&child).unwrap();
+        assert_eq!(body["trace_id"], root.trace_id.as_str());
+        let empty = serde_json::to_string(&TraceContext::default()).unwrap();
+        assert_eq!(empty, "{}");
+
+        let span = OpenSpan::new(child.clone()).end("task".to_string(), vec![]);
+        let request = export_request("patoka", &[span]);
+        let scope = &request["resourceSpans"][0]["scopeSpans"][0];
+        let exported = &scope["spans"][0];
+        assert_eq!(exported["spanId"], child.span_id.as_str());
+        assert_eq!(exported["parentSpanId"], root.span_id.as_str());
+    }
+}
@@ -1,6 +1,10 @@
 use actix::prelude::*;
 use std::time::Duration;
+use tokio::time::Instant;
 
+/// Notifies the actor with `M`, once or at a fixed rate. The timer is
+/// cancelled by `cancel` or a restart, so that a stale notification never
+/// arrives after the state it was meant for has changed.
 #[derive(Clone)]
 pub struct Timer<M>
 where
@@ -10,6 +14,22 @@ where
     timeout_message: M,
     handle: Option<SpawnHandle>,
     duration: Option<Duration>,
+
+    /// Fixed rate, see `start_interval`.
+    interval: bool,
+
+    /// Of a one-shot timer.
+    deadline: Option<Instant>,
+}
+
+impl<M> Default for Timer<M>
+where
+    M: Message + Send + Default + Clone + 'static,
+    M::Result: Send,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<M> Timer<M>
@@ -22,14 +42,15 @@ where
             timeout_message: M::default(),
             handle: None,
             duration: None,
+            interval: false,
+            deadline: None,
         }
     }
 
     pub fn with_duration(duration: Duration) -> Self {
         Self {
-            timeout_message: M::default(),
-            handle: None,
             duration: Some(duration),
+            ..Self::new()
         }
     }
 
@@ -41,36 +62,98 @@ where
         Self::with_duration(Duration::from_millis(msecs))
     }
 
+    /// A timer `reset` to notify every `period`.
+    pub fn interval(period: Duration) -> Self {
+        Self {
+            interval: true,
+            ..Self::with_duration(period)
+        }
+    }
+
+    pub fn interval_s(secs: u64) -> Self {
+        Self::interval(Duration::from_secs(secs))
+    }
+
+    pub fn interval_ms(msecs: u64) -> Self {
+        Self::interval(Duration::from_millis(msecs))
+    }
+
+    /// A started one-shot timer.
+    pub fn after<A>(ctx: &mut A::Context, duration: Duration) -> Self
+    where
+        A: Actor<Context=Context<A>>,
+        A: Handler<M>,
+    {
+        let mut timer = Self::new();
+        timer.start::<A>(ctx, duration);
+        timer
+    }
+
+    /// Notify once in `duration`.
     pub fn start<A>(&mut self, ctx: &mut A::Context, duration: Duration)
     where
         A: Actor<Context=Context<A>>,
         A: Handler<M>,
     {
         self.cancel::<A>(ctx);
-        self.duration = Some(duration.clone());
+        self.duration = Some(duration);
+        self.interval = false;
+        self.deadline = Some(Instant::now() + duration);
         self.handle = Some(
             ctx.notify_later(self.timeout_message.clone(), duration)
         );
     }
 
+    /// Notify every `period`. The notifications do not drift with the time
+    /// the handler takes: the n-th one is due `n * period` after the start.
+    pub fn start_interval<A>(&mut self, ctx: &mut A::Context, period: Duration)
+    where
+        A: Actor<Context=Context<A>>,
+        A: Handler<M>,
+    {
+        self.cancel::<A>(ctx);
+        self.duration = Some(period);
+        self.interval = true;
+
+        let msg = self.timeout_message.clone();
+        self.handle = Some(ctx.run_interval(period, move |act, ctx| {
+            // Handled in place: the interval is cancelled along with it.
+            let _ = <A as Handler<M>>::handle(act, msg.clone(), ctx);
+        }));
+    }
+
     pub fn cancel<A>(&mut self, ctx: &mut A::Context)
     where
         A: Actor<Context=Context<A>>,
         A: Handler<M>,
     {
-        if let Some(h) = self.handle {
+        if let Some(h) = self.handle.take() {
             ctx.cancel_future(h);
-            self.handle = None;
         }
+        self.deadline = None;
     }
 
+    /// Start again with the last duration, in the last mode.
     pub fn reset<A>(&mut self, ctx: &mut A::Context)
     where
         A: Actor<Context=Context<A>>,
         A: Handler<M>,
     {
-        if let Some(d) = self.duration {
-            self.start::<A>(ctx, d);
+        match self.duration {
+            Some(d) if self.interval => self.start_interval::<A>(ctx, d),
+            Some(d) => self.start::<A>(ctx, d),
+            None => {},
         }
     }
+
+    /// Started, not cancelled and, if one-shot, not fired yet.
+    pub fn is_active(&self) -> bool {
+        self.handle.is_some()
+            && self.deadline.is_none_or(|d| Instant::now() < d)
+    }
+
+    /// The last duration or period.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
 }
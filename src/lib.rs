@@ -4,21 +4,25 @@ extern crate slog;
 use actix::prelude::*;
 use clap::{App, Arg, crate_version};
 
-use crate::{
-    core::{env, app_state},
-    worker::{dispatcher, router, processor, task_tree},
-};
+use crate::core::env;
 
+pub use crate::app::{AppHandle, PatokaApp};
+
+pub mod app;
 pub mod center;
 #[macro_use]
 pub mod control;
 pub mod core;
 pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
 #[macro_use]
 pub mod worker;
 pub mod transport;
 pub mod utils;
 
+/// Run the app with the configuration file of the command line in the
+/// current thread. See `PatokaApp` to customize the app.
 pub fn run_app<F>(app_name: &str, run_tasks: F)
 where
     F: FnOnce() + 'static
@@ -34,7 +38,7 @@ where
         )
         .get_matches();
 
-    let config = matches.value_of("config").unwrap_or("cfg/patoka.toml");
+    let config = matches.value_of("config").unwrap_or(app::DEFAULT_CONFIG);
     if let Err(_) = env::load(config) {
         std::process::exit(0);
     }
@@ -42,12 +46,7 @@ where
     let system = System::new();
 
     system.block_on(async {
-        app_state::start();
-        dispatcher::start();
-        router::start();
-        task_tree::start();
-        processor::start();
-        center::router::start();
+        app::start_services();
         run_tasks();
     });
 
@@ -0,0 +1,90 @@
+use serde_json::Value;
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use crate::storage::{
+    migrations,
+    postgres::PostgresBackend,
+    sqlite::SqliteBackend,
+};
+
+/// A statement parameter, converted to the type the database expects,
+/// e.g. `json!(42)` for an `int4` column.
+pub type Param = Value;
+
+pub type BackendFuture<T> =
+    Pin<Box<dyn Future<Output = Result<T, String>> + Send>>;
+
+/// A single statement with its parameters.
+pub struct Statement {
+    pub sql: String,
+    pub params: Vec<Param>,
+}
+
+impl Statement {
+    pub fn new(sql: &str, params: Vec<Param>) -> Self {
+        Self {
+            sql: sql.to_string(),
+            params,
+        }
+    }
+}
+
+/// The database `DbExecutor` works with.
+pub trait Backend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the number of affected rows.
+    fn execute(&self, stmt: Statement) -> BackendFuture<u64>;
+
+    /// Returns the rows as JSON objects, column name --> value.
+    fn query(&self, stmt: Statement) -> BackendFuture<Vec<Value>>;
+
+    /// Execute the statements in a single transaction. Returns the number
+    /// of rows affected by each one.
+    fn transaction(&self, stmts: Vec<Statement>) -> BackendFuture<Vec<u64>>;
+
+    /// Run `script`, statements without parameters separated by semicolons,
+    /// then `stmts` in a single transaction.
+    fn run_script(
+        &self,
+        script: String,
+        stmts: Vec<Statement>,
+    ) -> BackendFuture<()>;
+
+    /// Execute `sql` once per row of parameters in a single transaction.
+    /// Returns the number of affected rows.
+    fn execute_batch(
+        &self,
+        sql: String,
+        rows: Vec<Vec<Param>>,
+    ) -> BackendFuture<u64> {
+        let stmts = rows.into_iter()
+            .map(|params| Statement { sql: sql.clone(), params })
+            .collect();
+        let tx = self.transaction(stmts);
+
+        Box::pin(async move {
+            Ok(tx.await?.iter().sum())
+        })
+    }
+}
+
+/// `Backend::name` of the backend `connect` chooses for `url`.
+pub fn name_of(url: &str) -> &'static str {
+    match SqliteBackend::path(url) {
+        Some(_) => "sqlite",
+        None => "postgres",
+    }
+}
+
+/// SQLite if `url` is `sqlite://<path>` or `sqlite::memory:`, Postgres
+/// otherwise. Applies the pending migrations or exits.
+pub async fn connect(url: &str) -> Result<Arc<dyn Backend>, String> {
+    let backend: Arc<dyn Backend> = match SqliteBackend::path(url) {
+        Some(path) => Arc::new(SqliteBackend::open(path)?),
+        None => Arc::new(PostgresBackend::connect(url).await?),
+    };
+
+    migrations::run_or_exit(backend.as_ref()).await;
+    Ok(backend)
+}
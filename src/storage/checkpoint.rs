@@ -0,0 +1,268 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use slog::Logger;
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    core::{
+        env::{self, PATOKA_ROOT_DIR},
+        logger::create_logger,
+    },
+    storage::{backend::Backend, kv::KvStore},
+};
+
+/// `KvStore` namespace of the checkpoints kept in the database.
+const NAMESPACE: &str = "checkpoints";
+
+lazy_static! {
+    static ref PARAMS: CheckpointParams =
+        env::load_opt("checkpoint").unwrap_or_default();
+
+    /// Task name --> State
+    static ref CHECKPOINTS: Mutex<HashMap<String, Value>> =
+        Mutex::new(if PARAMS.in_db() { HashMap::new() } else { load_files() });
+}
+
+/// `[checkpoint]` configuration section.
+#[derive(Deserialize)]
+struct CheckpointParams {
+    /// "file" (default) or "db" for the database of `app.db`.
+    #[serde(default = "default_storage")]
+    storage: String,
+
+    /// The checkpoints are written to `{dir}/{task name}.json`.
+    #[serde(default = "default_dir")]
+    dir: String,
+}
+
+fn default_storage() -> String { "file".to_string() }
+
+fn default_dir() -> String { "data/checkpoints".to_string() }
+
+impl Default for CheckpointParams {
+    fn default() -> Self {
+        Self {
+            storage: default_storage(),
+            dir: default_dir(),
+        }
+    }
+}
+
+impl CheckpointParams {
+    fn in_db(&self) -> bool {
+        self.storage == "db"
+    }
+
+    fn dir(&self) -> PathBuf {
+        PathBuf::from(env::full_path(
+            &self.dir,
+            "$PATOKA_ROOT_DIR",
+            &PATOKA_ROOT_DIR,
+        ))
+    }
+}
+
+fn file_path(task_name: &str) -> PathBuf {
+    let file_name: String = task_name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+
+    PARAMS.dir().join(format!("{}.json", file_name))
+}
+
+fn load_files() -> HashMap<String, Value> {
+    let mut checkpoints = HashMap::new();
+
+    let entries = match fs::read_dir(PARAMS.dir()) {
+        Ok(entries) => entries,
+        Err(_) => return checkpoints,
+    };
+
+    for entry in entries.flatten() {
+        let file: Option<FileCheckpoint> = fs::read(entry.path()).ok()
+            .and_then(|data| serde_json::from_slice(&data).ok());
+
+        if let Some(f) = file {
+            checkpoints.insert(f.task_name, f.state);
+        }
+    }
+
+    checkpoints
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileCheckpoint {
+    task_name: String,
+    state: Value,
+}
+
+/// Replace the file at once, so that a crash does not leave it half
+/// written.
+fn write_file(task_name: &str, state: &Value) -> io::Result<()> {
+    let path = file_path(task_name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let data = serde_json::to_vec(&FileCheckpoint {
+        task_name: task_name.to_string(),
+        state: state.clone(),
+    })?;
+
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, &path)
+}
+
+fn remove_file(task_name: &str) -> io::Result<()> {
+    match fs::remove_file(file_path(task_name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The last checkpoint of the task, given to its next instance in
+/// `ClientContext`.
+pub fn last(task_name: &str) -> Option<Value> {
+    CHECKPOINTS.lock().unwrap().get(task_name).cloned()
+}
+
+/// Load the checkpoints kept in the database, if configured so. Called by
+/// `db_executor::init`.
+pub async fn preload(backend: Arc<dyn Backend>) -> Result<(), String> {
+    if !PARAMS.in_db() {
+        return Ok(());
+    }
+
+    let entries = KvStore::with_backend(NAMESPACE, backend)
+        .entries::<Value>()
+        .await?;
+
+    CHECKPOINTS.lock().unwrap().extend(entries);
+    Ok(())
+}
+
+/// Save the state of the task, `None` to forget it, e.g. when the task has
+/// completed.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Checkpoint {
+    pub task_name: String,
+    pub state: Option<Value>,
+}
+
+/// Persists the checkpoints in order.
+pub struct CheckpointStore {
+    log: Logger,
+    kv: Option<KvStore>,
+}
+
+impl Default for CheckpointStore {
+    fn default() -> Self {
+        Self {
+            log: create_logger("checkpoint_store"),
+            kv: None,
+        }
+    }
+}
+
+impl Actor for CheckpointStore {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Checkpoint Store started: {}", PARAMS.storage);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Checkpoint Store stopped.");
+    }
+}
+
+impl Supervised for CheckpointStore {}
+
+impl SystemService for CheckpointStore {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Checkpoint Store system service started.")
+    }
+}
+
+impl Handler<Checkpoint> for CheckpointStore {
+    type Result = ();
+
+    fn handle(&mut self, msg: Checkpoint, ctx: &mut Self::Context) {
+        {
+            let mut checkpoints = CHECKPOINTS.lock().unwrap();
+            match msg.state {
+                Some(ref state) => {
+                    checkpoints.insert(msg.task_name.clone(), state.clone());
+                },
+                None => {
+                    checkpoints.remove(&msg.task_name);
+                },
+            }
+        }
+
+        if !PARAMS.in_db() {
+            let res = match msg.state {
+                Some(ref state) => write_file(&msg.task_name, state),
+                None => remove_file(&msg.task_name),
+            };
+
+            if let Err(e) = res {
+                error!(
+                    self.log,
+                    "Failed to save the checkpoint of {}: {}",
+                    msg.task_name,
+                    e,
+                );
+            }
+            return;
+        }
+
+        let kv = self.kv.get_or_insert_with(|| KvStore::new(NAMESPACE))
+            .clone();
+        let log = self.log.clone();
+
+        // The next checkpoint is handled once this one is saved.
+        async move {
+            let res = match msg.state {
+                Some(ref state) => kv.set(&msg.task_name, state).await,
+                None => kv.delete(&msg.task_name).await.map(|_| ()),
+            };
+
+            if let Err(e) = res {
+                error!(
+                    log,
+                    "Failed to save the checkpoint of {}: {}",
+                    msg.task_name,
+                    e,
+                );
+            }
+        }
+        .into_actor(self)
+        .wait(ctx);
+    }
+}
+
+pub fn start() -> Addr<CheckpointStore> {
+    CheckpointStore::from_registry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let path = file_path("crawl/shop a");
+        assert!(path.ends_with("crawl_shop_a.json"));
+    }
+}
@@ -1,31 +1,28 @@
 use actix::prelude::*;
-use bb8;
-use bb8_postgres::PostgresConnectionManager;
 use lazy_static::lazy_static;
 use num_cpus;
+use serde_json::Value;
 use slog::Logger;
-use std::{
-    str::FromStr,
-    sync::{Mutex, RwLock}
-};
-use tokio_postgres;
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::{
     core::{arbiter_pool, logger::create_logger},
     env,
+    storage::{
+        backend::{self, Backend, Statement},
+        checkpoint,
+    },
 };
 
-pub type Pool = bb8::Pool<PostgresConnectionManager<tokio_postgres::NoTls>>;
-
 pub struct DbExecutor {
-    pub pool: Pool,
+    pub backend: Arc<dyn Backend>,
     pub log: Logger,
 }
 
 impl DbExecutor {
-    pub fn new(pool: Pool, log: Logger) -> Self {
+    pub fn new(backend: Arc<dyn Backend>, log: Logger) -> Self {
         Self {
-            pool,
+            backend,
             log
         }
     }
@@ -34,7 +31,8 @@ impl DbExecutor {
 lazy_static! {
     static ref DB_EXECUTOR_POOL: DbExecutorPool = DbExecutorPool::new();
 
-    static ref DB_POOL: RwLock<Option<Pool>> = RwLock::new(None);
+    static ref DB_BACKEND: RwLock<Option<Arc<dyn Backend>>> =
+        RwLock::new(None);
 }
 
 impl Actor for DbExecutor {
@@ -49,16 +47,99 @@ impl Actor for DbExecutor {
     }
 }
 
+/// Execute `statement` once per row of text parameters in a single
+/// transaction. Returns the number of affected rows.
+#[derive(Message)]
+#[rtype(result = "Result<u64, String>")]
+pub struct ExecuteBatch {
+    pub statement: String,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Handler<ExecuteBatch> for DbExecutor {
+    type Result = ResponseFuture<Result<u64, String>>;
+
+    fn handle(
+        &mut self,
+        msg: ExecuteBatch,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let rows = msg.rows.into_iter()
+            .map(|row| row.into_iter().map(Value::from).collect())
+            .collect();
+
+        self.backend.execute_batch(msg.statement, rows)
+    }
+}
+
+/// Execute the statement. Returns the number of affected rows.
+#[derive(Message)]
+#[rtype(result = "Result<u64, String>")]
+pub struct Execute(pub Statement);
+
+impl Handler<Execute> for DbExecutor {
+    type Result = ResponseFuture<Result<u64, String>>;
+
+    fn handle(
+        &mut self,
+        msg: Execute,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.backend.execute(msg.0)
+    }
+}
+
+/// Run the query. Returns the rows as JSON objects, column name --> value.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<Value>, String>")]
+pub struct Query(pub Statement);
+
+impl Handler<Query> for DbExecutor {
+    type Result = ResponseFuture<Result<Vec<Value>, String>>;
+
+    fn handle(
+        &mut self,
+        msg: Query,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.backend.query(msg.0)
+    }
+}
+
+/// Execute the statements in a single transaction. Returns the number of
+/// rows affected by each one.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<u64>, String>")]
+pub struct Transaction(pub Vec<Statement>);
+
+impl Handler<Transaction> for DbExecutor {
+    type Result = ResponseFuture<Result<Vec<u64>, String>>;
+
+    fn handle(
+        &mut self,
+        msg: Transaction,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.backend.transaction(msg.0)
+    }
+}
+
+/// `None` until `init`.
+pub fn backend() -> Option<Arc<dyn Backend>> {
+    DB_BACKEND.read().unwrap().clone()
+}
+
 pub fn run() -> Addr<DbExecutor> {
     DB_EXECUTOR_POOL.next()
 }
 
+/// Connect to `app.db`: `sqlite://<path>` for SQLite, a Postgres URL or
+/// connection string otherwise.
 pub async fn init() {
     let db_config: String = env::get_var("app.db").parse().unwrap();
-    let cfg = tokio_postgres::config::Config::from_str(&db_config).unwrap();
-    let manager = PostgresConnectionManager::new(cfg, tokio_postgres::NoTls);
-    let pool = Pool::builder().build(manager).await.unwrap();
-    *DB_POOL.write().unwrap() = Some(pool);
+    let backend = backend::connect(&db_config).await.unwrap();
+    checkpoint::preload(backend.clone()).await.unwrap();
+    *DB_BACKEND.write().unwrap() = Some(backend);
 }
 
 pub struct DbExecutorPool {
@@ -73,18 +154,18 @@ impl DbExecutorPool {
         let log = create_logger("db_executor_pool");
         let capacity = num_cpus::get();
 
-        let pp = &*DB_POOL.read().unwrap();
-        let p = pp.as_ref().unwrap();
+        let bb = &*DB_BACKEND.read().unwrap();
+        let b = bb.as_ref().unwrap();
 
         let mut executors = Vec::new();
         for i in 0..capacity {
-            let pool = p.clone();
+            let backend = b.clone();
             let log = create_logger(&format!("db_executor_{}", i));
 
             executors.push(
                 DbExecutor::start_in_arbiter(
                     &arbiter_pool::next(),
-                    move |_| { DbExecutor::new(pool, log) },
+                    move |_| { DbExecutor::new(backend, log) },
                 )
             );
         }
@@ -0,0 +1,200 @@
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use crate::storage::{
+    backend::{Backend, Statement},
+    db_executor,
+};
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS patoka_kv (\
+    namespace text NOT NULL, \
+    key text NOT NULL, \
+    value text NOT NULL, \
+    PRIMARY KEY (namespace, key))";
+
+/// A handle to the state of a task persisted across restarts, cheap to
+/// clone. The table is created on the first request of the handle, so it is
+/// better kept by the client than created per request.
+#[derive(Clone)]
+pub struct KvStore {
+    namespace: String,
+    backend: Option<Arc<dyn Backend>>,
+    table: Arc<OnceCell<()>>,
+}
+
+impl KvStore {
+    /// The keys of `namespace` in the database of `app.db`.
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            backend: db_executor::backend(),
+            table: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// The state of the task `task_name`.
+    pub fn for_task(task_name: &str) -> Self {
+        Self::new(task_name)
+    }
+
+    pub fn with_backend(namespace: &str, backend: Arc<dyn Backend>) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            backend: Some(backend),
+            table: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub async fn get<T>(&self, key: &str) -> Result<Option<T>, String>
+    where
+        T: DeserializeOwned,
+    {
+        let rows = self.backend().await?.query(Statement::new(
+            "SELECT value FROM patoka_kv WHERE namespace = $1 AND key = $2",
+            vec![json!(self.namespace), json!(key)],
+        )).await?;
+
+        match rows.first().and_then(|r| r["value"].as_str()) {
+            Some(v) => serde_json::from_str(v).map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    /// All the keys of the namespace with their values.
+    pub async fn entries<T>(&self) -> Result<Vec<(String, T)>, String>
+    where
+        T: DeserializeOwned,
+    {
+        let rows = self.backend().await?.query(Statement::new(
+            "SELECT key, value FROM patoka_kv WHERE namespace = $1",
+            vec![json!(self.namespace)],
+        )).await?;
+
+        rows.iter()
+            .map(|r| {
+                let key = r["key"].as_str().unwrap_or_default().to_string();
+                let value = r["value"].as_str().unwrap_or_default();
+                serde_json::from_str(value)
+                    .map(|v| (key, v))
+                    .map_err(|e| e.to_string())
+            })
+            .collect()
+    }
+
+    pub async fn set<T>(&self, key: &str, value: &T) -> Result<(), String>
+    where
+        T: Serialize,
+    {
+        self.backend().await?.execute(Statement::new(
+            "INSERT INTO patoka_kv (namespace, key, value) \
+             VALUES ($1, $2, $3) \
+             ON CONFLICT (namespace, key) DO UPDATE SET value = excluded.value",
+            vec![json!(self.namespace), json!(key), to_json(value)?],
+        )).await?;
+
+        Ok(())
+    }
+
+    /// `False` if there was no such key.
+    pub async fn delete(&self, key: &str) -> Result<bool, String> {
+        let affected = self.backend().await?.execute(Statement::new(
+            "DELETE FROM patoka_kv WHERE namespace = $1 AND key = $2",
+            vec![json!(self.namespace), json!(key)],
+        )).await?;
+
+        Ok(affected > 0)
+    }
+
+    /// Set `key` to `new` if its value is `expected`, `None` for no value.
+    /// The values are compared as serialized, so `T` should serialize the
+    /// same value the same way, e.g. no `HashMap`. `False` if the value
+    /// has been different.
+    pub async fn compare_and_swap<T>(
+        &self,
+        key: &str,
+        expected: Option<&T>,
+        new: &T,
+    ) -> Result<bool, String>
+    where
+        T: Serialize,
+    {
+        let stmt = match expected {
+            Some(expected) => Statement::new(
+                "UPDATE patoka_kv SET value = $4 \
+                 WHERE namespace = $1 AND key = $2 AND value = $3",
+                vec![
+                    json!(self.namespace),
+                    json!(key),
+                    to_json(expected)?,
+                    to_json(new)?,
+                ],
+            ),
+            None => Statement::new(
+                "INSERT INTO patoka_kv (namespace, key, value) \
+                 VALUES ($1, $2, $3) \
+                 ON CONFLICT (namespace, key) DO NOTHING",
+                vec![json!(self.namespace), json!(key), to_json(new)?],
+            ),
+        };
+
+        Ok(self.backend().await?.execute(stmt).await? == 1)
+    }
+
+    async fn backend(&self) -> Result<&Arc<dyn Backend>, String> {
+        let backend = self.backend.as_ref()
+            .ok_or_else(|| "Storage is not initialized".to_string())?;
+
+        self.table.get_or_try_init(|| async {
+            backend.execute(Statement::new(CREATE_TABLE, vec![])).await
+                .map(|_| ())
+        }).await?;
+
+        Ok(backend)
+    }
+}
+
+/// The value as a JSON text parameter.
+fn to_json<T: Serialize>(value: &T) -> Result<Value, String> {
+    serde_json::to_string(value)
+        .map(Value::String)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sqlite::SqliteBackend;
+
+    #[actix::test]
+    async fn get_set_and_swap() {
+        let backend = Arc::new(SqliteBackend::open(":memory:").unwrap());
+        let a = KvStore::with_backend("task_a", backend.clone());
+        let b = KvStore::with_backend("task_b", backend);
+
+        assert_eq!(a.get::<u64>("cursor").await.unwrap(), None);
+        a.set("cursor", &10u64).await.unwrap();
+        a.set("cursor", &20u64).await.unwrap();
+        assert_eq!(a.get::<u64>("cursor").await.unwrap(), Some(20));
+        assert_eq!(b.get::<u64>("cursor").await.unwrap(), None);
+
+        let swapped = a.compare_and_swap("cursor", Some(&10u64), &30).await;
+        assert!(!swapped.unwrap());
+        let swapped = a.compare_and_swap("cursor", Some(&20u64), &30).await;
+        assert!(swapped.unwrap());
+        assert!(!a.compare_and_swap("cursor", None, &40u64).await.unwrap());
+        assert!(b.compare_and_swap("cursor", None, &40u64).await.unwrap());
+        assert_eq!(a.get::<u64>("cursor").await.unwrap(), Some(30));
+
+        assert!(a.delete("cursor").await.unwrap());
+        assert!(!a.delete("cursor").await.unwrap());
+        assert_eq!(b.get::<u64>("cursor").await.unwrap(), Some(40));
+        let entries = b.entries::<u64>().await.unwrap();
+        assert_eq!(entries, vec![("cursor".to_string(), 40)]);
+    }
+}
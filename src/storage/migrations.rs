@@ -0,0 +1,209 @@
+use serde_json::json;
+use slog::Logger;
+use std::{collections::BTreeMap, fs};
+
+use crate::{
+    core::{env, logger::create_logger},
+    storage::backend::{Backend, Statement},
+};
+
+const SCHEMA_TABLE: &str = "patoka_schema_migrations";
+
+/// Version, name, Postgres SQL, SQLite SQL
+const EMBEDDED: &[(i64, &str, &str, &str)] = &[
+    (
+        1,
+        "task_messages",
+        include_str!("migrations/0001_task_messages.sql"),
+        include_str!("migrations/sqlite/0001_task_messages.sql"),
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub sql: String,
+}
+
+impl Migration {
+    /// `None` unless the file name is `<version>_<name>.sql`.
+    fn from_file_name(file_name: &str, sql: String) -> Option<Self> {
+        let stem = file_name.strip_suffix(".sql")?;
+        let (version, name) = stem.split_once('_')?;
+
+        Some(Self {
+            version: version.parse().ok()?,
+            name: name.to_string(),
+            sql,
+        })
+    }
+}
+
+/// The embedded migrations for `backend`, e.g. "sqlite", and the ones in
+/// `app.db_migrations_dir`, by version.
+pub fn load(backend: &str) -> Result<Vec<Migration>, String> {
+    let mut migrations = BTreeMap::new();
+    for (version, name, postgres, sqlite) in EMBEDDED {
+        let sql = if backend == "sqlite" { sqlite } else { postgres };
+        migrations.insert(*version, Migration {
+            version: *version,
+            name: name.to_string(),
+            sql: sql.to_string(),
+        });
+    }
+
+    let dir = match env::get_opt_var("app.db_migrations_dir") {
+        Some(dir) => dir,
+        None => return Ok(migrations.into_values().collect()),
+    };
+
+    let entries = fs::read_dir(&dir)
+        .map_err(|e| format!("{}: {}", dir, e))?;
+
+    for entry in entries {
+        let path = entry.map_err(|e| format!("{}: {}", dir, e))?.path();
+        let file_name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) if n.ends_with(".sql") => n.to_string(),
+            _ => continue,
+        };
+
+        let sql = fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let m = Migration::from_file_name(&file_name, sql)
+            .ok_or_else(|| format!("Invalid migration name: {}", file_name))?;
+
+        if migrations.contains_key(&m.version) {
+            return Err(format!("Duplicate migration version: {}", file_name));
+        }
+        migrations.insert(m.version, m);
+    }
+
+    Ok(migrations.into_values().collect())
+}
+
+/// The migrations not applied yet. An error if the database has a version
+/// unknown to the binary, i.e. it has been migrated by a newer one.
+fn pending<'a>(
+    migrations: &'a [Migration],
+    applied: &[i64],
+) -> Result<Vec<&'a Migration>, String> {
+    let known = migrations.iter().map(|m| m.version).max().unwrap_or(0);
+    if let Some(ahead) = applied.iter().find(|v| **v > known) {
+        return Err(format!(
+            "The database is at version {}, ahead of the latest known {}",
+            ahead,
+            known,
+        ));
+    }
+
+    Ok(migrations.iter().filter(|m| !applied.contains(&m.version)).collect())
+}
+
+/// Apply the pending migrations, each in its own transaction. Returns their
+/// number.
+pub async fn run(backend: &dyn Backend, log: &Logger)
+    -> Result<usize, String>
+{
+    let migrations = load(backend.name())?;
+
+    backend.execute(Statement::new(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+                version bigint PRIMARY KEY, \
+                name text NOT NULL, \
+                applied_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP)",
+            SCHEMA_TABLE,
+        ),
+        vec![],
+    )).await?;
+
+    let applied: Vec<i64> = backend
+        .query(Statement::new(
+            &format!("SELECT version FROM {}", SCHEMA_TABLE),
+            vec![],
+        ))
+        .await?
+        .iter()
+        .filter_map(|row| row["version"].as_i64())
+        .collect();
+
+    let pending = pending(&migrations, &applied)?;
+    for m in &pending {
+        let record = Statement::new(
+            &format!(
+                "INSERT INTO {} (version, name) VALUES ($1, $2)",
+                SCHEMA_TABLE,
+            ),
+            vec![json!(m.version), json!(m.name)],
+        );
+
+        backend.run_script(m.sql.clone(), vec![record]).await
+            .map_err(|e| format!("Migration {}_{}: {}", m.version, m.name, e))?;
+
+        info!(log, "Applied migration {}_{}.", m.version, m.name);
+    }
+
+    Ok(pending.len())
+}
+
+/// Apply the pending migrations or exit: the binary is not to run against
+/// a database it does not know the schema of.
+pub async fn run_or_exit(backend: &dyn Backend) {
+    let log = create_logger("migrations");
+    match run(backend, &log).await {
+        Ok(0) => {},
+        Ok(n) => info!(log, "Applied {} migrations.", n),
+        Err(e) => {
+            error!(log, "Failed to migrate the database: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sqlite::SqliteBackend;
+
+    fn migration(version: i64) -> Migration {
+        Migration {
+            version,
+            name: format!("m{}", version),
+            sql: String::new(),
+        }
+    }
+
+    #[test]
+    fn pending_and_ahead() {
+        let m = Migration::from_file_name("0002_users.sql", String::new());
+        assert_eq!(m.unwrap().version, 2);
+        let m = Migration::from_file_name("users.sql", String::new());
+        assert!(m.is_none());
+
+        let migrations = vec![migration(1), migration(2), migration(3)];
+        let versions: Vec<i64> = pending(&migrations, &[1, 3]).unwrap()
+            .iter()
+            .map(|m| m.version)
+            .collect();
+        assert_eq!(versions, vec![2]);
+
+        assert!(pending(&migrations, &[1, 4]).is_err());
+    }
+
+    #[actix::test]
+    async fn sqlite() {
+        let backend = SqliteBackend::open(":memory:").unwrap();
+        let log = create_logger("migrations");
+
+        assert_eq!(run(&backend, &log).await.unwrap(), EMBEDDED.len());
+        assert_eq!(run(&backend, &log).await.unwrap(), 0);
+
+        let inserted = backend.execute(Statement::new(
+            "INSERT INTO task_messages (task_name, task_uuid, data) \
+             VALUES ($1, $2, $3)",
+            vec![json!("a"), json!("uuid"), json!("{}")],
+        )).await.unwrap();
+        assert_eq!(inserted, 1);
+    }
+}
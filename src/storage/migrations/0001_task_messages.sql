@@ -0,0 +1,11 @@
+-- The default table of the `postgres` task sink.
+CREATE TABLE IF NOT EXISTS task_messages (
+    id bigserial PRIMARY KEY,
+    task_name text NOT NULL,
+    task_uuid text NOT NULL,
+    data jsonb,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS task_messages_task_uuid
+    ON task_messages (task_uuid);
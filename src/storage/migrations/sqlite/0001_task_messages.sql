@@ -0,0 +1,11 @@
+-- The default table of the `postgres` task sink.
+CREATE TABLE IF NOT EXISTS task_messages (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    task_name text NOT NULL,
+    task_uuid text NOT NULL,
+    data text,
+    created_at text NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS task_messages_task_uuid
+    ON task_messages (task_uuid);
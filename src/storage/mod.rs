@@ -1 +1,10 @@
+pub mod backend;
+pub mod checkpoint;
 pub mod db_executor;
+pub mod kv;
+pub mod migrations;
+pub mod postgres;
+pub mod query;
+pub mod sqlite;
+
+pub use query::{execute, query, query_one, transaction};
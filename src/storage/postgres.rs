@@ -0,0 +1,342 @@
+use bb8;
+use bb8_postgres::PostgresConnectionManager;
+use bytes::{BufMut, BytesMut};
+use serde_json::{Map, Number, Value};
+use slog::Logger;
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    io,
+    str::FromStr,
+    time::Duration,
+};
+use tokio_postgres::{
+    self,
+    Row,
+    error::SqlState,
+    types::{FromSql, IsNull, ToSql, Type, to_sql_checked},
+};
+
+use crate::{
+    core::logger::create_logger,
+    storage::backend::{Backend, BackendFuture, Param, Statement},
+};
+
+pub type Pool = bb8::Pool<PostgresConnectionManager<tokio_postgres::NoTls>>;
+
+/// Attempts of a request failing with a transient error.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// The delay before the next attempt grows by that much each time.
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+pub struct PostgresBackend {
+    pool: Pool,
+    log: Logger,
+}
+
+impl PostgresBackend {
+    pub async fn connect(url: &str) -> Result<Self, String> {
+        let cfg = tokio_postgres::config::Config::from_str(url)
+            .map_err(|e| e.to_string())?;
+        let manager =
+            PostgresConnectionManager::new(cfg, tokio_postgres::NoTls);
+        let pool = Pool::builder().build(manager).await
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            pool,
+            log: create_logger("postgres"),
+        })
+    }
+}
+
+impl Backend for PostgresBackend {
+    fn name(&self) -> &'static str { "postgres" }
+
+    fn execute(&self, stmt: Statement) -> BackendFuture<u64> {
+        let pool = self.pool.clone();
+        let log = self.log.clone();
+
+        Box::pin(async move {
+            with_retries(&log, || async {
+                let conn = pool.get().await?;
+                let params = PgParam::wrap(&stmt.params);
+                Ok(conn.execute(stmt.sql.as_str(), &refs(&params)).await?)
+            }).await
+        })
+    }
+
+    fn query(&self, stmt: Statement) -> BackendFuture<Vec<Value>> {
+        let pool = self.pool.clone();
+        let log = self.log.clone();
+
+        Box::pin(async move {
+            with_retries(&log, || async {
+                let conn = pool.get().await?;
+                let params = PgParam::wrap(&stmt.params);
+                let rows = conn.query(stmt.sql.as_str(), &refs(&params))
+                    .await?;
+                Ok(rows.iter().map(row_to_json).collect())
+            }).await
+        })
+    }
+
+    fn transaction(&self, stmts: Vec<Statement>) -> BackendFuture<Vec<u64>> {
+        let pool = self.pool.clone();
+        let log = self.log.clone();
+
+        Box::pin(async move {
+            with_retries(&log, || async {
+                let mut conn = pool.get().await?;
+                let tx = conn.transaction().await?;
+
+                let mut affected = Vec::with_capacity(stmts.len());
+                for stmt in &stmts {
+                    let params = PgParam::wrap(&stmt.params);
+                    affected.push(
+                        tx.execute(stmt.sql.as_str(), &refs(&params)).await?
+                    );
+                }
+
+                tx.commit().await?;
+                Ok(affected)
+            }).await
+        })
+    }
+
+    fn run_script(
+        &self,
+        script: String,
+        stmts: Vec<Statement>,
+    ) -> BackendFuture<()> {
+        let pool = self.pool.clone();
+        let log = self.log.clone();
+
+        Box::pin(async move {
+            with_retries(&log, || async {
+                let mut conn = pool.get().await?;
+                let tx = conn.transaction().await?;
+                tx.batch_execute(&script).await?;
+
+                for stmt in &stmts {
+                    let params = PgParam::wrap(&stmt.params);
+                    tx.execute(stmt.sql.as_str(), &refs(&params)).await?;
+                }
+
+                Ok(tx.commit().await?)
+            }).await
+        })
+    }
+
+    /// The statement is prepared once.
+    fn execute_batch(
+        &self,
+        sql: String,
+        rows: Vec<Vec<Param>>,
+    ) -> BackendFuture<u64> {
+        let pool = self.pool.clone();
+        let log = self.log.clone();
+
+        Box::pin(async move {
+            with_retries(&log, || async {
+                let mut conn = pool.get().await?;
+                let tx = conn.transaction().await?;
+                let stmt = tx.prepare(&sql).await?;
+
+                let mut affected = 0;
+                for row in &rows {
+                    let params = PgParam::wrap(row);
+                    affected += tx.execute(&stmt, &refs(&params)).await?;
+                }
+
+                tx.commit().await?;
+                Ok(affected)
+            }).await
+        })
+    }
+}
+
+/// A JSON parameter converted to the type of the statement parameter.
+#[derive(Debug)]
+struct PgParam<'a>(&'a Value);
+
+impl<'a> PgParam<'a> {
+    fn wrap(params: &'a [Param]) -> Vec<Self> {
+        params.iter().map(PgParam).collect()
+    }
+}
+
+fn refs<'a>(params: &'a [PgParam<'a>]) -> Vec<&'a (dyn ToSql + Sync)> {
+    params.iter().map(|p| p as _).collect()
+}
+
+type ToSqlResult = Result<IsNull, Box<dyn Error + Sync + Send>>;
+
+impl ToSql for PgParam<'_> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> ToSqlResult {
+        match self.0 {
+            Value::Null => Ok(IsNull::Yes),
+            v if is_json(ty) => {
+                if *ty == Type::JSONB {
+                    out.put_u8(1);
+                }
+                serde_json::to_writer(out.writer(), v)?;
+                Ok(IsNull::No)
+            },
+            Value::Bool(b) => checked(b, ty, out),
+            Value::Number(n) => number_to_sql(n, ty, out),
+            Value::String(s) => checked(s, ty, out),
+            v => checked(v.to_string(), ty, out),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn is_json(ty: &Type) -> bool {
+    *ty == Type::JSON || *ty == Type::JSONB
+}
+
+fn checked<T: ToSql>(v: T, ty: &Type, out: &mut BytesMut) -> ToSqlResult {
+    if !T::accepts(ty) {
+        return Err(format!("Cannot convert {:?} to {}", v, ty).into());
+    }
+
+    v.to_sql(ty, out)
+}
+
+fn number_to_sql(n: &Number, ty: &Type, out: &mut BytesMut) -> ToSqlResult {
+    let int = || n.as_i64().ok_or_else(|| format!("Not an integer: {}", n));
+    let float = n.as_f64().unwrap_or_default();
+
+    match *ty {
+        Type::INT2 => i16::try_from(int()?)?.to_sql(ty, out),
+        Type::INT4 => i32::try_from(int()?)?.to_sql(ty, out),
+        Type::INT8 => int()?.to_sql(ty, out),
+        Type::OID => u32::try_from(int()?)?.to_sql(ty, out),
+        Type::FLOAT4 => (float as f32).to_sql(ty, out),
+        Type::FLOAT8 => float.to_sql(ty, out),
+        _ => checked(n.to_string(), ty, out),
+    }
+}
+
+enum DbError {
+    Pool(bb8::RunError<tokio_postgres::Error>),
+    Postgres(tokio_postgres::Error),
+}
+
+impl DbError {
+    /// The request may succeed if retried: the connection has been lost or
+    /// not established in time, or the transaction has conflicted with
+    /// another one.
+    fn is_transient(&self) -> bool {
+        let e = match self {
+            DbError::Pool(bb8::RunError::TimedOut) => return true,
+            DbError::Pool(bb8::RunError::User(e)) => e,
+            DbError::Postgres(e) => e,
+        };
+
+        if e.is_closed() {
+            return true;
+        }
+
+        match e.code() {
+            Some(code) => {
+                code.code().starts_with("08")
+                    || *code == SqlState::T_R_SERIALIZATION_FAILURE
+                    || *code == SqlState::T_R_DEADLOCK_DETECTED
+                    || *code == SqlState::ADMIN_SHUTDOWN
+                    || *code == SqlState::CANNOT_CONNECT_NOW
+            },
+            None => e.source().is_some_and(|s| s.is::<io::Error>()),
+        }
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::Pool(e) => write!(f, "{}", e),
+            DbError::Postgres(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<bb8::RunError<tokio_postgres::Error>> for DbError {
+    fn from(e: bb8::RunError<tokio_postgres::Error>) -> Self {
+        DbError::Pool(e)
+    }
+}
+
+impl From<tokio_postgres::Error> for DbError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        DbError::Postgres(e)
+    }
+}
+
+/// Run `request` again while it fails with a transient error, at most
+/// `MAX_ATTEMPTS` times. A statement may thus be executed twice if the
+/// connection is lost before its result is received.
+async fn with_retries<T, F, Fut>(
+    log: &Logger,
+    mut request: F,
+) -> Result<T, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DbError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Ok(v) => return Ok(v),
+            Err(e) if e.is_transient() && attempt < MAX_ATTEMPTS => {
+                warn!(log, "Attempt {} failed, retrying: {}", attempt, e);
+                actix::clock::sleep(RETRY_DELAY * attempt).await;
+                attempt += 1;
+            },
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+fn row_to_json(row: &Row) -> Value {
+    let mut obj = Map::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        obj.insert(column.name().to_string(), column_value(row, i));
+    }
+
+    Value::Object(obj)
+}
+
+/// The value of a column of a type without a JSON counterpart is taken as
+/// text if possible, `null` otherwise.
+fn column_value(row: &Row, i: usize) -> Value {
+    match *row.columns()[i].type_() {
+        Type::BOOL => get::<bool>(row, i),
+        Type::CHAR => get::<i8>(row, i),
+        Type::INT2 => get::<i16>(row, i),
+        Type::INT4 => get::<i32>(row, i),
+        Type::INT8 => get::<i64>(row, i),
+        Type::OID => get::<u32>(row, i),
+        Type::FLOAT4 => get::<f32>(row, i),
+        Type::FLOAT8 => get::<f64>(row, i),
+        _ => get::<String>(row, i),
+    }
+}
+
+fn get<'a, T>(row: &'a Row, i: usize) -> Value
+where
+    T: FromSql<'a> + Into<Value>,
+{
+    row.try_get::<_, Option<T>>(i)
+        .ok()
+        .flatten()
+        .map_or(Value::Null, Into::into)
+}
+
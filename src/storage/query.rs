@@ -0,0 +1,86 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::storage::{
+    backend::{Param, Statement},
+    db_executor::{self, Execute, Query, Transaction},
+};
+
+/// The rows of the query mapped by the column names into `T`.
+pub async fn query<T>(sql: &str, params: Vec<Param>) -> Result<Vec<T>, String>
+where
+    T: DeserializeOwned,
+{
+    let rows = db_executor::run()
+        .send(Query(Statement::new(sql, params)))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    from_rows(rows)
+}
+
+/// The first row of the query, if any.
+pub async fn query_one<T>(
+    sql: &str,
+    params: Vec<Param>,
+) -> Result<Option<T>, String>
+where
+    T: DeserializeOwned,
+{
+    Ok(query(sql, params).await?.into_iter().next())
+}
+
+/// Returns the number of affected rows.
+pub async fn execute(sql: &str, params: Vec<Param>) -> Result<u64, String> {
+    db_executor::run()
+        .send(Execute(Statement::new(sql, params)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Execute the statements in a single transaction. Returns the number of
+/// rows affected by each one.
+pub async fn transaction(
+    statements: Vec<Statement>,
+) -> Result<Vec<u64>, String> {
+    db_executor::run()
+        .send(Transaction(statements))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn from_rows<T>(rows: Vec<Value>) -> Result<Vec<T>, String>
+where
+    T: DeserializeOwned,
+{
+    rows.into_iter()
+        .map(|row| serde_json::from_value(row).map_err(|e| e.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct User {
+        id: i64,
+        name: Option<String>,
+    }
+
+    #[test]
+    fn map_rows() {
+        let rows = vec![
+            json!({ "id": 1, "name": "a" }),
+            json!({ "id": 2, "name": null }),
+        ];
+        let users: Vec<User> = from_rows(rows).unwrap();
+        assert_eq!(users[1], User { id: 2, name: None });
+
+        let bad: Result<Vec<User>, _> = from_rows(vec![json!({ "id": "x" })]);
+        assert!(bad.is_err());
+
+    }
+}
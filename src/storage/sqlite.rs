@@ -0,0 +1,227 @@
+use rusqlite::{
+    Connection,
+    params_from_iter,
+    types::{Value as SqlValue, ValueRef},
+};
+use serde_json::{Map, Value};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::storage::backend::{Backend, BackendFuture, Param, Statement};
+
+/// A statement waits for a lock held by another process for that long.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A single SQLite connection shared by the executors. The statements are
+/// run in the blocking threads of the runtime, one at a time.
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteBackend {
+    /// The database path of an `app.db` URL, `None` if not SQLite.
+    pub fn path(url: &str) -> Option<&str> {
+        url.strip_prefix("sqlite://")
+            .or_else(|| url.strip_prefix("sqlite:"))
+    }
+
+    /// `:memory:` for an in-memory database.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = if path == ":memory:" {
+            Connection::open_in_memory()
+        } else {
+            Connection::open(path)
+        }.map_err(|e| format!("{}: {}", path, e))?;
+
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn run<T, F>(&self, f: F) -> BackendFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let mut conn = conn.lock().unwrap();
+                f(&mut conn).map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+        })
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn name(&self) -> &'static str { "sqlite" }
+
+    fn execute(&self, stmt: Statement) -> BackendFuture<u64> {
+        self.run(move |conn| {
+            let n = conn.execute(&numbered(&stmt.sql), params(&stmt.params))?;
+            Ok(n as u64)
+        })
+    }
+
+    fn query(&self, stmt: Statement) -> BackendFuture<Vec<Value>> {
+        self.run(move |conn| {
+            let mut prepared = conn.prepare(&numbered(&stmt.sql))?;
+            let columns: Vec<String> = prepared.column_names().iter()
+                .map(|c| c.to_string())
+                .collect();
+
+            let rows = prepared.query_map(params(&stmt.params), |row| {
+                let mut obj = Map::new();
+                for (i, column) in columns.iter().enumerate() {
+                    obj.insert(column.clone(), to_json(row.get_ref(i)?));
+                }
+                Ok(Value::Object(obj))
+            })?;
+
+            rows.collect()
+        })
+    }
+
+    fn transaction(&self, stmts: Vec<Statement>) -> BackendFuture<Vec<u64>> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+
+            let mut affected = Vec::with_capacity(stmts.len());
+            for stmt in &stmts {
+                let sql = numbered(&stmt.sql);
+                let n = tx.execute(&sql, params(&stmt.params))?;
+                affected.push(n as u64);
+            }
+
+            tx.commit()?;
+            Ok(affected)
+        })
+    }
+
+    fn run_script(
+        &self,
+        script: String,
+        stmts: Vec<Statement>,
+    ) -> BackendFuture<()> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute_batch(&script)?;
+
+            for stmt in &stmts {
+                tx.execute(&numbered(&stmt.sql), params(&stmt.params))?;
+            }
+
+            tx.commit()
+        })
+    }
+}
+
+/// Postgres `$N` parameters as `?N`: SQLite takes `$N` for a name and
+/// numbers the parameters in the order of appearance.
+fn numbered(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut quoted = false;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            quoted = !quoted;
+        }
+
+        let param = c == '$'
+            && !quoted
+            && chars.peek().is_some_and(char::is_ascii_digit);
+        out.push(if param { '?' } else { c });
+    }
+
+    out
+}
+
+fn params(params: &[Param]) -> impl rusqlite::Params + '_ {
+    params_from_iter(params.iter().map(to_sql))
+}
+
+fn to_sql(param: &Param) -> SqlValue {
+    match param {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        v => SqlValue::Text(v.to_string()),
+    }
+}
+
+/// A blob is taken as text if possible, `null` otherwise.
+fn to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Value::from(f),
+        ValueRef::Text(t) | ValueRef::Blob(t) => {
+            std::str::from_utf8(t).map_or(Value::Null, Value::from)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[actix::test]
+    async fn execute_and_query() {
+        assert_eq!(SqliteBackend::path("sqlite::memory:"), Some(":memory:"));
+        assert!(SqliteBackend::path("postgres://localhost/db").is_none());
+        let sql = numbered("SET a = $2 WHERE b = '$1'");
+        assert_eq!(sql, "SET a = ?2 WHERE b = '$1'");
+
+        let db = SqliteBackend::open(":memory:").unwrap();
+        db.execute(Statement::new(
+            "CREATE TABLE t (id INTEGER, name TEXT, score REAL)",
+            vec![],
+        )).await.unwrap();
+
+        let affected = db.transaction(vec![
+            Statement::new(
+                "INSERT INTO t VALUES ($1, $2, $3)",
+                vec![json!(1), json!("a"), json!(0.5)],
+            ),
+            Statement::new(
+                "INSERT INTO t VALUES ($1, $2, $3)",
+                vec![json!(2), Value::Null, json!(1.5)],
+            ),
+        ]).await.unwrap();
+        assert_eq!(affected, vec![1, 1]);
+
+        let rows = db.query(Statement::new(
+            "SELECT id, name FROM t WHERE score > $1 ORDER BY id",
+            vec![json!(0)],
+        )).await.unwrap();
+        assert_eq!(rows, vec![
+            json!({ "id": 1, "name": "a" }),
+            json!({ "id": 2, "name": null }),
+        ]);
+
+        let failed = db.transaction(vec![
+            Statement::new("INSERT INTO t VALUES (3, 'c', 0)", vec![]),
+            Statement::new("INSERT INTO missing VALUES (1)", vec![]),
+        ]).await;
+        assert!(failed.is_err());
+
+        let count = db.query(Statement::new(
+            "SELECT count(*) AS n FROM t",
+            vec![],
+        )).await.unwrap();
+        assert_eq!(count, vec![json!({ "n": 2 })]);
+    }
+}
@@ -0,0 +1,21 @@
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Stop the time of the current runtime. Must be called from within it. It
+/// then jumps to the nearest timer once all the tasks are idle.
+pub fn pause() {
+    time::pause();
+}
+
+pub fn resume() {
+    time::resume();
+}
+
+/// Move the paused time forward firing the timers on the way.
+pub async fn advance(duration: Duration) {
+    time::advance(duration).await;
+}
+
+pub fn now() -> Instant {
+    Instant::now()
+}
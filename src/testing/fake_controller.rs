@@ -0,0 +1,225 @@
+use actix::prelude::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+use crate::worker::{
+    client::ReplyError,
+    controller::WorkerRequest,
+    worker_message::*,
+};
+
+type ResponseFn = Box<dyn FnMut(&WorkerMessage) -> Vec<serde_json::Value>>;
+
+/// Messages received by a `FakeController`. Shared with the test.
+pub type Received = Arc<Mutex<Vec<WorkerMessage>>>;
+
+/// Stands in for a `WorkerController` and its worker process. Every message
+/// sent to the worker is answered with the next scripted response or, when
+/// the script is exhausted, by the response function.
+///
+/// The responses are sent to the client of the task (or resolve the
+/// request) with the task UUID and correlation ID of the message they
+/// answer.
+pub struct FakeController {
+    /// Delay --> `WorkerMessagePayload::data`
+    script: VecDeque<(Duration, serde_json::Value)>,
+
+    respond_with: Option<ResponseFn>,
+
+    received: Received,
+
+    /// Task UUID --> Client
+    clients: HashMap<String, Recipient<WorkerMessage>>,
+
+    /// Correlation ID --> Sender
+    pending_replies: HashMap<String, oneshot::Sender<WorkerMessage>>,
+}
+
+impl FakeController {
+    pub fn new() -> Self {
+        Self {
+            script: VecDeque::new(),
+            respond_with: None,
+            received: Arc::new(Mutex::new(vec![])),
+            clients: HashMap::new(),
+            pending_replies: HashMap::new(),
+        }
+    }
+
+    /// Answer the next message with `data`, e.g.
+    /// `json!({ "task_result": { ... } })`.
+    pub fn respond(self, data: serde_json::Value) -> Self {
+        self.respond_after(Duration::ZERO, data)
+    }
+
+    /// Answer the next message with `data` after `delay`.
+    pub fn respond_after(
+        mut self,
+        delay: Duration,
+        data: serde_json::Value,
+    ) -> Self {
+        self.script.push_back((delay, data));
+        self
+    }
+
+    /// Answer the messages not covered by the script. Each returned value
+    /// is sent as a separate response.
+    pub fn respond_with<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&WorkerMessage) -> Vec<serde_json::Value> + 'static,
+    {
+        self.respond_with = Some(Box::new(f));
+        self
+    }
+
+    /// The messages sent to the worker so far.
+    pub fn received(&self) -> Received {
+        self.received.clone()
+    }
+
+    /// Returns the number of responses.
+    fn handle_worker_message(
+        &mut self,
+        msg: WorkerMessage,
+        ctx: &mut Context<Self>,
+    ) -> usize {
+        self.received.lock().unwrap().push(msg.clone());
+
+        let responses = match self.script.pop_front() {
+            Some(r) => vec![r],
+            None => match self.respond_with {
+                Some(ref mut f) => f(&msg).into_iter()
+                    .map(|data| (Duration::ZERO, data))
+                    .collect(),
+                None => vec![],
+            },
+        };
+
+        let count = responses.len();
+        for (delay, data) in responses {
+            let response = response(&msg, data);
+            if delay.is_zero() {
+                self.send_message_to_client(response);
+            } else {
+                ctx.run_later(delay, move |act, _| {
+                    act.send_message_to_client(response);
+                });
+            }
+        }
+
+        count
+    }
+
+    fn send_message_to_client(&mut self, msg: WorkerMessage) {
+        if let Some(tx) = self.pending_replies
+            .remove(&msg.payload.correlation_id)
+        {
+            let _ = tx.send(msg);
+            return;
+        }
+
+        if let Some(client) = self.clients.get(&msg.payload.task_uuid) {
+            client.do_send(msg);
+        }
+    }
+}
+
+impl Default for FakeController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn response(msg: &WorkerMessage, data: serde_json::Value) -> WorkerMessage {
+    let mut payload = WorkerMessagePayload::new();
+    payload.dest = Dest::Client;
+    payload.worker_id = "fake_worker".to_string();
+    payload.task_uuid = msg.payload.task_uuid.clone();
+    payload.plugin = msg.payload.plugin.clone();
+    payload.correlation_id = msg.payload.correlation_id.clone();
+    payload.data = data;
+
+    WorkerMessage::new(payload)
+}
+
+impl Actor for FakeController {
+    type Context = Context<Self>;
+}
+
+impl Handler<WorkerMessage> for FakeController {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: WorkerMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        match msg.payload.dest {
+            Dest::Worker => {
+                self.handle_worker_message(msg, ctx);
+            },
+            Dest::Client => self.send_message_to_client(msg),
+            _ => {},
+        }
+    }
+}
+
+impl Handler<WorkerRequest> for FakeController {
+    type Result = ResponseFuture<Result<WorkerMessage, ReplyError>>;
+
+    fn handle(
+        &mut self,
+        msg: WorkerRequest,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        let correlation_id = msg.msg.payload.correlation_id.clone();
+        let (tx, rx) = oneshot::channel();
+        self.pending_replies.insert(correlation_id.clone(), tx);
+
+        // Dropping the sender fails the request.
+        if self.handle_worker_message(msg.msg, ctx) == 0 {
+            self.pending_replies.remove(&correlation_id);
+        }
+
+        Box::pin(async move {
+            rx.await.map_err(|_| ReplyError::Canceled)
+        })
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct RegisterClient {
+    task_uuid: String,
+    client: Recipient<WorkerMessage>,
+}
+
+impl Handler<RegisterClient> for FakeController {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: RegisterClient,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.clients.insert(msg.task_uuid, msg.client);
+    }
+}
+
+/// Same as `controller::start_task`.
+pub fn start_task(
+    controller_addr: &Addr<FakeController>,
+    msg: WorkerMessage,
+    client: Recipient<WorkerMessage>,
+) {
+    controller_addr.do_send(RegisterClient {
+        task_uuid: msg.payload.task_uuid.clone(),
+        client,
+    });
+
+    controller_addr.do_send(msg);
+}
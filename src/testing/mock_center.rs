@@ -0,0 +1,201 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    center::message::*,
+    control::message::ControlMessage,
+    transport::router::CONTEXT,
+};
+
+/// How often the socket thread checks for the messages to send and whether
+/// to stop.
+const POLL_TIMEOUT_MS: i64 = 50;
+
+/// How often `wait_for` checks the received messages.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Stands in for the center: binds a ZMQ ROUTER socket the apps connect to
+/// (`center.address`), records the received messages and sends the
+/// injected ones to all the apps seen so far. Pings are answered.
+pub struct MockCenter {
+    address: String,
+    received: Arc<Mutex<Vec<CenterMessagePayload>>>,
+    outgoing: Sender<String>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockCenter {
+    /// E.g. "tcp://127.0.0.1:*" to bind to a random port. See `address`.
+    pub fn bind(address: &str) -> Result<Self, zmq::Error> {
+        let socket = CONTEXT.socket(zmq::ROUTER)?;
+        socket.bind(address)?;
+
+        let address = socket.get_last_endpoint()?
+            .unwrap_or_else(|_| address.to_string());
+
+        let received = Arc::new(Mutex::new(vec![]));
+        let running = Arc::new(AtomicBool::new(true));
+        let (outgoing, outgoing_rx) = mpsc::channel();
+
+        let thread = {
+            let received = received.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                run(socket, received, outgoing_rx, running)
+            })
+        };
+
+        Ok(Self {
+            address,
+            received,
+            outgoing,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// The bound address to be used as `center.address`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// All the messages received so far.
+    pub fn received(&self) -> Vec<CenterMessagePayload> {
+        self.received.lock().unwrap().clone()
+    }
+
+    pub fn received_subject(
+        &self,
+        subject: Subject,
+    ) -> Vec<CenterMessagePayload> {
+        self.received.lock().unwrap().iter()
+            .filter(|m| m.subject == subject)
+            .cloned()
+            .collect()
+    }
+
+    /// Wait for the first message matching `predicate` for up to `timeout`.
+    pub async fn wait_for<F>(
+        &self,
+        timeout: Duration,
+        predicate: F,
+    ) -> Option<CenterMessagePayload>
+    where
+        F: Fn(&CenterMessagePayload) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let found = self.received.lock().unwrap().iter()
+                .find(|m| predicate(m))
+                .cloned();
+
+            if found.is_some() || Instant::now() >= deadline {
+                return found;
+            }
+
+            actix::clock::sleep(WAIT_INTERVAL).await;
+        }
+    }
+
+    /// Send `msg` to the apps. It is delayed until an app is known, i.e.
+    /// has sent something.
+    pub fn send(&self, msg: CenterMessage) {
+        let body = serde_json::to_string(&msg.payload).unwrap();
+        let _ = self.outgoing.send(body);
+    }
+
+    pub fn send_control(&self, msg: ControlMessage) {
+        self.send(create(
+            Dest::App,
+            Subject::Control,
+            msg.dest_id.clone(),
+            msg.cmd.clone(),
+            msg,
+        ));
+    }
+}
+
+impl Drop for MockCenter {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+fn run(
+    socket: zmq::Socket,
+    received: Arc<Mutex<Vec<CenterMessagePayload>>>,
+    outgoing: Receiver<String>,
+    running: Arc<AtomicBool>,
+) {
+    // Identities of the connected apps.
+    let mut apps: Vec<Vec<u8>> = vec![];
+    let mut pending: Vec<String> = vec![];
+
+    while running.load(Ordering::Relaxed) {
+        let readable = match socket.poll(zmq::POLLIN, POLL_TIMEOUT_MS) {
+            Ok(n) => n > 0,
+            Err(_) => break,
+        };
+
+        if readable {
+            let parts = match socket.recv_multipart(0) {
+                Ok(p) => p,
+                Err(_) => break,
+            };
+
+            if let [identity, body] = parts.as_slice() {
+                if !apps.contains(identity) {
+                    apps.push(identity.clone());
+                }
+
+                let payload: Option<CenterMessagePayload> =
+                    std::str::from_utf8(body).ok()
+                        .and_then(|b| serde_json::from_str(b).ok());
+                if let Some(payload) = payload {
+                    if payload.subject == Subject::Ping {
+                        let pong = create_no_data(
+                            Dest::App,
+                            Subject::Pong,
+                            payload.entity_id.clone(),
+                            payload.message.clone(),
+                        );
+                        let pong = serde_json::to_string(&pong.payload)
+                            .unwrap();
+                        let _ = socket.send_multipart(
+                            [identity.as_slice(), pong.as_bytes()],
+                            0,
+                        );
+                    }
+
+                    received.lock().unwrap().push(payload);
+                }
+            }
+        }
+
+        pending.extend(outgoing.try_iter());
+        if apps.is_empty() {
+            continue;
+        }
+
+        for body in pending.drain(..) {
+            for identity in &apps {
+                let _ = socket.send_multipart(
+                    [identity.as_slice(), body.as_bytes()],
+                    0,
+                );
+            }
+        }
+    }
+}
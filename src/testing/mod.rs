@@ -0,0 +1,63 @@
+pub mod clock;
+pub mod fake_controller;
+pub mod mock_center;
+
+use actix::prelude::*;
+use std::time::Duration;
+
+use crate::worker::{
+    task::{ControllerAddr, TaskWrapper},
+    worker_message::WorkerMessage,
+};
+
+pub use fake_controller::FakeController;
+pub use mock_center::MockCenter;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often `run_task` checks whether the client has stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct TaskRun {
+    pub task_uuid: String,
+
+    /// The messages sent to the worker.
+    pub received: Vec<WorkerMessage>,
+
+    /// `false` if the client has not stopped in time.
+    pub finished: bool,
+}
+
+/// Execute `task` in the current arbiter with `controller` standing in for
+/// the worker and wait until the task client stops or `timeout` (of the
+/// `clock`) elapses.
+pub async fn run_task<T>(
+    task: &T,
+    controller: FakeController,
+    timeout: Duration,
+) -> TaskRun
+where
+    T: TaskWrapper + ?Sized,
+{
+    let received = controller.received();
+    let controller_addr = ControllerAddr::Fake(controller.start());
+    let ctx = task.execute_in_arbiter(&Arbiter::current(), controller_addr);
+
+    let deadline = clock::now() + timeout;
+    let mut finished = true;
+    while ctx.stop_task_addr.connected() {
+        if clock::now() >= deadline {
+            finished = false;
+            break;
+        }
+
+        actix::clock::sleep(POLL_INTERVAL).await;
+    }
+
+    let received = received.lock().unwrap().clone();
+    TaskRun {
+        task_uuid: ctx.task_uuid,
+        received,
+        finished,
+    }
+}
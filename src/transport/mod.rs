@@ -2,3 +2,4 @@ pub mod connector;
 pub mod message;
 pub mod router;
 pub mod router_registry;
+pub mod trace;
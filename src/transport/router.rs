@@ -10,10 +10,34 @@ use zmq;
 use crate::transport::{
     message::{Identity, RawMessage},
     router_registry::{self, *},
+    trace::{TracePoint, Tracer},
 };
 
 pub type RawMessageRecipient = Recipient<RawMessage>;
 
+/// How often the router loop checks whether it should stop if it has not
+/// been woken up. See `wake_up`.
+const POLL_TIMEOUT_MS: i64 = 100;
+
+/// The router listens on it to be woken up when it should stop.
+fn wake_up_address(backend_address: &str) -> String {
+    format!("inproc://wake_up_{}", backend_address)
+}
+
+/// Make the router with the BE `backend_address` check whether it should
+/// stop right away instead of on the poll timeout.
+pub fn wake_up(backend_address: &str) {
+    let socket = match CONTEXT.socket(zmq::PUSH) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+
+    let _ = socket.set_linger(100);
+    if socket.connect(&wake_up_address(backend_address)).is_ok() {
+        let _ = socket.send("", zmq::DONTWAIT);
+    }
+}
+
 lazy_static! {
     pub static ref CONTEXT: zmq::Context = zmq::Context::new();
 }
@@ -27,6 +51,12 @@ pub struct MessageRouter {
 
     /// If `true`, connect to the `frontend_address`. Otherwise, listen on it.
     active_mode: bool,
+
+    /// Whether the FE is connected. Tracked in the active mode only.
+    connected: Option<Arc<AtomicBool>>,
+
+    /// `router_trace.enabled`
+    tracer: Option<Tracer>,
 }
 
 impl MessageRouter {
@@ -38,14 +68,37 @@ impl MessageRouter {
         backend_address: String,
         active_mode: bool,
     ) {
-        let mut router = MessageRouter::new(
+        Self::start_router(MessageRouter::new(
             log,
             dispatcher_addr,
             frontend_address,
             backend_address,
             active_mode,
+        ));
+    }
+
+    /// Start an active router and keep `connected` up to date with the state
+    /// of the FE connection.
+    pub fn start_monitored(
+        log: Logger,
+        dispatcher_addr: Recipient<RawMessage>,
+        frontend_address: String,
+        backend_address: String,
+        connected: Arc<AtomicBool>,
+    ) {
+        let mut router = MessageRouter::new(
+            log,
+            dispatcher_addr,
+            frontend_address,
+            backend_address,
+            true,
         );
+        router.connected = Some(connected);
 
+        Self::start_router(router);
+    }
+
+    fn start_router(mut router: MessageRouter) {
         // Register `running` to make itself controllable from outside.
         let registry_addr = router_registry::start();
 
@@ -54,9 +107,16 @@ impl MessageRouter {
             control_link: RegistryValue::Running(router.running.clone()),
         });
 
-        thread::spawn(move || {
+        let address = router.backend_address.clone();
+        let handle = thread::spawn(move || {
             router.start_internal();
         });
+
+        // Joined on `StopAndJoin`.
+        registry_addr.do_send(RegisterRouterControlLinkMessage {
+            address,
+            control_link: RegistryValue::Thread(handle),
+        });
     }
 
     pub fn new(
@@ -66,6 +126,8 @@ impl MessageRouter {
         backend_address: String,
         active_mode: bool,
     ) -> Self {
+        let tracer = Tracer::load(&log, &backend_address);
+
         Self {
             log,
             dispatcher_addr,
@@ -73,6 +135,8 @@ impl MessageRouter {
             backend_address,
             running: Arc::new(AtomicBool::new(true)),
             active_mode,
+            connected: None,
+            tracer,
         }
     }
 
@@ -80,6 +144,10 @@ impl MessageRouter {
         let fe_type = if self.active_mode { zmq::DEALER } else { zmq::ROUTER };
         let frontend_socket = CONTEXT.socket(fe_type).unwrap();
         let backend_socket = CONTEXT.socket(zmq::ROUTER).unwrap();
+        let wake_up_socket = CONTEXT.socket(zmq::PULL).unwrap();
+
+        let monitor_socket = self.connected.as_ref()
+            .and_then(|_| self.monitor(&frontend_socket));
 
         if self.active_mode {
             match frontend_socket.connect(&self.frontend_address) {
@@ -118,21 +186,32 @@ impl MessageRouter {
         backend_socket.bind(&self.backend_address)
             .expect("Failed to bind router BE");
 
+        wake_up_socket.bind(&wake_up_address(&self.backend_address))
+            .expect("Failed to bind router wake up socket");
+
         info!(self.log, "Message Router started.");
 
         loop {
-            let mut items = [
+            let mut items = vec![
                 frontend_socket.as_poll_item(zmq::POLLIN),
                 backend_socket.as_poll_item(zmq::POLLIN),
+                wake_up_socket.as_poll_item(zmq::POLLIN),
             ];
+            if let Some(ref m) = monitor_socket {
+                items.push(m.as_poll_item(zmq::POLLIN));
+            }
 
-            let rc = zmq::poll(&mut items, -1).unwrap();
+            let rc = zmq::poll(&mut items, POLL_TIMEOUT_MS).unwrap();
 
-            if rc == -1 || !self.running.load(Ordering::Relaxed) {
+            if !self.running.load(Ordering::Relaxed) {
                 info!(self.log, "Exiting loop.");
                 break;
             }
 
+            if rc == 0 {
+                continue;
+            }
+
             if items[0].is_readable() {
                 // Active router has the FE of type DEALER.
                 // DEALER has no identity part.
@@ -163,8 +242,24 @@ impl MessageRouter {
                 if let Some(body) = body_msg.as_str() {
                     //debug!(self.log, "[FE] Body:\n\n'{}'\n", body);
 
-                    let msg = RawMessage::new(identity, body);
+                    let trace = self.tracer.as_mut()
+                        .and_then(|t| t.trace(body));
+                    let msg = match trace {
+                        Some((ref trace_id, ref stamped)) => {
+                            self.trace_point(trace_id, TracePoint::FeIn);
+                            RawMessage::new(
+                                identity,
+                                stamped.as_deref().unwrap_or(body),
+                            )
+                        },
+                        None => RawMessage::new(identity, body),
+                    };
+
                     self.dispatcher_addr.do_send(msg);
+
+                    if let Some((trace_id, _)) = trace {
+                        self.trace_point(&trace_id, TracePoint::BeOut);
+                    }
                 }
                 else {
                     assert!(false);
@@ -183,13 +278,24 @@ impl MessageRouter {
                 let identity = backend_socket.recv_msg(0).unwrap();
                 //trace!(self.log, "[BE] Identity: {:?}.", identity);
 
-                let body_msg = backend_socket.recv_msg(0).unwrap();
+                let mut body_msg = backend_socket.recv_msg(0).unwrap();
                 let more = body_msg.get_more();
                 if more {
                     warn!(self.log, "[BE] Expecting more data.");
                     assert!(false);
                 }
 
+                let trace = match (self.tracer.as_mut(), body_msg.as_str()) {
+                    (Some(t), Some(body)) => t.trace(body),
+                    _ => None,
+                };
+                if let Some((ref trace_id, ref stamped)) = trace {
+                    self.trace_point(trace_id, TracePoint::BeIn);
+                    if let Some(stamped) = stamped {
+                        body_msg = zmq::Message::from(stamped.as_bytes());
+                    }
+                }
+
                 /*if let Some(body) = body_msg.as_str() {
                     trace!(self.log, "[BE] Body:\n\n'{}'\n", body);
                 }*/
@@ -200,10 +306,98 @@ impl MessageRouter {
                 }
 
                 frontend_socket.send(body_msg, 0).unwrap();
+
+                if let Some((trace_id, _)) = trace {
+                    self.trace_point(&trace_id, TracePoint::FeOut);
+                }
+            }
+
+            if items[2].is_readable() {
+                let _ = wake_up_socket.recv_msg(0);
+            }
+
+            if items.get(3).is_some_and(|i| i.is_readable()) {
+                if let Some(ref m) = monitor_socket {
+                    self.handle_monitor_event(m);
+                }
             }
         }
 
+        // Close the sockets before the thread is reported as joined.
+        drop(monitor_socket);
+        drop(frontend_socket);
+        drop(backend_socket);
+        drop(wake_up_socket);
+
         info!(self.log, "Message Router stopped.");
     }
 }
 
+impl MessageRouter {
+    fn trace_point(&mut self, trace_id: &str, point: TracePoint) {
+        if let Some(ref mut t) = self.tracer {
+            t.point(trace_id, point);
+        }
+    }
+
+    /// Create a socket receiving the connection events of `socket`.
+    fn monitor(&self, socket: &zmq::Socket) -> Option<zmq::Socket> {
+        let endpoint = format!("inproc://monitor_{}", self.backend_address);
+        let events = zmq::SocketEvent::CONNECTED as i32
+            | zmq::SocketEvent::DISCONNECTED as i32;
+
+        let monitor = socket.monitor(&endpoint, events)
+            .and_then(|_| CONTEXT.socket(zmq::PAIR))
+            .and_then(|m| m.connect(&endpoint).map(|_| m));
+
+        match monitor {
+            Ok(m) => Some(m),
+            Err(e) => {
+                error!(
+                    self.log,
+                    "Failed to monitor [FRONTEND ADDRESS] {}: {}",
+                    self.frontend_address,
+                    e,
+                );
+                None
+            }
+        }
+    }
+
+    fn handle_monitor_event(&self, monitor: &zmq::Socket) {
+        // The event ID (u16) and value (u32), then the endpoint.
+        let parts = match monitor.recv_multipart(0) {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+
+        let event = match parts.first() {
+            Some(e) if e.len() >= 2 => u16::from_ne_bytes([e[0], e[1]]),
+            _ => return,
+        };
+
+        let connected = match zmq::SocketEvent::from_raw(event) {
+            zmq::SocketEvent::CONNECTED => true,
+            zmq::SocketEvent::DISCONNECTED => false,
+            _ => return,
+        };
+
+        if connected {
+            info!(
+                self.log,
+                "Connected to [FRONTEND ADDRESS] {}.",
+                self.frontend_address,
+            );
+        } else {
+            warn!(
+                self.log,
+                "Disconnected from [FRONTEND ADDRESS] {}.",
+                self.frontend_address,
+            );
+        }
+
+        if let Some(ref c) = self.connected {
+            c.store(connected, Ordering::Relaxed);
+        }
+    }
+}
@@ -2,13 +2,15 @@ use actix::prelude::*;
 use slog::Logger;
 use std::collections::HashMap;
 use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread::{self, JoinHandle};
+use tokio::sync::oneshot;
 
 use crate::{
-    core::logger::create_logger,
-    transport::{
-        message::RawMessage,
-        router::RawMessageRecipient,
+    core::{
+        logger::create_logger,
+        status_aggregator::{self, GetStatusSnapshot, StatusSnapshot},
     },
+    transport::router::{self, RawMessageRecipient},
 };
 
 type ArcAtomicBool = Arc<AtomicBool>;
@@ -19,6 +21,9 @@ pub enum RegistryValue {
 
     /// Connector to either the router's backend or frontend.
     Connector(RawMessageRecipient),
+
+    /// The router's thread.
+    Thread(JoinHandle<()>),
 }
 
 pub struct RegisterRouterControlLinkMessage {
@@ -37,6 +42,7 @@ pub struct RouterRegistry {
     log: Logger,
     running_map: HashMap<String, ArcAtomicBool>,
     connectors: HashMap<String, RawMessageRecipient>,
+    threads: HashMap<String, JoinHandle<()>>,
 }
 
 impl Default for RouterRegistry {
@@ -45,6 +51,7 @@ impl Default for RouterRegistry {
             log: create_logger("router_registry"),
             running_map: HashMap::new(),
             connectors: HashMap::new(),
+            threads: HashMap::new(),
         }
     }
 }
@@ -52,8 +59,10 @@ impl Default for RouterRegistry {
 impl Actor for RouterRegistry {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
+    fn started(&mut self, ctx: &mut Self::Context) {
         info!(self.log, "Router Registry started.");
+
+        status_aggregator::register("routers", ctx.address().recipient());
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -93,7 +102,10 @@ impl Handler<RegisterRouterControlLinkMessage> for RouterRegistry {
                     msg.address,
                 );
                 self.connectors.insert(msg.address, connector);
-            }
+            },
+            RegistryValue::Thread(handle) => {
+                self.threads.insert(msg.address, handle);
+            },
         }
     }
 }
@@ -114,26 +126,90 @@ impl Handler<StopRouterMessage> for RouterRegistry {
         msg: StopRouterMessage,
         _ctx: &mut Self::Context
     ) -> Self::Result {
-        if let Some(running) = self.running_map.get(&msg.address) {
-            info!(
-                self.log,
-                "Stopping [ROUTER ADDRESS] {}",
-                msg.address,
-            );
-            running.store(false, Ordering::Relaxed);
+        self.stop(&msg.address);
+    }
+}
+
+/// Stop the routers and wait for their threads to exit, i.e. the sockets to
+/// be closed. All the routers if `address` is `None`.
+pub struct StopAndJoin {
+    pub address: Option<String>,
+}
+
+impl Message for StopAndJoin {
+    type Result = ();
+}
+
+impl Handler<StopAndJoin> for RouterRegistry {
+    type Result = ResponseFuture<()>;
+
+    fn handle(
+        &mut self,
+        msg: StopAndJoin,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let addresses: Vec<String> = match msg.address {
+            Some(a) => vec![a],
+            None => self.running_map.keys().cloned().collect(),
+        };
+
+        let mut handles = vec![];
+        for address in addresses {
+            self.stop(&address);
+            if let Some(h) = self.threads.remove(&address) {
+                handles.push(h);
+            }
         }
-        if let Some(connector) = self.connectors.get(&msg.address) {
-            // Send a message to "wake up" the router.
-            info!(
-                self.log,
-                "Sending message to stop [ROUTER ADDRESS] {}",
-                msg.address,
-            );
-            connector.do_send(RawMessage::dummy());
+
+        // Do not block the arbiter while joining.
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            for h in handles {
+                let _ = h.join();
+            }
+            let _ = tx.send(());
+        });
+
+        Box::pin(async move {
+            let _ = rx.await;
+        })
+    }
+}
+
+impl RouterRegistry {
+    fn stop(&self, address: &str) {
+        if let Some(running) = self.running_map.get(address) {
+            info!(self.log, "Stopping [ROUTER ADDRESS] {}", address);
+            running.store(false, Ordering::Relaxed);
+            router::wake_up(address);
         }
     }
 }
 
+/// BE address --> Running
+impl Handler<GetStatusSnapshot> for RouterRegistry {
+    type Result = MessageResult<GetStatusSnapshot>;
+
+    fn handle(
+        &mut self,
+        _msg: GetStatusSnapshot,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let snapshot = self.running_map.iter()
+            .fold(StatusSnapshot::new(), |s, (address, running)| {
+                s.with(address, running.load(Ordering::Relaxed))
+            });
+
+        MessageResult(snapshot)
+    }
+}
+
+/// Stop all the routers and wait until their sockets are closed. To be
+/// awaited before the system is stopped.
+pub async fn stop_and_join_all() {
+    let _ = start().send(StopAndJoin { address: None }).await;
+}
+
 pub fn start() -> Addr<RouterRegistry> {
     RouterRegistry::from_registry()
 }
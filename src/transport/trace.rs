@@ -0,0 +1,198 @@
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::core::{
+    env::{self, PATOKA_ROOT_DIR},
+    timestamp::now_ms,
+};
+
+/// Traces not completed for that long are forgotten.
+const TRACE_TTL: Duration = Duration::from_secs(60);
+
+/// `[router_trace]` configuration section.
+#[derive(Deserialize)]
+struct TraceParams {
+    #[serde(default)]
+    enabled: bool,
+
+    /// Share of the messages to trace, 0..1.
+    #[serde(default = "default_sample_rate")]
+    sample_rate: f64,
+
+    /// Append the trace points to this file as JSON lines.
+    #[serde(default)]
+    export: Option<String>,
+}
+
+fn default_sample_rate() -> f64 { 0.01 }
+
+/// Where a message has been seen by a router.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TracePoint {
+    /// Received on the FE, e.g. from a worker.
+    FeIn,
+
+    /// Handed over to the dispatcher.
+    BeOut,
+
+    /// Received on the BE from a connector.
+    BeIn,
+
+    /// Sent on the FE.
+    FeOut,
+}
+
+#[derive(Serialize)]
+struct TraceRecord<'a> {
+    trace_id: &'a str,
+    router: &'a str,
+    point: TracePoint,
+    ts_ms: i64,
+
+    /// Since the previous point of the trace.
+    elapsed_us: u64,
+}
+
+/// Stamps a sample of the messages passing a router with `trace_id` and
+/// reports the time between the points they pass. A worker copying
+/// `trace_id` to its replies makes the time spent on the node side visible
+/// as well.
+pub struct Tracer {
+    log: Logger,
+    router: String,
+    sample_rate: f64,
+    export: Option<File>,
+
+    /// Trace ID --> Time of the last point
+    traces: HashMap<String, Instant>,
+}
+
+impl Tracer {
+    /// `None` unless `router_trace.enabled`.
+    pub fn load(log: &Logger, router: &str) -> Option<Self> {
+        let params: TraceParams = env::load_opt("router_trace")?;
+        if !params.enabled {
+            return None;
+        }
+
+        let export = params.export.and_then(|path| {
+            let path = env::full_path(
+                &path,
+                "$PATOKA_ROOT_DIR",
+                &PATOKA_ROOT_DIR,
+            );
+
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(f) => Some(f),
+                Err(e) => {
+                    error!(log, "Failed to open trace export {}: {}", path, e);
+                    None
+                },
+            }
+        });
+
+        Some(Self {
+            log: log.clone(),
+            router: router.to_string(),
+            sample_rate: params.sample_rate,
+            export,
+            traces: HashMap::new(),
+        })
+    }
+
+    /// The trace ID of `body` if it is traced. An untraced message is
+    /// sampled and, if chosen, stamped: the new body is returned as well.
+    pub fn trace(&mut self, body: &str) -> Option<(String, Option<String>)> {
+        // Avoid parsing the messages not traced.
+        if !body.contains("\"trace_id\"") {
+            if rand::random::<f64>() >= self.sample_rate {
+                return None;
+            }
+
+            let mut value: serde_json::Value =
+                serde_json::from_str(body).ok()?;
+            let trace_id = Uuid::new_v4().to_string();
+            value.as_object_mut()?
+                .insert("trace_id".to_string(), trace_id.clone().into());
+
+            return Some((trace_id, Some(value.to_string())));
+        }
+
+        let value: serde_json::Value = serde_json::from_str(body).ok()?;
+        let trace_id = value.get("trace_id")?.as_str()?.to_string();
+        Some((trace_id, None))
+    }
+
+    /// The message `trace_id` has passed `point`.
+    pub fn point(&mut self, trace_id: &str, point: TracePoint) {
+        let now = Instant::now();
+        let elapsed = self.traces.insert(trace_id.to_string(), now)
+            .map(|prev| now - prev)
+            .unwrap_or_default();
+
+        let record = TraceRecord {
+            trace_id,
+            router: &self.router,
+            point,
+            ts_ms: now_ms(),
+            elapsed_us: elapsed.as_micros() as u64,
+        };
+
+        debug!(
+            self.log,
+            "[TRACE] {} [POINT] {:?} [ELAPSED] {} us",
+            trace_id,
+            point,
+            record.elapsed_us,
+        );
+
+        if let Some(ref mut f) = self.export {
+            let line = serde_json::to_string(&record).unwrap();
+            if let Err(e) = writeln!(f, "{}", line) {
+                warn!(self.log, "Failed to export a trace: {}", e);
+            }
+        }
+
+        if matches!(point, TracePoint::FeOut | TracePoint::BeOut) {
+            self.traces.retain(|_, t| t.elapsed() < TRACE_TTL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::logger::create_logger;
+
+    fn tracer(sample_rate: f64) -> Tracer {
+        Tracer {
+            log: create_logger("trace_test"),
+            router: "inproc://test".to_string(),
+            sample_rate,
+            export: None,
+            traces: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn stamp_and_reuse_trace_id() {
+        let mut t = tracer(1.0);
+        let (trace_id, stamped) = t.trace(r#"{"dest":"worker"}"#).unwrap();
+        let stamped = stamped.unwrap();
+        assert!(stamped.contains(&trace_id));
+
+        let (same_id, restamped) = t.trace(&stamped).unwrap();
+        assert_eq!(same_id, trace_id);
+        assert!(restamped.is_none());
+
+        assert!(tracer(0.0).trace(r#"{"dest":"worker"}"#).is_none());
+    }
+}
@@ -0,0 +1,59 @@
+use serde_json::Value;
+
+/// Apply a JSON merge patch (RFC 7396): the members of the patch object
+/// replace those of `target`, `null` removes the member, and any other
+/// patch replaces `target` as a whole.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        },
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            let member = target.entry(key.as_str()).or_insert(Value::Null);
+            merge_patch(member, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge() {
+        let mut target = json!({
+            "url": "http://a",
+            "auth": { "user": "u", "password": "p" },
+            "depth": 2,
+        });
+
+        merge_patch(&mut target, &json!({
+            "url": "http://b",
+            "auth": { "password": "q" },
+            "depth": null,
+            "tags": ["x"],
+        }));
+
+        assert_eq!(target, json!({
+            "url": "http://b",
+            "auth": { "user": "u", "password": "q" },
+            "tags": ["x"],
+        }));
+
+        merge_patch(&mut target, &json!([1]));
+        assert_eq!(target, json!([1]));
+    }
+}
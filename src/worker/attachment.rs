@@ -0,0 +1,426 @@
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    core::env::{self, PATOKA_ROOT_DIR},
+    worker::worker_message::WorkerMessage,
+};
+
+lazy_static! {
+    static ref PARAMS: AttachmentParams =
+        env::load_opt("attachments").unwrap_or_default();
+
+    /// (Task UUID, Attachment ID) --> Attachment being streamed
+    static ref PARTIALS: Mutex<HashMap<(String, String), Partial>> =
+        Mutex::new(HashMap::new());
+}
+
+/// `[attachments]` configuration section.
+#[derive(Deserialize)]
+struct AttachmentParams {
+    #[serde(default = "default_dir")]
+    dir: String,
+
+    /// A streamed attachment growing larger is dropped. 0 for no limit.
+    #[serde(default = "default_max_size_mb")]
+    max_size_mb: u64,
+
+    /// Keep the files of the closed tasks.
+    #[serde(default)]
+    keep: bool,
+
+    #[serde(default = "default_capture_on_error")]
+    capture_on_error: bool,
+
+    /// The failed task is stopped once captured or after that long.
+    #[serde(default = "default_capture_timeout_s")]
+    capture_timeout_s: u64,
+}
+
+fn default_dir() -> String { "data/attachments".to_string() }
+
+fn default_max_size_mb() -> u64 { 100 }
+
+fn default_capture_on_error() -> bool { true }
+
+fn default_capture_timeout_s() -> u64 { 10 }
+
+impl Default for AttachmentParams {
+    fn default() -> Self {
+        Self {
+            dir: default_dir(),
+            max_size_mb: default_max_size_mb(),
+            keep: false,
+            capture_on_error: default_capture_on_error(),
+            capture_timeout_s: default_capture_timeout_s(),
+        }
+    }
+}
+
+fn dir() -> PathBuf {
+    PathBuf::from(env::full_path(
+        &PARAMS.dir,
+        "$PATOKA_ROOT_DIR",
+        &PATOKA_ROOT_DIR,
+    ))
+}
+
+/// `capture_on_error`
+pub fn capture_on_error() -> bool {
+    PARAMS.capture_on_error
+}
+
+/// `capture_timeout_s`
+pub fn capture_timeout() -> Duration {
+    Duration::from_secs(PARAMS.capture_timeout_s)
+}
+
+/// Where the worker writes the attachments of the task.
+pub fn task_dir(task_uuid: &str) -> PathBuf {
+    dir().join(file_name(task_uuid))
+}
+
+fn file_name(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// A file the worker has written under `task_dir` or streamed in
+/// `attachment_chunk` messages, checked against `sha256` if set.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Attachment {
+    /// The name unless given.
+    #[serde(default)]
+    pub id: String,
+
+    pub name: String,
+
+    #[serde(default)]
+    pub content_type: String,
+
+    /// Relative to `task_dir` as sent by the worker, absolute as received
+    /// by the client.
+    pub path: String,
+
+    #[serde(default)]
+    pub size: u64,
+
+    #[serde(default)]
+    pub sha256: String,
+}
+
+impl Attachment {
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+
+    /// The file still has `sha256`.
+    pub fn verify(&self) -> io::Result<bool> {
+        Ok(hash_file(Path::new(&self.path))?.1 == self.sha256)
+    }
+}
+
+impl WorkerMessage {
+    pub fn attachment(&self) -> Option<Attachment> {
+        self.payload.data.get("attachment")
+            .and_then(|a| serde_json::from_value(a.clone()).ok())
+    }
+}
+
+#[derive(Deserialize)]
+struct AttachmentChunk {
+    id: String,
+
+    #[serde(default)]
+    name: String,
+
+    #[serde(default)]
+    content_type: String,
+
+    seq: u64,
+    data: String,
+
+    #[serde(default)]
+    last: bool,
+
+    #[serde(default)]
+    sha256: String,
+}
+
+struct Partial {
+    attachment: Attachment,
+    file: File,
+    hasher: Sha256,
+    next_seq: u64,
+}
+
+pub fn is_attachment(msg: &WorkerMessage) -> bool {
+    let data = &msg.payload.data;
+    data.get("attachment").is_some() || data.get("attachment_chunk").is_some()
+}
+
+/// Turn `msg` into the `attachment` message to pass to the client. False
+/// if the attachment is not complete yet.
+pub fn receive(msg: &mut WorkerMessage) -> Result<bool, String> {
+    let task_uuid = msg.payload.task_uuid.clone();
+
+    let attachment = match msg.payload.data.get("attachment_chunk") {
+        Some(c) => {
+            let chunk = serde_json::from_value(c.clone())
+                .map_err(|e| format!("Invalid attachment chunk: {}", e))?;
+
+            match receive_chunk(&task_uuid, chunk)? {
+                Some(a) => a,
+                None => return Ok(false),
+            }
+        },
+        None => {
+            let attachment = msg.attachment()
+                .ok_or_else(|| "Invalid attachment".to_string())?;
+            receive_file(&task_uuid, attachment)?
+        },
+    };
+
+    msg.payload.data = serde_json::json!({ "attachment": attachment });
+    Ok(true)
+}
+
+fn receive_file(
+    task_uuid: &str,
+    mut attachment: Attachment,
+) -> Result<Attachment, String> {
+    let task_dir = task_dir(task_uuid);
+    let path = task_dir.join(&attachment.path).canonicalize()
+        .map_err(|e| format!("Attachment {}: {}", attachment.path, e))?;
+
+    let task_dir = task_dir.canonicalize().map_err(|e| e.to_string())?;
+    if !path.starts_with(&task_dir) {
+        return Err(format!(
+            "Attachment {} is outside of {}",
+            attachment.path,
+            task_dir.display(),
+        ));
+    }
+
+    let (size, sha256) = hash_file(&path).map_err(|e| e.to_string())?;
+    check_hash(&attachment.name, &attachment.sha256, &sha256)?;
+
+    if attachment.id.is_empty() {
+        attachment.id = attachment.name.clone();
+    }
+    attachment.path = path.to_string_lossy().to_string();
+    attachment.size = size;
+    attachment.sha256 = sha256;
+
+    Ok(attachment)
+}
+
+fn receive_chunk(
+    task_uuid: &str,
+    chunk: AttachmentChunk,
+) -> Result<Option<Attachment>, String> {
+    let key = (task_uuid.to_string(), chunk.id.clone());
+    let mut partials = PARTIALS.lock().unwrap();
+
+    if chunk.seq == 0 {
+        partials.insert(key.clone(), create_partial(task_uuid, &chunk)?);
+    }
+
+    let partial = partials.get_mut(&key).ok_or_else(|| format!(
+        "Attachment {} chunk {} without chunk 0",
+        chunk.id,
+        chunk.seq,
+    ))?;
+
+    if let Err(e) = write_chunk(partial, &chunk) {
+        if let Some(p) = partials.remove(&key) {
+            let _ = fs::remove_file(&p.attachment.path);
+        }
+        return Err(e);
+    }
+
+    if !chunk.last {
+        return Ok(None);
+    }
+
+    let p = partials.remove(&key).unwrap();
+    let mut attachment = p.attachment;
+    attachment.sha256 = hex(&p.hasher.finalize());
+
+    let checked = check_hash(&chunk.id, &chunk.sha256, &attachment.sha256);
+    if let Err(e) = checked {
+        let _ = fs::remove_file(&attachment.path);
+        return Err(e);
+    }
+
+    Ok(Some(attachment))
+}
+
+fn create_partial(
+    task_uuid: &str,
+    chunk: &AttachmentChunk,
+) -> Result<Partial, String> {
+    let task_dir = task_dir(task_uuid);
+    fs::create_dir_all(&task_dir).map_err(|e| e.to_string())?;
+
+    let name = if chunk.name.is_empty() { &chunk.id } else { &chunk.name };
+    let path = task_dir.join(file_name(name));
+    let file = File::create(&path).map_err(|e| e.to_string())?;
+
+    Ok(Partial {
+        attachment: Attachment {
+            id: chunk.id.clone(),
+            name: name.clone(),
+            content_type: chunk.content_type.clone(),
+            path: path.to_string_lossy().to_string(),
+            size: 0,
+            sha256: String::new(),
+        },
+        file,
+        hasher: Sha256::new(),
+        next_seq: 0,
+    })
+}
+
+fn write_chunk(p: &mut Partial, chunk: &AttachmentChunk) -> Result<(), String> {
+    if chunk.seq != p.next_seq {
+        return Err(format!(
+            "Attachment {} chunk {} instead of {}",
+            chunk.id,
+            chunk.seq,
+            p.next_seq,
+        ));
+    }
+
+    let data = decode_base64(&chunk.data)?;
+    p.attachment.size += data.len() as u64;
+
+    let max_size = PARAMS.max_size_mb * 1024 * 1024;
+    if max_size > 0 && p.attachment.size > max_size {
+        return Err(format!(
+            "Attachment {} exceeds {} MB",
+            chunk.id,
+            PARAMS.max_size_mb,
+        ));
+    }
+
+    p.file.write_all(&data).map_err(|e| e.to_string())?;
+    p.hasher.update(&data);
+    p.next_seq += 1;
+
+    Ok(())
+}
+
+/// Any hash matches if none is expected.
+fn check_hash(name: &str, expected: &str, actual: &str) -> Result<(), String> {
+    if !expected.is_empty() && !expected.eq_ignore_ascii_case(actual) {
+        return Err(format!("Attachment {} hash mismatch", name));
+    }
+
+    Ok(())
+}
+
+/// Size, SHA-256 (hex).
+fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+
+    Ok((size, hex(&hasher.finalize())))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_base64(s: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+
+    for c in s.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' | b'\n' | b'\r' => continue,
+            _ => return Err(format!("Invalid base64 character {:?}", c)),
+        };
+
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Move the attachment out of `task_dir`, so that it outlives the task.
+pub fn persist(
+    task_uuid: &str,
+    attachment: &Attachment,
+) -> io::Result<Attachment> {
+    let dir = dir().join("errors").join(file_name(task_uuid));
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(file_name(&attachment.name));
+    fs::rename(&attachment.path, &path)?;
+
+    Ok(Attachment {
+        path: path.to_string_lossy().to_string(),
+        ..attachment.clone()
+    })
+}
+
+/// Remove the attachments of the closed task, unless `keep`.
+pub fn cleanup(task_uuid: &str) {
+    PARTIALS.lock().unwrap().retain(|(t, _), _| t != task_uuid);
+
+    if !PARAMS.keep {
+        let _ = fs::remove_dir_all(task_dir(task_uuid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64("cGF0b2th").unwrap(), b"patoka");
+        assert_eq!(decode_base64("cGF0b2s=").unwrap(), b"patok");
+        assert_eq!(decode_base64("cGE=\n").unwrap(), b"pa");
+        assert!(decode_base64("cG*=").is_err());
+    }
+}
@@ -1,20 +1,140 @@
-
 use actix::prelude::*;
-use crate::transport::connector::*;
+use serde_derive::Deserialize;
+use slog::Logger;
+
+use crate::{
+    core::logger::create_logger,
+    transport::{
+        message::RawMessage,
+        router::CONTEXT,
+        router_registry::{self, *},
+    },
+    worker::{
+        node_registry,
+        router::{self, WorkerRouter},
+    },
+};
 
-pub struct WorkerBackendConnectorParameters;
+/// Sends the messages to the workers through the router serving their
+/// controller. See `router::for_controller`.
+pub struct WorkerBackendConnector {
+    log: Logger,
+
+    /// Connected to the BE of the respective routers.
+    sockets: Vec<(&'static WorkerRouter, zmq::Socket)>,
+}
 
-impl ConnectorParameters for WorkerBackendConnectorParameters {
-    fn name() -> &'static str {
-        "worker_backend_connector"
+impl Default for WorkerBackendConnector {
+    fn default() -> Self {
+        let sockets = router::routers().iter()
+            .map(|r| (r, CONTEXT.socket(zmq::DEALER).unwrap()))
+            .collect();
+
+        Self {
+            log: create_logger("worker_backend_connector"),
+            sockets,
+        }
     }
+}
+
+impl Actor for WorkerBackendConnector {
+    type Context = Context<Self>;
 
-    fn router() -> &'static str {
-        "inproc://router"
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Started.");
+
+        let registry_addr = router_registry::start();
+
+        for (router, socket) in &self.sockets {
+            // Register itself to be used to control the router.
+            registry_addr.do_send(RegisterRouterControlLinkMessage {
+                address: router.backend.clone(),
+                control_link: RegistryValue::Connector(
+                    ctx.address().recipient()
+                ),
+            });
+
+            match socket.connect(&router.backend) {
+                Ok(_) => {
+                    info!(
+                        self.log,
+                        "Connected to [ROUTER ADDRESS] {}.",
+                        router.backend,
+                    );
+                },
+                Err(_) => {
+                    error!(
+                        self.log,
+                        "Failed to connect to [ROUTER ADDRESS] {}.",
+                        router.backend,
+                    );
+                }
+            }
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Stopped.");
     }
 }
 
-pub type WorkerBackendConnector = Connector<WorkerBackendConnectorParameters>;
+impl Supervised for WorkerBackendConnector {}
+
+impl SystemService for WorkerBackendConnector {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "System service started.")
+    }
+}
+
+#[derive(Deserialize)]
+struct WorkerIdOnly {
+    #[serde(default)]
+    dest: String,
+
+    #[serde(default)]
+    worker_id: String,
+}
+
+impl Handler<RawMessage> for WorkerBackendConnector {
+    type Result = ();
+
+    /// Sends `msg` to the router serving the controller `worker_id` or the
+    /// node.
+    fn handle(
+        &mut self,
+        msg: RawMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let ids = serde_json::from_str::<WorkerIdOnly>(&msg.body)
+            .unwrap_or(WorkerIdOnly {
+                dest: String::new(),
+                worker_id: String::new(),
+            });
+
+        let router = if ids.dest == "node" {
+            node_registry::router_for_node(&ids.worker_id)
+                .and_then(router::by_name)
+        } else {
+            router::for_controller(&ids.worker_id)
+        };
+
+        let router = match router {
+            Some(r) => r,
+            None => {
+                warn!(self.log, "No worker router configured.");
+                return;
+            },
+        };
+
+        let socket = self.sockets.iter()
+            .find(|(r, _)| r.name == router.name)
+            .map(|(_, s)| s);
+        if let Some(socket) = socket {
+            socket.send(msg.identity, zmq::SNDMORE).unwrap();
+            socket.send(msg.body.as_bytes(), 0).unwrap();
+        }
+    }
+}
 
 pub fn start() -> Addr<WorkerBackendConnector>
 {
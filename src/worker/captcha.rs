@@ -0,0 +1,393 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    control::{message::ControlMessage, registry},
+    core::env,
+    worker::{question::ANSWER_CMD, worker_message::WorkerMessage},
+};
+
+/// `ControlMessage::orig_id` of the answers.
+const ORIG_ID: &str = "captcha_solver";
+
+lazy_static! {
+    static ref PARAMS: CaptchaParams =
+        env::load_opt("captcha").unwrap_or_default();
+
+    static ref SOLVER: RwLock<Option<Arc<dyn CaptchaSolver>>> =
+        RwLock::new(default_solver());
+}
+
+/// A `task_question` of type "captcha", answered by the solver as a
+/// `task_answer` control request, or passed to the client if it fails.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Challenge {
+    /// Text on an image, base64 encoded.
+    Image { image: String },
+
+    Recaptcha { site_key: String, page_url: String },
+
+    Hcaptcha { site_key: String, page_url: String },
+}
+
+pub type SolveFuture = Pin<Box<dyn Future<Output = Result<String, String>>>>;
+
+/// Turns a challenge into the token (or the text) to answer it with.
+pub trait CaptchaSolver: Send + Sync {
+    /// Polled in the arbiter of the controller.
+    fn solve(&self, challenge: Challenge) -> SolveFuture;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SolverApi {
+    #[default]
+    TwoCaptcha,
+    AntiCaptcha,
+}
+
+/// `[captcha]` configuration section.
+#[derive(Clone, Default, Deserialize)]
+struct CaptchaParams {
+    #[serde(default)]
+    enabled: bool,
+
+    #[serde(default)]
+    api: SolverApi,
+
+    /// E.g. "https://2captcha.com" or "https://api.anti-captcha.com".
+    #[serde(default)]
+    url: String,
+
+    #[serde(default)]
+    key: String,
+
+    #[serde(default = "default_poll_interval_s")]
+    poll_interval_s: u64,
+
+    #[serde(default = "default_timeout_s")]
+    timeout_s: u64,
+}
+
+fn default_poll_interval_s() -> u64 { 5 }
+
+fn default_timeout_s() -> u64 { 180 }
+
+fn default_solver() -> Option<Arc<dyn CaptchaSolver>> {
+    if PARAMS.enabled {
+        Some(Arc::new(HttpSolver { params: PARAMS.clone() }))
+    } else {
+        None
+    }
+}
+
+/// Replace the solver, `None` to pass the CAPTCHAs to the clients.
+pub fn set_solver(solver: Option<Arc<dyn CaptchaSolver>>) {
+    *SOLVER.write().unwrap() = solver;
+}
+
+pub fn solver() -> Option<Arc<dyn CaptchaSolver>> {
+    SOLVER.read().unwrap().clone()
+}
+
+/// The challenge of a CAPTCHA question of the worker.
+pub fn challenge_of(msg: &WorkerMessage) -> Option<Challenge> {
+    msg.question()
+        .filter(|q| q.get("type").and_then(|t| t.as_str()) == Some("captcha"))
+        .and_then(|q| serde_json::from_value(q).ok())
+}
+
+/// The `task_answer` request with `token` to the question of `msg`.
+pub fn answer(msg: &WorkerMessage, token: String) -> ControlMessage {
+    let mut data = json!({ "type": "captcha", "token": token });
+    let question_id = msg.question()
+        .and_then(|q| q.get("question_id").cloned());
+    if let Some(id) = question_id {
+        data["question_id"] = id;
+    }
+
+    ControlMessage::request_with_data(
+        &msg.payload.task_uuid,
+        ORIG_ID,
+        ANSWER_CMD,
+        data,
+    )
+}
+
+/// Send the answer to the task.
+pub fn send_answer(msg: &WorkerMessage, token: String) {
+    registry::send(answer(msg, token));
+}
+
+/// The built-in solver of the `[captcha]` section.
+struct HttpSolver {
+    params: CaptchaParams,
+}
+
+impl CaptchaSolver for HttpSolver {
+    fn solve(&self, challenge: Challenge) -> SolveFuture {
+        let params = self.params.clone();
+        Box::pin(async move {
+            let client = awc::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .finish();
+
+            match params.api {
+                SolverApi::TwoCaptcha => {
+                    solve_two_captcha(&client, &params, challenge).await
+                },
+                SolverApi::AntiCaptcha => {
+                    solve_anti_captcha(&client, &params, challenge).await
+                },
+            }
+        })
+    }
+}
+
+/// The form fields of `in.php`.
+fn two_captcha_form(key: &str, challenge: Challenge) -> Vec<(String, String)> {
+    let mut form = vec![
+        ("key".to_string(), key.to_string()),
+        ("json".to_string(), "1".to_string()),
+    ];
+
+    let fields = match challenge {
+        Challenge::Image { image } => {
+            vec![("method", "base64".to_string()), ("body", image)]
+        },
+        Challenge::Recaptcha { site_key, page_url } => vec![
+            ("method", "userrecaptcha".to_string()),
+            ("googlekey", site_key),
+            ("pageurl", page_url),
+        ],
+        Challenge::Hcaptcha { site_key, page_url } => vec![
+            ("method", "hcaptcha".to_string()),
+            ("sitekey", site_key),
+            ("pageurl", page_url),
+        ],
+    };
+
+    form.extend(fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+    form
+}
+
+/// `{ "status": 1, "request": <ID or token> }`
+#[derive(Deserialize)]
+struct TwoCaptchaResponse {
+    status: u8,
+    request: String,
+}
+
+async fn solve_two_captcha(
+    client: &awc::Client,
+    params: &CaptchaParams,
+    challenge: Challenge,
+) -> Result<String, String> {
+    let url = params.url.trim_end_matches('/');
+    let form = two_captcha_form(&params.key, challenge);
+
+    let created: TwoCaptchaResponse = client.post(format!("{}/in.php", url))
+        .send_form(&form)
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+    if created.status != 1 {
+        return Err(created.request);
+    }
+
+    let result_url = format!(
+        "{}/res.php?key={}&action=get&json=1&id={}",
+        url,
+        params.key,
+        created.request,
+    );
+
+    poll(params, || async {
+        let r: TwoCaptchaResponse = client.get(&result_url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match (r.status, r.request.as_str()) {
+            (1, _) => Ok(Some(r.request)),
+            (_, "CAPCHA_NOT_READY") => Ok(None),
+            _ => Err(r.request),
+        }
+    }).await
+}
+
+/// The `task` of `createTask`.
+fn anti_captcha_task(challenge: Challenge) -> Value {
+    match challenge {
+        Challenge::Image { image } => {
+            json!({ "type": "ImageToTextTask", "body": image })
+        },
+        Challenge::Recaptcha { site_key, page_url } => json!({
+            "type": "RecaptchaV2TaskProxyless",
+            "websiteKey": site_key,
+            "websiteURL": page_url,
+        }),
+        Challenge::Hcaptcha { site_key, page_url } => json!({
+            "type": "HCaptchaTaskProxyless",
+            "websiteKey": site_key,
+            "websiteURL": page_url,
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AntiCaptchaResponse {
+    #[serde(default)]
+    error_id: u32,
+
+    #[serde(default)]
+    error_description: Option<String>,
+
+    #[serde(default)]
+    task_id: Option<u64>,
+
+    #[serde(default)]
+    status: Option<String>,
+
+    #[serde(default)]
+    solution: Option<Value>,
+}
+
+impl AntiCaptchaResponse {
+    fn error(&self) -> Option<String> {
+        (self.error_id != 0).then(|| {
+            self.error_description.clone()
+                .unwrap_or_else(|| format!("Error {}", self.error_id))
+        })
+    }
+
+    /// `text` of an image, `gRecaptchaResponse` of the others.
+    fn token(&self) -> Option<String> {
+        let solution = self.solution.as_ref()?;
+        solution.get("gRecaptchaResponse")
+            .or_else(|| solution.get("text"))
+            .and_then(|t| t.as_str())
+            .map(String::from)
+    }
+}
+
+async fn solve_anti_captcha(
+    client: &awc::Client,
+    params: &CaptchaParams,
+    challenge: Challenge,
+) -> Result<String, String> {
+    let url = params.url.trim_end_matches('/');
+    let request = json!({
+        "clientKey": params.key,
+        "task": anti_captcha_task(challenge),
+    });
+
+    let created: AntiCaptchaResponse = client
+        .post(format!("{}/createTask", url))
+        .send_json(&request)
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(e) = created.error() {
+        return Err(e);
+    }
+
+    let task_id = created.task_id.ok_or("No taskId")?;
+    let request = json!({ "clientKey": params.key, "taskId": task_id });
+    let result_url = format!("{}/getTaskResult", url);
+
+    poll(params, || async {
+        let r: AntiCaptchaResponse = client.post(&result_url)
+            .send_json(&request)
+            .await
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let Some(e) = r.error() {
+            return Err(e);
+        }
+
+        match r.status.as_deref() {
+            Some("ready") => r.token().ok_or("No solution".into()).map(Some),
+            _ => Ok(None),
+        }
+    }).await
+}
+
+/// Call `check` every `poll_interval_s` until it has the token or
+/// `timeout_s` has passed.
+async fn poll<F, Fut>(
+    params: &CaptchaParams,
+    check: F,
+) -> Result<String, String>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Option<String>, String>>,
+{
+    let started = Instant::now();
+    let interval = Duration::from_secs(params.poll_interval_s.max(1));
+
+    while started.elapsed() < Duration::from_secs(params.timeout_s) {
+        actix::clock::sleep(interval).await;
+        if let Some(token) = check().await? {
+            return Ok(token);
+        }
+    }
+
+    Err(format!("Not solved in {} s", params.timeout_s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::worker_message::{Dest, WorkerMessagePayload};
+
+    #[test]
+    fn captcha_question() {
+        let mut payload = WorkerMessagePayload::new();
+        payload.dest = Dest::Client;
+        payload.task_uuid = "t1".to_string();
+        payload.data = json!({
+            "task_question": {
+                "type": "captcha",
+                "kind": "recaptcha",
+                "site_key": "k",
+                "page_url": "https://a.example",
+                "question_id": "q1",
+            }
+        });
+        let msg = WorkerMessage::new(payload);
+
+        let challenge = challenge_of(&msg).unwrap();
+        assert_eq!(challenge, Challenge::Recaptcha {
+            site_key: "k".into(),
+            page_url: "https://a.example".into(),
+        });
+
+        let form = two_captcha_form("key", challenge);
+        assert!(form.contains(&("googlekey".into(), "k".into())));
+
+        let answer = answer(&msg, "token".into());
+        assert_eq!(answer.dest_id, "t1");
+        assert_eq!(answer.cmd, ANSWER_CMD);
+        assert_eq!(answer.data["question_id"], "q1");
+    }
+}
@@ -0,0 +1,129 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    core::{
+        env,
+        error_bus::{self, PatokaError},
+    },
+    worker::task::TaskStatus,
+};
+
+lazy_static! {
+    static ref PARAMS: CircuitBreakerParams =
+        env::load_opt("circuit_breaker").unwrap_or_default();
+
+    /// Task Name --> Circuit
+    static ref CIRCUITS: Mutex<HashMap<String, Circuit>> =
+        Mutex::new(HashMap::new());
+}
+
+/// `[circuit_breaker]` configuration section. The tasks of an open circuit
+/// are neither started nor restarted until `cool_down_s` has passed.
+#[derive(Deserialize)]
+struct CircuitBreakerParams {
+    /// 0 disables the circuits.
+    #[serde(default = "default_failures")]
+    failures: usize,
+
+    #[serde(default = "default_cool_down_s")]
+    cool_down_s: u64,
+}
+
+fn default_failures() -> usize { 5 }
+
+fn default_cool_down_s() -> u64 { 300 }
+
+impl Default for CircuitBreakerParams {
+    fn default() -> Self {
+        Self {
+            failures: default_failures(),
+            cool_down_s: default_cool_down_s(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Circuit {
+    /// In a row.
+    failures: usize,
+
+    open_until: Option<Instant>,
+}
+
+/// Count the finished task of `name`. Opening the circuit is reported to
+/// the center.
+pub fn record(name: &str, status: TaskStatus) {
+    if PARAMS.failures == 0 {
+        return;
+    }
+
+    let mut circuits = CIRCUITS.lock().unwrap();
+    match status {
+        TaskStatus::FinishedSuccess => {
+            circuits.remove(name);
+        },
+        TaskStatus::FinishedFailure => {
+            let circuit = circuits.entry(name.to_string()).or_default();
+            circuit.failures += 1;
+
+            if circuit.failures < PARAMS.failures
+                || circuit.open_until.is_some()
+            {
+                return;
+            }
+
+            let reason = format!("{} failures in a row", circuit.failures);
+            open(circuit, name, &reason);
+        },
+        _ => {},
+    }
+}
+
+/// Open the circuit of `name` for `cool_down_s` unless open, e.g. on the
+/// failure rate of `task_stats`.
+pub fn trip(name: &str, reason: &str) {
+    let mut circuits = CIRCUITS.lock().unwrap();
+    let circuit = circuits.entry(name.to_string()).or_default();
+    if circuit.open_until.is_none() {
+        open(circuit, name, reason);
+    }
+}
+
+fn open(circuit: &mut Circuit, name: &str, reason: &str) {
+    let cool_down = Duration::from_secs(PARAMS.cool_down_s);
+    circuit.open_until = Some(Instant::now() + cool_down);
+
+    error_bus::report(PatokaError::error(
+        "circuit_breaker",
+        format!(
+            "Circuit open for [TASK NAME] {}: {}. Tasks held for {} s.",
+            name,
+            reason,
+            PARAMS.cool_down_s,
+        ),
+    ));
+}
+
+/// How long the circuit of `name` stays open, `None` if closed or
+/// half-open.
+pub fn open_for(name: &str) -> Option<Duration> {
+    let mut circuits = CIRCUITS.lock().unwrap();
+    let circuit = circuits.get_mut(name)?;
+    let until = circuit.open_until?;
+
+    let now = Instant::now();
+    if until > now {
+        return Some(until - now);
+    }
+
+    // Half-open.
+    circuit.open_until = None;
+    circuit.failures = PARAMS.failures.saturating_sub(1);
+    None
+}
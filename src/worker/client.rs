@@ -1,28 +1,150 @@
 use actix::prelude::*;
+use std::time::Duration;
+use uuid::Uuid;
 
 use crate::{
     control::message::StopTask,
+    core::arbiter_pool,
+    storage::{
+        checkpoint::{self, Checkpoint},
+        kv::KvStore,
+    },
     worker::{
-        controller::{WorkerController},
-        task::{ControllerAddr, GenTaskDefinition},
+        cancellation::CancellationToken,
+        controller::{WorkerController, WorkerRequest},
+        task::{ControllerAddr, GenTaskDefinition, TaskDefinition},
         worker_message::{WorkerMessage},
     },
 };
 
+#[derive(Debug)]
+pub enum ReplyError {
+    /// The controller could not be reached or the request has timed out.
+    Mailbox(MailboxError),
+
+    /// The controller is not reserved for the task.
+    Rejected,
+
+    /// The task has been closed before the reply arrived.
+    Canceled,
+
+    /// The worker process has crashed or been replaced before replying.
+    WorkerLost,
+
+    /// The client has no controller.
+    NoController,
+
+    /// The worker protocol version does not support replies.
+    Unsupported,
+}
+
+impl From<MailboxError> for ReplyError {
+    fn from(e: MailboxError) -> Self {
+        ReplyError::Mailbox(e)
+    }
+}
+
 #[derive(Clone)]
 pub struct ClientContext<T> {
     pub task_uuid: String,
     pub worker_id: String,
     pub controller_addr: ControllerAddr,
     pub task_definition: T,
+
+    /// Cancelled when the task is stopped.
+    pub cancellation: CancellationToken,
+
+    /// The last state checkpointed by a previous instance of the task.
+    pub checkpoint: Option<serde_json::Value>,
 }
 
 impl<T> ClientContext<T> {
     pub fn send_worker_message(&self, msg: WorkerMessage) {
-        if let ControllerAddr::Controller(addr) = &self.controller_addr {
-            addr.do_send(msg);
+        match &self.controller_addr {
+            ControllerAddr::Controller(addr) => addr.do_send(msg),
+            #[cfg(feature = "testing")]
+            ControllerAddr::Fake(addr) => addr.do_send(msg),
+            _ => {},
         }
     }
+
+    /// Send `msg` to the worker and wait for the reply carrying the same
+    /// correlation ID. The worker must copy `correlation_id` to the reply.
+    pub async fn request(
+        &self,
+        msg: WorkerMessage,
+    ) -> Result<WorkerMessage, ReplyError> {
+        self.request_(msg, None).await
+    }
+
+    /// Same as `request` but fails with `MailboxError::Timeout` if no reply
+    /// arrived in `timeout`.
+    pub async fn request_timeout(
+        &self,
+        msg: WorkerMessage,
+        timeout: Duration,
+    ) -> Result<WorkerMessage, ReplyError> {
+        self.request_(msg, Some(timeout)).await
+    }
+
+    async fn request_(
+        &self,
+        mut msg: WorkerMessage,
+        timeout: Option<Duration>,
+    ) -> Result<WorkerMessage, ReplyError> {
+        let recipient = match &self.controller_addr {
+            ControllerAddr::Controller(addr) => {
+                addr.clone().recipient::<WorkerRequest>()
+            },
+            #[cfg(feature = "testing")]
+            ControllerAddr::Fake(addr) => addr.clone().recipient(),
+            _ => return Err(ReplyError::NoController),
+        };
+
+        msg.payload.correlation_id = Uuid::new_v4().to_string();
+        let request = recipient.send(WorkerRequest { msg });
+        match timeout {
+            Some(t) => request.timeout(t).await?,
+            None => request.await?,
+        }
+    }
+}
+
+impl<T: TaskDefinition> ClientContext<T> {
+    /// The persistent state of the task, shared by the tasks of the same
+    /// name. Requires the storage to be initialized.
+    pub fn state(&self) -> KvStore {
+        KvStore::for_task(self.task_definition.name())
+    }
+
+    /// The checkpoint to resume from, `None` if there is none or it does
+    /// not deserialize into `S`.
+    pub fn last_checkpoint<S>(&self) -> Option<S>
+    where
+        S: serde::de::DeserializeOwned,
+    {
+        self.checkpoint.clone()
+            .and_then(|v| serde_json::from_value(v).ok())
+    }
+
+    /// Save the state for the next instance of the task to resume from.
+    pub fn checkpoint<S: serde::Serialize>(&self, state: &S) {
+        if let Ok(state) = serde_json::to_value(state) {
+            self.send_checkpoint(Some(state));
+        }
+    }
+
+    /// Forget the checkpoint, e.g. the task has completed.
+    pub fn clear_checkpoint(&self) {
+        self.send_checkpoint(None);
+    }
+
+    fn send_checkpoint(&self, state: Option<serde_json::Value>) {
+        checkpoint::start().do_send(Checkpoint {
+            task_name: self.task_definition.name().to_string(),
+            state,
+        });
+    }
 }
 
 pub type GenClientContext<P> = ClientContext<GenTaskDefinition<P>>;
@@ -42,7 +164,8 @@ pub trait WorkerClient: Actor + Handler<StopTask> + Clone {
     {
         Self::start_in_arbiter(
             arbiter,
-            move |_| {
+            move |c| {
+                arbiter_pool::track(&c.address());
                 Self::new(ctx)
             }
         )
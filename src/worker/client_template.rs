@@ -0,0 +1,188 @@
+use actix::prelude::*;
+
+use crate::{
+    center::send::{send_center_task_finished, send_center_task_result},
+    control::message::StopTask,
+    worker::{
+        client::GenClientContext,
+        setup::setup_with_controller,
+        task::TaskStatus,
+        worker_message::WorkerMessage,
+    },
+};
+
+/// What the task does after a result.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    /// Wait for more messages, e.g. the task has several steps.
+    Continue,
+
+    /// Finish with success.
+    Finish,
+}
+
+/// The hooks of a task client, wired by `impl_task_client!`. The template
+/// sends the task on start and reports the result and the finish.
+pub trait TaskClient: Actor<Context = Context<Self>> + Clone {
+    type Params: serde::Serialize + Clone;
+
+    fn new(ctx: GenClientContext<Self::Params>) -> Self;
+
+    fn client_ctx(&self) -> &GenClientContext<Self::Params>;
+
+    /// Once the task has been sent to the controller.
+    fn on_start(&mut self, _ctx: &mut Context<Self>) {}
+
+    /// Every message of the worker, before `on_result` or `on_error`.
+    fn on_worker_message(
+        &mut self,
+        _msg: &WorkerMessage,
+        _ctx: &mut Context<Self>,
+    ) {}
+
+    /// The task result, already sent to the center.
+    fn on_result(
+        &mut self,
+        _result: serde_json::Value,
+        _ctx: &mut Context<Self>,
+    ) -> Flow {
+        Flow::Finish
+    }
+
+    /// The error handler of the controller decides whether the task fails,
+    /// see `error_handler`.
+    fn on_error(
+        &mut self,
+        _error: serde_json::Value,
+        _ctx: &mut Context<Self>,
+    ) {}
+
+    /// Before the client stops, with the status sent to the center.
+    fn on_stop(&mut self, _status: TaskStatus, _ctx: &mut Context<Self>) {}
+}
+
+pub fn started<C>(client: &mut C, ctx: &mut Context<C>)
+where
+    C: TaskClient + Handler<WorkerMessage>,
+{
+    let client_ctx = client.client_ctx();
+    setup_with_controller(
+        &client_ctx.task_uuid,
+        None,
+        None,
+        ctx.address().recipient(),
+        &client_ctx.controller_addr,
+        client_ctx.task_definition.make_message(),
+        client_ctx.task_definition.name.clone(),
+    );
+
+    client.on_start(ctx);
+}
+
+pub fn handle_worker_message<C: TaskClient>(
+    client: &mut C,
+    msg: WorkerMessage,
+    ctx: &mut Context<C>,
+) {
+    client.on_worker_message(&msg, ctx);
+
+    if let Some(e) = msg.error() {
+        client.on_error(e, ctx);
+    } else if let Some(result) = msg.result::<serde_json::Value>() {
+        send_center_task_result(&client.client_ctx().task_uuid, &result);
+
+        if client.on_result(result, ctx) == Flow::Finish {
+            finish(client, TaskStatus::FinishedSuccess, ctx);
+        }
+    }
+}
+
+/// Stopped before finishing, e.g. the task has failed.
+pub fn handle_stop_task<C: TaskClient>(
+    client: &mut C,
+    _msg: StopTask,
+    ctx: &mut Context<C>,
+) {
+    finish(client, TaskStatus::FinishedFailure, ctx);
+}
+
+/// Report `status` to the center and stop.
+pub fn finish<C: TaskClient>(
+    client: &mut C,
+    status: TaskStatus,
+    ctx: &mut Context<C>,
+) {
+    client.on_stop(status, ctx);
+
+    let client_ctx = client.client_ctx();
+    send_center_task_finished(
+        &client_ctx.task_uuid,
+        status,
+        &client_ctx.task_definition.name,
+    );
+
+    ctx.stop();
+}
+
+/// Implement `Actor`, `WorkerClient` and the message handlers of the
+/// `TaskClient`.
+#[macro_export]
+macro_rules! impl_task_client {
+    ($x:ty) => {
+        impl ::actix::Actor for $x {
+            type Context = ::actix::Context<Self>;
+
+            fn started(&mut self, ctx: &mut Self::Context) {
+                $crate::worker::client_template::started(self, ctx);
+            }
+        }
+
+        impl $crate::worker::client::WorkerClient for $x {
+            type TaskDefinition = $crate::worker::task::GenTaskDefinition<
+                <$x as $crate::worker::client_template::TaskClient>::Params
+            >;
+
+            fn new(
+                ctx: $crate::worker::client::ClientContext<
+                    Self::TaskDefinition
+                >,
+            ) -> Self {
+                <$x as $crate::worker::client_template::TaskClient>::new(ctx)
+            }
+        }
+
+        impl ::actix::Handler<$crate::worker::worker_message::WorkerMessage>
+            for $x
+        {
+            type Result = ();
+
+            fn handle(
+                &mut self,
+                msg: $crate::worker::worker_message::WorkerMessage,
+                ctx: &mut Self::Context,
+            ) -> Self::Result {
+                $crate::worker::client_template::handle_worker_message(
+                    self,
+                    msg,
+                    ctx,
+                );
+            }
+        }
+
+        impl ::actix::Handler<$crate::control::message::StopTask> for $x {
+            type Result = ();
+
+            fn handle(
+                &mut self,
+                msg: $crate::control::message::StopTask,
+                ctx: &mut Self::Context,
+            ) -> Self::Result {
+                $crate::worker::client_template::handle_stop_task(
+                    self,
+                    msg,
+                    ctx,
+                );
+            }
+        }
+    }
+}
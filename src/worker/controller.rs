@@ -2,36 +2,123 @@ use actix::prelude::*;
 use serde_json::json;
 use slog::Logger;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     mem,
     process::{Command, Child},
+    str::FromStr,
+    time::{Duration, Instant},
 };
+use tokio::sync::oneshot;
 
 use crate::{
     control::{registry, message::*},
     core::{
+        alerting,
+        backpressure,
         env::{self, *},
+        error_bus::{self, PatokaError},
         logger::create_logger,
         monitor::*,
+        notifier::{self, EventKind},
+        proxy::{self, Proxy},
+        status_aggregator::{self, GetStatusSnapshot, StatusSnapshot},
+        telemetry,
         timer::Timer,
         timestamp,
     },
     worker::{
+        attachment,
+        captcha::{self, Challenge},
         controller_message::*,
         dispatcher::{self, TaskDispatcher},
         worker_message::*,
         plugin::*,
+        process_group::{self, Signal},
+        recycle,
         state::*,
-        task_writer,
+        client::ReplyError,
+        session_recorder::{self, Direction, Record, SessionRecorder},
+        node_registry::{self, StartWorker},
+        router,
+        slots::{self, TaskSlots},
+        task_tree,
+        task_writer::{self, TaskWriter},
+        worker_auth,
     },
-    transport::message::*,
+    transport::message::{clone_identity, is_empty},
 };
 
+/// Span from the first message of a task sent to the worker to the task
+/// close.
+const WORKER_EXCHANGE_SPAN: &str = "worker_exchange";
+
+/// What to do with the tasks dispatched to a worker process that had to be
+/// recovered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WorkerCrashPolicy {
+    /// Hand the tasks to the task reprocessor to run them again.
+    Resend,
+
+    /// Report an error to the task clients.
+    Fail,
+}
+
+impl FromStr for WorkerCrashPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resend" => Ok(WorkerCrashPolicy::Resend),
+            "fail" => Ok(WorkerCrashPolicy::Fail),
+            _ => Err(format!("Invalid worker_crash_policy {}", s)),
+        }
+    }
+}
+
+/// `general.heartbeat_interval_s`, read on use so that a reload applies
+/// to the next heartbeat.
+fn heartbeat_interval() -> Duration {
+    let s = env::opt_var("general.heartbeat_interval_s").ok().flatten();
+    Duration::from_secs(s.unwrap_or(2))
+}
+
+/// `general.worker_stop_grace_ms`: the time the worker process is given to
+/// exit on the stop request before SIGTERM.
+fn worker_stop_grace() -> Duration {
+    let ms = env::opt_var("general.worker_stop_grace_ms").ok().flatten();
+    Duration::from_millis(ms.unwrap_or(2000))
+}
+
+/// `general.worker_term_grace_ms`: after SIGTERM, before SIGKILL.
+fn worker_term_grace() -> Duration {
+    let ms = env::opt_var("general.worker_term_grace_ms").ok().flatten();
+    Duration::from_millis(ms.unwrap_or(3000))
+}
+
+/// `general.heartbeat_timeout_s`, see `heartbeat_interval`.
+fn heartbeat_timeout() -> Duration {
+    let s = env::opt_var("general.heartbeat_timeout_s").ok().flatten();
+    Duration::from_secs(s.unwrap_or(10))
+}
+
 struct ActiveClient {
     pub addr: Recipient<WorkerMessage>,
-    pub task_writer: Option<Recipient<WorkerMessage>>,
+    pub task_writer: Option<Addr<TaskWriter>>,
+    pub recorder: Option<Addr<SessionRecorder>>,
+}
+
+/// The screenshot and the DOM dump of a failed task being captured.
+struct ErrorCapture {
+    /// UUID of the `capture` control request.
+    request_uuid: String,
+
+    error: serde_json::Value,
+    attachments: Vec<attachment::Attachment>,
 }
 
+/// A screenshot and a DOM dump.
+const CAPTURED_ATTACHMENTS: usize = 2;
+
 pub struct WorkerController {
     /// Worker/Controller identifier.
     id: String,
@@ -49,8 +136,11 @@ pub struct WorkerController {
     /// Worker process handle.
     worker_process: Option<Child>,
 
-    /// The worker ZMQ identity. Used to route messages.
-    identity: Identity,
+    /// The worker process is being stopped to be created again.
+    recovering: bool,
+
+    /// The worker process has been SIGTERM'd as the controller is stopping.
+    terminating: bool,
 
     /// Current worker state.
     state: WorkerState,
@@ -64,8 +154,8 @@ pub struct WorkerController {
     delayed_client_messages: Vec<WorkerMessage>,
 
     /// The controller would handle only the tasks for those it has been
-    /// reserved.
-    reserved_tasks: HashSet<String>,
+    /// reserved, up to the number of tasks the worker runs at the same time.
+    slots: TaskSlots,
 
     /// Used to send `HeartbeatRequest` messages periodically.
     heartbeat_interval_timer: Timer<HeartbeatIntervalMessage>,
@@ -74,21 +164,72 @@ pub struct WorkerController {
     /// a specified amount of time.
     heartbeat_timeout_timer: Timer<HeartbeatTimeoutMessage>,
 
+    /// The oldest heartbeat request not answered yet, for the latency.
+    heartbeat_sent_at: Option<Instant>,
+
     /// Own address.
     own_addr: Option<Addr<WorkerController>>,
 
-    /// Periodically generate status report.
-    report_status_timer: ReportStatusTimer,
+    /// Expires the reservation leases.
+    slots_check_timer: RegularCheckTimer,
 
     /// `True` when the controller does not start the worker process but
     /// instead communicates with a process managed from outside.
     external_worker: bool,
 
     /// No heartbeats, the state is not checked and considered always ready.
-    /// The identity is updated on every message from the worker.
     simple_protocol: bool,
+
+    /// The token an external worker has to authenticate with, see
+    /// `worker_auth`.
+    auth_token: Option<String>,
+
+    /// The identity the last valid token has been received from.
+    worker_identity: Option<Vec<u8>>,
+
+    /// Proxy the current worker plugin has been set up with.
+    current_proxy: Option<Proxy>,
+
+    /// Browser profile the current worker plugin has been set up with.
+    current_profile: String,
+
+    /// Task UUID --> The first message sent to the worker.
+    /// Tasks dispatched to the worker process and not finished yet.
+    in_flight_tasks: HashMap<String, WorkerMessage>,
+
+    /// Task UUID --> The last message sent to the worker, resent to retry
+    /// the step with a fresh fingerprint.
+    last_steps: HashMap<String, WorkerMessage>,
+
+    /// `general.worker_crash_policy`: "resend" (default) or "fail".
+    worker_crash_policy: WorkerCrashPolicy,
+
+    /// Correlation ID --> (Task UUID, Reply sender)
+    /// Requests awaiting a reply from the worker.
+    pending_replies: HashMap<String, (String, ReplySender)>,
+
+    /// Task UUID --> Capture awaited before the failed task is stopped.
+    captures: HashMap<String, ErrorCapture>,
+
+    /// Negotiated with the worker on `started`.
+    protocol_version: u32,
+
+    /// Set up once the worker process is ready, see `Preload`.
+    preload_plugin: WorkerPlugin,
+
+    /// Tasks closed since the worker process has been created.
+    tasks_served: u64,
+
+    /// When the worker process has been created.
+    process_started_at: Option<Instant>,
+
+    /// Why the worker process is to be recycled once the tasks running are
+    /// closed, see `recycle`. No task is reserved for meanwhile.
+    recycle_reason: Option<String>,
 }
 
+type ReplySender = oneshot::Sender<Result<WorkerMessage, ReplyError>>;
+
 impl WorkerController {
     pub fn new(id: String) -> Self {
         let logger_name = format!("worker_controller_{}", id);
@@ -109,41 +250,105 @@ impl WorkerController {
                 false
             };
 
+        let auth_token = if external_worker {
+            worker_auth::token(&id)
+        } else {
+            None
+        };
+
+        let worker_crash_policy =
+            match env::get_opt_var("general.worker_crash_policy") {
+                Some(v) => v.parse().unwrap_or_else(|e: String| {
+                    error_bus::publish(PatokaError::warning(
+                        "worker_controller",
+                        format!("{}, using resend", e),
+                    ));
+                    WorkerCrashPolicy::Resend
+                }),
+                None => WorkerCrashPolicy::Resend,
+            };
+
+        let slots = TaskSlots::new(
+            slots::max_concurrent_tasks(&id, WorkerPlugin::None),
+        );
+
         WorkerController {
             id,
             log,
             dispatcher_addr: dispatcher::start(),
             active_clients: HashMap::new(),
             worker_process: None,
-            identity: new_identity(),
+            recovering: false,
+            terminating: false,
             state,
             delayed_worker_messages: vec![],
             delayed_client_messages: vec![],
-            reserved_tasks: HashSet::new(),
-            heartbeat_interval_timer: Timer::new_s(2),
-            heartbeat_timeout_timer: Timer::new_s(10),
+            slots,
+            heartbeat_interval_timer: Timer::new(),
+            heartbeat_timeout_timer: Timer::new(),
+            heartbeat_sent_at: None,
             own_addr: None,
-            report_status_timer: ReportStatusTimer::new_s(5),
+            slots_check_timer: RegularCheckTimer::interval_s(5),
             external_worker,
             simple_protocol,
+            auth_token,
+            worker_identity: None,
+            current_proxy: None,
+            current_profile: String::new(),
+            in_flight_tasks: HashMap::new(),
+            last_steps: HashMap::new(),
+            worker_crash_policy,
+            pending_replies: HashMap::new(),
+            captures: HashMap::new(),
+            protocol_version: PROTOCOL_VERSION,
+            preload_plugin: WorkerPlugin::None,
+            tasks_served: 0,
+            process_started_at: None,
+            recycle_reason: None,
         }
     }
 
+    /// Module name used to publish errors.
+    fn module(&self) -> String {
+        format!("worker_controller_{}", self.id)
+    }
+
     fn create_worker_process(&mut self) {
+        self.tasks_served = 0;
+        self.process_started_at = Some(Instant::now());
+
         let main_path = env::full_path(
             "$PATOKA_X_DIR/build/src/main.js",
             "$PATOKA_X_DIR",
             &PATOKA_X_DIR,
         );
 
-        let router_port = env::get_var("general.router_port");
+        let controller_address = match router::for_controller(&self.id) {
+            Some(r) => r.connect.clone(),
+            None => {
+                self.state.error();
+                error_bus::publish(PatokaError::critical(
+                    &self.module(),
+                    "No worker router configured.".to_string(),
+                ));
+                return;
+            },
+        };
+
+        if let Some(node_id) = node_registry::node_for_controller(&self.id) {
+            node_registry::start().do_send(StartWorker {
+                node_id: node_id.to_string(),
+                worker_id: self.id.clone(),
+                controller: controller_address,
+            });
+            self.state.starting();
+            return;
+        }
+
         let args = [
             main_path,
             format!("--worker_id={}", self.id),
-            format!(
-                "--controller={}",
-                "tcp://127.0.0.1:".to_string() + &router_port
-            ),
+            format!("--controller={}", controller_address),
         ];
 
         info!(self.log, "Creating worker process: node {:?}", args);
@@ -162,41 +367,447 @@ impl WorkerController {
             },
         };
         self.worker_process =
-            match Command::new("node").args(&args)
-                .env("NODE_PATH", node_path_env)
-                .spawn()
-            {
+            match process_group::spawn(
+                Command::new("node").args(&args)
+                    .env("NODE_PATH", node_path_env)
+            ) {
                 Ok(child) => {
                     self.state.starting();
                     Some(child)
                 },
                 Err(e) => {
                     self.state.error();
-                    error!(self.log, "Failed to create worker process: {}", e);
+                    error_bus::publish(PatokaError::critical(
+                        &self.module(),
+                        format!("Failed to create worker process: {}", e),
+                    ));
                     None
                 }
             };
     }
 
-    fn recover_worker_process(&mut self) {
-        if let Some(ref mut wp) = self.worker_process {
-            if let Err(e) = wp.kill() {
-                warn!(self.log, "Worker process killed with [ERROR] {}.", e);
-            } else {
-                debug!(self.log, "Worker process killed.");
+    /// SIGTERM the worker process along with the processes it has started.
+    /// `False` if it has exited already.
+    fn terminate_worker_process(&mut self) -> bool {
+        let wp = match self.worker_process {
+            Some(ref mut wp) => wp,
+            None => return false,
+        };
+        if process_group::has_exited(wp) {
+            return false;
+        }
+
+        if let Err(e) = process_group::signal(wp, Signal::Term) {
+            warn!(self.log, "Failed to SIGTERM: {}", e);
+        }
+        true
+    }
+
+    /// Kill the worker process along with the processes it has started.
+    fn kill_worker_process(&mut self) {
+        if let Some(mut wp) = self.worker_process.take() {
+            if process_group::has_exited(&mut wp) {
+                return;
+            }
+
+            match process_group::kill(&mut wp) {
+                Ok(()) => debug!(self.log, "Worker process group stopped."),
+                Err(e) => warn!(self.log, "Worker process [ERROR] {}.", e),
+            }
+        }
+    }
+
+    /// Ask the worker process to stop, then SIGTERM and SIGKILL its process
+    /// group once the grace periods have passed. A new process is created
+    /// then.
+    fn recover_worker_process(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if self.recovering {
+            return;
+        }
+
+        let wp = match self.worker_process {
+            Some(ref mut wp) => wp,
+            None => {
+                self.restart_worker_process();
+                return;
+            },
+        };
+
+        if process_group::has_exited(wp) {
+            self.worker_process = None;
+            self.restart_worker_process();
+            return;
+        }
+
+        self.recovering = true;
+
+        let stop = ControllerMessage::new(
+            self.id.clone(),
+            Dest::Worker,
+            Subject::Stop,
+        );
+        self.send_urgent_message_to_worker(stop.into());
+
+        ctx.run_later(worker_stop_grace(), |act, ctx| {
+            let wp = match act.worker_process {
+                Some(ref mut wp) => wp,
+                None => return act.finish_recovery(),
+            };
+            if process_group::has_exited(wp) {
+                return act.finish_recovery();
+            }
+
+            debug!(act.log, "Worker process has not stopped. SIGTERM.");
+            if let Err(e) = process_group::signal(wp, Signal::Term) {
+                warn!(act.log, "Failed to SIGTERM: {}", e);
+            }
+
+            ctx.run_later(worker_term_grace(), |act, _| {
+                if let Some(ref mut wp) = act.worker_process {
+                    if !process_group::has_exited(wp) {
+                        warn!(act.log, "Worker process alive. SIGKILL.");
+                        if let Err(e) = process_group::kill(wp) {
+                            warn!(act.log, "Worker process [ERROR] {}.", e);
+                        }
+                    }
+                }
+                act.finish_recovery();
+            });
+        });
+    }
+
+    fn finish_recovery(&mut self) {
+        self.recovering = false;
+        self.worker_process = None;
+
+        let recycled = self.recycle_reason.is_some();
+        self.restart_worker_process();
+
+        if !recycled {
+            notifier::notify(
+                EventKind::WorkerRecovered,
+                &self.id,
+                "The worker process has been replaced".to_string(),
+            );
+        }
+    }
+
+    fn restart_worker_process(&mut self) {
+        // The new process has no plugin set up.
+        self.state.plugin(WorkerPlugin::None);
+        self.heartbeat_sent_at = None;
+
+        // Not a failure of the worker.
+        match self.recycle_reason.take() {
+            Some(reason) => notifier::notify(
+                EventKind::WorkerRecycled,
+                &self.id,
+                format!("The worker process has been recycled: {}", reason),
+            ),
+            None => self.state.health_mut().recovered(),
+        }
+
+        self.fail_pending_replies();
+        self.create_worker_process();
+        self.handle_in_flight_tasks();
+    }
+
+    /// The new process will not reply to the requests sent to the old one.
+    fn fail_pending_replies(&mut self) {
+        for (_, (task_uuid, tx)) in self.pending_replies.drain() {
+            debug!(self.log, "Worker lost [TASK UUID] {}", task_uuid);
+            let _ = tx.send(Err(ReplyError::WorkerLost));
+        }
+    }
+
+    /// Start draining the worker process once its recycle policy applies,
+    /// recycle it once drained.
+    fn check_recycle(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if self.external_worker || self.recovering {
+            return;
+        }
+
+        if self.recycle_reason.is_none() {
+            let policy = match recycle::policy(self.state.current_plugin()) {
+                Some(p) => p,
+                None => return,
+            };
+            let uptime = self.process_started_at
+                .map_or(Duration::ZERO, |at| at.elapsed());
+            let pgid = self.worker_process.as_ref().map(|wp| wp.id());
+            let rss = || pgid.and_then(recycle::rss_kb);
+
+            self.recycle_reason =
+                policy.reason(self.tasks_served, uptime, rss);
+            match self.recycle_reason {
+                Some(ref reason) => {
+                    info!(self.log, "Draining the worker process: {}", reason);
+                },
+                None => return,
             }
+        }
+
+        if self.in_flight_tasks.is_empty()
+            && self.active_clients.is_empty()
+            && self.slots.is_empty()
+        {
+            info!(self.log, "Recycling the worker process.");
+            self.recover_worker_process(ctx);
+        }
+    }
+
+    /// Apply the crash policy to the tasks the lost worker process was
+    /// running.
+    fn handle_in_flight_tasks(&mut self) {
+        if self.in_flight_tasks.is_empty() {
+            return;
+        }
+
+        info!(
+            self.log,
+            "{} tasks were in flight when the worker process was lost. \
+                [POLICY] {:?}",
+            self.in_flight_tasks.len(),
+            self.worker_crash_policy,
+        );
+
+        let tasks = mem::take(&mut self.in_flight_tasks);
+        for (task_uuid, msg) in tasks {
+            match self.worker_crash_policy {
+                WorkerCrashPolicy::Resend => {
+                    debug!(self.log, "Reprocess [TASK UUID] {}", task_uuid);
+
+                    task_tree::reprocess_task(task_uuid);
+                },
+                WorkerCrashPolicy::Fail => {
+                    debug!(self.log, "Fail [TASK UUID] {}", task_uuid);
 
-            match wp.wait() {
-                Ok(exit_status) => {
-                    debug!(self.log, "Exit status: {}.", exit_status);
+                    self.fail_message(
+                        msg,
+                        "worker_lost",
+                        "Worker process has been lost.",
+                    );
                 },
+            }
+        }
+    }
+
+    /// Whether `msg` comes from the authenticated worker. A `started` or a
+    /// heartbeat response with a valid token makes its identity accepted.
+    fn authenticate(&mut self, msg: &WorkerMessage) -> bool {
+        let token = match self.auth_token {
+            Some(ref t) => t,
+            None => return true,
+        };
+
+        let identity = &msg.identity as &[u8];
+        if is_empty(&msg.identity)
+            || self.worker_identity.as_deref() == Some(identity) {
+            return true;
+        }
+
+        let valid = msg.payload.dest == Dest::Controller
+            && ControllerMessage::from(msg.clone()).is_ok_and(|cm| {
+                let hello = matches!(
+                    cm.subject,
+                    Subject::Started | Subject::HeartbeatResponse,
+                );
+                hello && worker_auth::verify(token, &cm.details)
+            });
+
+        if !valid {
+            warn!(
+                self.log,
+                "Rejecting a message from an unauthenticated identity."
+            );
+            return false;
+        }
+
+        info!(self.log, "Worker authenticated.");
+        self.worker_identity = Some(identity.to_vec());
+        self.dispatcher_addr.do_send(dispatcher::AcceptWorkerIdentity {
+            worker_id: self.id.clone(),
+            identity: clone_identity(&msg.identity),
+        });
+
+        true
+    }
+
+    /// Answer the CAPTCHA question `msg` by the solver, or pass it to the
+    /// client if there is none or it fails.
+    fn solve_captcha(
+        &mut self,
+        msg: WorkerMessage,
+        challenge: Challenge,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let solver = match captcha::solver() {
+            Some(s) => s,
+            None => return self.send_message_to_client(msg),
+        };
+
+        debug!(
+            self.log,
+            "Solving a CAPTCHA for [TASK UUID] {}",
+            msg.payload.task_uuid,
+        );
+
+        solver.solve(challenge)
+            .into_actor(self)
+            .map(move |result, act, _| match result {
+                Ok(token) => captcha::send_answer(&msg, token),
                 Err(e) => {
-                    warn!(self.log, "Exit status with [ERROR] {}.", e);
+                    warn!(
+                        act.log,
+                        "Failed to solve a CAPTCHA for [TASK UUID] {}: {}",
+                        msg.payload.task_uuid,
+                        e,
+                    );
+                    act.send_message_to_client(msg);
                 },
+            })
+            .spawn(ctx);
+    }
+
+    /// Pass the attachment to the client once complete, unless captured
+    /// for the error report.
+    fn receive_attachment(
+        &mut self,
+        mut msg: WorkerMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        match attachment::receive(&mut msg) {
+            Ok(true) => {
+                if !self.add_captured(&msg, ctx) {
+                    self.send_message_to_client(msg);
+                }
+            },
+            Ok(false) => {},
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "Dropped an attachment of [TASK UUID] {}: {}",
+                    msg.payload.task_uuid,
+                    e,
+                );
+                self.fail_message(msg, "attachment", &e);
+            },
+        }
+    }
+
+    /// Ask the worker of the failed task for a screenshot and a DOM dump,
+    /// then stop the task. At once if not a headless browser task.
+    fn capture_failed_task(
+        &mut self,
+        task_uuid: String,
+        error: serde_json::Value,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let capture = attachment::capture_on_error()
+            && self.state.is_plugin(WorkerPlugin::HeadlessBrowser)
+            && self.active_clients.contains_key(&task_uuid)
+            && !self.captures.contains_key(&task_uuid);
+
+        if !capture {
+            return self.handle_stop_task(StopTask { task_uuid }, ctx);
+        }
+
+        let cm = ControlMessage::request_with_data(
+            &task_uuid,
+            &task_uuid,
+            "capture",
+            json!({ "screenshot": true, "dom": true }),
+        );
+        info!(self.log, "Capture the failed [TASK UUID] {}", task_uuid);
+
+        self.captures.insert(task_uuid.clone(), ErrorCapture {
+            request_uuid: cm.uuid.clone(),
+            error,
+            attachments: Vec::new(),
+        });
+        self.send_urgent_message_to_worker(
+            create_control_request(self.id.to_string(), cm).into()
+        );
+
+        ctx.run_later(attachment::capture_timeout(), move |act, ctx| {
+            act.finish_capture(&task_uuid, ctx);
+        });
+    }
+
+    /// Return `true` if `msg` is an attachment of a capture.
+    fn add_captured(
+        &mut self,
+        msg: &WorkerMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> bool {
+        let task_uuid = &msg.payload.task_uuid;
+        let capture = match self.captures.get_mut(task_uuid) {
+            Some(c) if c.request_uuid == msg.payload.correlation_id => c,
+            _ => return false,
+        };
+
+        if let Some(a) = msg.attachment() {
+            match attachment::persist(task_uuid, &a) {
+                Ok(a) => capture.attachments.push(a),
+                Err(e) => warn!(
+                    self.log,
+                    "Failed to keep {} of [TASK UUID] {}: {}",
+                    a.name,
+                    task_uuid,
+                    e,
+                ),
             }
         }
 
-        self.create_worker_process();
+        if capture.attachments.len() >= CAPTURED_ATTACHMENTS {
+            let task_uuid = task_uuid.clone();
+            self.finish_capture(&task_uuid, ctx);
+        }
+
+        true
+    }
+
+    /// Report the error with what has been captured and stop the task.
+    fn finish_capture(
+        &mut self,
+        task_uuid: &str,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let capture = match self.captures.remove(task_uuid) {
+            Some(c) => c,
+            None => return,
+        };
+
+        error_bus::report(
+            PatokaError::error("worker_controller", capture.error.to_string())
+                .task(task_uuid)
+                .attachments(capture.attachments)
+        );
+
+        let task_uuid = task_uuid.to_string();
+        self.handle_stop_task(StopTask { task_uuid }, ctx);
+    }
+
+    /// Reply to the client with an error instead of the worker.
+    fn fail_message(&mut self, msg: WorkerMessage, kind: &str, message: &str) {
+        let payload = WorkerMessagePayload {
+            dest: Dest::Client,
+            worker_id: self.id.clone(),
+            task_uuid: msg.payload.task_uuid,
+            plugin: msg.payload.plugin,
+            profile: msg.payload.profile,
+            data: json!({
+                "error": {
+                    "kind": kind,
+                    "message": message,
+                }
+            }),
+            correlation_id: msg.payload.correlation_id,
+            protocol_version: PROTOCOL_VERSION,
+            trace: msg.payload.trace,
+        };
+
+        self.send_message_to_client(WorkerMessage::new(payload));
     }
 
     fn handle_controller_message(&mut self, msg: WorkerMessage) {
@@ -238,9 +849,69 @@ impl WorkerController {
         }
     }
 
+    /// The capabilities are reported with `started` by the newer workers.
+    fn update_capabilities(&mut self, msg: &ControllerMessage) {
+        if let Some(c) = WorkerCapabilities::from_details(&msg.details) {
+            if self.state.capabilities() != Some(&c) {
+                info!(
+                    self.log,
+                    "[PLUGINS] {:?} [MAX CONCURRENCY] {} [NODE] {}",
+                    c.plugins,
+                    c.max_concurrency,
+                    c.node_version,
+                );
+                self.state.set_capabilities(c);
+                self.slots.set_capacity(self.slots_capacity());
+            }
+        }
+    }
+
+    /// The configured number of slots limited by the worker.
+    fn slots_capacity(&self) -> usize {
+        let capacity = slots::max_concurrent_tasks(
+            &self.id,
+            self.state.current_plugin(),
+        );
+
+        match self.state.capabilities() {
+            Some(c) if c.max_concurrency > 0 => {
+                capacity.min(c.max_concurrency)
+            },
+            _ => capacity,
+        }
+    }
+
+    /// Agree on the protocol version with the worker. Returns `false` if
+    /// the worker is incompatible.
+    fn negotiate_protocol(&mut self, msg: &ControllerMessage) -> bool {
+        match negotiate(msg.min_protocol_version, msg.protocol_version) {
+            Ok(version) => {
+                if version != self.protocol_version {
+                    info!(self.log, "[PROTOCOL VERSION] {}", version);
+                }
+
+                self.protocol_version = version;
+                true
+            },
+            Err(e) => {
+                self.state.error();
+                error_bus::publish(PatokaError::critical(
+                    &self.module(),
+                    e.to_string(),
+                ));
+                false
+            },
+        }
+    }
+
     fn handle_started_message(&mut self, msg: ControllerMessage) {
         debug!(self.log, "Worker process has started.");
-        self.identity = msg.identity;
+
+        if !self.negotiate_protocol(&msg) {
+            return;
+        }
+
+        self.update_capabilities(&msg);
 
         // Start heartbeat timers.
         if !self.external_worker {
@@ -254,6 +925,24 @@ impl WorkerController {
         trace!(self.log, "Worker process is ready.");
         self.state.ready();
         self.send_delayed_messages();
+        self.preload();
+    }
+
+    /// Set the preload plugin up on a worker process with none, e.g. a new
+    /// one, unless a task is waiting for it.
+    fn preload(&mut self) {
+        let plugin = self.preload_plugin;
+        if plugin == WorkerPlugin::None
+            || !self.state.is_ready()
+            || !self.state.is_plugin(WorkerPlugin::None)
+            || !self.state.supports_plugin(plugin)
+            || !self.delayed_worker_messages.is_empty()
+        {
+            return;
+        }
+
+        let profile = desired_profile(plugin, "").to_string();
+        self.setup_worker_plugin(plugin, profile);
     }
 
     fn handle_plugin_ready_message(&mut self, msg: ControllerMessage) {
@@ -261,6 +950,7 @@ impl WorkerController {
             debug!(self.log, "Worker plugin has been set up.");
             let plugin = WorkerPlugin::from_str(plugin_name.as_str().unwrap());
             self.state.plugin(plugin);
+            self.slots.set_capacity(self.slots_capacity());
             self.state.ready();
             self.send_delayed_messages();
         } else {
@@ -288,8 +978,18 @@ impl WorkerController {
     }
 
     fn handle_heartbeat_response(&mut self, msg: ControllerMessage) {
+        if let Some(sent_at) = self.heartbeat_sent_at.take() {
+            self.state.health_mut().heartbeat(sent_at.elapsed());
+        }
+
         if self.external_worker {
-            self.identity = msg.identity;
+            // An external worker may have been started before the
+            // controller, i.e. there was no `started`.
+            if !self.negotiate_protocol(&msg) {
+                return;
+            }
+
+            self.update_capabilities(&msg);
 
             if self.state.is_initial() {
                 // Stop all running tasks in the worker.
@@ -311,7 +1011,10 @@ impl WorkerController {
         if let Some(ref own_addr) = self.own_addr {
             own_addr.do_send(HeartbeatResponseReceivedMessage::default());
         } else {
-            panic!("Controller own address is not set.");
+            error_bus::publish(PatokaError::critical(
+                &self.module(),
+                "Controller own address is not set.".to_string(),
+            ));
         }
     }
 
@@ -323,11 +1026,13 @@ impl WorkerController {
                 registry::send(m);
             },
             Err(_) => {
-                error!(
-                    self.log,
-                    "Invalid control message format: {:?}",
-                    msg.details,
-                );
+                error_bus::publish(PatokaError::error(
+                    &self.module(),
+                    format!(
+                        "Invalid control message format: {:?}",
+                        msg.details,
+                    ),
+                ));
             }
         }
     }
@@ -391,22 +1096,66 @@ impl WorkerController {
         // Check the plugin.
         if !self.simple_protocol {
             let desired_plugin = WorkerPlugin::from_str(&msg.payload.plugin);
-            if !self.state.is_plugin(desired_plugin) {
+            if !self.state.supports_plugin(desired_plugin) {
+                warn!(
+                    self.log,
+                    "Worker does not support [PLUGIN] {:?} for [TASK UUID] {}",
+                    desired_plugin,
+                    msg.payload.task_uuid,
+                );
+                self.fail_message(
+                    msg,
+                    "plugin_unsupported",
+                    "Worker does not support the plugin.",
+                );
+                return;
+            }
+
+            let desired_profile =
+                desired_profile(desired_plugin, &msg.payload.profile)
+                    .to_string();
+            if !self.state.is_plugin(desired_plugin)
+                || desired_profile != self.current_profile
+            {
                 debug!(
                     self.log,
                     "Worker plugin will be changed. Put the message to \
                         the delayed messages queue."
                 );
                 self.put_message_to_delayed_queue(msg);
-                self.setup_worker_plugin(desired_plugin);
+                self.setup_worker_plugin(desired_plugin, desired_profile);
                 return;
             }
         }
 
         // Now the message can be sent.
+        let client = &self.active_clients[&msg.payload.task_uuid];
+        if let Some(r) = &client.recorder {
+            r.do_send(Record {
+                direction: Direction::ToWorker,
+                msg: msg.clone(),
+            });
+        }
+
+        if !self.in_flight_tasks.contains_key(&msg.payload.task_uuid) {
+            telemetry::begin_span(
+                WORKER_EXCHANGE_SPAN,
+                &msg.payload.task_uuid,
+                &msg.payload.trace,
+            );
+            self.in_flight_tasks.insert(
+                msg.payload.task_uuid.clone(),
+                msg.clone(),
+            );
+        }
+
+        self.last_steps.insert(msg.payload.task_uuid.clone(), msg.clone());
         self.send_message_to_worker(msg);
 
-        if !self.simple_protocol {
+        // The worker reports `ready` when it is able to run more tasks.
+        if !self.simple_protocol
+            && self.in_flight_tasks.len() >= self.slots.capacity()
+        {
             self.state.busy();
         }
     }
@@ -419,8 +1168,12 @@ impl WorkerController {
     }
 
     fn send_message_to_worker(&mut self, mut msg: WorkerMessage) {
-        msg.identity = Identity::from(&self.identity as &[u8]);
-        self.dispatcher_addr.do_send(msg);
+        msg.payload.protocol_version = self.protocol_version;
+        backpressure::do_send(
+            "controller.dispatcher",
+            &self.dispatcher_addr,
+            msg,
+        );
     }
 
     fn put_message_to_delayed_queue(&mut self, msg: WorkerMessage) {
@@ -428,22 +1181,52 @@ impl WorkerController {
     }
 
     fn is_reserved_for_task(&self, task_uuid: &str) -> bool {
-        self.reserved_tasks.contains(task_uuid)
+        self.slots.contains(task_uuid)
     }
 
-    fn reserve_for_task(&mut self, task_uuid: &str) {
-        self.reserved_tasks.insert(task_uuid.to_string());
-    }
-
-    /// Forward `message` to the respective client.
+    /// Forward `message` to the respective client. A reply to a request is
+    /// resolved instead.
     fn send_message_to_client(&mut self, msg: WorkerMessage) {
         if let Some(c) = self.active_clients.get(&msg.payload.task_uuid) {
-            self.identity = clone_identity(&msg.identity);
             if let Some(addr) = &c.task_writer {
-                addr.do_send(msg.clone());
+                backpressure::do_send(
+                    "controller.task_writer",
+                    addr,
+                    msg.clone(),
+                );
+            }
+
+            if let Some(r) = &c.recorder {
+                backpressure::do_send(
+                    "controller.recorder",
+                    r,
+                    Record {
+                        direction: Direction::ToClient,
+                        msg: msg.clone(),
+                    },
+                );
             }
 
-            c.addr.do_send(msg);
+            let reply = self.pending_replies
+                .remove(&msg.payload.correlation_id);
+            match reply {
+                Some((task_uuid, tx)) => {
+                    if tx.send(Ok(msg)).is_err() {
+                        debug!(
+                            self.log,
+                            "Reply is not awaited anymore [TASK UUID] {}",
+                            task_uuid,
+                        );
+                    }
+                },
+                None => {
+                    backpressure::do_send(
+                        "controller.client",
+                        &c.addr,
+                        msg,
+                    );
+                },
+            }
         } else {
             warn!(
                 self.log,
@@ -455,18 +1238,78 @@ impl WorkerController {
         }
     }
 
-    fn setup_worker_plugin(&mut self, plugin: WorkerPlugin) {
-        debug!(self.log, "Setup worker plugin {:?}", plugin);
-        let msg = setup_plugin_message(plugin, &self.id);
+    fn setup_worker_plugin(&mut self, plugin: WorkerPlugin, profile: String) {
+        debug!(
+            self.log,
+            "Setup worker plugin {:?} [PROFILE] {}",
+            plugin,
+            profile,
+        );
+        self.current_proxy = next_proxy(plugin);
+        let msg = setup_plugin_message(
+            plugin,
+            &self.id,
+            self.current_proxy.clone(),
+            &profile,
+        );
+        self.current_profile = profile;
         self.send_urgent_message_to_worker(msg);
         self.state.busy();
     }
 
+    /// Set the current plugin up again with a fresh proxy and user agent.
+    /// Messages to the worker are delayed until the plugin is ready.
+    fn rotate_fingerprint(&mut self, task_uuid: &str) {
+        let plugin = self.state.current_plugin();
+        if !plugin.uses_proxy() {
+            debug!(
+                self.log,
+                "Nothing to rotate for [TASK UUID] {} [PLUGIN] {:?}",
+                task_uuid,
+                plugin,
+            );
+            return;
+        }
+
+        if let Some(ref p) = self.current_proxy {
+            proxy::mark_blocked(p);
+            info!(
+                self.log,
+                "Rotate proxy {} (blocked {} times) for [TASK UUID] {}",
+                p.address,
+                proxy::blocked_count(p),
+                task_uuid,
+            );
+        } else {
+            info!(self.log, "Rotate user agent for [TASK UUID] {}", task_uuid);
+        }
+
+        self.setup_worker_plugin(plugin, self.current_profile.clone());
+    }
+
+    /// Resend the last message of the task, e.g. once the fingerprint has
+    /// been rotated. Sent once the worker is set up again.
+    fn retry_step(&mut self, task_uuid: &str) {
+        let mut msg = match self.last_steps.get(task_uuid) {
+            Some(m) => m.clone(),
+            None => return,
+        };
+
+        info!(self.log, "Retry the last step of [TASK UUID] {}", task_uuid);
+
+        // Must not expire while the worker is being set up.
+        msg.created_at = timestamp::now_ms();
+        self.send_regular_message_to_worker(msg);
+    }
+
     fn handle_stop_task(
         &mut self,
         msg: StopTask,
         ctx: &mut <Self as Actor>::Context,
     ) {
+        self.in_flight_tasks.remove(&msg.task_uuid);
+        self.last_steps.remove(&msg.task_uuid);
+
         let cm = ControlMessage::request(
             &msg.task_uuid,
             &msg.task_uuid,
@@ -478,12 +1321,73 @@ impl WorkerController {
         );
     }
 
+    fn handle_pause_task(
+        &mut self,
+        msg: PauseTask,
+        _ctx: &mut <Self as Actor>::Context,
+    ) {
+        let cm = ControlMessage::request(
+            &msg.task_uuid,
+            &msg.task_uuid,
+            "pause_task"
+        );
+
+        self.send_urgent_message_to_worker(
+            create_control_request(self.id.to_string(), cm).into()
+        );
+    }
+
+    fn handle_resume_task(
+        &mut self,
+        msg: ResumeTask,
+        _ctx: &mut <Self as Actor>::Context,
+    ) {
+        let cm = ControlMessage::request(
+            &msg.task_uuid,
+            &msg.task_uuid,
+            "resume_task"
+        );
+
+        self.send_urgent_message_to_worker(
+            create_control_request(self.id.to_string(), cm).into()
+        );
+    }
+
     fn handle_close_task(
         &mut self,
         msg: CloseTask,
         ctx: &mut <Self as Actor>::Context,
     ) {
-        self.active_clients.remove(&msg.task_uuid);
+        attachment::cleanup(&msg.task_uuid);
+        self.dispatcher_addr.do_send(dispatcher::ForgetTask {
+            task_uuid: msg.task_uuid.clone(),
+            controller_id: self.id.clone(),
+        });
+
+        if let Some(c) = self.active_clients.remove(&msg.task_uuid) {
+            if let Some(w) = c.task_writer {
+                w.do_send(msg.clone());
+            }
+
+            if let Some(r) = c.recorder {
+                r.do_send(msg.clone());
+            }
+        }
+        self.in_flight_tasks.remove(&msg.task_uuid);
+        self.last_steps.remove(&msg.task_uuid);
+        self.state.health_mut().task_closed(&msg.task_uuid);
+        self.tasks_served += 1;
+        telemetry::end_span(
+            WORKER_EXCHANGE_SPAN,
+            &msg.task_uuid,
+            vec![("worker_id".to_string(), self.id.clone())],
+        );
+
+        // Dropping the senders cancels the pending requests.
+        self.pending_replies
+            .retain(|_, (task_uuid, _)| *task_uuid != msg.task_uuid);
+
+        self.check_recycle(ctx);
     }
 }
 
@@ -501,6 +1405,7 @@ impl Actor for WorkerController {
         self.dispatcher_addr.do_send(dispatcher::RegisterController {
             controller_id: self.id.clone(),
             controller_addr: ctx.address(),
+            authenticated: self.auth_token.is_some(),
         });
 
         // Create worker process that is managed by the controller.
@@ -510,10 +1415,27 @@ impl Actor for WorkerController {
             self.create_worker_process();
         }
 
-        self.report_status_timer.reset::<Self>(ctx);
+        self.slots_check_timer.reset::<Self>(ctx);
+        status_aggregator::register(
+            &format!("controller.{}", self.id),
+            ctx.address().recipient(),
+        );
+    }
+
+    /// The worker process is given `worker_term_grace` to exit after
+    /// SIGTERM before the controller stops.
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        if self.terminating || !self.terminate_worker_process() {
+            return Running::Stop;
+        }
+
+        self.terminating = true;
+        ctx.run_later(worker_term_grace(), |_, ctx| ctx.stop());
+        Running::Continue
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.kill_worker_process();
         info!(self.log, "Stopped.");
     }
 }
@@ -525,11 +1447,15 @@ impl Handler<WorkerMessage> for WorkerController {
     fn handle(
         &mut self,
         msg: WorkerMessage,
-        _ctx: &mut Self::Context
+        ctx: &mut Self::Context
     ) -> Self::Result {
 
         //trace!(self.log, "Received message: {}",  msg.payload.header());
 
+        if !self.authenticate(&msg) {
+            return;
+        }
+
         match msg.payload.dest {
             Dest::Controller => {
                 // A message for itself.
@@ -537,7 +1463,14 @@ impl Handler<WorkerMessage> for WorkerController {
             },
             Dest::Client => {
                 // A message from the worker to a client.
-                self.send_message_to_client(msg);
+                if attachment::is_attachment(&msg) {
+                    return self.receive_attachment(msg, ctx);
+                }
+
+                match captcha::challenge_of(&msg) {
+                    Some(c) => self.solve_captcha(msg, c, ctx),
+                    None => self.send_message_to_client(msg),
+                }
             },
             Dest::Worker => {
                 if !self.is_reserved_for_task(&msg.payload.task_uuid) {
@@ -577,9 +1510,21 @@ impl Handler<RegisterClient> for WorkerController {
     ) -> Self::Result {
         info!(self.log, "Register a client for [TASK UUID] {}", msg.task_uuid);
 
+        if !self.slots.claim(&msg.task_uuid) {
+            warn!(
+                self.log,
+                "No reservation to claim for [TASK UUID] {}",
+                msg.task_uuid,
+            );
+        }
+
         let active_client = ActiveClient {
             addr: msg.client,
             task_writer: task_writer::get_writer(&msg.task_name),
+            recorder: session_recorder::start(
+                &msg.task_name,
+                &msg.task_uuid,
+            ),
         };
 
         self.active_clients.insert(msg.task_uuid, active_client);
@@ -589,9 +1534,17 @@ impl Handler<RegisterClient> for WorkerController {
 
 /// Reserve the controller to process the given task.
 /// It is possible for controller to process more than one task simultaneously.
-/// The capability to do so is determined by the controller's `state`.
+/// The capability to do so is determined by the controller's `state` and its
+/// free slots.
 pub struct ReserveForTask {
     pub task_uuid: String,
+
+    /// Plugin the task requires. The worker must be able to load it.
+    pub plugin: WorkerPlugin,
+
+    /// The reservation is released unless the task client registers
+    /// within the lease. Zero for never.
+    pub lease: Duration,
 }
 
 impl Message for ReserveForTask {
@@ -606,7 +1559,16 @@ impl Handler<ReserveForTask> for WorkerController {
         msg: ReserveForTask,
         _ctx: &mut Self::Context
     ) -> Self::Result {
-        if !self.state.is_ready() && !self.state.is_starting()
+        if let Some(ref reason) = self.recycle_reason {
+            debug!(
+                self.log,
+                "Unable to reserve the controller for [TASK UUID] {}: \
+                    recycling the worker process, {}",
+                msg.task_uuid,
+                reason,
+            );
+            false
+        } else if !self.state.is_ready() && !self.state.is_starting()
             && !(self.external_worker && self.state.is_initial()) {
             debug!(
                 self.log,
@@ -616,18 +1578,264 @@ impl Handler<ReserveForTask> for WorkerController {
                 self.state.current_state(),
             );
             false
+        } else if !self.state.supports_plugin(msg.plugin) {
+            debug!(
+                self.log,
+                "Unable to reserve the controller for [TASK UUID] {} \
+                    [PLUGIN] {:?} is not supported",
+                msg.task_uuid,
+                msg.plugin,
+            );
+            false
+        } else if !self.slots.reserve(&msg.task_uuid, msg.lease) {
+            debug!(
+                self.log,
+                "No free slot for [TASK UUID] {} [SLOTS] {}",
+                msg.task_uuid,
+                self.slots.capacity(),
+            );
+            false
         } else {
             debug!(
                 self.log,
-                "Reserved the controller for [TASK UUID] {}",
-                msg.task_uuid
+                "Reserved the controller for [TASK UUID] {} [FREE SLOTS] {}",
+                msg.task_uuid,
+                self.slots.free(),
             );
-            self.reserve_for_task(&msg.task_uuid);
+            self.dispatcher_addr.do_send(dispatcher::RouteTask {
+                task_uuid: msg.task_uuid,
+                controller_id: self.id.clone(),
+            });
             true
         }
     }
 }
 
+/// The current load of the controller, e.g. to select one for a task.
+pub struct GetLoad;
+
+pub struct ControllerLoad {
+    pub free_slots: usize,
+
+    /// The plugin the worker has been set up with.
+    pub plugin: WorkerPlugin,
+
+    /// 0 to 1, see `health`.
+    pub health_score: f64,
+
+    /// The worker process is up, not being created or recovered.
+    pub warm: bool,
+}
+
+impl Message for GetLoad {
+    type Result = ControllerLoad;
+}
+
+impl<A, M> dev::MessageResponse<A, M> for ControllerLoad
+where
+    A: Actor,
+    M: Message<Result = ControllerLoad>,
+{
+    fn handle(
+        self,
+        _ctx: &mut A::Context,
+        tx: Option<dev::OneshotSender<M::Result>>,
+    ) {
+        if let Some(tx) = tx {
+            let _ = tx.send(self);
+        }
+    }
+}
+
+impl Handler<GetLoad> for WorkerController {
+    type Result = ControllerLoad;
+
+    fn handle(
+        &mut self,
+        _msg: GetLoad,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        ControllerLoad {
+            free_slots: self.slots.free(),
+            plugin: self.state.current_plugin(),
+            health_score: self.state.health().score(),
+            warm: !self.recovering
+                && (self.state.is_ready() || self.state.is_busy()),
+        }
+    }
+}
+
+/// Set `plugin` up once the worker process is ready and again whenever it
+/// is replaced, so that the first task requiring it does not wait. Sent to
+/// the standby controllers, see `ControllerPool`.
+pub struct Preload {
+    pub plugin: WorkerPlugin,
+}
+
+impl Message for Preload {
+    type Result = ();
+}
+
+impl Handler<Preload> for WorkerController {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: Preload,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.preload_plugin = msg.plugin;
+        self.preload();
+    }
+}
+
+/// Sent on the task teardown to free the slot reserved for the task.
+pub struct ReleaseReservation {
+    pub task_uuid: String,
+}
+
+impl Message for ReleaseReservation {
+    type Result = ();
+}
+
+impl Handler<ReleaseReservation> for WorkerController {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: ReleaseReservation,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        debug!(self.log, "Release [TASK UUID] {}", msg.task_uuid);
+
+        self.slots.release(&msg.task_uuid);
+        self.dispatcher_addr.do_send(dispatcher::ForgetTask {
+            task_uuid: msg.task_uuid,
+            controller_id: self.id.clone(),
+        });
+        self.check_recycle(ctx);
+    }
+}
+
+/// Sent by the task tree once the task has finished. The worker process
+/// is not running it anymore, so it is no longer in flight.
+pub struct TaskFinished {
+    pub task_uuid: String,
+}
+
+impl Message for TaskFinished {
+    type Result = ();
+}
+
+impl Handler<TaskFinished> for WorkerController {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: TaskFinished,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.in_flight_tasks.remove(&msg.task_uuid);
+        self.last_steps.remove(&msg.task_uuid);
+    }
+}
+
+/// Sent by the error handler when a task has failed: the task is stopped,
+/// captured first if a headless browser one.
+pub struct StopFailedTask {
+    pub task_uuid: String,
+    pub error: serde_json::Value,
+}
+
+impl Message for StopFailedTask {
+    type Result = ();
+}
+
+impl Handler<StopFailedTask> for WorkerController {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: StopFailedTask,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.state.health_mut().task_failed(&msg.task_uuid);
+        self.capture_failed_task(msg.task_uuid, msg.error, ctx);
+    }
+}
+
+/// A message to the worker awaiting a correlated reply.
+/// `msg.payload.correlation_id` must be set and unique.
+pub struct WorkerRequest {
+    pub msg: WorkerMessage,
+}
+
+impl Message for WorkerRequest {
+    type Result = Result<WorkerMessage, ReplyError>;
+}
+
+impl Handler<WorkerRequest> for WorkerController {
+    type Result = ResponseFuture<Result<WorkerMessage, ReplyError>>;
+
+    fn handle(
+        &mut self,
+        msg: WorkerRequest,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let msg = msg.msg;
+        let task_uuid = msg.payload.task_uuid.clone();
+        if !self.is_reserved_for_task(&task_uuid) {
+            warn!(self.log, "Rejecting unexpected [TASK UUID] {}", task_uuid);
+            return Box::pin(async { Err(ReplyError::Rejected) });
+        }
+
+        // The older workers do not copy the correlation ID to the reply.
+        if self.protocol_version < 2 {
+            return Box::pin(async { Err(ReplyError::Unsupported) });
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.pending_replies.insert(
+            msg.payload.correlation_id.clone(),
+            (task_uuid, tx),
+        );
+        self.send_regular_message_to_worker(msg);
+
+        Box::pin(async move {
+            rx.await.unwrap_or(Err(ReplyError::Canceled))
+        })
+    }
+}
+
+/// Sent when a task has been blocked/denied by the target: the worker should
+/// retry with a different proxy and user agent.
+pub struct RotateFingerprint {
+    pub task_uuid: String,
+
+    /// Resend the last message of the task once rotated.
+    pub retry: bool,
+}
+
+impl Message for RotateFingerprint {
+    type Result = ();
+}
+
+impl Handler<RotateFingerprint> for WorkerController {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: RotateFingerprint,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.rotate_fingerprint(&msg.task_uuid);
+
+        if msg.retry {
+            self.retry_step(&msg.task_uuid);
+        }
+    }
+}
+
 #[derive(Clone, Default, Message)]
 #[rtype(result = "()")]
 pub struct HeartbeatIntervalMessage {
@@ -641,15 +1849,20 @@ impl Handler<HeartbeatIntervalMessage> for WorkerController {
         _msg: HeartbeatIntervalMessage,
         ctx: &mut Self::Context
     ) -> Self::Result {
-        let heartbeat_request = ControllerMessage::with_identity(
+        let heartbeat_request = ControllerMessage::new(
             self.id.clone(),
             Dest::Worker,
             Subject::HeartbeatRequest,
-            clone_identity(&self.identity),
         );
         self.send_message_to_worker(heartbeat_request.into());
+        self.heartbeat_sent_at.get_or_insert_with(Instant::now);
 
-        self.heartbeat_interval_timer.reset::<Self>(ctx);
+        // Reloaded.
+        let interval = heartbeat_interval();
+        if self.heartbeat_interval_timer.duration() != Some(interval) {
+            self.heartbeat_interval_timer
+                .start_interval::<Self>(ctx, interval);
+        }
     }
 }
 
@@ -664,15 +1877,24 @@ impl Handler<HeartbeatTimeoutMessage> for WorkerController {
     fn handle(
         &mut self,
         _msg: HeartbeatTimeoutMessage,
-        _ctx: &mut Self::Context
+        ctx: &mut Self::Context
     ) -> Self::Result {
+        // Until the recovered worker process is alive.
+        self.heartbeat_interval_timer.cancel::<Self>(ctx);
+
         warn!(
             self.log,
             "Worker is not responding on heartbeat requests. Will try to \
                 recover the worker process."
         );
         self.state.error();
-        self.recover_worker_process();
+        notifier::notify(
+            EventKind::HeartbeatLost,
+            &self.id,
+            format!("No heartbeat response in {:?}", heartbeat_timeout()),
+        );
+        alerting::heartbeat_lost(&self.id, heartbeat_timeout());
+        self.recover_worker_process(ctx);
     }
 }
 
@@ -689,27 +1911,55 @@ impl Handler<HeartbeatResponseReceivedMessage> for WorkerController {
         _msg: HeartbeatResponseReceivedMessage,
         ctx: &mut Self::Context
     ) -> Self::Result {
-        self.heartbeat_interval_timer.reset::<Self>(ctx);
-        self.heartbeat_timeout_timer.reset::<Self>(ctx);
+        if !self.heartbeat_interval_timer.is_active() {
+            self.heartbeat_interval_timer
+                .start_interval::<Self>(ctx, heartbeat_interval());
+        }
+        self.heartbeat_timeout_timer
+            .start::<Self>(ctx, heartbeat_timeout());
+    }
+}
+
+impl Handler<GetStatusSnapshot> for WorkerController {
+    type Result = MessageResult<GetStatusSnapshot>;
+
+    fn handle(
+        &mut self,
+        _msg: GetStatusSnapshot,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let snapshot = StatusSnapshot::new()
+            .with("active_clients", self.active_clients.len())
+            .with("in_flight_tasks", self.in_flight_tasks.len())
+            .with("free_slots", self.slots.free())
+            .with("tasks_served", self.tasks_served)
+            .with("recycling", self.recycle_reason.is_some());
+
+        MessageResult(self.state.health().add_to(snapshot))
     }
 }
 
-impl Handler<ReportStatusMessage> for WorkerController {
+impl Handler<RegularCheckMessage> for WorkerController {
     type Result = ();
 
     fn handle(
         &mut self,
-        _msg: ReportStatusMessage,
+        _msg: RegularCheckMessage,
         ctx: &mut Self::Context
     ) -> Self::Result {
-        let number_of_active_clients = self.active_clients.len();
-        /*info!(
-            self.log,
-            "[STATUS] Number of active clients: {}.",
-            number_of_active_clients,
-        );*/
-
-        self.report_status_timer.reset::<Self>(ctx);
+        for task_uuid in self.slots.expire() {
+            warn!(
+                self.log,
+                "Reservation lease expired for [TASK UUID] {}",
+                task_uuid,
+            );
+            self.dispatcher_addr.do_send(dispatcher::ForgetTask {
+                task_uuid,
+                controller_id: self.id.clone(),
+            });
+        }
+        slots::publish(&self.id, self.slots.status());
+        self.check_recycle(ctx);
     }
 }
 
@@ -739,6 +1989,8 @@ impl Handler<ControlMessage> for WorkerController {
 
 handler_impl_stop_task!(WorkerController);
 handler_impl_close_task!(WorkerController);
+handler_impl_pause_task!(WorkerController);
+handler_impl_resume_task!(WorkerController);
 
 pub fn start_task(
     controller_addr: &Addr<WorkerController>,
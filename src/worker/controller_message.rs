@@ -5,6 +5,7 @@ use std::fmt;
 
 use crate::{
     control::message::*,
+    core::telemetry::TraceContext,
     worker::worker_message::*,
     transport::message::*,
 };
@@ -33,6 +34,20 @@ pub enum Subject {
 
     ControlResponse,
 
+    /// Node --> Controller. A remote node has connected.
+    Register,
+
+    /// Node --> Controller. Sent periodically by a remote node.
+    NodeHeartbeat,
+
+    /// Controller --> Node. Start a worker process, `details` has the
+    /// `worker_id` and the `controller` address.
+    StartWorker,
+
+    /// Controller --> Worker. Finish up, e.g. flush the partial results and
+    /// close the browsers, and exit.
+    Stop,
+
     Custom(String),
 }
 
@@ -47,6 +62,10 @@ impl Subject {
             "heartbeat_response" => Subject::HeartbeatResponse,
             "control_request" => Subject::ControlRequest,
             "control_response" => Subject::ControlResponse,
+            "register" => Subject::Register,
+            "node_heartbeat" => Subject::NodeHeartbeat,
+            "start_worker" => Subject::StartWorker,
+            "stop" => Subject::Stop,
             _ => Subject::Custom(s.to_string()),
         }
     }
@@ -61,6 +80,10 @@ impl Subject {
             Subject::HeartbeatResponse => "heartbeat_response".to_string(),
             Subject::ControlRequest => "control_request".to_string(),
             Subject::ControlResponse => "control_response".to_string(),
+            Subject::Register => "register".to_string(),
+            Subject::NodeHeartbeat => "node_heartbeat".to_string(),
+            Subject::StartWorker => "start_worker".to_string(),
+            Subject::Stop => "stop".to_string(),
             Subject::Custom(s) => s.clone(),
         }
     }
@@ -80,12 +103,25 @@ pub struct ControllerMessage {
     pub dest: Dest,
     pub subject: Subject,
     pub details: serde_json::Value,
+
+    /// Supported by the sender. See `ControllerMessageBody`.
+    pub min_protocol_version: u32,
+    pub protocol_version: u32,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct ControllerMessageBody {
     pub subject: String,
     pub details: serde_json::Value,
+
+    /// The highest protocol version supported by the sender. The worker
+    /// sends it with `started` to negotiate the version.
+    #[serde(default = "legacy_protocol_version")]
+    pub protocol_version: u32,
+
+    /// The lowest protocol version supported by the sender.
+    #[serde(default = "legacy_protocol_version")]
+    pub min_protocol_version: u32,
 }
 
 impl ControllerMessage {
@@ -96,6 +132,8 @@ impl ControllerMessage {
             dest,
             subject,
             details: serde_json::to_value({}).unwrap(),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            protocol_version: PROTOCOL_VERSION,
         }
     }
 
@@ -110,6 +148,8 @@ impl ControllerMessage {
             dest: wm.payload.dest,
             subject: Subject::from_str(&body.subject),
             details: body.details,
+            min_protocol_version: body.min_protocol_version,
+            protocol_version: body.protocol_version,
         })
     }
 
@@ -125,6 +165,8 @@ impl ControllerMessage {
             dest,
             subject,
             details: serde_json::to_value({}).unwrap(),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            protocol_version: PROTOCOL_VERSION,
         }
     }
 
@@ -140,6 +182,8 @@ impl ControllerMessage {
             dest,
             subject,
             details,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            protocol_version: PROTOCOL_VERSION,
         }
     }
 }
@@ -152,6 +196,8 @@ impl Clone for ControllerMessage {
             dest: self.dest,
             subject: self.subject.clone(),
             details: self.details.clone(),
+            min_protocol_version: self.min_protocol_version,
+            protocol_version: self.protocol_version,
         }
     }
 }
@@ -161,6 +207,8 @@ impl Into<WorkerMessage> for ControllerMessage {
         let data = json!({
             "subject": Subject::as_str(&self.subject),
             "details": self.details,
+            "protocol_version": self.protocol_version,
+            "min_protocol_version": self.min_protocol_version,
         });
 
         let payload = WorkerMessagePayload {
@@ -168,7 +216,11 @@ impl Into<WorkerMessage> for ControllerMessage {
             worker_id: self.worker_id,
             task_uuid: String::new(),
             plugin: String::new(),
+            profile: String::new(),
             data,
+            correlation_id: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            trace: TraceContext::default(),
         };
 
         WorkerMessage::with_identity(payload, self.identity)
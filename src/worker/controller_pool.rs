@@ -1,12 +1,225 @@
 use actix::prelude::*;
+use serde_derive::Deserialize;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use crate::worker::controller::{WorkerController, ReserveForTask};
+use crate::{
+    core::{
+        arbiter_pool,
+        env,
+        error_bus::{self, PatokaError},
+    },
+    worker::{
+        health,
+        slots,
+        controller::{
+            ControllerLoad,
+            GetLoad,
+            Preload,
+            ReserveForTask,
+            WorkerController,
+        },
+        plugin::WorkerPlugin,
+    },
+};
+
+/// Module name used to publish errors.
+const MODULE: &str = "controller_pool";
+
+/// How the pool picks a controller for a task:
+/// `general.controller_selection`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionStrategy {
+    /// One after another. The default.
+    RoundRobin,
+
+    /// The most free slots first.
+    LeastLoaded,
+
+    /// The controllers with the plugin the task requires already set up
+    /// first, so that the plugin is not set up again.
+    PluginAffinity,
+
+    /// The tasks of the same name go to the same controller while it is
+    /// able to accept them.
+    Sticky,
+}
+
+impl FromStr for SelectionStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(SelectionStrategy::RoundRobin),
+            "least_loaded" => Ok(SelectionStrategy::LeastLoaded),
+            "plugin_affinity" => Ok(SelectionStrategy::PluginAffinity),
+            "sticky" => Ok(SelectionStrategy::Sticky),
+            _ => Err(format!("Invalid controller_selection {}", s)),
+        }
+    }
+}
+
+impl SelectionStrategy {
+    pub fn load() -> Self {
+        match env::get_opt_var("general.controller_selection") {
+            Some(v) => parse_strategy(&v),
+            None => SelectionStrategy::RoundRobin,
+        }
+    }
+}
+
+/// "round_robin" if invalid.
+fn parse_strategy(v: &str) -> SelectionStrategy {
+    v.parse().unwrap_or_else(|e: String| {
+        error_bus::publish(PatokaError::warning(
+            MODULE,
+            format!("{}, using round_robin", e),
+        ));
+        SelectionStrategy::RoundRobin
+    })
+}
+
+/// Number of worker controllers, either a number or "auto" (one per CPU).
+/// 1 if not set or invalid.
+pub fn parse_capacity(v: Option<&str>) -> usize {
+    match v {
+        None => 1,
+        Some("auto") => num_cpus::get(),
+        Some(v) => match v.parse() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                error_bus::publish(PatokaError::warning(
+                    MODULE,
+                    format!("Invalid number_of_workers {}, using 1", v),
+                ));
+                1
+            },
+        },
+    }
+}
+
+/// `[queues.<name>]` configuration section: a pool of controllers of its
+/// own for the tasks of the queue, see `TaskDefinition::queue`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct QueueParams {
+    /// Like `general.number_of_workers`.
+    #[serde(default)]
+    pub number_of_workers: Option<String>,
+
+    /// Like `general.controller_selection`.
+    #[serde(default)]
+    pub controller_selection: Option<String>,
+
+    /// The plugin of the tasks of the queue not requiring any.
+    #[serde(default)]
+    pub plugin: Option<String>,
+
+    /// Like `general.standby_workers`, with `plugin` preloaded.
+    #[serde(default)]
+    pub standby_workers: usize,
+}
+
+impl QueueParams {
+    pub fn capacity(&self) -> usize {
+        parse_capacity(self.number_of_workers.as_deref())
+    }
+
+    fn standby_plugin(&self) -> WorkerPlugin {
+        match self.plugin {
+            Some(ref p) => WorkerPlugin::from_str(p),
+            None => WorkerPlugin::None,
+        }
+    }
+
+    fn strategy(&self) -> SelectionStrategy {
+        match self.controller_selection {
+            Some(ref s) => parse_strategy(s),
+            None => SelectionStrategy::load(),
+        }
+    }
+}
+
+/// Idle controllers the default pool keeps with their worker processes
+/// started and `general.standby_plugin` set up: `general.standby_workers`,
+/// 0 by default.
+fn standby_workers() -> usize {
+    env::get_opt_var("general.standby_workers")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+fn standby_plugin() -> WorkerPlugin {
+    match env::get_opt_var("general.standby_plugin") {
+        Some(p) => WorkerPlugin::from_str(&p),
+        None => WorkerPlugin::None,
+    }
+}
+
+/// Queue name --> Params
+pub fn queue_params() -> HashMap<String, QueueParams> {
+    env::load_opt("queues").unwrap_or_default()
+}
+
+/// The controllers are preferred by their health score rounded down to a
+/// quarter.
+const HEALTH_BUCKETS: f64 = 4.0;
+
+/// A controller not answering `GetLoad` in time is considered unhealthy.
+const LOAD_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The loads are reused for that long unless the strategy depends on the
+/// free slots or the plugins.
+const LOADS_TTL: Duration = Duration::from_secs(1);
+
+/// A controller skipped for its health score.
+struct Exclusion {
+    until: Instant,
+
+    /// When excluded.
+    score: f64,
+}
+
+/// The controller reserved for a task.
+pub struct ControllerInfo {
+    pub addr: Addr<WorkerController>,
+    pub id: String,
+
+    /// `True` if the controller has been created for the task.
+    pub created: bool,
+}
 
 pub struct ControllerPool {
     controllers: Vec<Addr<WorkerController>>,
     controller_ids: Vec<String>,
     capacity: usize,
     next_to_use: usize,
+    strategy: SelectionStrategy,
+
+    /// Of the controller IDs, "<queue>-" for a named queue.
+    id_prefix: String,
+
+    /// Controller index --> Exclusion
+    excluded: HashMap<usize, Exclusion>,
+
+    /// Indices of the controllers not given any task until promoted, see
+    /// `general.standby_workers`. Not counted in the capacity.
+    standby: Vec<usize>,
+
+    standby_workers: usize,
+
+    /// Set up on the standby workers.
+    standby_plugin: WorkerPlugin,
+
+    /// Controller index --> Load, `None` for a controller not answering.
+    loads: Vec<Option<ControllerLoad>>,
+
+    /// When the loads were received.
+    loads_at: Option<Instant>,
 }
 
 impl ControllerPool {
@@ -16,75 +229,408 @@ impl ControllerPool {
             controller_ids: vec![],
             capacity,
             next_to_use: 0,
+            strategy: SelectionStrategy::load(),
+            id_prefix: String::new(),
+            excluded: HashMap::new(),
+            standby: vec![],
+            standby_workers: standby_workers(),
+            standby_plugin: standby_plugin(),
+            loads: vec![],
+            loads_at: None,
+        }
+    }
+
+    pub fn for_queue(queue: &str, params: &QueueParams) -> Self {
+        ControllerPool {
+            strategy: params.strategy(),
+            id_prefix: format!("{}-", queue),
+            standby_workers: params.standby_workers,
+            standby_plugin: params.standby_plugin(),
+            ..Self::new(params.capacity())
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The controllers already started are kept when the capacity is
+    /// decreased, but no new ones are started.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// Like the capacity, the standby controllers already started are kept
+    /// when decreased.
+    pub fn set_standby(&mut self, workers: usize, plugin: WorkerPlugin) {
+        self.standby_workers = workers;
+        self.standby_plugin = plugin;
+    }
+
+    /// The controllers given tasks.
+    fn active(&self) -> usize {
+        self.controllers.len() - self.standby.len()
+    }
+
+    fn start_controller(&mut self, arbiter: &ArbiterHandle) -> usize {
+        let controller_id =
+            format!("{}{}", self.id_prefix, self.controllers.len());
+        self.controller_ids.push(controller_id.clone());
+
+        let wc = WorkerController::new(controller_id);
+        let controller_address = WorkerController::start_in_arbiter(
+            arbiter,
+            move |c| {
+                arbiter_pool::track(&c.address());
+                wc
+            }
+        );
+
+        self.controllers.push(controller_address);
+        self.controllers.len() - 1
+    }
+
+    /// Start the standby controllers missing.
+    pub fn warm_up(&mut self) {
+        while self.standby.len() < self.standby_workers {
+            let i = self.start_controller(&arbiter_pool::next());
+            if self.standby_plugin != WorkerPlugin::None {
+                self.controllers[i].do_send(Preload {
+                    plugin: self.standby_plugin,
+                });
+            }
+            self.standby.push(i);
+        }
+    }
+
+    /// Swap the controllers whose worker processes are being created or
+    /// recovered for the warm standby ones.
+    fn replace_cold(&mut self) {
+        let loads = &self.loads;
+        let is_warm = |i: usize| loads[i].as_ref().is_some_and(|l| l.warm);
+
+        for i in 0..self.controllers.len() {
+            if self.standby.contains(&i) || is_warm(i) {
+                continue;
+            }
+
+            match self.standby.iter().position(|&s| is_warm(s)) {
+                Some(pos) => self.standby[pos] = i,
+                None => break,
+            }
+        }
+    }
+
+    /// Start or promote a controller if the active ones are fewer than the
+    /// capacity. Returns the index of the controller started.
+    fn grow(&mut self, arbiter: &ArbiterHandle) -> Option<usize> {
+        if self.active() >= self.capacity {
+            return None;
+        }
+
+        // A standby controller is promoted rather than a new one started.
+        if self.standby.is_empty() {
+            Some(self.start_controller(arbiter))
+        } else {
+            self.standby.remove(0);
+            None
+        }
+    }
+
+    /// The controllers to ask for their load, `None` if the loads received
+    /// last will do.
+    fn to_probe(&self) -> Option<Vec<Addr<WorkerController>>> {
+        let fresh = self.loads.len() == self.controllers.len()
+            && self.loads_at.is_some_and(|at| at.elapsed() < LOADS_TTL);
+
+        match self.strategy {
+            SelectionStrategy::LeastLoaded
+            | SelectionStrategy::PluginAffinity => {},
+            _ if fresh => return None,
+            _ => {},
         }
+
+        Some(self.controllers.clone())
+    }
+
+    fn set_loads(&mut self, loads: Vec<Option<ControllerLoad>>) {
+        self.loads = loads;
+        self.loads_at = Some(Instant::now());
+    }
+
+    /// The controller `i` has been reserved for a task.
+    fn reserved(&mut self, i: usize) {
+        self.next_to_use = (i + 1) % self.controllers.len();
     }
 
-    pub async fn next(
+    /// Indices of the active controllers in the order of preference: the
+    /// healthier first, then by the strategy. The excluded controllers are
+    /// left out unless all of them are.
+    fn candidates(
         &mut self,
-        arbiter: &ArbiterHandle,
-        task_uuid: &str,
-    ) -> Option<(Addr<WorkerController>, String, bool)> {
-        let mut created = false;
+        task_name: &str,
+        plugin: WorkerPlugin,
+    ) -> Vec<usize> {
+        let len = self.controllers.len();
+        if len == 0 {
+            return vec![];
+        }
+
+        // A controller started by another pool user since the probe.
+        self.loads.resize_with(len, || None);
+
+        let start = match self.strategy {
+            SelectionStrategy::Sticky => {
+                let mut hasher = DefaultHasher::new();
+                task_name.hash(&mut hasher);
+                hasher.finish() as usize % len
+            },
+            _ => self.next_to_use % len,
+        };
 
-        if self.controllers.len() < self.capacity {
-            let controller_id = self.next_to_use.to_string();
-            self.controller_ids.push(controller_id.clone());
+        let mut order: Vec<usize> = (0..len).map(|i| (start + i) % len)
+            .collect();
 
-            let wc = WorkerController::new(controller_id);
-            let controller_address = WorkerController::start_in_arbiter(
-                arbiter,
-                move |_| {
-                    wc
-                }
-            );
+        self.replace_cold();
+        order.retain(|i| !self.standby.contains(i));
 
-            self.controllers.push(controller_address);
+        let scores: Vec<f64> = self.loads.iter()
+            .map(|l| l.as_ref().map_or(0.0, |l| l.health_score))
+            .collect();
 
-            created = true;
+        self.update_exclusions(&scores);
+        if order.iter().any(|i| !self.is_excluded(*i)) {
+            order.retain(|i| !self.is_excluded(*i));
         }
 
-        // Try to find a controller that is ready to accept the task.
-        let orig_next_to_use = self.next_to_use;
+        let loads = &self.loads;
+        match self.strategy {
+            SelectionStrategy::LeastLoaded => {
+                // Stable: the round robin order among the equally loaded.
+                order.sort_by_key(|&i| {
+                    let free = loads[i].as_ref().map_or(0, |l| l.free_slots);
+                    std::cmp::Reverse(free)
+                });
+            },
+            SelectionStrategy::PluginAffinity => {
+                order.sort_by_key(|&i| {
+                    !loads[i].as_ref().is_some_and(|l| l.plugin == plugin)
+                });
+            },
+            _ => {},
+        }
 
-        loop {
-            let addr = &self.controllers[self.next_to_use];
+        order.sort_by_key(|&i| {
+            std::cmp::Reverse((scores[i] * HEALTH_BUCKETS) as u32)
+        });
 
-            let reserve_result = self.try_to_reserve_for_task(
-                addr,
-                task_uuid.to_string(),
-            ).await;
+        order
+    }
 
-            self.next_to_use += 1;
-            if self.next_to_use >= self.controllers.len() {
-                self.next_to_use = 0;
+    /// Exclude the controllers below `health::min_score` for
+    /// `health::exclude_for`, again once it has passed only if the score
+    /// has gone down meanwhile.
+    fn update_exclusions(&mut self, scores: &[f64]) {
+        let min_score = health::min_score();
+        let now = Instant::now();
+
+        for (i, &score) in scores.iter().enumerate() {
+            if score >= min_score {
+                self.excluded.remove(&i);
+                continue;
             }
 
-            if reserve_result {
-                let id = self.controller_ids[self.next_to_use].to_owned();
-                return Some((addr.clone(), id, created));
+            let exclude = self.excluded.get(&i)
+                .is_none_or(|e| e.until <= now && score < e.score);
+            if exclude {
+                let until = now + health::exclude_for();
+                self.excluded.insert(i, Exclusion { until, score });
             }
+        }
+    }
+
+    fn is_excluded(&self, i: usize) -> bool {
+        self.excluded.get(&i).is_some_and(|e| e.until > Instant::now())
+    }
+
+    /// The controller `i` as a candidate for a task.
+    fn info(&self, i: usize, created: Option<usize>) -> ControllerInfo {
+        ControllerInfo {
+            addr: self.controllers[i].clone(),
+            id: self.controller_ids[i].clone(),
+            created: created == Some(i),
+        }
+    }
+}
 
-            if self.next_to_use == orig_next_to_use {
-                // We've made a full cycle over the controllers.
-                // Result is negative.
-                break;
+/// The default pool and those of the named queues.
+pub struct ControllerPools {
+    default: ControllerPool,
+
+    /// Queue name --> Pool
+    queues: HashMap<String, ControllerPool>,
+
+    /// Queue name --> Plugin of the tasks not requiring any
+    plugins: HashMap<String, WorkerPlugin>,
+}
+
+impl ControllerPools {
+    pub fn new(capacity: usize) -> Self {
+        let mut pools = Self {
+            default: ControllerPool::new(capacity),
+            queues: HashMap::new(),
+            plugins: HashMap::new(),
+        };
+
+        pools.load_queues();
+        pools
+    }
+
+    /// Add the queues configured meanwhile, resize the rest. The queues no
+    /// longer configured are kept.
+    fn load_queues(&mut self) {
+        for (name, params) in queue_params() {
+            match self.queues.get_mut(&name) {
+                Some(pool) => {
+                    pool.set_capacity(params.capacity());
+                    pool.set_standby(
+                        params.standby_workers,
+                        params.standby_plugin(),
+                    );
+                },
+                None => {
+                    let pool = ControllerPool::for_queue(&name, &params);
+                    self.queues.insert(name.clone(), pool);
+                },
+            }
+
+            match params.plugin {
+                Some(ref p) => {
+                    let plugin = WorkerPlugin::from_str(p);
+                    self.plugins.insert(name, plugin);
+                },
+                None => {
+                    self.plugins.remove(&name);
+                },
             }
         }
+    }
 
-        None
+    pub fn reload(&mut self, capacity: usize) {
+        self.default.set_capacity(capacity);
+        self.default.set_standby(standby_workers(), standby_plugin());
+        self.load_queues();
     }
 
-    async fn try_to_reserve_for_task(
-        &self,
-        controller_addr: &Addr<WorkerController>,
-        task_uuid: String,
-    ) -> bool {
-        let res = controller_addr.send(ReserveForTask { task_uuid }).await;
+    /// Start the standby controllers of all the pools.
+    pub fn warm_up(&mut self) {
+        self.default.warm_up();
+        for pool in self.queues.values_mut() {
+            pool.warm_up();
+        }
+    }
+
+    pub fn has_queue(&self, queue: &str) -> bool {
+        self.queues.contains_key(queue)
+    }
 
-        match res {
-            Ok(r) => { r },
-            _ => { false },
+    /// The pool of `queue`, the default one if the queue is not configured.
+    pub fn get(&mut self, queue: &str) -> &mut ControllerPool {
+        match self.queues.get_mut(queue) {
+            Some(pool) => pool,
+            None => &mut self.default,
         }
     }
 
+    pub fn plugin(&self, queue: &str) -> Option<WorkerPlugin> {
+        self.plugins.get(queue).copied()
+    }
+
+    /// Queue name --> Capacity
+    pub fn capacities(&self) -> BTreeMap<String, usize> {
+        self.queues.iter()
+            .map(|(name, pool)| (name.clone(), pool.capacity()))
+            .collect()
+    }
+}
+
+/// Reserve a controller of the pool of `queue` for the task, trying them in
+/// the order of preference. The pools are not locked while the controllers
+/// are asked for their load or reserved.
+pub async fn next(
+    pools: &Mutex<ControllerPools>,
+    queue: &str,
+    arbiter: &ArbiterHandle,
+    task_uuid: &str,
+    task_name: &str,
+    plugin: WorkerPlugin,
+) -> Option<ControllerInfo> {
+    let (created, to_probe) = {
+        let mut pools = pools.lock().unwrap();
+        let pool = pools.get(queue);
+        (pool.grow(arbiter), pool.to_probe())
+    };
+
+    if let Some(controllers) = to_probe {
+        let loads = probe_loads(&controllers).await;
+        pools.lock().unwrap().get(queue).set_loads(loads);
+    }
+
+    let candidates: Vec<_> = {
+        let mut pools = pools.lock().unwrap();
+        let pool = pools.get(queue);
+        pool.candidates(task_name, plugin).into_iter()
+            .map(|i| (i, pool.info(i, created)))
+            .collect()
+    };
+
+    let mut reserved = None;
+    for (i, info) in candidates {
+        if try_to_reserve_for_task(&info.addr, task_uuid, plugin).await {
+            reserved = Some((i, info));
+            break;
+        }
+    }
+
+    let mut pools = pools.lock().unwrap();
+    let pool = pools.get(queue);
+    if let Some((i, _)) = reserved {
+        pool.reserved(i);
+    }
+    pool.warm_up();
+
+    reserved.map(|(_, info)| info)
+}
+
+/// `None` for a controller not answering in time. The controllers are
+/// asked at once.
+async fn probe_loads(
+    controllers: &[Addr<WorkerController>],
+) -> Vec<Option<ControllerLoad>> {
+    let requests: Vec<_> = controllers.iter()
+        .map(|addr| addr.send(GetLoad).timeout(LOAD_PROBE_TIMEOUT))
+        .collect();
+
+    let mut loads = Vec::with_capacity(requests.len());
+    for request in requests {
+        loads.push(request.await.ok());
+    }
+
+    loads
+}
+
+async fn try_to_reserve_for_task(
+    controller_addr: &Addr<WorkerController>,
+    task_uuid: &str,
+    plugin: WorkerPlugin,
+) -> bool {
+    let res = controller_addr
+        .send(ReserveForTask {
+            task_uuid: task_uuid.to_string(),
+            plugin,
+            lease: slots::reservation_lease(),
+        })
+        .await;
+
+    res.unwrap_or(false)
 }
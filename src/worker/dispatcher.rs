@@ -1,43 +1,149 @@
 use actix::prelude::*;
 use slog::Logger;
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use crate::{
-    core::logger::create_logger,
+    core::{
+        backpressure,
+        error_bus::{self, PatokaError},
+        logger::create_logger,
+        monitor::{self, MailboxProbe},
+    },
     worker::{
         controller::{WorkerController},
         backend_connector::{self, WorkerBackendConnector},
-        worker_message::*,
+        identity_table::IdentityTable,
+        node_registry,
+        worker_message::{self, *},
     },
+    handler_impl_mailbox_probe,
     transport::message::*,
 };
 
+/// Module name used to publish errors.
+const MODULE: &str = "task_dispatcher";
+
+/// How often the stale worker identities are removed.
+const IDENTITY_CLEANUP_INTERVAL: Duration = Duration::from_secs(30);
+
 pub struct RegisterController {
     pub controller_id: String,
     pub controller_addr: Addr<WorkerController>,
+
+    /// The worker identity is set by `AcceptWorkerIdentity` only, see
+    /// `worker_auth`.
+    pub authenticated: bool,
 }
 
 impl Message for RegisterController {
     type Result = ();
 }
 
+/// The controller has validated the token sent from `identity`.
+pub struct AcceptWorkerIdentity {
+    pub worker_id: String,
+    pub identity: Identity,
+}
+
+impl Message for AcceptWorkerIdentity {
+    type Result = ();
+}
+
+/// The controller has been reserved for the task: the messages of the task
+/// are routed to it if their worker ID is empty or unknown.
+pub struct RouteTask {
+    pub task_uuid: String,
+    pub controller_id: String,
+}
+
+impl Message for RouteTask {
+    type Result = ();
+}
+
+/// The task is closed or its reservation released. The route is kept if
+/// the task has been routed to another controller meanwhile.
+pub struct ForgetTask {
+    pub task_uuid: String,
+    pub controller_id: String,
+}
+
+impl Message for ForgetTask {
+    type Result = ();
+}
+
 pub struct TaskDispatcher {
     log: Logger,
     router_addr: Addr<WorkerBackendConnector>,
-    controllers: HashMap<String, Addr<WorkerController>>
+    controllers: HashMap<String, Addr<WorkerController>>,
+
+    /// Where to send the messages to the workers.
+    identities: IdentityTable,
+
+    /// Worker ID --> Accepted identity
+    /// The workers authenticated by their controllers.
+    authenticated: HashMap<String, Option<Vec<u8>>>,
+
+    /// Task UUID --> Controller ID
+    /// The fallback route of the messages by task.
+    tasks: HashMap<String, String>,
 }
 
 impl TaskDispatcher {
+    /// Set the worker ID of `msg` to the controller of its task if the one
+    /// it has is empty or unknown. A worker ID disagreeing with the
+    /// controller of the task is reported and kept.
+    fn resolve_route(&self, msg: &mut WorkerMessage) {
+        let worker_id = &msg.payload.worker_id;
+        let controller_id = match self.tasks.get(&msg.payload.task_uuid) {
+            Some(id) if id != worker_id => id,
+            _ => return,
+        };
+
+        if self.controllers.contains_key(worker_id) {
+            error_bus::publish(PatokaError::warning(
+                MODULE,
+                format!(
+                    "Route mismatch [WORKER ID] {} [CONTROLLER ID] {}",
+                    worker_id,
+                    controller_id,
+                ),
+            ).task(&msg.payload.task_uuid));
+            return;
+        }
+
+        debug!(
+            self.log,
+            "Routing [TASK UUID] {} to [CONTROLLER ID] {} instead of \
+                [WORKER ID] {:?}",
+            msg.payload.task_uuid,
+            controller_id,
+            worker_id,
+        );
+        msg.payload.worker_id = controller_id.clone();
+    }
+
     fn send_to_controller(&self, msg: WorkerMessage) {
         if let Some(addr) = self.controllers.get(&msg.payload.worker_id) {
-            addr.do_send(msg);
+            backpressure::do_send("dispatcher.controller", addr, msg);
         } else {
-            warn!(
-                self.log,
-                "Unable to send a message to an unregistered controller \
-                    [WORKER ID] {}",
-                msg.payload.worker_id,
-            );
+            error_bus::publish(PatokaError::warning(
+                MODULE,
+                format!(
+                    "Unable to send a message to an unregistered controller \
+                        [WORKER ID] {}",
+                    msg.payload.worker_id,
+                ),
+            ).task(&msg.payload.task_uuid));
+        }
+    }
+
+    /// The identity of `msg` may be routed to.
+    fn is_accepted(&self, msg: &WorkerMessage) -> bool {
+        match self.authenticated.get(&msg.payload.worker_id) {
+            Some(accepted) => {
+                accepted.as_deref() == Some(&msg.identity as &[u8])
+            },
+            None => true,
         }
     }
 }
@@ -45,9 +151,12 @@ impl TaskDispatcher {
 impl Default for TaskDispatcher {
     fn default() -> Self {
         Self {
-            log: create_logger("task_dispatcher"),
+            log: create_logger(MODULE),
             router_addr: backend_connector::start(),
             controllers: HashMap::new(),
+            identities: IdentityTable::load(),
+            authenticated: HashMap::new(),
+            tasks: HashMap::new(),
         }
     }
 }
@@ -55,8 +164,17 @@ impl Default for TaskDispatcher {
 impl Actor for TaskDispatcher {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
+    fn started(&mut self, ctx: &mut Self::Context) {
         info!(self.log, "Task Dispatcher started.");
+
+        monitor::watch_mailbox(MODULE, ctx.address().recipient());
+
+        ctx.run_interval(IDENTITY_CLEANUP_INTERVAL, |act, _ctx| {
+            let removed = act.identities.remove_stale();
+            if removed > 0 {
+                debug!(act.log, "Removed {} stale worker identities.", removed);
+            }
+        });
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -64,6 +182,8 @@ impl Actor for TaskDispatcher {
     }
 }
 
+handler_impl_mailbox_probe!(TaskDispatcher);
+
 impl Supervised for TaskDispatcher {}
 
 impl SystemService for TaskDispatcher {
@@ -81,8 +201,8 @@ impl Handler<RawMessage> for TaskDispatcher {
         _ctx: &mut Self::Context
     ) -> Self::Result {
 
-        match RawMessage::to::<WorkerMessagePayload>(msg) {
-            Ok(worker_message) => {
+        match worker_message::parse(msg) {
+            Ok(mut worker_message) => {
                 /*trace!(
                     self.log,
                     "Received a worker message: {}",
@@ -91,8 +211,21 @@ impl Handler<RawMessage> for TaskDispatcher {
 
                 match worker_message.payload.dest {
                     Dest::Controller | Dest::Client => {
+                        self.resolve_route(&mut worker_message);
+
+                        // Validated by the controller.
+                        if self.is_accepted(&worker_message) {
+                            self.identities.update(&worker_message);
+                        }
                         self.send_to_controller(worker_message);
                     },
+                    Dest::Node => {
+                        backpressure::do_send(
+                            "dispatcher.node_registry",
+                            &node_registry::start(),
+                            worker_message,
+                        );
+                    },
                     Dest::Worker => {
                         warn!(self.log, "Not expecting dest Worker.");
                     }
@@ -102,7 +235,10 @@ impl Handler<RawMessage> for TaskDispatcher {
                 }
             },
             Err(e) => {
-                warn!(self.log, "Invalid raw worker message: {}", e);
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    format!("Invalid raw worker message: {}", e),
+                ));
             }
         }
 
@@ -114,15 +250,33 @@ impl Handler<WorkerMessage> for TaskDispatcher {
 
     fn handle(
         &mut self,
-        msg: WorkerMessage,
+        mut msg: WorkerMessage,
         _ctx: &mut Self::Context
     ) -> Self::Result {
         match msg.payload.dest {
             Dest::Controller | Dest::Client => {
+                self.resolve_route(&mut msg);
                 self.send_to_controller(msg);
             },
             Dest::Worker => {
-                self.router_addr.do_send(RawMessage::from(msg));
+                // Not seen yet, e.g. an external worker not connected yet,
+                // the message is sent as is.
+                if let Some(identity) = self.identities.lookup(&msg) {
+                    msg.identity = identity;
+                }
+
+                backpressure::do_send(
+                    "dispatcher.router",
+                    &self.router_addr,
+                    RawMessage::from(msg),
+                );
+            },
+            Dest::Node => {
+                backpressure::do_send(
+                    "dispatcher.router",
+                    &self.router_addr,
+                    RawMessage::from(msg),
+                );
             },
             _ => {
                 warn!(self.log, "Unknown message dest.");
@@ -142,10 +296,62 @@ impl Handler<RegisterController> for TaskDispatcher {
 
         info!(self.log, "Registering [CONTROLLER ID] {}.", msg.controller_id);
 
+        if msg.authenticated {
+            self.authenticated.insert(msg.controller_id.clone(), None);
+        }
+
         self.controllers.insert(msg.controller_id, msg.controller_addr);
     }
 }
 
+impl Handler<AcceptWorkerIdentity> for TaskDispatcher {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: AcceptWorkerIdentity,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        info!(self.log, "Accepted a new identity of [WORKER ID] {}.",
+            msg.worker_id);
+
+        self.authenticated
+            .insert(msg.worker_id.clone(), Some(msg.identity.to_vec()));
+
+        let mut payload = WorkerMessagePayload::new();
+        payload.dest = Dest::Controller;
+        payload.worker_id = msg.worker_id;
+        self.identities
+            .update(&WorkerMessage::with_identity(payload, msg.identity));
+    }
+}
+
+impl Handler<RouteTask> for TaskDispatcher {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: RouteTask,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.tasks.insert(msg.task_uuid, msg.controller_id);
+    }
+}
+
+impl Handler<ForgetTask> for TaskDispatcher {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: ForgetTask,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        if self.tasks.get(&msg.task_uuid) == Some(&msg.controller_id) {
+            self.tasks.remove(&msg.task_uuid);
+        }
+    }
+}
+
 pub fn start() -> Addr<TaskDispatcher> {
     TaskDispatcher::from_registry()
 }
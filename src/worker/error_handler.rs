@@ -2,17 +2,15 @@ use actix::prelude::*;
 use serde_derive::Deserialize;
 use serde_json;
 use slog::Logger;
+use std::collections::HashMap;
 
 use crate::{
-    control::{
-        message::StopTask,
-    },
     core::{
         env,
         logger::create_logger,
     },
     worker::{
-        controller::WorkerController,
+        controller::{RotateFingerprint, StopFailedTask},
         task::{ControllerAddr, TaskStatus},
         task_assistant::self,
         worker_message::WorkerMessage,
@@ -28,6 +26,38 @@ pub struct TaskErrorHandlerParams {
     /// Delay before task restart, ms.
     #[serde(default)]
     restart_delay: usize,
+
+    /// Rotate the worker's proxy and user agent when the task is
+    /// blocked/denied by the target. `true` by default.
+    #[serde(default = "default_rotate_on_blocked")]
+    rotate_on_blocked: bool,
+
+    /// Error category --> Policy, e.g. `parse = "fail_fast"`. See
+    /// `default_policy` for the rest.
+    #[serde(default)]
+    policies: HashMap<String, ErrorPolicy>,
+
+    /// The "rotate_proxy" errors in a row the proxy is marked blocked and
+    /// the step is retried with a fresh proxy and user agent after.
+    #[serde(default = "default_blocked_threshold")]
+    blocked_threshold: usize,
+
+    /// Rotations per task, then the "rotate_proxy" errors are retried with
+    /// the same fingerprint.
+    #[serde(default = "default_max_rotations")]
+    max_rotations: usize,
+}
+
+fn default_rotate_on_blocked() -> bool {
+    true
+}
+
+fn default_blocked_threshold() -> usize {
+    1
+}
+
+fn default_max_rotations() -> usize {
+    3
 }
 
 impl TaskErrorHandlerParams {
@@ -35,8 +65,120 @@ impl TaskErrorHandlerParams {
         Self {
             max_errors_then_failure: 0,
             restart_delay: 0,
+            rotate_on_blocked: default_rotate_on_blocked(),
+            policies: HashMap::new(),
+            blocked_threshold: default_blocked_threshold(),
+            max_rotations: default_max_rotations(),
+        }
+    }
+
+    pub fn policy(&self, category: ErrorCategory) -> ErrorPolicy {
+        match self.policies.get(category.as_str()) {
+            Some(p) => *p,
+            None => self.default_policy(category),
         }
     }
+
+    /// The blocked and CAPTCHA errors rotate the fingerprint if
+    /// `rotate_on_blocked`, the rest are retried.
+    fn default_policy(&self, category: ErrorCategory) -> ErrorPolicy {
+        match category {
+            ErrorCategory::Blocked | ErrorCategory::Captcha
+                if self.rotate_on_blocked => ErrorPolicy::RotateProxy,
+            _ => ErrorPolicy::Retry,
+        }
+    }
+}
+
+/// What a worker error is about, parsed from its `status` and
+/// `kind`/`type`/`code`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Network,
+
+    /// Blocked or denied access by the target, e.g. 403.
+    Blocked,
+
+    Captcha,
+    Parse,
+    Timeout,
+
+    /// Anything else.
+    Internal,
+}
+
+/// HTTP statuses the targets usually respond with when they block a client.
+const BLOCKED_STATUSES: [u64; 3] = [403, 407, 429];
+
+const TIMEOUT_STATUSES: [u64; 2] = [408, 504];
+
+const NETWORK_STATUSES: [u64; 2] = [502, 503];
+
+/// Error kinds reported by the worker, by category.
+const KINDS: [(ErrorCategory, &[&str]); 5] = [
+    (ErrorCategory::Blocked, &["blocked", "denied", "forbidden"]),
+    (ErrorCategory::Captcha, &["captcha"]),
+    (
+        ErrorCategory::Timeout,
+        &["timeout", "timed_out", "etimedout", "navigation_timeout"],
+    ),
+    (
+        ErrorCategory::Network,
+        &[
+            "network", "connection", "dns", "econnrefused", "econnreset",
+            "enotfound", "ehostunreach", "proxy",
+        ],
+    ),
+    (ErrorCategory::Parse, &["parse", "parsing", "syntax", "selector"]),
+];
+
+impl ErrorCategory {
+    pub fn of(error: &serde_json::Value) -> Self {
+        for key in ["kind", "type", "code"] {
+            let kind = match error.get(key).and_then(|k| k.as_str()) {
+                Some(k) => k.to_lowercase(),
+                None => continue,
+            };
+
+            for (category, kinds) in KINDS {
+                if kinds.contains(&kind.as_str()) {
+                    return category;
+                }
+            }
+        }
+
+        match error.get("status").and_then(|s| s.as_u64()) {
+            Some(s) if BLOCKED_STATUSES.contains(&s) => ErrorCategory::Blocked,
+            Some(s) if TIMEOUT_STATUSES.contains(&s) => ErrorCategory::Timeout,
+            Some(s) if NETWORK_STATUSES.contains(&s) => ErrorCategory::Network,
+            _ => ErrorCategory::Internal,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Network => "network",
+            ErrorCategory::Blocked => "blocked",
+            ErrorCategory::Captcha => "captcha",
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Internal => "internal",
+        }
+    }
+}
+
+/// What the handler does on an error of a category.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Count the error towards `max_errors_then_failure`.
+    Retry,
+
+    /// Retry with a different proxy and user agent.
+    RotateProxy,
+
+    /// Fail the task at once.
+    FailFast,
 }
 
 #[derive(Clone)]
@@ -47,6 +189,11 @@ pub struct TaskErrorHandler {
     params: TaskErrorHandlerParams,
     failure: bool,
     error_counter: usize,
+
+    /// "rotate_proxy" errors in a row.
+    blocked_counter: usize,
+
+    rotations: usize,
 }
 
 impl TaskErrorHandler {
@@ -78,6 +225,8 @@ impl TaskErrorHandler {
             params,
             failure: false,
             error_counter: 0,
+            blocked_counter: 0,
+            rotations: 0,
         }
     }
 
@@ -100,17 +249,25 @@ impl TaskErrorHandler {
         ctx: &mut C,
     ) -> bool {
         if let Some(e) = msg.error() {
+            let category = ErrorCategory::of(&e);
+            let policy = self.params.policy(category);
+
             self.error_counter += 1;
 
             debug!(
                 self.log,
-                "Error [TASK UUID] {} [ERROR COUNTER] {} [PARAMS] {:?}",
+                "Error [TASK UUID] {} [CATEGORY] {:?} [POLICY] {:?} \
+                    [ERROR COUNTER] {} [PARAMS] {:?}",
                 self.task_uuid,
+                category,
+                policy,
                 self.error_counter,
                 self.params,
             );
 
-            if self.error_counter > self.params.max_errors_then_failure {
+            if policy == ErrorPolicy::FailFast
+                || self.error_counter > self.params.max_errors_then_failure
+            {
                 info!(
                     self.log,
                     "Terminate with FAILURE [TASK UUID] {}",
@@ -122,20 +279,73 @@ impl TaskErrorHandler {
                 if let ControllerAddr::Controller(addr) =
                     &self.controller_addr
                 {
-                    addr.do_send(
-                        StopTask { task_uuid: self.task_uuid.clone() }
-                    );
+                    addr.do_send(StopFailedTask {
+                        task_uuid: self.task_uuid.clone(),
+                        error: e,
+                    });
                 }
 
                 ctx.stop();
+            } else if policy == ErrorPolicy::RotateProxy
+                && self.rotations < self.params.max_rotations
+            {
+                self.rotate(category);
             }
 
             true
         } else {
             // Reset.
             self.error_counter = 0;
+            self.blocked_counter = 0;
 
             false
         }
     }
+
+    /// Retry the step with a different fingerprint once `blocked_threshold`
+    /// is reached.
+    fn rotate(&mut self, category: ErrorCategory) {
+        self.blocked_counter += 1;
+        if self.blocked_counter < self.params.blocked_threshold.max(1) {
+            return;
+        }
+
+        self.blocked_counter = 0;
+        self.rotations += 1;
+
+        info!(
+            self.log,
+            "Rotate the fingerprint of [TASK UUID] {} [CATEGORY] {:?} \
+                [ROTATIONS] {}/{}",
+            self.task_uuid,
+            category,
+            self.rotations,
+            self.params.max_rotations,
+        );
+
+        if let ControllerAddr::Controller(addr) = &self.controller_addr {
+            addr.do_send(RotateFingerprint {
+                task_uuid: self.task_uuid.clone(),
+                retry: true,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn categories() {
+        let of = |e| ErrorCategory::of(&e);
+        assert_eq!(of(json!({ "status": 403 })), ErrorCategory::Blocked);
+        assert_eq!(of(json!({ "kind": "CAPTCHA" })), ErrorCategory::Captcha);
+        assert_eq!(of(json!({ "code": "ECONNRESET" })), ErrorCategory::Network);
+        assert_eq!(of(json!({ "type": "timeout" })), ErrorCategory::Timeout);
+        assert_eq!(of(json!({ "status": 504 })), ErrorCategory::Timeout);
+        assert_eq!(of(json!({ "kind": "parse" })), ErrorCategory::Parse);
+        assert_eq!(of(json!({ "message": "?" })), ErrorCategory::Internal);
+    }
 }
@@ -0,0 +1,207 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::core::{env, status_aggregator::StatusSnapshot};
+
+lazy_static! {
+    static ref PARAMS: WorkerHealthParams =
+        env::load_opt("worker_health").unwrap_or_default();
+}
+
+/// `[worker_health]` configuration section.
+#[derive(Deserialize)]
+struct WorkerHealthParams {
+    /// 0 disables the exclusion.
+    #[serde(default = "default_min_score")]
+    min_score: f64,
+
+    #[serde(default = "default_exclude_s")]
+    exclude_s: u64,
+
+    #[serde(default = "default_window_s")]
+    window_s: u64,
+
+    /// The heartbeat latency halving the score.
+    #[serde(default = "default_max_latency_ms")]
+    max_latency_ms: u64,
+}
+
+fn default_min_score() -> f64 { 0.5 }
+
+fn default_exclude_s() -> u64 { 30 }
+
+fn default_window_s() -> u64 { 300 }
+
+fn default_max_latency_ms() -> u64 { 1000 }
+
+impl Default for WorkerHealthParams {
+    fn default() -> Self {
+        Self {
+            min_score: default_min_score(),
+            exclude_s: default_exclude_s(),
+            window_s: default_window_s(),
+            max_latency_ms: default_max_latency_ms(),
+        }
+    }
+}
+
+pub fn min_score() -> f64 {
+    PARAMS.min_score
+}
+
+/// How long a controller below `min_score` is excluded.
+pub fn exclude_for() -> Duration {
+    Duration::from_secs(PARAMS.exclude_s)
+}
+
+/// The weight of the latest heartbeat latency in the moving average.
+const LATENCY_WEIGHT: f64 = 0.3;
+
+/// Heartbeat latency, failed tasks and recoveries within `window_s`, scored
+/// 0 to 1 for the controller pool.
+pub struct WorkerHealth {
+    /// Exponential moving average, `None` until the first heartbeat.
+    latency_ms: Option<f64>,
+
+    /// (Finished at, Failed) of the tasks within the window.
+    outcomes: VecDeque<(Instant, bool)>,
+
+    /// Within the window.
+    recoveries: VecDeque<Instant>,
+
+    /// Failed and not closed yet.
+    failed_tasks: HashSet<String>,
+
+    window: Duration,
+}
+
+impl Default for WorkerHealth {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(PARAMS.window_s))
+    }
+}
+
+impl WorkerHealth {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            latency_ms: None,
+            outcomes: VecDeque::new(),
+            recoveries: VecDeque::new(),
+            failed_tasks: HashSet::new(),
+            window,
+        }
+    }
+
+    pub fn heartbeat(&mut self, latency: Duration) {
+        let ms = latency.as_secs_f64() * 1000.0;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(avg) => avg + LATENCY_WEIGHT * (ms - avg),
+            None => ms,
+        });
+    }
+
+    /// Counted once the task is closed.
+    pub fn task_failed(&mut self, task_uuid: &str) {
+        self.failed_tasks.insert(task_uuid.to_string());
+    }
+
+    pub fn task_closed(&mut self, task_uuid: &str) {
+        let failed = self.failed_tasks.remove(task_uuid);
+        self.outcomes.push_back((Instant::now(), failed));
+        self.expire();
+    }
+
+    pub fn recovered(&mut self) {
+        self.recoveries.push_back(Instant::now());
+        self.expire();
+    }
+
+    pub fn latency_ms(&self) -> Option<f64> {
+        self.latency_ms
+    }
+
+    /// Of the tasks closed within the window, 0 if none.
+    pub fn error_rate(&self) -> f64 {
+        let (total, failed) = self.outcomes.iter()
+            .filter(|(at, _)| at.elapsed() < self.window)
+            .fold((0, 0), |(t, f), (_, failed)| (t + 1, f + *failed as u32));
+
+        if total == 0 {
+            0.0
+        } else {
+            failed as f64 / total as f64
+        }
+    }
+
+    pub fn recent_recoveries(&self) -> usize {
+        self.recoveries.iter()
+            .filter(|at| at.elapsed() < self.window)
+            .count()
+    }
+
+    /// 1 for a healthy worker: the success rate, halved by each recent
+    /// recovery and by up to a half by the latency.
+    pub fn score(&self) -> f64 {
+        let max_latency = PARAMS.max_latency_ms.max(1) as f64;
+        let latency = self.latency_ms.unwrap_or_default() / max_latency;
+        let latency_factor = 1.0 - latency.min(1.0) / 2.0;
+        let recovery_factor = 0.5f64.powi(self.recent_recoveries() as i32);
+
+        (1.0 - self.error_rate()) * latency_factor * recovery_factor
+    }
+
+    pub fn add_to(&self, snapshot: StatusSnapshot) -> StatusSnapshot {
+        snapshot
+            .with("health_score", self.score())
+            .with("heartbeat_latency_ms", self.latency_ms)
+            .with("error_rate", self.error_rate())
+            .with("recent_recoveries", self.recent_recoveries())
+    }
+
+    fn expire(&mut self) {
+        let window = self.window;
+        while self.outcomes.front().is_some_and(|(at, _)| {
+            at.elapsed() >= window
+        }) {
+            self.outcomes.pop_front();
+        }
+        while self.recoveries.front().is_some_and(|at| {
+            at.elapsed() >= window
+        }) {
+            self.recoveries.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score() {
+        let mut health = WorkerHealth::new(Duration::from_secs(60));
+        assert_eq!(health.score(), 1.0);
+
+        health.task_failed("a");
+        health.task_closed("a");
+        health.task_closed("b");
+        assert_eq!(health.error_rate(), 0.5);
+        assert_eq!(health.score(), 0.5);
+
+        health.recovered();
+        assert_eq!(health.score(), 0.25);
+
+        health.heartbeat(Duration::from_millis(PARAMS.max_latency_ms * 2));
+        assert_eq!(health.score(), 0.125);
+
+        let mut health = WorkerHealth::new(Duration::ZERO);
+        health.task_failed("a");
+        health.task_closed("a");
+        health.recovered();
+        assert_eq!(health.score(), 1.0);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    core::env,
+    transport::message::*,
+    worker::worker_message::WorkerMessage,
+};
+
+/// `general.worker_identity_ttl_s`
+const DEFAULT_TTL_S: u64 = 300;
+
+struct Entry {
+    identity: Vec<u8>,
+    seen_at: Instant,
+}
+
+impl Entry {
+    fn new(identity: &Identity) -> Self {
+        Self {
+            identity: identity.to_vec(),
+            seen_at: Instant::now(),
+        }
+    }
+}
+
+/// The ZMQ identities the workers have last sent their messages from. The
+/// messages to a worker are routed to the identity the task is run by, or
+/// the one the worker ID has been seen with.
+pub struct IdentityTable {
+    ttl: Duration,
+
+    /// Worker ID --> Identity
+    workers: HashMap<String, Entry>,
+
+    /// Task UUID --> Identity
+    /// Several external workers may share a worker ID.
+    tasks: HashMap<String, Entry>,
+}
+
+impl IdentityTable {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            workers: HashMap::new(),
+            tasks: HashMap::new(),
+        }
+    }
+
+    pub fn load() -> Self {
+        let ttl_s = env::get_opt_var("general.worker_identity_ttl_s")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_S);
+
+        Self::new(Duration::from_secs(ttl_s))
+    }
+
+    /// Remember the identity of a message from the worker.
+    pub fn update(&mut self, msg: &WorkerMessage) {
+        if is_empty(&msg.identity) {
+            return;
+        }
+
+        // Another worker reconnected with the same identity.
+        let identity = &msg.identity as &[u8];
+        let worker_id = &msg.payload.worker_id;
+        self.workers.retain(|id, e| id == worker_id || e.identity != identity);
+
+        let prev = self.workers
+            .insert(worker_id.clone(), Entry::new(&msg.identity));
+
+        // The worker reconnected, its tasks are not run by the old identity.
+        if let Some(prev) = prev.filter(|p| p.identity != identity) {
+            self.tasks.retain(|_, e| e.identity != prev.identity);
+        }
+
+        if !msg.payload.task_uuid.is_empty() {
+            self.tasks.insert(
+                msg.payload.task_uuid.clone(),
+                Entry::new(&msg.identity),
+            );
+        }
+    }
+
+    /// The identity to send `msg` to. `None` if the worker has not been
+    /// seen recently.
+    pub fn lookup(&self, msg: &WorkerMessage) -> Option<Identity> {
+        self.tasks.get(&msg.payload.task_uuid)
+            .or_else(|| self.workers.get(&msg.payload.worker_id))
+            .filter(|e| e.seen_at.elapsed() < self.ttl)
+            .map(|e| Identity::from(e.identity.as_slice()))
+    }
+
+    /// Forget the identities not seen for longer than TTL. Returns the
+    /// number of entries removed.
+    pub fn remove_stale(&mut self) -> usize {
+        let before = self.workers.len() + self.tasks.len();
+        let ttl = self.ttl;
+        self.workers.retain(|_, e| e.seen_at.elapsed() < ttl);
+        self.tasks.retain(|_, e| e.seen_at.elapsed() < ttl);
+
+        before - self.workers.len() - self.tasks.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::worker_message::{Dest, WorkerMessagePayload};
+
+    fn message(
+        worker_id: &str,
+        task_uuid: &str,
+        identity: &[u8],
+    ) -> WorkerMessage {
+        let mut payload = WorkerMessagePayload::new();
+        payload.dest = Dest::Client;
+        payload.worker_id = worker_id.to_string();
+        payload.task_uuid = task_uuid.to_string();
+
+        WorkerMessage::with_identity(payload, Identity::from(identity))
+    }
+
+    #[test]
+    fn route_by_task_then_worker() {
+        let mut table = IdentityTable::new(Duration::from_secs(60));
+        table.update(&message("w1", "", b"a"));
+        table.update(&message("w1", "t2", b"b"));
+
+        let to_worker = message("w1", "", b"");
+        assert_eq!(&table.lookup(&to_worker).unwrap() as &[u8], b"b");
+        let to_task = message("w1", "t2", b"");
+        assert_eq!(&table.lookup(&to_task).unwrap() as &[u8], b"b");
+
+        // The identity is taken over by another worker.
+        table.update(&message("w2", "", b"b"));
+        assert_eq!(table.len(), 1);
+        assert!(table.lookup(&message("w3", "", b"")).is_none());
+    }
+
+    #[test]
+    fn remove_stale() {
+        let mut table = IdentityTable::new(Duration::ZERO);
+        table.update(&message("w1", "t1", b"a"));
+        assert!(table.lookup(&message("w1", "", b"")).is_none());
+        assert_eq!(table.remove_stale(), 2);
+        assert!(table.is_empty());
+    }
+}
@@ -1,8 +1,13 @@
 #[macro_use]
 pub mod tracker;
 
+pub mod attachment;
 pub mod backend_connector;
+pub mod cancellation;
+pub mod captcha;
+pub mod circuit_breaker;
 pub mod client;
+pub mod client_template;
 pub mod controller;
 pub mod controller_message;
 pub mod controller_pool;
@@ -10,17 +15,38 @@ pub mod dispatcher;
 pub mod error_handler;
 pub mod external;
 pub mod external_message;
+pub mod health;
+pub mod identity_table;
 pub mod link;
+pub mod name_pattern;
+pub mod node_registry;
+pub mod params_schema;
 pub mod plugin;
+pub mod process_group;
 pub mod processor;
+pub mod question;
+pub mod recurring;
+pub mod recycle;
 pub mod reprocessor;
+pub mod result_chunk;
 pub mod router;
+pub mod session_recorder;
 pub mod setup;
+pub mod slots;
+pub mod startup;
 pub mod state;
 pub mod task;
 pub mod task_assistant;
+pub mod task_future;
+pub mod task_history;
+pub mod task_labels;
+pub mod task_lifecycle;
 pub mod task_reader;
+pub mod task_sink;
+pub mod task_stats;
 pub mod task_tree;
 pub mod task_writer;
-pub mod worker_message;
+pub mod unique_names;
 pub mod unique_task;
+pub mod worker_auth;
+pub mod worker_message;
@@ -0,0 +1,103 @@
+use regex::Regex;
+
+const REGEX_PREFIX: &str = "re:";
+
+/// "site.section.item", a glob "site.*.item" or "site.**", or a regex
+/// "re:site\.(a|b)\..+".
+#[derive(Clone, Debug)]
+pub enum NamePattern {
+    Exact(String),
+
+    /// "prefix.**", the names under "prefix.".
+    Prefix(String),
+
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        if let Some(re) = pattern.strip_prefix(REGEX_PREFIX) {
+            return compile(pattern, &format!("^(?:{})$", re));
+        }
+
+        if !is_glob(pattern) {
+            return Ok(NamePattern::Exact(pattern.to_string()));
+        }
+
+        if let Some(prefix) = pattern.strip_suffix("**") {
+            if prefix.ends_with('.') && !is_glob(prefix) {
+                return Ok(NamePattern::Prefix(prefix.to_string()));
+            }
+        }
+
+        compile(pattern, &glob_to_regex(pattern))
+    }
+
+    pub fn is_exact(&self) -> bool {
+        matches!(self, NamePattern::Exact(_))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Exact(n) => n == name,
+            NamePattern::Prefix(p) => name.starts_with(p.as_str()),
+            NamePattern::Regex(re) => re.is_match(name),
+        }
+    }
+}
+
+fn compile(pattern: &str, re: &str) -> Result<NamePattern, String> {
+    Regex::new(re)
+        .map(NamePattern::Regex)
+        .map_err(|e| format!("Invalid name pattern {}: {}", pattern, e))
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+fn glob_to_regex(pattern: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            },
+            '*' => re.push_str("[^.]*"),
+            '?' => re.push_str("[^.]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let p = |s| NamePattern::parse(s).unwrap();
+
+        assert!(p("a.b").is_exact());
+        assert!(p("a.b").matches("a.b"));
+
+        assert!(matches!(p("a.**"), NamePattern::Prefix(_)));
+        assert!(p("a.**").matches("a.b.c"));
+        assert!(!p("a.**").matches("ab.c"));
+
+        assert!(p("a.*.c").matches("a.b.c"));
+        assert!(!p("a.*.c").matches("a.b.b.c"));
+        assert!(p("a.**.c").matches("a.b.b.c"));
+        assert!(p("a.?").matches("a.b"));
+
+        assert!(p(r"re:a\.(b|c)").matches("a.c"));
+        assert!(!p(r"re:a\.(b|c)").matches("a.cd"));
+        assert!(NamePattern::parse("re:(").is_err());
+    }
+}
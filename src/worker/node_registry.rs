@@ -0,0 +1,375 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use slog::Logger;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    core::{
+        env,
+        logger::create_logger,
+        monitor::*,
+        timestamp::{now, Timestamp},
+    },
+    transport::message::*,
+    worker::{
+        controller_message::*,
+        dispatcher,
+        router,
+        worker_message::*,
+    },
+};
+
+lazy_static! {
+    static ref PARAMS: NodesParams =
+        env::load_opt("worker_nodes").unwrap_or_default();
+
+    /// Node ID --> Controller ID patterns
+    static ref CONTROLLERS: Vec<(String, Vec<Regex>)> = PARAMS.nodes.iter()
+        .map(|(id, p)| {
+            let patterns = p.controllers.iter()
+                .map(|c| Regex::new(c).unwrap())
+                .collect();
+            (id.clone(), patterns)
+        })
+        .collect();
+
+    /// Updated by the registry, read by `status`.
+    static ref STATUS: Mutex<BTreeMap<String, NodeStatus>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// `[worker_nodes]` configuration section.
+#[derive(Default, Deserialize)]
+struct NodesParams {
+    /// A node is lost if it has not sent a heartbeat for that long.
+    #[serde(default = "default_heartbeat_timeout_s")]
+    heartbeat_timeout_s: u64,
+
+    /// Node ID --> Parameters
+    #[serde(default)]
+    nodes: BTreeMap<String, NodeParams>,
+}
+
+fn default_heartbeat_timeout_s() -> u64 { 15 }
+
+/// `[worker_nodes.nodes.<node id>]` configuration section.
+#[derive(Deserialize)]
+struct NodeParams {
+    /// Worker router the node connects to.
+    #[serde(default = "default_router")]
+    router: String,
+
+    /// Patterns of the IDs of the controllers whose worker processes run on
+    /// the node.
+    #[serde(default)]
+    controllers: Vec<String>,
+}
+
+fn default_router() -> String { router::DEFAULT_ROUTER.to_string() }
+
+/// The node the worker process of the controller is started on, if any.
+pub fn node_for_controller(controller_id: &str) -> Option<&'static str> {
+    CONTROLLERS.iter()
+        .find(|(_, patterns)| patterns.iter().any(|re| {
+            re.is_match(controller_id)
+        }))
+        .map(|(id, _)| id.as_str())
+}
+
+/// Name of the worker router the node connects to.
+pub fn router_for_node(node_id: &str) -> Option<&'static str> {
+    PARAMS.nodes.get(node_id).map(|p| p.router.as_str())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub node_id: String,
+
+    /// Registered and sending heartbeats.
+    pub online: bool,
+
+    pub registered_at: Option<Timestamp>,
+
+    /// Since the last heartbeat.
+    pub heartbeat_age_ms: u64,
+
+    /// Worker processes started on the node.
+    pub workers: Vec<String>,
+}
+
+/// All the configured and registered nodes.
+pub fn status() -> Vec<NodeStatus> {
+    STATUS.lock().unwrap().values().cloned().collect()
+}
+
+/// A registered node.
+struct Node {
+    identity: Identity,
+    registered_at: Timestamp,
+    last_heartbeat: Instant,
+    online: bool,
+
+    /// Controller IDs.
+    workers: Vec<String>,
+}
+
+/// Tracks the remote nodes: machines running the worker processes of the
+/// controllers targeted to them. A node registers itself through a worker
+/// router, starts the worker processes on request and sends heartbeats.
+pub struct NodeRegistry {
+    log: Logger,
+
+    /// Node ID --> Node
+    nodes: HashMap<String, Node>,
+
+    /// Node ID --> `start_worker` requests waiting for the node.
+    pending: HashMap<String, Vec<StartWorker>>,
+
+    check_timer: RegularCheckTimer,
+}
+
+impl NodeRegistry {
+    fn handle_node_message(&mut self, msg: ControllerMessage) {
+        match msg.subject {
+            Subject::Register => self.register(msg),
+            Subject::NodeHeartbeat => {
+                match self.nodes.get_mut(&msg.worker_id) {
+                    Some(node) => {
+                        node.last_heartbeat = Instant::now();
+                        node.identity = msg.identity;
+                        if !node.online {
+                            info!(
+                                self.log,
+                                "[NODE] {} is back online.",
+                                msg.worker_id,
+                            );
+                            node.online = true;
+                        }
+                    },
+                    None => {
+                        warn!(
+                            self.log,
+                            "Heartbeat from unregistered [NODE] {}",
+                            msg.worker_id,
+                        );
+                    },
+                }
+            },
+            _ => {
+                warn!(
+                    self.log,
+                    "Ignore node message with unexpected [SUBJECT] {:?}",
+                    msg.subject,
+                );
+            },
+        }
+
+        self.update_status();
+    }
+
+    fn register(&mut self, msg: ControllerMessage) {
+        let node_id = msg.worker_id.clone();
+        info!(self.log, "Registering [NODE] {}.", node_id);
+
+        // The worker processes of a re-registered node are gone.
+        self.nodes.insert(node_id.clone(), Node {
+            identity: msg.identity,
+            registered_at: now(),
+            last_heartbeat: Instant::now(),
+            online: true,
+            workers: vec![],
+        });
+
+        for request in self.pending.remove(&node_id).unwrap_or_default() {
+            self.start_worker(request);
+        }
+    }
+
+    fn start_worker(&mut self, request: StartWorker) {
+        let node = match self.nodes.get_mut(&request.node_id) {
+            Some(n) if n.online => n,
+            _ => {
+                debug!(
+                    self.log,
+                    "[NODE] {} is not online. Delay starting [WORKER ID] {}",
+                    request.node_id,
+                    request.worker_id,
+                );
+                self.pending.entry(request.node_id.clone())
+                    .or_default()
+                    .push(request);
+                return;
+            },
+        };
+
+        info!(
+            self.log,
+            "Start [WORKER ID] {} on [NODE] {}.",
+            request.worker_id,
+            request.node_id,
+        );
+
+        if !node.workers.contains(&request.worker_id) {
+            node.workers.push(request.worker_id.clone());
+        }
+
+        let cm = ControllerMessage {
+            identity: clone_identity(&node.identity),
+            worker_id: request.node_id,
+            dest: Dest::Node,
+            subject: Subject::StartWorker,
+            details: json!({
+                "worker_id": request.worker_id,
+                "controller": request.controller,
+            }),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            protocol_version: PROTOCOL_VERSION,
+        };
+
+        dispatcher::start().do_send::<WorkerMessage>(cm.into());
+        self.update_status();
+    }
+
+    fn check_heartbeats(&mut self) {
+        // Read on use so that a reload applies.
+        let timeout_s = env::opt_var("worker_nodes.heartbeat_timeout_s")
+            .ok()
+            .flatten()
+            .unwrap_or(PARAMS.heartbeat_timeout_s);
+        let timeout = Duration::from_secs(timeout_s);
+        for (node_id, node) in self.nodes.iter_mut() {
+            if node.online && node.last_heartbeat.elapsed() > timeout {
+                warn!(self.log, "[NODE] {} is lost.", node_id);
+                node.online = false;
+            }
+        }
+
+        self.update_status();
+    }
+
+    fn update_status(&self) {
+        let mut status = STATUS.lock().unwrap();
+        status.clear();
+
+        for node_id in PARAMS.nodes.keys() {
+            status.insert(node_id.clone(), NodeStatus {
+                node_id: node_id.clone(),
+                online: false,
+                registered_at: None,
+                heartbeat_age_ms: 0,
+                workers: vec![],
+            });
+        }
+
+        for (node_id, node) in &self.nodes {
+            status.insert(node_id.clone(), NodeStatus {
+                node_id: node_id.clone(),
+                online: node.online,
+                registered_at: Some(node.registered_at),
+                heartbeat_age_ms: node.last_heartbeat.elapsed().as_millis()
+                    as u64,
+                workers: node.workers.clone(),
+            });
+        }
+    }
+}
+
+impl Default for NodeRegistry {
+    fn default() -> Self {
+        Self {
+            log: create_logger("node_registry"),
+            nodes: HashMap::new(),
+            pending: HashMap::new(),
+            check_timer: RegularCheckTimer::new_s(1),
+        }
+    }
+}
+
+impl Actor for NodeRegistry {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Started.");
+
+        self.update_status();
+        self.check_timer.reset::<Self>(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Stopped.");
+    }
+}
+
+impl Supervised for NodeRegistry {}
+
+impl SystemService for NodeRegistry {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "System service started.")
+    }
+}
+
+impl Handler<RegularCheckMessage> for NodeRegistry {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: RegularCheckMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.check_heartbeats();
+        self.check_timer.reset::<Self>(ctx);
+    }
+}
+
+/// A message from a node.
+impl Handler<WorkerMessage> for NodeRegistry {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: WorkerMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        match ControllerMessage::from(msg) {
+            Ok(cm) => self.handle_node_message(cm),
+            Err(e) => warn!(self.log, "Invalid node message format: {}", e),
+        }
+    }
+}
+
+/// Start the worker process of a controller on a node. Delayed until the
+/// node is online.
+#[derive(Clone, Message)]
+#[rtype(result = "()")]
+pub struct StartWorker {
+    pub node_id: String,
+
+    /// Controller ID.
+    pub worker_id: String,
+
+    /// Router address the worker process connects to.
+    pub controller: String,
+}
+
+impl Handler<StartWorker> for NodeRegistry {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: StartWorker,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.start_worker(msg);
+    }
+}
+
+pub fn start() -> Addr<NodeRegistry> {
+    NodeRegistry::from_registry()
+}
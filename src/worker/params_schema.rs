@@ -0,0 +1,218 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::RwLock};
+
+lazy_static! {
+    /// Task Name --> Schema
+    static ref SCHEMAS: RwLock<BTreeMap<String, Value>> =
+        RwLock::new(BTreeMap::new());
+}
+
+/// A params type describing itself, see `register_for`.
+pub trait ParamsSchema {
+    fn params_schema() -> Value;
+}
+
+/// Validate the params of the tasks `name` with `schema`, unless their
+/// definition has a schema of its own.
+pub fn register(name: &str, schema: Value) {
+    SCHEMAS.write().unwrap().insert(name.to_string(), schema);
+}
+
+/// `register` the schema of the params type `P`.
+pub fn register_for<P: ParamsSchema>(name: &str) {
+    register(name, P::params_schema());
+}
+
+pub fn get(name: &str) -> Option<Value> {
+    SCHEMAS.read().unwrap().get(name).cloned()
+}
+
+/// Task Name --> Schema
+pub fn schemas() -> BTreeMap<String, Value> {
+    SCHEMAS.read().unwrap().clone()
+}
+
+/// All the errors, e.g. "/url: expected string", joined. The keywords
+/// other than `type`, `enum`, `properties`, `required`, `items`, the
+/// length, size and range bounds, etc. are ignored.
+pub fn validate(schema: &Value, params: &Value) -> Result<(), String> {
+    let mut errors = vec![];
+    check(schema, params, "", &mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Invalid params: {}", errors.join("; ")))
+    }
+}
+
+fn check(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let mut error = |e: String| {
+        let path = if path.is_empty() { "/" } else { path };
+        errors.push(format!("{}: {}", path, e));
+    };
+
+    if let Some(t) = schema.get("type") {
+        let types: Vec<&str> = match t {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => vec![],
+        };
+
+        if !types.is_empty() && !types.iter().any(|t| is_type(value, t)) {
+            error(format!("expected {}", types.join(" or ")));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        if !values.contains(value) {
+            error(format!("expected one of {}", Value::from(values.clone())));
+        }
+    }
+
+    if let Some(c) = schema.get("const") {
+        if c != value {
+            error(format!("expected {}", c));
+        }
+    }
+
+    match value {
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            let bound = |key| schema.get(key).and_then(Value::as_f64);
+
+            if bound("minimum").is_some_and(|m| n < m) {
+                error(format!("less than {}", schema["minimum"]));
+            }
+            if bound("maximum").is_some_and(|m| n > m) {
+                error(format!("greater than {}", schema["maximum"]));
+            }
+            if let Some(m) = bound("exclusiveMinimum").filter(|m| n <= *m) {
+                error(format!("not greater than {}", m));
+            }
+            if let Some(m) = bound("exclusiveMaximum").filter(|m| n >= *m) {
+                error(format!("not less than {}", m));
+            }
+        },
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            let limit = |key| schema.get(key).and_then(Value::as_u64);
+
+            if limit("minLength").is_some_and(|m| len < m) {
+                error(format!("shorter than {}", schema["minLength"]));
+            }
+            if limit("maxLength").is_some_and(|m| len > m) {
+                error(format!("longer than {}", schema["maxLength"]));
+            }
+            if let Some(p) = schema.get("pattern").and_then(Value::as_str) {
+                match Regex::new(p) {
+                    Ok(re) if !re.is_match(s) => {
+                        error(format!("does not match {}", p));
+                    },
+                    Ok(_) => {},
+                    Err(e) => error(format!("invalid pattern {}: {}", p, e)),
+                }
+            }
+        },
+        Value::Array(items) => {
+            let len = items.len() as u64;
+            let limit = |key| schema.get(key).and_then(Value::as_u64);
+
+            if limit("minItems").is_some_and(|m| len < m) {
+                error(format!("fewer than {} items", schema["minItems"]));
+            }
+            if limit("maxItems").is_some_and(|m| len > m) {
+                error(format!("more than {} items", schema["maxItems"]));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    let item_path = format!("{}/{}", path, i);
+                    check(item_schema, item, &item_path, errors);
+                }
+            }
+        },
+        Value::Object(fields) => {
+            let required = schema.get("required").and_then(Value::as_array);
+            for r in required.into_iter().flatten().filter_map(Value::as_str) {
+                if !fields.contains_key(r) {
+                    error(format!("missing {}", r));
+                }
+            }
+
+            let properties =
+                schema.get("properties").and_then(Value::as_object);
+            let additional = schema.get("additionalProperties");
+
+            for (key, field) in fields {
+                let field_path = format!("{}/{}", path, key);
+                match properties.and_then(|p| p.get(key)) {
+                    Some(s) => check(s, field, &field_path, errors),
+                    None => match additional {
+                        Some(Value::Bool(false)) => errors.push(format!(
+                            "{}: not allowed",
+                            field_path,
+                        )),
+                        Some(s @ Value::Object(_)) => {
+                            check(s, field, &field_path, errors)
+                        },
+                        _ => {},
+                    },
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+fn is_type(value: &Value, t: &str) -> bool {
+    match t {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64()
+                || value.is_u64()
+                || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        },
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn validation() {
+        let schema = json!({
+            "type": "object",
+            "required": ["url"],
+            "properties": {
+                "url": { "type": "string", "pattern": "^https?://" },
+                "depth": { "type": "integer", "minimum": 0, "maximum": 5 },
+                "tags": { "type": "array", "items": { "enum": ["a", "b"] } },
+            },
+            "additionalProperties": false,
+        });
+
+        let valid = json!({ "url": "http://a", "depth": 2, "tags": ["a"] });
+        assert!(validate(&schema, &valid).is_ok());
+
+        let invalid = json!({ "depth": 7, "tags": ["c"], "x": 1 });
+        let e = validate(&schema, &invalid).unwrap_err();
+        assert!(e.contains("/: missing url"));
+        assert!(e.contains("/depth: greater than 5"));
+        assert!(e.contains("/tags/0: expected one of"));
+        assert!(e.contains("/x: not allowed"));
+
+        let e = validate(&schema, &json!({ "url": 1 })).unwrap_err();
+        assert_eq!(e, "Invalid params: /url: expected string");
+    }
+}
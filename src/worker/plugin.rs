@@ -3,11 +3,19 @@ use serde_json;
 use serde_json::json;
 use std::collections::{HashMap};
 use std::fmt;
+use std::fs;
+use std::path::Path;
 
 use crate::core::env::{self, *};
-use crate::core::proxy;
+use crate::core::proxy::{self, Proxy};
+use crate::core::telemetry::TraceContext;
 use crate::core::user_agent;
-use crate::worker::worker_message::{WorkerMessage, Dest, WorkerMessagePayload};
+use crate::worker::worker_message::{
+    WorkerMessage,
+    Dest,
+    WorkerMessagePayload,
+    PROTOCOL_VERSION,
+};
 
 #[derive(Clone, PartialEq, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -33,6 +41,11 @@ impl WorkerPlugin {
             _ => WorkerPlugin::None,
         }
     }
+
+    /// `True` if the plugin is set up with a proxy and a user agent.
+    pub fn uses_proxy(&self) -> bool {
+        *self == WorkerPlugin::HeadlessBrowser
+    }
 }
 
 impl fmt::Debug for WorkerPlugin {
@@ -72,28 +85,125 @@ impl PluginSettings {
     }
 }
 
-fn plugin_settings(plugin: WorkerPlugin) -> PluginSettings {
+/// `[plugin.headless_browser.profiles.<name>]` configuration section.
+/// A login session is reused by the tasks declaring the same profile. A
+/// profile not configured gets the default paths.
+#[derive(Clone, Default, Deserialize)]
+pub struct BrowserProfile {
+    /// Cookies are loaded from and saved to the file.
+    /// `$PATOKA_ROOT_DIR/profiles/<name>/cookies.json` by default.
+    #[serde(default)]
+    pub cookie_jar: Option<String>,
+
+    /// `$PATOKA_ROOT_DIR/profiles/<name>/local_storage` by default.
+    #[serde(default)]
+    pub local_storage_dir: Option<String>,
+
+    /// E.g. "1280x800". The plugin default if not set.
+    #[serde(default)]
+    pub viewport: Option<String>,
+}
+
+impl BrowserProfile {
+    pub fn load(name: &str) -> Self {
+        env::load_opt(&format!("plugin.headless_browser.profiles.{}", name))
+            .unwrap_or_default()
+    }
+
+    /// The plugin parameters. The directories are created if missing.
+    fn params(&self, name: &str) -> HashMap<String, String> {
+        let profile_dir = format!("$PATOKA_ROOT_DIR/profiles/{}", name);
+        let path = |p: &Option<String>, default: &str| env::full_path(
+            p.as_deref().unwrap_or(default),
+            "$PATOKA_ROOT_DIR",
+            &PATOKA_ROOT_DIR,
+        );
+
+        let cookie_jar = path(
+            &self.cookie_jar,
+            &format!("{}/cookies.json", profile_dir),
+        );
+        let local_storage_dir = path(
+            &self.local_storage_dir,
+            &format!("{}/local_storage", profile_dir),
+        );
+
+        if let Some(dir) = std::path::Path::new(&cookie_jar).parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::create_dir_all(&local_storage_dir);
+
+        let mut params = HashMap::new();
+        params.insert("profile".to_string(), name.to_string());
+        params.insert("cookie_jar".to_string(), cookie_jar);
+        params.insert("local_storage_dir".to_string(), local_storage_dir);
+
+        if let Some((width, height)) = self.viewport.as_deref()
+            .and_then(parse_viewport)
+        {
+            params.insert("viewport_width".to_string(), width.to_string());
+            params.insert("viewport_height".to_string(), height.to_string());
+        }
+
+        params
+    }
+}
+
+/// "<width>x<height>"
+fn parse_viewport(s: &str) -> Option<(u32, u32)> {
+    let (width, height) = s.split_once('x')?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+/// The profile a message to the worker requires with the plugin. Only the
+/// headless browser has profiles.
+pub fn desired_profile(plugin: WorkerPlugin, profile: &str) -> &str {
+    if plugin == WorkerPlugin::HeadlessBrowser {
+        profile
+    } else {
+        ""
+    }
+}
+
+/// The script of the plugin under `$PATOKA_X_DIR`.
+fn script_path(plugin: WorkerPlugin) -> String {
+    env::full_path(
+        &format!(
+            "$PATOKA_X_DIR/build/src/plugin/{}_plugin.js",
+            WorkerPlugin::as_str(plugin),
+        ),
+        "$PATOKA_X_DIR",
+        &PATOKA_X_DIR,
+    )
+}
+
+/// The plugins whose script is installed, i.e. the worker processes of this
+/// node can be set up with.
+pub fn installed() -> Vec<WorkerPlugin> {
+    [WorkerPlugin::Basic, WorkerPlugin::HeadlessBrowser]
+        .into_iter()
+        .filter(|p| Path::new(&script_path(*p)).exists())
+        .collect()
+}
+
+fn plugin_settings(
+    plugin: WorkerPlugin,
+    proxy: Option<Proxy>,
+    profile: &str,
+) -> PluginSettings {
     match plugin {
         WorkerPlugin::Basic => {
             PluginSettings::new(
                 WorkerPlugin::as_str(plugin).to_string(),
-                env::full_path(
-                    "$PATOKA_X_DIR/build/src/plugin/basic_plugin.js",
-                    "$PATOKA_X_DIR",
-                    &PATOKA_X_DIR,
-                ),
+                script_path(plugin),
                 HashMap::new(),
             )
         },
         WorkerPlugin::HeadlessBrowser => {
             PluginSettings::new(
                 WorkerPlugin::as_str(plugin).to_string(),
-                env::full_path(
-                    "$PATOKA_X_DIR/build/src/plugin/headless_browser_plugin.js",
-                    "$PATOKA_X_DIR",
-                    &PATOKA_X_DIR,
-                ),
-                params_headless_browser(),
+                script_path(plugin),
+                params_headless_browser(proxy, profile),
             )
         },
         WorkerPlugin::None => {
@@ -102,11 +212,25 @@ fn plugin_settings(plugin: WorkerPlugin) -> PluginSettings {
     }
 }
 
+/// Proxy to set the plugin up with. `None` if the plugin does not use a proxy
+/// or proxies are disabled.
+pub fn next_proxy(plugin: WorkerPlugin) -> Option<Proxy> {
+    if plugin.uses_proxy() {
+        proxy::next()
+    } else {
+        None
+    }
+}
+
+/// Every call generates a new user agent, so setting the plugin up again
+/// with a different `proxy` changes the worker's fingerprint.
 pub fn setup_plugin_message(
     plugin: WorkerPlugin,
     worker_id: &str,
+    proxy: Option<Proxy>,
+    profile: &str,
 ) -> WorkerMessage {
-    let settings = plugin_settings(plugin);
+    let settings = plugin_settings(plugin, proxy, profile);
     let data = json!({
         "plugin": serde_json::to_value(settings).unwrap(),
     });
@@ -118,20 +242,32 @@ pub fn setup_plugin_message(
         worker_id: worker_id.to_string(),
         task_uuid: String::new(),
         plugin: WorkerPlugin::as_str(plugin).to_string(),
+        profile: profile.to_string(),
         data,
+        correlation_id: String::new(),
+        protocol_version: PROTOCOL_VERSION,
+        trace: TraceContext::default(),
     };
 
     WorkerMessage::new(payload)
 }
 
-fn params_headless_browser() -> HashMap<String, String> {
+fn params_headless_browser(
+    proxy: Option<Proxy>,
+    profile: &str,
+) -> HashMap<String, String> {
     let mut params = HashMap::new();
 
+    // Cookie jar, local storage, viewport
+    if !profile.is_empty() {
+        params.extend(BrowserProfile::load(profile).params(profile));
+    }
+
     // User-Agent header
     params.insert("user_agent".to_string(), user_agent::random_ua());
 
     // Proxy
-    if let Some(proxy) = proxy::next() {
+    if let Some(proxy) = proxy {
         let proxy_server = proxy.type_ + "://" + &proxy.address;
         params.insert("proxy_server".to_string(), proxy_server);
     }
@@ -145,3 +281,18 @@ fn params_headless_browser() -> HashMap<String, String> {
 
     params
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_of_browser_only() {
+        assert_eq!(parse_viewport("1280x800"), Some((1280, 800)));
+        assert_eq!(parse_viewport("1280"), None);
+
+        let browser = WorkerPlugin::HeadlessBrowser;
+        assert_eq!(desired_profile(browser, "login"), "login");
+        assert_eq!(desired_profile(WorkerPlugin::Basic, "login"), "");
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    io,
+    process::{Child, Command},
+};
+
+/// Sent to a process group.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    Term,
+    Kill,
+}
+
+/// Spawn `cmd` as the leader of a new process group, so that the processes
+/// it starts, e.g. the browsers, are killed along with it.
+pub fn spawn(cmd: &mut Command) -> io::Result<Child> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+
+    cmd.spawn()
+}
+
+/// Kill the process group of `child` and reap `child`. Falls back to
+/// killing `child` alone if the group could not be signalled.
+pub fn kill(child: &mut Child) -> io::Result<()> {
+    if let Err(e) = signal(child, Signal::Kill) {
+        child.kill()?;
+        child.wait()?;
+        return Err(e);
+    }
+
+    child.wait().map(|_| ())
+}
+
+/// Send `sig` to the process group of `child`.
+#[cfg(unix)]
+pub fn signal(child: &Child, sig: Signal) -> io::Result<()> {
+    let sig = match sig {
+        Signal::Term => libc::SIGTERM,
+        Signal::Kill => libc::SIGKILL,
+    };
+
+    let pgid = child.id() as libc::pid_t;
+    if unsafe { libc::killpg(pgid, sig) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn signal(_child: &Child, _sig: Signal) -> io::Result<()> {
+    Err(io::Error::other("Process groups are not supported"))
+}
+
+/// Whether `child` has exited, reaped if so.
+pub fn has_exited(child: &mut Child) -> bool {
+    matches!(child.try_wait(), Ok(Some(_)))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        io::{BufRead, BufReader},
+        process::Stdio,
+        thread,
+        time::Duration,
+    };
+
+    /// Neither running nor sleeping: gone or a zombie not reaped yet.
+    fn is_dead(pid: &str) -> bool {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => stat.contains(") Z"),
+            Err(_) => true,
+        }
+    }
+
+    #[test]
+    fn kills_grandchildren() {
+        let mut child = spawn(
+            Command::new("sh")
+                .args(["-c", "sleep 30 & echo $!; wait"])
+                .stdout(Stdio::piped()),
+        ).unwrap();
+
+        let mut grandchild = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut grandchild)
+            .unwrap();
+        let grandchild = grandchild.trim();
+
+        kill(&mut child).unwrap();
+
+        for _ in 0..50 {
+            if is_dead(grandchild) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("{} survived", grandchild);
+    }
+}
@@ -1,32 +1,53 @@
 use actix::prelude::*;
 use lazy_static::lazy_static;
 use slog::Logger;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Mutex;
+use std::fmt;
+use std::time::Duration;
 
 use crate::{
     center::message,
     core::{
         app_state::{self, *},
         arbiter_pool,
+        disk_guard,
+        env,
+        error_bus::{self, PatokaError},
         logger::create_logger,
-        monitor::*,
+        ratelimit,
+        status_aggregator::{self, GetStatusSnapshot, StatusSnapshot},
+        timestamp::{now, Timestamp},
     },
     transport::message::RawMessage,
     worker::{
-        controller_pool::{ControllerPool},
+        circuit_breaker,
+        controller_pool::{
+            self,
+            ControllerInfo,
+            ControllerPools,
+            parse_capacity,
+        },
         plugin::WorkerPlugin,
         reprocessor::{self, ReprocessTask},
         task::*,
         task_reader,
         task_tree::{self, NewTask},
         tracker::{TaskUpdate},
+        unique_names::{self, UniqueMode, UniqueRejection},
     },
 };
 
 lazy_static! {
-    pub static ref CONTROLLER_POOL: Mutex<ControllerPool>
-        = Mutex::new(ControllerPool::new(1));
+    pub static ref CONTROLLER_POOLS: Mutex<ControllerPools>
+        = Mutex::new(ControllerPools::new(controller_pool_capacity()));
+}
+
+/// Number of worker controllers of the default queue:
+/// `general.number_of_workers`, either a number or "auto" (one per CPU).
+/// 1 by default.
+pub fn controller_pool_capacity() -> usize {
+    parse_capacity(env::get_opt_var("general.number_of_workers").as_deref())
 }
 
 pub type TaskWrapperItem = Box<dyn TaskWrapper>;
@@ -37,6 +58,98 @@ impl Message for TaskWrapperItemMessage {
     type Result = ();
 }
 
+/// Tasks to process at once, e.g. the items enqueued at startup.
+pub struct BatchTaskMessage {
+    pub tasks: Vec<TaskWrapperItem>,
+
+    /// Delay between the tasks to ramp the load up. The tasks are processed
+    /// together if zero.
+    pub spacing: Duration,
+}
+
+impl Message for BatchTaskMessage {
+    type Result = ();
+}
+
+/// Resize the controller pools to `controller_pool_capacity` and the
+/// `[queues]`. The pools are locked by the processor only, so that they are
+/// never resized while in use.
+pub struct ReloadPoolCapacity;
+
+impl Message for ReloadPoolCapacity {
+    type Result = ();
+}
+
+/// A task to process at `at`, e.g. a follow-up scrape, by `submit_after`
+/// or `submit_at`. At once if `at` is past.
+pub struct DelayedTaskMessage {
+    pub task: TaskWrapperItem,
+    pub at: Timestamp,
+}
+
+impl Message for DelayedTaskMessage {
+    type Result = ();
+}
+
+/// How often the delayed tasks are checked for being due.
+const DELAY_TICK: Duration = Duration::from_secs(1);
+
+/// Module name used to publish errors.
+const MODULE: &str = "task_processor";
+
+/// Why a task submitted is not processed.
+#[derive(Clone, Debug)]
+pub enum Rejection {
+    /// See `unique_names`.
+    Unique(UniqueRejection),
+
+    /// See `params_schema`.
+    InvalidParams {
+        task_uuid: String,
+        name: String,
+        error: String,
+    },
+}
+
+impl Rejection {
+    pub fn task_uuid(&self) -> &str {
+        match self {
+            Rejection::Unique(r) => &r.task_uuid,
+            Rejection::InvalidParams { task_uuid, .. } => task_uuid,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Unique(r) => write!(f, "{}", r),
+            Rejection::InvalidParams { task_uuid, name, error } => write!(
+                f,
+                "[TASK UUID] {} [NAME] {} rejected: {}",
+                task_uuid,
+                name,
+                error,
+            ),
+        }
+    }
+}
+
+/// A task to process unless its params are invalid or its unique name is
+/// claimed, see `unique_names`. Queued in the "queue" mode.
+pub struct SubmitTask(pub TaskWrapperItem);
+
+impl Message for SubmitTask {
+    type Result = Result<(), Rejection>;
+}
+
+/// Process the tasks held by the app mode, if the mode accepts them now.
+pub struct ProcessHeldTasks;
+
+impl Message for ProcessHeldTasks {
+    type Result = ();
+}
+
 fn reprocess_task(task: TaskWrapperItem) {
     let task_reprocessor = reprocessor::start();
     task_reprocessor.do_send(ReprocessTask { task });
@@ -45,83 +158,308 @@ fn reprocess_task(task: TaskWrapperItem) {
 pub struct TaskProcessor {
     log: Logger,
 
-    /// Periodically generate status report.
-    report_status_timer: ReportStatusTimer,
+    /// The tasks not accepted by the app mode. See `app_state::mode`.
+    held: Vec<TaskWrapperItem>,
+
+    /// (Due at, Sequence number) --> Task, checked every `DELAY_TICK`.
+    delayed: BTreeMap<(Timestamp, u64), TaskWrapperItem>,
+    delayed_seq: u64,
+
+    /// Task Name --> Tasks waiting for the name to be released, checked
+    /// every `DELAY_TICK`. The latest one only in the "latest_wins" mode.
+    unique_queued: HashMap<String, VecDeque<TaskWrapperItem>>,
 }
 
 impl TaskProcessor {
-    fn process_task(
+    fn process_due(&mut self, ctx: &mut Context<Self>) {
+        let now = now();
+        while let Some(entry) = self.delayed.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+
+            let task = entry.remove();
+            debug!(self.log, "Delayed [TASK UUID] {} is due.", task.uuid());
+            self.process_task(task, ctx);
+        }
+
+        let released: Vec<_> = self.unique_queued.keys()
+            .filter(|name| !unique_names::is_claimed(name))
+            .cloned()
+            .collect();
+
+        for name in released {
+            let queue = self.unique_queued.get_mut(&name).unwrap();
+            if let Some(task) = queue.pop_front() {
+                if queue.is_empty() {
+                    self.unique_queued.remove(&name);
+                }
+                self.process_task(task, ctx);
+            }
+        }
+    }
+
+    /// Validate the params of the task and claim its unique name. The task
+    /// is returned if claimed or not unique, queued or rejected by
+    /// `unique_names::mode` otherwise.
+    fn admit(
         &mut self,
-        mut task: TaskWrapperItem,
-        ctx: &mut <TaskProcessor as Actor>::Context
-    ) {
-        debug!(self.log, "New task arrived [TASK UUID] {}.", task.uuid());
+        task: TaskWrapperItem,
+    ) -> Result<Option<TaskWrapperItem>, Rejection> {
+        if let Err(error) = task.validate_params() {
+            return Err(Rejection::InvalidParams {
+                task_uuid: task.uuid().to_owned(),
+                name: task.name().to_owned(),
+                error,
+            });
+        }
 
-        let mut arbiter_addr = arbiter_pool::next();
-        let arbiter_addr_clone = arbiter_addr.clone();
+        if !unique_names::is_unique(task.name()) {
+            return Ok(Some(task));
+        }
 
-        let task_uuid = task.uuid().to_owned();
+        let rejection = match unique_names::claim(task.name(), task.uuid()) {
+            Ok(()) => return Ok(Some(task)),
+            Err(r) => r,
+        };
 
-        let task_clone = task.clone_box();
+        if unique_names::mode() == UniqueMode::Reject {
+            return Err(Rejection::Unique(rejection));
+        }
 
-        let mut has_reader = false;
+        info!(
+            self.log,
+            "Queue [TASK UUID] {} until [NAME] {} is released by \
+                [TASK UUID] {}",
+            task.uuid(),
+            task.name(),
+            rejection.running_task_uuid,
+        );
+
+        let queue = self.unique_queued.entry(task.name().to_owned())
+            .or_default();
+
+        if unique_names::mode() == UniqueMode::LatestWins {
+            for pending in queue.drain(..) {
+                info!(
+                    self.log,
+                    "Replace pending [TASK UUID] {} with [TASK UUID] {}",
+                    pending.uuid(),
+                    task.uuid(),
+                );
+            }
+        }
 
-        let reader_addr = match task_reader::get_reader(task.name()) {
-            Some(addr) => {
-                has_reader = true;
-                ControllerAddr::Reader(addr)
+        queue.push_back(task);
+        Ok(None)
+    }
+
+    /// `admit` for the tasks submitted without waiting for the result.
+    fn admit_or_report(
+        &mut self,
+        task: TaskWrapperItem,
+    ) -> Option<TaskWrapperItem> {
+        match self.admit(task) {
+            Ok(task) => task,
+            Err(rejection) => {
+                warn!(self.log, "{}", rejection);
+
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    rejection.to_string(),
+                ).task(rejection.task_uuid()));
+                None
             },
-            _ => ControllerAddr::None,
-        };
+        }
+    }
 
-        if /*task.plugin() == WorkerPlugin::None ||*/ has_reader {
-            // The task works without controller.
-            let task_exec_ctx = task.execute_in_arbiter(
-                &arbiter_addr,
-                reader_addr,
+    /// Hold the task if not accepted by the app mode or while the disk space
+    /// is critical, delay it while the circuit of its name is open. The
+    /// task is returned otherwise.
+    fn hold(&mut self, task: TaskWrapperItem) -> Option<TaskWrapperItem> {
+        if let Some(wait) = circuit_breaker::open_for(task.name()) {
+            info!(
+                self.log,
+                "Delay [TASK UUID] {} [NAME] {} by {} s: circuit open",
+                task.uuid(),
+                task.name(),
+                wait.as_secs(),
             );
 
-            task_tree::start().do_send(
-                NewTask { ctx: task_exec_ctx, task: task_clone }
+            let wait = chrono::Duration::from_std(wait).unwrap_or_default();
+            self.delayed_seq += 1;
+            self.delayed.insert((now() + wait, self.delayed_seq), task);
+            return None;
+        }
+
+        if disk_guard::holds_tasks() {
+            info!(
+                self.log,
+                "Hold [TASK UUID] {} [NAME] {}: no disk space",
+                task.uuid(),
+                task.name(),
             );
 
-            return;
+            self.held.push(task);
+            return None;
         }
 
-        async move {
-            let mut controller_pool = CONTROLLER_POOL.lock().unwrap();
-            controller_pool.next(&arbiter_addr_clone, &task_uuid).await
+        let mode = app_state::mode();
+        if mode.accepts(!task.parent_uuid().is_empty()) {
+            return Some(task);
+        }
 
-        }.into_actor(self)
-            .then(move |controller_details, act, _| {
-                if controller_details.is_none() {
-                    warn!(
-                        act.log,
-                        "Unable to find a suitable controller for [TASK UUID] \
-                            {}.",
-                        task.uuid(),
-                    );
+        info!(
+            self.log,
+            "Hold [TASK UUID] {} [NAME] {} in {:?} mode",
+            task.uuid(),
+            task.name(),
+            mode,
+        );
 
-                    reprocess_task(task);
+        self.held.push(task);
+        None
+    }
 
-                } else {
-                    let (controller_addr, controller_id, created) =
-                        controller_details.unwrap();
+    /// Apply the plugin default of the queue of the task.
+    fn queue(&self, task: &mut TaskWrapperItem) -> String {
+        let queue = task.queue().to_owned();
+        if queue.is_empty() {
+            return queue;
+        }
 
-                    if created {
-                        // Run controller and master in different arbiters.
-                        arbiter_addr = arbiter_pool::next();
-                    }
+        let pools = CONTROLLER_POOLS.lock().unwrap();
+        if !pools.has_queue(&queue) {
+            warn!(
+                self.log,
+                "Unknown [QUEUE] {} of [TASK UUID] {}. Using the default.",
+                queue,
+                task.uuid(),
+            );
+        }
 
-                    task.update_worker_id(controller_id.to_string());
+        if let Some(plugin) = pools.plugin(&queue) {
+            task.set_default_plugin(plugin);
+        }
 
-                    let task_exec_ctx = task.execute_in_arbiter(
-                        &arbiter_addr,
-                        ControllerAddr::Controller(controller_addr),
-                    );
+        queue
+    }
+
+    fn process_task(
+        &mut self,
+        task: TaskWrapperItem,
+        ctx: &mut <TaskProcessor as Actor>::Context
+    ) {
+        debug!(self.log, "New task arrived [TASK UUID] {}.", task.uuid());
+
+        let task = match self.admit_or_report(task) {
+            Some(task) => task,
+            None => return,
+        };
+
+        self.process_admitted(task, ctx);
+    }
+
+    fn process_admitted(
+        &mut self,
+        task: TaskWrapperItem,
+        ctx: &mut <TaskProcessor as Actor>::Context
+    ) {
+        let task = match self.hold(task) {
+            Some(task) => task,
+            None => return,
+        };
+
+        let task = match self.run_with_reader(task) {
+            Some(task) => task,
+            None => return,
+        };
+
+        let mut task = match self.throttle(task, ctx) {
+            Some(task) => task,
+            None => return,
+        };
+
+        let arbiter_addr = arbiter_pool::next();
+        let arbiter_addr_clone = arbiter_addr.clone();
+
+        let queue = self.queue(&mut task);
+        let task_uuid = task.uuid().to_owned();
+        let plugin = task.plugin();
+        let task_name = task.name().to_owned();
+
+        async move {
+            controller_pool::next(
+                &CONTROLLER_POOLS,
+                &queue,
+                &arbiter_addr_clone,
+                &task_uuid,
+                &task_name,
+                plugin,
+            ).await
+
+        }.into_actor(self)
+            .then(move |controller_info, act, _| {
+                act.run_with_controller(task, controller_info, arbiter_addr);
 
-                    task_tree::start().do_send(
-                        NewTask { ctx: task_exec_ctx, task: task_clone }
+                async {}.into_actor(act)
+            })
+            .wait(ctx);
+    }
+
+    /// Find the controllers for all the tasks in a single wait of the
+    /// processor.
+    fn process_batch(
+        &mut self,
+        tasks: Vec<TaskWrapperItem>,
+        ctx: &mut <TaskProcessor as Actor>::Context
+    ) {
+        debug!(self.log, "New batch of {} tasks arrived.", tasks.len());
+
+        let mut tasks: Vec<_> = tasks.into_iter()
+            .filter_map(|task| {
+                self.admit_or_report(task)
+                    .and_then(|task| self.hold(task))
+                    .and_then(|task| self.run_with_reader(task))
+                    .and_then(|task| self.throttle(task, ctx))
+            })
+            .map(|task| (task, arbiter_pool::next()))
+            .collect();
+
+        let requests: Vec<_> = tasks.iter_mut()
+            .map(|(task, arbiter_addr)| (
+                arbiter_addr.clone(),
+                self.queue(task),
+                task.uuid().to_owned(),
+                task.name().to_owned(),
+                task.plugin(),
+            ))
+            .collect();
+
+        async move {
+            let mut controllers = Vec::with_capacity(requests.len());
+            for (arbiter_addr, queue, task_uuid, task_name, plugin) in requests
+            {
+                controllers.push(controller_pool::next(
+                    &CONTROLLER_POOLS,
+                    &queue,
+                    &arbiter_addr,
+                    &task_uuid,
+                    &task_name,
+                    plugin,
+                ).await);
+            }
+
+            controllers
+
+        }.into_actor(self)
+            .then(move |controllers, act, _| {
+                let tasks = tasks.into_iter().zip(controllers);
+                for ((task, arbiter_addr), controller_info) in tasks {
+                    act.run_with_controller(
+                        task,
+                        controller_info,
+                        arbiter_addr,
                     );
                 }
 
@@ -129,13 +467,110 @@ impl TaskProcessor {
             })
             .wait(ctx);
     }
+
+    /// Process the task again once the rate limit of its domain allows, see
+    /// `ratelimit::task_key`. The task is returned if it may start now.
+    fn throttle(
+        &mut self,
+        task: TaskWrapperItem,
+        ctx: &mut <TaskProcessor as Actor>::Context
+    ) -> Option<TaskWrapperItem> {
+        let key = match ratelimit::task_key(&task.params()) {
+            Some(k) => k,
+            None => return Some(task),
+        };
+
+        match ratelimit::try_acquire(&key) {
+            Ok(()) => Some(task),
+            Err(wait) => {
+                debug!(
+                    self.log,
+                    "Throttle [TASK UUID] {} [KEY] {} for {:?}",
+                    task.uuid(),
+                    key,
+                    wait,
+                );
+
+                ctx.run_later(wait, move |act, ctx| {
+                    act.process_task(task, ctx);
+                });
+                None
+            },
+        }
+    }
+
+    /// Run the task if it works without a controller. The task is returned
+    /// otherwise.
+    fn run_with_reader(
+        &mut self,
+        task: TaskWrapperItem,
+    ) -> Option<TaskWrapperItem> {
+        let reader_addr = match task_reader::get_reader(task.name()) {
+            Some(addr) => ControllerAddr::Reader(addr),
+            None => return Some(task),
+        };
+
+        // The task works without controller.
+        let task_clone = task.clone_box();
+        let task_exec_ctx = task.execute_in_arbiter(
+            &arbiter_pool::next(),
+            reader_addr,
+        );
+
+        task_tree::start().do_send(
+            NewTask { ctx: task_exec_ctx, task: task_clone }
+        );
+
+        None
+    }
+
+    fn run_with_controller(
+        &mut self,
+        mut task: TaskWrapperItem,
+        controller_info: Option<ControllerInfo>,
+        mut arbiter_addr: ArbiterHandle,
+    ) {
+        let controller_info = match controller_info {
+            Some(c) => c,
+            None => {
+                warn!(
+                    self.log,
+                    "Unable to find a suitable controller for [TASK UUID] {}.",
+                    task.uuid(),
+                );
+
+                reprocess_task(task);
+                return;
+            },
+        };
+
+        if controller_info.created {
+            // Run controller and master in different arbiters.
+            arbiter_addr = arbiter_pool::next();
+        }
+
+        let task_clone = task.clone_box();
+        task.update_worker_id(controller_info.id);
+
+        let task_exec_ctx = task.execute_in_arbiter(
+            &arbiter_addr,
+            ControllerAddr::Controller(controller_info.addr),
+        );
+
+        task_tree::start().do_send(
+            NewTask { ctx: task_exec_ctx, task: task_clone }
+        );
+    }
 }
 
 impl Default for TaskProcessor {
     fn default() -> Self {
         TaskProcessor {
             log: create_logger("task_processor"),
-            report_status_timer: ReportStatusTimer::new_s(5),
+            held: Vec::new(),
+            delayed: BTreeMap::new(),
+            delayed_seq: 0,
+            unique_queued: HashMap::new(),
         }
     }
 }
@@ -146,7 +581,15 @@ impl Actor for TaskProcessor {
     fn started(&mut self, ctx: &mut Self::Context) {
         info!(self.log, "Task Processor started.");
 
-        self.report_status_timer.reset::<Self>(ctx);
+        status_aggregator::register("processor", ctx.address().recipient());
+        ctx.run_interval(DELAY_TICK, |act, ctx| act.process_due(ctx));
+
+        info!(
+            self.log,
+            "Controller pool capacity: {}",
+            controller_pool_capacity(),
+        );
+        CONTROLLER_POOLS.lock().unwrap().warm_up();
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -174,16 +617,119 @@ impl Handler<TaskWrapperItemMessage> for TaskProcessor {
     }
 }
 
-impl Handler<ReportStatusMessage> for TaskProcessor {
+impl Handler<SubmitTask> for TaskProcessor {
+    type Result = Result<(), Rejection>;
+
+    fn handle(
+        &mut self,
+        msg: SubmitTask,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        if let Some(task) = self.admit(msg.0)? {
+            self.process_admitted(task, ctx);
+        }
+        Ok(())
+    }
+}
+
+impl Handler<BatchTaskMessage> for TaskProcessor {
     type Result = ();
 
     fn handle(
         &mut self,
-        _msg: ReportStatusMessage,
+        msg: BatchTaskMessage,
         ctx: &mut Self::Context
     ) -> Self::Result {
+        if msg.spacing.is_zero() {
+            self.process_batch(msg.tasks, ctx);
+            return;
+        }
+
+        for (i, task) in msg.tasks.into_iter().enumerate() {
+            ctx.run_later(msg.spacing * i as u32, move |act, ctx| {
+                act.process_task(task, ctx);
+            });
+        }
+    }
+}
+
+impl Handler<DelayedTaskMessage> for TaskProcessor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: DelayedTaskMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        if msg.at <= now() {
+            self.process_task(msg.task, ctx);
+            return;
+        }
 
-        self.report_status_timer.reset::<Self>(ctx);
+        debug!(
+            self.log,
+            "Delay [TASK UUID] {} until {}",
+            msg.task.uuid(),
+            msg.at.to_rfc3339(),
+        );
+        self.delayed_seq += 1;
+        self.delayed.insert((msg.at, self.delayed_seq), msg.task);
+    }
+}
+
+impl Handler<ProcessHeldTasks> for TaskProcessor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: ProcessHeldTasks,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        let tasks: Vec<_> = self.held.drain(..).collect();
+        if !tasks.is_empty() {
+            // Those still not accepted are held again.
+            self.process_batch(tasks, ctx);
+        }
+    }
+}
+
+impl Handler<ReloadPoolCapacity> for TaskProcessor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: ReloadPoolCapacity,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let capacity = controller_pool_capacity();
+        let mut pools = CONTROLLER_POOLS.lock().unwrap();
+        pools.reload(capacity);
+        pools.warm_up();
+        info!(self.log, "Controller pool capacity: {}", capacity);
+
+        for (queue, capacity) in pools.capacities() {
+            info!(self.log, "[QUEUE] {} pool capacity: {}", queue, capacity);
+        }
+    }
+}
+
+impl Handler<GetStatusSnapshot> for TaskProcessor {
+    type Result = MessageResult<GetStatusSnapshot>;
+
+    fn handle(
+        &mut self,
+        _msg: GetStatusSnapshot,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        MessageResult(
+            StatusSnapshot::new()
+                .with("held_tasks", self.held.len())
+                .with("delayed_tasks", self.delayed.len())
+                .with(
+                    "unique_queued_tasks",
+                    self.unique_queued.values().map(|q| q.len()).sum::<usize>(),
+                )
+        )
     }
 }
 
@@ -191,3 +737,42 @@ pub fn start() -> Addr<TaskProcessor> {
     let addr = TaskProcessor::from_registry();
     addr
 }
+
+/// Apply `general.number_of_workers` and `[queues]` once reloaded.
+pub fn reload_pool_capacity() -> Result<(), String> {
+    start().try_send(ReloadPoolCapacity).map_err(|e| e.to_string())
+}
+
+/// Submit the tasks to be processed together.
+pub fn submit_tasks(tasks: Vec<TaskWrapperItem>) {
+    start().do_send(BatchTaskMessage { tasks, spacing: Duration::ZERO });
+}
+
+/// Submit the task, rejected if its params are invalid or another of its
+/// unique name is running and `unique_names::mode` is "reject".
+pub async fn try_submit(task: TaskWrapperItem) -> Result<(), Rejection> {
+    match start().send(SubmitTask(task)).await {
+        Ok(result) => result,
+        // The processor is a system service, never stopped.
+        Err(_) => Ok(()),
+    }
+}
+
+/// Submit the tasks to be started one per `spacing`.
+pub fn submit_tasks_spaced(tasks: Vec<TaskWrapperItem>, spacing: Duration) {
+    start().do_send(BatchTaskMessage { tasks, spacing });
+}
+
+/// Submit the task to be processed once `delay` has passed, to a second.
+pub fn submit_after(delay: Duration, task: TaskWrapperItem) {
+    let delay = chrono::Duration::from_std(delay)
+        .unwrap_or(chrono::Duration::MAX);
+    let at = now().checked_add_signed(delay)
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+    submit_at(at, task);
+}
+
+/// Submit the task to be processed at `at`, to a second.
+pub fn submit_at(at: Timestamp, task: TaskWrapperItem) {
+    start().do_send(DelayedTaskMessage { task, at });
+}
@@ -0,0 +1,201 @@
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::{
+    center::send::{send_center_task_question, send_control_msg},
+    control::{command::CommandResponse, message::{ControlMessage, Type}},
+    core::env,
+    worker::tracker::dismiss_task_question,
+};
+
+/// `ControlMessage::cmd` of the answers.
+pub const ANSWER_CMD: &str = "task_answer";
+
+lazy_static! {
+    /// Task UUID --> Pending question
+    static ref PENDING: Mutex<HashMap<String, Pending>> =
+        Mutex::new(HashMap::new());
+}
+
+struct Pending {
+    question_id: String,
+    answer: oneshot::Sender<Value>,
+}
+
+/// A question to ask with `ask`.
+pub struct Question {
+    payload: Value,
+    task_name: String,
+
+    /// `questions.timeout_s` by default, none if 0.
+    timeout: Option<Duration>,
+
+    /// Resolves the question on timeout instead of an error.
+    default_answer: Option<Value>,
+}
+
+impl Question {
+    pub fn new<P: serde::Serialize>(payload: P) -> Self {
+        let timeout_s = env::opt_var::<u64>("questions.timeout_s")
+            .ok()
+            .flatten()
+            .unwrap_or(0);
+
+        Self {
+            payload: json!(payload),
+            task_name: String::new(),
+            timeout: (timeout_s > 0).then(|| Duration::from_secs(timeout_s)),
+            default_answer: None,
+        }
+    }
+
+    /// Notifies the subscribers of the task by name too.
+    pub fn task_name(mut self, name: &str) -> Self {
+        self.task_name = name.to_string();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn no_timeout(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+
+    pub fn default_answer<A: serde::Serialize>(mut self, answer: A) -> Self {
+        self.default_answer = Some(json!(answer));
+        self
+    }
+}
+
+/// Ask the question of task `task_uuid` with the default settings.
+pub async fn ask_question<P: serde::Serialize>(
+    task_uuid: String,
+    payload: P,
+) -> Result<Value, String> {
+    ask(task_uuid, Question::new(payload)).await
+}
+
+/// Ask `question` and wait for the `task_answer` control request, or the
+/// default answer on timeout. A task has one question pending at most.
+pub async fn ask(
+    task_uuid: String,
+    question: Question,
+) -> Result<Value, String> {
+    let question_id = Uuid::new_v4().to_string();
+    let (tx, rx) = oneshot::channel();
+
+    {
+        // A question no longer awaited is replaced.
+        let mut pending = PENDING.lock().unwrap();
+        if pending.get(&task_uuid).is_some_and(|p| !p.answer.is_closed()) {
+            return Err(format!("A question of {} is pending", task_uuid));
+        }
+
+        pending.insert(task_uuid.clone(), Pending {
+            question_id: question_id.clone(),
+            answer: tx,
+        });
+    }
+
+    send_center_task_question(
+        &task_uuid,
+        &json!({
+            "question_id": question_id,
+            "question": question.payload,
+        }),
+        &question.task_name,
+    );
+
+    let answer = match question.timeout {
+        Some(t) => tokio::time::timeout(t, rx).await.ok(),
+        None => Some(rx.await),
+    };
+
+    match answer {
+        Some(Ok(answer)) => Ok(answer),
+        Some(Err(_)) => Err("The question has been dropped".to_string()),
+        None => {
+            cancel(&task_uuid, &question_id);
+            let timeout = question.timeout.unwrap_or_default();
+            question.default_answer
+                .ok_or_else(|| format!("No answer in {:?}", timeout))
+        },
+    }
+}
+
+/// Forget the question unless answered meanwhile.
+fn cancel(task_uuid: &str, question_id: &str) {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.get(task_uuid).is_some_and(|p| p.question_id == question_id) {
+        pending.remove(task_uuid);
+        dismiss_task_question(task_uuid.to_string());
+    }
+}
+
+fn matches(question_id: &str, answer: &Value) -> bool {
+    match answer.get("question_id").and_then(|id| id.as_str()) {
+        Some(id) => id == question_id,
+        None => true,
+    }
+}
+
+/// Resolve the pending question the `task_answer` request `msg` is for and
+/// respond to it. Otherwise `msg` is returned to be routed as usual.
+pub fn answer(msg: ControlMessage) -> Option<ControlMessage> {
+    if msg.type_ != Type::Request || msg.cmd != ANSWER_CMD {
+        return Some(msg);
+    }
+
+    let pending = {
+        let mut pending = PENDING.lock().unwrap();
+        match pending.get(&msg.dest_id) {
+            Some(p) if matches(&p.question_id, &msg.data) => {
+                pending.remove(&msg.dest_id)
+            },
+            _ => None,
+        }
+    };
+
+    let pending = match pending {
+        Some(p) => p,
+        None => return Some(msg),
+    };
+
+    dismiss_task_question(msg.dest_id.clone());
+
+    let response = if pending.answer.send(msg.data.clone()).is_ok() {
+        CommandResponse::ok(Value::Null)
+    } else {
+        CommandResponse {
+            result: "error".to_string(),
+            details: "The question is no longer awaited".to_string(),
+            data: Value::Null,
+        }
+    };
+
+    send_control_msg(msg.response(response));
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answer_matches_question() {
+        assert!(matches("q1", &json!({ "answer": "yes" })));
+        assert!(matches("q1", &json!({ "question_id": "q1" })));
+        assert!(!matches("q1", &json!({ "question_id": "q2" })));
+    }
+}
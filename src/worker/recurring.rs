@@ -0,0 +1,384 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use slog::Logger;
+use std::{collections::HashMap, fs, io, path::PathBuf, time::Duration};
+
+use crate::{
+    core::{
+        env::{self, PATOKA_ROOT_DIR},
+        logger::create_logger,
+        timestamp::now_ms,
+    },
+    worker::processor::{self, TaskWrapperItem},
+};
+
+/// How often the recurrences are checked for being due.
+const TICK: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref PARAMS: RecurringParams =
+        env::load_opt("recurring").unwrap_or_default();
+}
+
+/// `[recurring]` configuration section.
+#[derive(Deserialize)]
+struct RecurringParams {
+    /// ID --> Last run, in ms since the epoch.
+    #[serde(default = "default_path")]
+    path: String,
+
+    /// The most missed runs `CatchUp::RunAll` runs.
+    #[serde(default = "default_max_catch_up")]
+    max_catch_up: u64,
+}
+
+fn default_path() -> String { "data/recurring.json".to_string() }
+
+fn default_max_catch_up() -> u64 { 100 }
+
+impl Default for RecurringParams {
+    fn default() -> Self {
+        Self {
+            path: default_path(),
+            max_catch_up: default_max_catch_up(),
+        }
+    }
+}
+
+impl RecurringParams {
+    fn path(&self) -> PathBuf {
+        PathBuf::from(env::full_path(
+            &self.path,
+            "$PATOKA_ROOT_DIR",
+            &PATOKA_ROOT_DIR,
+        ))
+    }
+}
+
+/// What to do with the runs missed while the app was down.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUp {
+    #[default]
+    Skip,
+    RunOnce,
+
+    /// At most `max_catch_up`.
+    RunAll,
+}
+
+/// Creates the task of each run.
+pub type TaskFactory = Box<dyn Fn() -> TaskWrapperItem + Send>;
+
+#[derive(Clone, Debug)]
+pub struct Recurrence {
+    /// Unique, the key of the last run kept.
+    pub id: String,
+
+    pub every: Duration,
+
+    /// Run at the multiples of `every` since the epoch, e.g. on the hour,
+    /// instead of `every` after the first run.
+    pub aligned: bool,
+
+    pub catch_up: CatchUp,
+}
+
+impl Recurrence {
+    pub fn every(id: &str, every: Duration) -> Self {
+        Self {
+            id: id.to_string(),
+            every,
+            aligned: false,
+            catch_up: CatchUp::default(),
+        }
+    }
+
+    pub fn aligned(mut self) -> Self {
+        self.aligned = true;
+        self
+    }
+
+    pub fn catch_up(mut self, catch_up: CatchUp) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+
+    fn every_ms(&self) -> i64 {
+        (self.every.as_millis() as i64).max(1)
+    }
+
+    /// The number of runs due in (`last_ms`, `now_ms`] and the latest one.
+    fn due(&self, last_ms: i64, now_ms: i64) -> (u64, i64) {
+        let every = self.every_ms();
+
+        if self.aligned {
+            let latest = now_ms.div_euclid(every) * every;
+            let count = (latest - last_ms.div_euclid(every) * every) / every;
+            (count.max(0) as u64, latest.max(last_ms))
+        } else {
+            let count = (now_ms - last_ms).div_euclid(every).max(0);
+            (count as u64, last_ms + count * every)
+        }
+    }
+}
+
+struct Schedule {
+    recurrence: Recurrence,
+    factory: TaskFactory,
+    next_ms: i64,
+}
+
+/// Submits the tasks of `schedule` when due. The last runs are kept in
+/// `path`, so that the schedules survive restarts.
+pub struct RecurringTasks {
+    log: Logger,
+
+    /// ID --> Schedule
+    schedules: HashMap<String, Schedule>,
+
+    /// ID --> Last run, also of the recurrences not scheduled yet.
+    last_runs: HashMap<String, i64>,
+}
+
+impl RecurringTasks {
+    fn load(&mut self) {
+        let data = match fs::read(PARAMS.path()) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                error!(self.log, "Failed to read the last runs: {}", e);
+                return;
+            }
+        };
+
+        match serde_json::from_slice(&data) {
+            Ok(last_runs) => self.last_runs = last_runs,
+            Err(e) => error!(self.log, "Invalid last runs: {}", e),
+        }
+    }
+
+    /// Replace the file at once, so that a crash does not leave it half
+    /// written.
+    fn save(&self) {
+        let write = || -> io::Result<()> {
+            let path = PARAMS.path();
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            let tmp = path.with_extension("json.tmp");
+            fs::write(&tmp, serde_json::to_vec(&self.last_runs)?)?;
+            fs::rename(&tmp, &path)
+        };
+
+        if let Err(e) = write() {
+            error!(self.log, "Failed to write the last runs: {}", e);
+        }
+    }
+
+    fn run(&self, schedule: &Schedule, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        info!(
+            self.log,
+            "Run [RECURRENCE] {} x{}",
+            schedule.recurrence.id,
+            count,
+        );
+        let tasks = (0..count).map(|_| (schedule.factory)()).collect();
+        processor::submit_tasks(tasks);
+    }
+
+    fn add(&mut self, recurrence: Recurrence, factory: TaskFactory) {
+        let now = now_ms();
+        let every = recurrence.every_ms();
+
+        let (count, last) = match self.last_runs.get(&recurrence.id) {
+            Some(&last) => {
+                let (missed, latest) = recurrence.due(last, now);
+                let count = match recurrence.catch_up {
+                    CatchUp::Skip => 0,
+                    CatchUp::RunOnce => missed.min(1),
+                    CatchUp::RunAll => missed.min(PARAMS.max_catch_up),
+                };
+                if missed > 0 {
+                    info!(
+                        self.log,
+                        "[RECURRENCE] {} missed {} runs, {:?}: {} to run",
+                        recurrence.id,
+                        missed,
+                        recurrence.catch_up,
+                        count,
+                    );
+                }
+                (count, latest)
+            }
+            // The first run is at the next multiple if aligned, at once
+            // otherwise.
+            None if recurrence.aligned => (0, now.div_euclid(every) * every),
+            None => (1, now),
+        };
+
+        let schedule = Schedule {
+            next_ms: last + every,
+            recurrence,
+            factory,
+        };
+        self.run(&schedule, count);
+
+        let id = schedule.recurrence.id.clone();
+        self.last_runs.insert(id.clone(), last);
+        self.schedules.insert(id, schedule);
+        self.save();
+    }
+
+    fn run_due(&mut self) {
+        let now = now_ms();
+        let mut changed = false;
+
+        for (id, schedule) in &self.schedules {
+            if schedule.next_ms > now {
+                continue;
+            }
+
+            // Once even if the app has been too busy for several.
+            self.run(schedule, 1);
+            let last = self.last_runs.get(id).copied().unwrap_or(now);
+            let (_, latest) = schedule.recurrence.due(last, now);
+            self.last_runs.insert(id.clone(), latest);
+            changed = true;
+        }
+
+        for (id, schedule) in self.schedules.iter_mut() {
+            if let Some(&last) = self.last_runs.get(id) {
+                schedule.next_ms = last + schedule.recurrence.every_ms();
+            }
+        }
+
+        if changed {
+            self.save();
+        }
+    }
+}
+
+impl Default for RecurringTasks {
+    fn default() -> Self {
+        Self {
+            log: create_logger("recurring_tasks"),
+            schedules: HashMap::new(),
+            last_runs: HashMap::new(),
+        }
+    }
+}
+
+impl Actor for RecurringTasks {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Recurring Tasks started.");
+
+        self.load();
+        ctx.run_interval(TICK, |act, _ctx| act.run_due());
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Recurring Tasks stopped.");
+    }
+}
+
+impl Supervised for RecurringTasks {}
+
+impl SystemService for RecurringTasks {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Recurring Tasks system service started.")
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct AddRecurrence {
+    recurrence: Recurrence,
+    factory: TaskFactory,
+}
+
+impl Handler<AddRecurrence> for RecurringTasks {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: AddRecurrence,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        info!(
+            self.log,
+            "Schedule [RECURRENCE] {} every {:?}{}",
+            msg.recurrence.id,
+            msg.recurrence.every,
+            if msg.recurrence.aligned { " aligned" } else { "" },
+        );
+        self.add(msg.recurrence, msg.factory);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct RemoveRecurrence {
+    id: String,
+}
+
+impl Handler<RemoveRecurrence> for RecurringTasks {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: RemoveRecurrence,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        if self.schedules.remove(&msg.id).is_some() {
+            info!(self.log, "Unschedule [RECURRENCE] {}", msg.id);
+        }
+        self.last_runs.remove(&msg.id);
+        self.save();
+    }
+}
+
+pub fn start() -> Addr<RecurringTasks> {
+    RecurringTasks::from_registry()
+}
+
+/// Submit the task made by `factory` on each run of `recurrence`. Replaces
+/// the schedule of the same ID.
+pub fn schedule<F>(recurrence: Recurrence, factory: F)
+where
+    F: Fn() -> TaskWrapperItem + Send + 'static,
+{
+    start().do_send(AddRecurrence { recurrence, factory: Box::new(factory) });
+}
+
+/// Stop the recurrence and forget its last run.
+pub fn unschedule(id: &str) {
+    start().do_send(RemoveRecurrence { id: id.to_string() });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_runs() {
+        let every = Duration::from_millis(100);
+        let recurrence = Recurrence::every("a", every);
+        assert_eq!(recurrence.due(1030, 1129), (0, 1030));
+        assert_eq!(recurrence.due(1030, 1130), (1, 1130));
+        assert_eq!(recurrence.due(1030, 1455), (4, 1430));
+
+        let recurrence = recurrence.aligned();
+        assert_eq!(recurrence.due(1000, 1099), (0, 1000));
+        assert_eq!(recurrence.due(1030, 1100), (1, 1100));
+        assert_eq!(recurrence.due(1030, 1455), (4, 1400));
+    }
+}
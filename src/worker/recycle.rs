@@ -0,0 +1,143 @@
+use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{core::env, worker::plugin::WorkerPlugin};
+
+lazy_static! {
+    /// Plugin name --> Policy
+    static ref POLICIES: BTreeMap<String, RecyclePolicy> =
+        env::load_opt("worker_recycle").unwrap_or_default();
+}
+
+/// `[worker_recycle.<plugin>]`: the worker process is replaced once the
+/// running tasks are closed. 0 disables a limit.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RecyclePolicy {
+    #[serde(default)]
+    pub max_tasks: u64,
+
+    #[serde(default)]
+    pub max_uptime_s: u64,
+
+    #[serde(default)]
+    pub max_rss_mb: u64,
+}
+
+impl RecyclePolicy {
+    /// Why the worker process is to be recycled, `None` if not yet. The
+    /// RSS is only read, by `rss`, if limited.
+    pub fn reason<F>(
+        &self,
+        tasks: u64,
+        uptime: Duration,
+        rss: F,
+    ) -> Option<String>
+    where
+        F: FnOnce() -> Option<u64>,
+    {
+        if self.max_tasks > 0 && tasks >= self.max_tasks {
+            return Some(format!("{} tasks served", tasks));
+        }
+
+        if self.max_uptime_s > 0 && uptime.as_secs() >= self.max_uptime_s {
+            return Some(format!("up for {}s", uptime.as_secs()));
+        }
+
+        if self.max_rss_mb > 0 {
+            let rss_mb = rss()? / 1024;
+            if rss_mb >= self.max_rss_mb {
+                return Some(format!("RSS {} MB", rss_mb));
+            }
+        }
+
+        None
+    }
+}
+
+pub fn policy(plugin: WorkerPlugin) -> Option<&'static RecyclePolicy> {
+    POLICIES.get(WorkerPlugin::as_str(plugin))
+}
+
+/// The resident memory of the processes of the process group `pgid`, in
+/// KB. `None` where `/proc` is not available.
+#[cfg(target_os = "linux")]
+pub fn rss_kb(pgid: u32) -> Option<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let pid = entry.file_name();
+        let pid = match pid.to_str() {
+            Some(p) if p.bytes().all(|b| b.is_ascii_digit()) => p,
+            _ => continue,
+        };
+
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid));
+        if stat.ok().and_then(|s| process_group(&s)) != Some(pgid) {
+            continue;
+        }
+
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid));
+        total += status.ok().and_then(|s| vm_rss_kb(&s)).unwrap_or(0);
+    }
+
+    Some(total)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rss_kb(_pgid: u32) -> Option<u64> {
+    None
+}
+
+/// The 5th field of `/proc/<pid>/stat`, after the command in parentheses.
+#[cfg(target_os = "linux")]
+fn process_group(stat: &str) -> Option<u32> {
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(2)?.parse().ok()
+}
+
+/// "VmRSS:    1234 kB" of `/proc/<pid>/status`.
+#[cfg(target_os = "linux")]
+fn vm_rss_kb(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reasons() {
+        let policy = RecyclePolicy {
+            max_tasks: 100,
+            max_uptime_s: 3600,
+            max_rss_mb: 512,
+        };
+        let uptime = Duration::from_secs(60);
+
+        assert!(policy.reason(10, uptime, || Some(1024)).is_none());
+        assert_eq!(
+            policy.reason(100, uptime, || None).unwrap(),
+            "100 tasks served",
+        );
+        assert_eq!(
+            policy.reason(10, Duration::from_secs(3600), || None).unwrap(),
+            "up for 3600s",
+        );
+        assert_eq!(
+            policy.reason(10, uptime, || Some(600 * 1024)).unwrap(),
+            "RSS 600 MB",
+        );
+
+        let unlimited = RecyclePolicy::default();
+        assert!(unlimited.reason(1000, uptime, || panic!()).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn proc_fields() {
+        let stat = "42 (node (x)) S 1 42 42 0 -1";
+        assert_eq!(process_group(stat), Some(42));
+        assert_eq!(vm_rss_kb("Name:\tnode\nVmRSS:\t  2048 kB\n"), Some(2048));
+    }
+}
@@ -1,35 +1,304 @@
 use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde::de::IgnoredAny;
+use serde_derive::{Deserialize, Serialize};
 use slog::Logger;
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use crate::{
+    center::send::send_control_msg,
+    control::{
+        command::*,
+        message::*,
+        registry,
+    },
     core::{
+        env::{self, PATOKA_ROOT_DIR},
+        error_bus::{self, PatokaError},
         logger::create_logger,
-        monitor::*,
+        status_aggregator::{self, GetStatusSnapshot, StatusSnapshot},
+        timestamp::{self, Timestamp},
+    },
+    worker::{
+        client::WorkerClient,
+        processor::{self,  *},
+        task::{TaskDefinition, TaskWrapper, WorkerTask},
     },
-    worker::processor::{self,  *},
 };
 
-type Tasks = Vec<TaskWrapperItem>;
+type Tasks = Vec<Pending>;
+
+/// Creates a persisted task from its definition and UUID.
+type Restorer =
+    fn(serde_json::Value, String) -> Result<TaskWrapperItem, String>;
+
+/// Module name used to publish errors.
+const MODULE: &str = "task_reprocessor";
+
+/// How often the tasks put off by their backoff are checked for being due.
+const BACKOFF_TICK: Duration = Duration::from_secs(1);
+
+/// The attempts of a task not reprocessed for that long are forgotten, it
+/// has been run meanwhile.
+const ATTEMPTS_TTL: Duration = Duration::from_secs(600);
+
+/// How often the tasks are persisted if changed.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref PARAMS: ReprocessorParams =
+        env::load_opt("reprocessor").unwrap_or_default();
+
+    /// Client type name --> Restorer
+    static ref RESTORERS: Mutex<HashMap<&'static str, Restorer>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Restore the persisted tasks of the client `C`.
+pub fn restore_with<C>()
+where
+    C: WorkerClient + Actor<Context = Context<C>> + Send + Sync,
+    C::TaskDefinition: TaskDefinition + serde::de::DeserializeOwned,
+    WorkerTask<C>: TaskWrapper + 'static,
+{
+    RESTORERS.lock().unwrap().insert(
+        std::any::type_name::<C>(),
+        |definition, task_uuid| {
+            let definition = serde_json::from_value(definition)
+                .map_err(|e| e.to_string())?;
+            Ok(Box::new(WorkerTask::<C>::new_with_uuid(definition, task_uuid)))
+        },
+    );
+}
+
+/// `[reprocessor]` configuration section.
+#[derive(Deserialize)]
+struct ReprocessorParams {
+    /// 0 for never quarantined.
+    #[serde(default)]
+    max_attempts: u32,
+
+    /// Doubled on each attempt up to `max_backoff_ms`. 0 for none.
+    #[serde(default = "default_backoff_ms")]
+    backoff_ms: u64,
+
+    #[serde(default = "default_max_backoff_ms")]
+    max_backoff_ms: u64,
+
+    /// Where the tasks are persisted, e.g. "data/reprocessor.json". Empty
+    /// for nowhere.
+    #[serde(default)]
+    persist_path: String,
+}
+
+fn default_backoff_ms() -> u64 { 100 }
+
+fn default_max_backoff_ms() -> u64 { 5000 }
+
+impl Default for ReprocessorParams {
+    fn default() -> Self {
+        Self {
+            max_attempts: 0,
+            backoff_ms: default_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            persist_path: String::new(),
+        }
+    }
+}
+
+impl ReprocessorParams {
+    fn persist_path(&self) -> Option<PathBuf> {
+        if self.persist_path.is_empty() {
+            return None;
+        }
+
+        Some(PathBuf::from(env::full_path(
+            &self.persist_path,
+            "$PATOKA_ROOT_DIR",
+            &PATOKA_ROOT_DIR,
+        )))
+    }
+}
+
+/// Before the attempt following `attempts`.
+fn backoff(attempts: u32) -> Duration {
+    let factor = 1u64 << attempts.saturating_sub(1).min(32);
+    let ms = PARAMS.backoff_ms.saturating_mul(factor);
+    Duration::from_millis(ms.min(PARAMS.max_backoff_ms))
+}
+
+struct Attempts {
+    count: u32,
+    first_at: Instant,
+    last_at: Instant,
+}
+
+/// A task waiting for a worker.
+struct Pending {
+    task: TaskWrapperItem,
+
+    /// Of `Turns`.
+    key: String,
+
+    /// Not reprocessed before.
+    due: Instant,
+
+    /// The first attempt.
+    queued_at: Instant,
+}
+
+/// A task kept in `persist_path`.
+#[derive(Clone, Serialize, Deserialize)]
+struct PersistedTask {
+    /// See `TaskWrapper::client_type`.
+    client: String,
+
+    task_uuid: String,
+    definition: serde_json::Value,
+    attempts: u32,
+    queued_at: Timestamp,
+
+    #[serde(default)]
+    quarantined: bool,
+}
+
+/// When `at` was, `age` ago.
+fn timestamp_of(age: Duration) -> Timestamp {
+    let age = chrono::Duration::from_std(age).unwrap_or_default();
+    timestamp::now() - age
+}
 
+fn instant_of(at: Timestamp) -> Instant {
+    let age = (timestamp::now() - at).to_std().unwrap_or_default();
+    Instant::now().checked_sub(age).unwrap_or_else(Instant::now)
+}
+
+/// A task reprocessed `max_attempts` times.
+struct Quarantined {
+    task: TaskWrapperItem,
+    info: QuarantinedTask,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QuarantinedTask {
+    pub task_uuid: String,
+    pub name: String,
+    pub attempts: u32,
+    pub quarantined_at: Timestamp,
+}
+
+/// `general.fair_scheduling`: the tasks waiting for a worker are reprocessed
+/// in turn by their parent task, so that a master task with many subtasks
+/// does not starve the others. In the order of arrival otherwise.
+fn fair_scheduling() -> bool {
+    env::opt_var("general.fair_scheduling").ok().flatten().unwrap_or(true)
+}
+
+/// Items queued by key, taken one per key in turn.
+struct Turns<T> {
+    /// Key --> Items, oldest first
+    queues: HashMap<String, VecDeque<T>>,
+
+    /// The key first in turn is moved to the back on each `drain`.
+    keys: VecDeque<String>,
+
+    len: usize,
+}
+
+impl<T> Turns<T> {
+    fn new() -> Self {
+        Self {
+            queues: HashMap::new(),
+            keys: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, key: String, item: T) {
+        if !self.keys.contains(&key) {
+            self.keys.push_back(key.clone());
+        }
+
+        self.queues.entry(key).or_default().push_back(item);
+        self.len += 1;
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        self.queues.values().flatten()
+    }
+
+    /// All the items, one per key in turn.
+    fn drain(&mut self) -> Vec<T> {
+        let queues = &mut self.queues;
+        self.keys.retain(|k| queues.get(k).is_some_and(|q| !q.is_empty()));
+
+        let mut items = Vec::with_capacity(self.len);
+        let mut keys = self.keys.clone();
+        while let Some(key) = keys.pop_front() {
+            if let Some(item) = queues.get_mut(&key).and_then(|q| q.pop_front())
+            {
+                items.push(item);
+                keys.push_back(key);
+            }
+        }
+
+        // The keys without items are dropped on the next drain unless
+        // pushed again meanwhile.
+        queues.clear();
+        self.keys.rotate_left(self.keys.len().min(1));
+        self.len = 0;
+
+        items
+    }
+}
+
+/// The tasks no controller has been found for, reprocessed with a backoff
+/// and quarantined after `max_attempts`.
 pub struct TaskReprocessor {
     log: Logger,
     task_processor: Addr<TaskProcessor>,
 
-    /// Tasks to reprocess.
-    tasks: Tasks,
+    /// Tasks to reprocess by parent task UUID, or all under "" if not
+    /// `fair_scheduling`.
+    tasks: Turns<Pending>,
 
     /// Worker ID --> [ Task ].
     tasks_linked_with_worker: HashMap<String, Tasks>,
 
-    /// Periodically generate status report.
-    report_status_timer: ReportStatusTimer,
+    /// Task UUID --> Attempts
+    attempts: HashMap<String, Attempts>,
+
+    /// A worker has been ready while some of the tasks were not due yet.
+    /// They are reprocessed once due.
+    ready_pending: bool,
+
+    /// Oldest first.
+    quarantine: Vec<Quarantined>,
+
+    /// Persisted, no client has been registered to restore them with yet.
+    unrestored: Vec<PersistedTask>,
+
+    /// Changed since persisted.
+    dirty: bool,
+
+    commands: Arc<CommandRouter<Self>>,
 }
 
 impl TaskReprocessor {
     fn reprocess_tasks(&self, tasks: Tasks) {
-        for task in tasks {
-            self.reprocess_task(task);
+        for p in tasks {
+            self.reprocess_task(p.task);
         }
     }
 
@@ -37,6 +306,339 @@ impl TaskReprocessor {
         debug!(self.log, "Reprocessing [TASK UUID] {}.", task.uuid());
         self.task_processor.do_send(TaskWrapperItemMessage(task));
     }
+
+    /// Reprocess the tasks due, keep the rest.
+    fn reprocess_due(&mut self) {
+        let now = Instant::now();
+        self.ready_pending = false;
+        self.dirty = true;
+
+        for p in self.tasks.drain() {
+            if p.due <= now {
+                self.reprocess_task(p.task);
+            } else {
+                self.ready_pending = true;
+                self.tasks.push(p.key.clone(), p);
+            }
+        }
+    }
+
+    /// Count an attempt of the task. Returns the attempts so far.
+    fn count_attempt(&mut self, task_uuid: &str) -> u32 {
+        let now = Instant::now();
+        let attempts = self.attempts.entry(task_uuid.to_string())
+            .or_insert(Attempts { count: 0, first_at: now, last_at: now });
+        attempts.count += 1;
+        attempts.last_at = now;
+        attempts.count
+    }
+
+    /// Queue the task for its next attempt, or quarantine it.
+    fn enqueue(&mut self, task: TaskWrapperItem) {
+        let attempts = self.count_attempt(task.uuid());
+        if PARAMS.max_attempts > 0 && attempts > PARAMS.max_attempts {
+            self.quarantine(task, attempts - 1);
+            return;
+        }
+
+        let now = Instant::now();
+        let queued_at = self.attempts.get(task.uuid())
+            .map_or(now, |a| a.first_at);
+        let worker_id = task.worker_id().to_string();
+        let key = if !worker_id.is_empty() || !fair_scheduling() {
+            String::new()
+        } else {
+            task.parent_uuid().to_string()
+        };
+        let pending = Pending {
+            task,
+            key,
+            due: now + backoff(attempts),
+            queued_at,
+        };
+
+        self.dirty = true;
+        if worker_id.is_empty() {
+            self.tasks.push(pending.key.clone(), pending);
+        } else {
+            self.tasks_linked_with_worker.entry(worker_id)
+                .or_default()
+                .push(pending);
+        }
+    }
+
+    fn load(&mut self) {
+        let path = match PARAMS.persist_path() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                error!(self.log, "Failed to read the tasks: {}", e);
+                return;
+            }
+        };
+
+        match serde_json::from_slice(&data) {
+            Ok(tasks) => self.unrestored = tasks,
+            Err(e) => error!(self.log, "Invalid persisted tasks: {}", e),
+        }
+    }
+
+    /// The persisted tasks whose clients have been registered.
+    fn restore(&mut self) {
+        let restorers = RESTORERS.lock().unwrap().clone();
+
+        for p in std::mem::take(&mut self.unrestored) {
+            let restorer = match restorers.get(p.client.as_str()) {
+                Some(r) => r,
+                None => {
+                    self.unrestored.push(p);
+                    continue;
+                },
+            };
+
+            let task = match restorer(p.definition, p.task_uuid.clone()) {
+                Ok(task) => task,
+                Err(e) => {
+                    error!(
+                        self.log,
+                        "Unable to restore [TASK UUID] {}: {}",
+                        p.task_uuid,
+                        e,
+                    );
+                    continue;
+                },
+            };
+
+            info!(self.log, "Restored [TASK UUID] {}.", p.task_uuid);
+            if p.quarantined {
+                let info = QuarantinedTask {
+                    task_uuid: p.task_uuid,
+                    name: task.name().to_string(),
+                    attempts: p.attempts,
+                    quarantined_at: p.queued_at,
+                };
+                self.quarantine.push(Quarantined { task, info });
+            } else {
+                let queued_at = instant_of(p.queued_at);
+                self.attempts.insert(p.task_uuid, Attempts {
+                    count: p.attempts,
+                    first_at: queued_at,
+                    last_at: Instant::now(),
+                });
+                self.enqueue(task);
+            }
+        }
+    }
+
+    fn persisted(&self) -> Vec<PersistedTask> {
+        let persisted = |task: &TaskWrapperItem, attempts, queued_at| {
+            PersistedTask {
+                client: task.client_type().to_string(),
+                task_uuid: task.uuid().to_string(),
+                definition: task.definition(),
+                attempts,
+                queued_at,
+                quarantined: false,
+            }
+        };
+
+        let pending = self.pending().map(|p| {
+            let attempts = self.attempts.get(p.task.uuid())
+                .map_or(0, |a| a.count);
+            persisted(&p.task, attempts, timestamp_of(p.queued_at.elapsed()))
+        });
+        let quarantined = self.quarantine.iter().map(|q| PersistedTask {
+            quarantined: true,
+            ..persisted(&q.task, q.info.attempts, q.info.quarantined_at)
+        });
+
+        pending
+            .chain(quarantined)
+            .chain(self.unrestored.iter().cloned())
+            .collect()
+    }
+
+    /// Not quarantined.
+    fn pending(&self) -> impl Iterator<Item = &Pending> {
+        self.tasks.iter()
+            .chain(self.tasks_linked_with_worker.values().flatten())
+    }
+
+    fn save(&mut self) {
+        let path = match PARAMS.persist_path() {
+            Some(p) if self.dirty => p,
+            _ => return,
+        };
+
+        let tasks = self.persisted();
+        let write = || -> io::Result<()> {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            let tmp = path.with_extension("json.tmp");
+            fs::write(&tmp, serde_json::to_vec(&tasks)?)?;
+            fs::rename(&tmp, &path)
+        };
+
+        match write() {
+            Ok(()) => self.dirty = false,
+            Err(e) => error!(self.log, "Failed to persist the tasks: {}", e),
+        }
+    }
+
+    /// Number of the tasks by why they wait, by the worker they wait for,
+    /// and the age of the oldest one in seconds.
+    fn metrics(&self) -> (serde_json::Value, serde_json::Value, u64) {
+        let by_reason = serde_json::json!({
+            "no_controller": self.tasks.len(),
+            "worker_not_ready": self.tasks_linked_with_worker.values()
+                .map(Vec::len)
+                .sum::<usize>(),
+            "quarantined": self.quarantine.len(),
+            "unrestored": self.unrestored.len(),
+        });
+
+        let by_worker: BTreeMap<&str, usize> =
+            self.tasks_linked_with_worker.iter()
+                .map(|(worker_id, tasks)| (worker_id.as_str(), tasks.len()))
+                .collect();
+
+        let oldest = self.pending()
+            .map(|p| p.queued_at.elapsed().as_secs())
+            .max()
+            .unwrap_or(0);
+
+        (by_reason, serde_json::json!(by_worker), oldest)
+    }
+
+    fn quarantine(&mut self, task: TaskWrapperItem, attempts: u32) {
+        let info = QuarantinedTask {
+            task_uuid: task.uuid().to_string(),
+            name: task.name().to_string(),
+            attempts,
+            quarantined_at: timestamp::now(),
+        };
+
+        self.attempts.remove(&info.task_uuid);
+        self.dirty = true;
+        error_bus::publish(PatokaError::warning(
+            MODULE,
+            format!(
+                "Quarantined [TASK UUID] {} [NAME] {} after {} attempts",
+                info.task_uuid,
+                info.name,
+                attempts,
+            ),
+        ).task(&info.task_uuid));
+
+        self.quarantine.push(Quarantined { task, info });
+    }
+
+    /// Remove the task from the quarantine.
+    fn take_quarantined(
+        &mut self,
+        task_uuid: &str,
+    ) -> Result<TaskWrapperItem, CommandError> {
+        match self.quarantine.iter().position(|q| q.info.task_uuid == task_uuid)
+        {
+            Some(i) => {
+                self.dirty = true;
+                Ok(self.quarantine.remove(i).task)
+            },
+            None => Err(CommandError::InvalidArgs(format!(
+                "[TASK UUID] {} is not quarantined",
+                task_uuid,
+            ))),
+        }
+    }
+
+    fn handle_control_message(
+        &mut self,
+        msg: ControlMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        debug!(self.log, "[CONTROL] {:?}", msg);
+
+        let commands = self.commands.clone();
+        send_control_msg(commands.route(self, msg, ctx));
+    }
+}
+
+/// `data` is ignored.
+#[derive(Deserialize)]
+struct QuarantinedCommand(IgnoredAny);
+
+impl Command for QuarantinedCommand {
+    const NAME: &'static str = "quarantined";
+    type Response = Vec<QuarantinedTask>;
+}
+
+/// `data` is the task UUID.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct ReleaseQuarantinedCommand {
+    task_uuid: String,
+}
+
+impl Command for ReleaseQuarantinedCommand {
+    const NAME: &'static str = "release_quarantined";
+    type Response = ();
+}
+
+/// `data` is the task UUID.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct DropQuarantinedCommand {
+    task_uuid: String,
+}
+
+impl Command for DropQuarantinedCommand {
+    const NAME: &'static str = "drop_quarantined";
+    type Response = ();
+}
+
+impl CommandHandler<QuarantinedCommand> for TaskReprocessor {
+    fn handle_command(
+        &mut self,
+        _args: QuarantinedCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<Vec<QuarantinedTask>, CommandError> {
+        Ok(self.quarantine.iter().map(|q| q.info.clone()).collect())
+    }
+}
+
+impl CommandHandler<ReleaseQuarantinedCommand> for TaskReprocessor {
+    fn handle_command(
+        &mut self,
+        args: ReleaseQuarantinedCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        let task = self.take_quarantined(&args.task_uuid)?;
+        info!(self.log, "Released [TASK UUID] {}.", args.task_uuid);
+        self.reprocess_task(task);
+        Ok(())
+    }
+}
+
+impl CommandHandler<DropQuarantinedCommand> for TaskReprocessor {
+    fn handle_command(
+        &mut self,
+        args: DropQuarantinedCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.take_quarantined(&args.task_uuid)?;
+        info!(self.log, "Dropped [TASK UUID] {}.", args.task_uuid);
+        Ok(())
+    }
 }
 
 impl Default for TaskReprocessor {
@@ -44,9 +646,19 @@ impl Default for TaskReprocessor {
         TaskReprocessor {
             log: create_logger("task_reprocessor"),
             task_processor: processor::start(),
-            tasks: vec![],
+            tasks: Turns::new(),
             tasks_linked_with_worker: HashMap::new(),
-            report_status_timer: ReportStatusTimer::new_s(5),
+            attempts: HashMap::new(),
+            ready_pending: false,
+            quarantine: vec![],
+            unrestored: vec![],
+            dirty: false,
+            commands: Arc::new(
+                CommandRouter::new()
+                    .add::<QuarantinedCommand>()
+                    .add::<ReleaseQuarantinedCommand>()
+                    .add::<DropQuarantinedCommand>()
+            ),
         }
     }
 }
@@ -58,10 +670,31 @@ impl Actor for TaskReprocessor {
         info!(self.log, "Task Reprocessor started.");
 
         ctx.set_mailbox_capacity(1000000);
-        self.report_status_timer.reset::<Self>(ctx);
+        status_aggregator::register("reprocessor", ctx.address().recipient());
+        registry::register(
+            "reprocessor".to_string(),
+            ctx.address().recipient(),
+        );
+
+        ctx.run_interval(BACKOFF_TICK, |act, _ctx| {
+            if !act.unrestored.is_empty() {
+                act.restore();
+            }
+            if act.ready_pending {
+                act.reprocess_due();
+            }
+        });
+        ctx.run_interval(ATTEMPTS_TTL, |act, _ctx| {
+            act.attempts.retain(|_, a| a.last_at.elapsed() < ATTEMPTS_TTL);
+        });
+        ctx.run_interval(PERSIST_INTERVAL, |act, _ctx| act.save());
+
+        self.load();
+        self.restore();
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.save();
         info!(self.log, "Task Reprocessor stopped.");
     }
 }
@@ -92,20 +725,7 @@ impl Handler<ReprocessTask> for TaskReprocessor {
     ) -> Self::Result {
 
         debug!(self.log, "Task to reprocess [TASK UUID] {}.", msg.task.uuid());
-
-        if msg.task.worker_id() == "" {
-            self.tasks.push(msg.task);
-        } else {
-            if let Some(tasks) = self.tasks_linked_with_worker
-                .get_mut(msg.task.worker_id())
-            {
-                tasks.push(msg.task);
-                return;
-            }
-
-            let worker_id = msg.task.worker_id().to_string();
-            self.tasks_linked_with_worker.insert(worker_id, vec![msg.task]);
-        }
+        self.enqueue(msg.task);
     }
 }
 
@@ -132,35 +752,71 @@ impl Handler<WorkerReady> for TaskReprocessor {
         if let Some(tasks) = self.tasks_linked_with_worker
             .remove(&msg.worker_id)
         {
+            self.dirty = true;
             self.reprocess_tasks(tasks);
         } else {
-            while let Some(task) = self.tasks.pop() {
-                self.reprocess_task(task);
-            }
+            self.reprocess_due();
         }
     }
 }
 
-impl Handler<ReportStatusMessage> for TaskReprocessor {
-    type Result = ();
+impl Handler<GetStatusSnapshot> for TaskReprocessor {
+    type Result = MessageResult<GetStatusSnapshot>;
 
     fn handle(
         &mut self,
-        _msg: ReportStatusMessage,
-        ctx: &mut Self::Context
+        _msg: GetStatusSnapshot,
+        _ctx: &mut Self::Context
     ) -> Self::Result {
-        let number_of_tasks_to_reprocess = self.tasks.len();
-        /*info!(
-            self.log,
-            "[STATUS] Number of tasks to reprocess: {}.",
-            number_of_tasks_to_reprocess,
-        );*/
-
-        self.report_status_timer.reset::<Self>(ctx);
+        let (by_reason, by_worker, oldest) = self.metrics();
+
+        MessageResult(
+            StatusSnapshot::new()
+                .with("tasks_to_reprocess", self.tasks.len())
+                .with("workers", self.tasks_linked_with_worker.len())
+                .with("quarantined_tasks", self.quarantine.len())
+                .with("by_reason", by_reason)
+                .with("by_worker", by_worker)
+                .with("oldest_age_s", oldest)
+        )
     }
 }
 
+handler_impl_control_message!(TaskReprocessor);
+
 pub fn start() -> Addr<TaskReprocessor> {
     let addr = TaskReprocessor::from_registry();
     addr
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_by_key() {
+        let mut turns = Turns::new();
+        for item in ["a1", "a2", "a3", "b1", "c1", "c2"] {
+            turns.push(item[..1].to_string(), item);
+        }
+        assert_eq!(turns.len(), 6);
+        assert_eq!(turns.iter().count(), 6);
+        assert_eq!(turns.drain(), ["a1", "b1", "c1", "a2", "c2", "a3"]);
+
+        // "a" has been first, now "b" is.
+        for item in ["a2", "a3", "b2", "c2"] {
+            turns.push(item[..1].to_string(), item);
+        }
+        assert_eq!(turns.drain(), ["b2", "c2", "a2", "a3"]);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let base = Duration::from_millis(PARAMS.backoff_ms);
+        let max = Duration::from_millis(PARAMS.max_backoff_ms);
+
+        assert_eq!(backoff(1), base.min(max));
+        assert_eq!(backoff(2), (base * 2).min(max));
+        assert_eq!(backoff(100), max);
+    }
+}
@@ -0,0 +1,145 @@
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::worker::worker_message::WorkerMessage;
+
+/// Out of order chunks kept by default until the missing ones arrive.
+pub const DEFAULT_MAX_PENDING: usize = 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResultChunk {
+    pub seq: u64,
+    pub data: String,
+
+    #[serde(default)]
+    pub last: bool,
+}
+
+impl WorkerMessage {
+    pub fn result_chunk(&self) -> Option<ResultChunk> {
+        self.payload.data.get("result_chunk")
+            .and_then(|c| serde_json::from_value(c.clone()).ok())
+    }
+}
+
+/// The chunks of the result of a task, in order, processed as they arrive
+/// by `push` or all at once by `collect`.
+pub struct ResultAssembler {
+    next_seq: u64,
+
+    /// Sequence number of the last chunk, once received.
+    last_seq: Option<u64>,
+
+    /// Seq --> Data, the chunks received ahead of `next_seq`.
+    pending: BTreeMap<u64, String>,
+
+    max_pending: usize,
+
+    /// The pieces `collect` has got so far.
+    collected: String,
+}
+
+impl Default for ResultAssembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PENDING)
+    }
+}
+
+impl ResultAssembler {
+    pub fn new(max_pending: usize) -> Self {
+        Self {
+            next_seq: 0,
+            last_seq: None,
+            pending: BTreeMap::new(),
+            max_pending,
+            collected: String::new(),
+        }
+    }
+
+    /// All the chunks up to the last one have been pushed.
+    pub fn is_complete(&self) -> bool {
+        self.last_seq.is_some_and(|last| self.next_seq > last)
+    }
+
+    /// The pieces now in order, none if `chunk` is ahead of a missing one.
+    /// A repeated chunk is ignored.
+    pub fn push(&mut self, chunk: ResultChunk) -> Result<Vec<String>, String> {
+        if chunk.seq < self.next_seq || self.pending.contains_key(&chunk.seq) {
+            return Ok(vec![]);
+        }
+
+        if let Some(last) = self.last_seq {
+            if chunk.seq > last || chunk.last {
+                return Err(format!(
+                    "Result chunk {} after the last one {}",
+                    chunk.seq,
+                    last,
+                ));
+            }
+        }
+
+        if chunk.last {
+            self.last_seq = Some(chunk.seq);
+        }
+
+        if chunk.seq > self.next_seq {
+            if self.pending.len() >= self.max_pending {
+                return Err(format!(
+                    "Result chunk {} is missing, {} chunks after it",
+                    self.next_seq,
+                    self.pending.len(),
+                ));
+            }
+
+            self.pending.insert(chunk.seq, chunk.data);
+            return Ok(vec![]);
+        }
+
+        let mut pieces = vec![chunk.data];
+        self.next_seq += 1;
+        while let Some(data) = self.pending.remove(&self.next_seq) {
+            pieces.push(data);
+            self.next_seq += 1;
+        }
+
+        Ok(pieces)
+    }
+
+    /// The whole result text once the last chunk is in.
+    pub fn collect(
+        &mut self,
+        chunk: ResultChunk,
+    ) -> Result<Option<String>, String> {
+        for piece in self.push(chunk)? {
+            self.collected.push_str(&piece);
+        }
+
+        if self.is_complete() {
+            Ok(Some(std::mem::take(&mut self.collected)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(seq: u64, data: &str, last: bool) -> ResultChunk {
+        ResultChunk { seq, data: data.to_string(), last }
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut assembler = ResultAssembler::new(2);
+        assert_eq!(assembler.collect(chunk(1, "b", false)).unwrap(), None);
+        assert_eq!(assembler.collect(chunk(3, "d", true)).unwrap(), None);
+        assert!(assembler.push(chunk(4, "e", false)).is_err());
+        assert_eq!(assembler.collect(chunk(0, "a", false)).unwrap(), None);
+        assert_eq!(assembler.collect(chunk(0, "a", false)).unwrap(), None);
+        let result = assembler.collect(chunk(2, "c", false)).unwrap();
+        assert_eq!(result.as_deref(), Some("abcd"));
+        assert!(assembler.is_complete());
+    }
+}
@@ -1,20 +1,170 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+
 use crate::{
     core::{env, logger::create_logger},
     transport::router::MessageRouter,
-    worker::dispatcher,
+    worker::{dispatcher, node_registry},
 };
 
+/// Name of the router configured with `general.router_port`.
+pub const DEFAULT_ROUTER: &str = "default";
+
+lazy_static! {
+    static ref ROUTERS: Vec<WorkerRouter> = load_routers();
+}
+
+/// `[worker_routers.<name>]` configuration section.
+#[derive(Deserialize)]
+struct RouterParams {
+    /// ZMQ address the workers connect to, e.g. "tcp://0.0.0.0:3334".
+    bind: String,
+
+    /// Address passed to the worker processes. Derived from `bind` if not
+    /// set: a wildcard interface is replaced with 127.0.0.1.
+    #[serde(default)]
+    connect: Option<String>,
+
+    /// Patterns of the controller IDs served by the router.
+    #[serde(default)]
+    controllers: Vec<String>,
+}
+
+/// A router the worker processes connect to.
+pub struct WorkerRouter {
+    pub name: String,
+
+    /// FE address.
+    pub bind: String,
+
+    /// See `RouterParams::connect`.
+    pub connect: String,
+
+    /// BE address the backend connector sends the messages to.
+    pub backend: String,
+
+    controllers: Vec<Regex>,
+}
+
+impl WorkerRouter {
+    fn new(name: &str, params: RouterParams) -> Self {
+        let backend = if name == DEFAULT_ROUTER {
+            "inproc://router".to_string()
+        } else {
+            format!("inproc://router_{}", name)
+        };
+
+        let connect = params.connect
+            .unwrap_or_else(|| local_address(&params.bind));
+
+        let controllers = params.controllers.iter()
+            .map(|p| Regex::new(p).unwrap())
+            .collect();
+
+        Self {
+            name: name.to_string(),
+            bind: params.bind,
+            connect,
+            backend,
+            controllers,
+        }
+    }
+
+    fn serves(&self, controller_id: &str) -> bool {
+        self.controllers.iter().any(|re| re.is_match(controller_id))
+    }
+}
+
+/// "tcp://*:3333" --> "tcp://127.0.0.1:3333"
+fn local_address(bind: &str) -> String {
+    bind.replacen("://*:", "://127.0.0.1:", 1)
+        .replacen("://0.0.0.0:", "://127.0.0.1:", 1)
+}
+
+fn load_routers() -> Vec<WorkerRouter> {
+    let mut routers = vec![];
+
+    // The default router is there for the backward compatibility.
+    if let Some(port) = env::get_opt_var("general.router_port") {
+        let interface = env::get_opt_var("general.router_interface")
+            .unwrap_or_else(|| "*".to_string());
+
+        routers.push(WorkerRouter::new(
+            DEFAULT_ROUTER,
+            RouterParams {
+                bind: format!("tcp://{}:{}", interface, port),
+                connect: None,
+                controllers: vec![],
+            },
+        ));
+    }
+
+    let params: HashMap<String, RouterParams> =
+        env::load_opt("worker_routers").unwrap_or_default();
+    for (name, p) in params {
+        if name != DEFAULT_ROUTER {
+            routers.push(WorkerRouter::new(&name, p));
+        }
+    }
+
+    routers
+}
+
+/// All the configured routers.
+pub fn routers() -> &'static [WorkerRouter] {
+    &ROUTERS
+}
+
+pub fn by_name(name: &str) -> Option<&'static WorkerRouter> {
+    ROUTERS.iter().find(|r| r.name == name)
+}
+
+/// The router serving the controller: the one of the node the controller is
+/// targeted to, the first one whose `controllers` match the ID, otherwise
+/// the default one.
+pub fn for_controller(controller_id: &str) -> Option<&'static WorkerRouter> {
+    let node_router = node_registry::node_for_controller(controller_id)
+        .and_then(node_registry::router_for_node)
+        .and_then(by_name);
+    if node_router.is_some() {
+        return node_router;
+    }
+
+    ROUTERS.iter()
+        .find(|r| r.serves(controller_id))
+        .or_else(|| ROUTERS.iter().find(|r| r.name == DEFAULT_ROUTER))
+        .or_else(|| ROUTERS.first())
+}
+
+/// Start all the configured routers.
 pub fn start() {
-    let router_port = env::get_var("general.router_port");
-    let frontend_address = "tcp://*:".to_string() + &router_port;
-
-    let backend_address = "inproc://router".to_string();
-
-    MessageRouter::start(
-        create_logger("worker_message_router"),
-        dispatcher::start().into(),
-        frontend_address,
-        backend_address,
-        false,
-    );
+    for router in routers() {
+        let log_name = if router.name == DEFAULT_ROUTER {
+            "worker_message_router".to_string()
+        } else {
+            format!("worker_message_router_{}", router.name)
+        };
+
+        MessageRouter::start(
+            create_logger(&log_name),
+            dispatcher::start().into(),
+            router.bind.clone(),
+            router.backend.clone(),
+            false,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_address_of_wildcard() {
+        assert_eq!(local_address("tcp://*:3333"), "tcp://127.0.0.1:3333");
+        assert_eq!(local_address("tcp://0.0.0.0:1"), "tcp://127.0.0.1:1");
+        assert_eq!(local_address("tcp://10.0.0.2:1"), "tcp://10.0.0.2:1");
+    }
 }
@@ -0,0 +1,198 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{
+    fs::{self, File},
+    io::{self, prelude::*, BufWriter},
+};
+
+use crate::{
+    control::message::CloseTask,
+    core::{
+        arbiter_pool,
+        env::{self, PATOKA_ROOT_DIR},
+        logger::create_logger,
+        timestamp::{now, Timestamp},
+    },
+    worker::worker_message::*,
+};
+
+lazy_static! {
+    static ref PARAMS: RecorderParams =
+        env::load_opt("session_recorder").unwrap_or_default();
+
+    static ref TASK_PATTERNS: Vec<Regex> = PARAMS.tasks.iter()
+        .map(|p| Regex::new(p).unwrap())
+        .collect();
+}
+
+/// `[session_recorder]` configuration section.
+#[derive(Deserialize)]
+struct RecorderParams {
+    /// Record the sessions of the tasks whose names match any of the
+    /// patterns.
+    #[serde(default)]
+    tasks: Vec<String>,
+
+    /// Sessions are written to `{dir}/{task name}/{task uuid}.ndjson`.
+    #[serde(default = "default_dir")]
+    dir: String,
+}
+
+fn default_dir() -> String { "data/sessions".to_string() }
+
+impl Default for RecorderParams {
+    fn default() -> Self {
+        Self {
+            tasks: vec![],
+            dir: default_dir(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// From the client to the worker.
+    ToWorker,
+
+    /// From the worker to the client.
+    ToClient,
+}
+
+/// A line of a session file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionRecord {
+    pub ts: Timestamp,
+    pub direction: Direction,
+    pub msg: WorkerMessage,
+}
+
+/// Writes the messages exchanged by a task with its worker.
+pub struct SessionRecorder {
+    task_uuid: String,
+    file_path: String,
+    file: Option<BufWriter<File>>,
+    log: Logger,
+}
+
+impl SessionRecorder {
+    fn new(task_name: &str, task_uuid: &str) -> Self {
+        let dir = env::full_path(
+            &PARAMS.dir,
+            "$PATOKA_ROOT_DIR",
+            &PATOKA_ROOT_DIR,
+        );
+
+        Self {
+            task_uuid: task_uuid.to_string(),
+            file_path: format!("{}/{}/{}.ndjson", dir, task_name, task_uuid),
+            file: None,
+            log: create_logger(&format!("session_recorder_{}", task_uuid)),
+        }
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(dir) = std::path::Path::new(&self.file_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        self.file = Some(BufWriter::new(File::create(&self.file_path)?));
+        Ok(())
+    }
+
+    fn write(&mut self, record: &SessionRecord) -> io::Result<()> {
+        if let Some(ref mut file) = self.file {
+            serde_json::to_writer(&mut *file, record)?;
+            file.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        if let Some(ref mut file) = self.file {
+            if let Err(e) = file.flush() {
+                error!(self.log, "Failed to flush {}: {}", self.file_path, e);
+            }
+        }
+    }
+}
+
+impl Actor for SessionRecorder {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(
+            self.log,
+            "Recording [TASK UUID] {} to {}",
+            self.task_uuid,
+            self.file_path,
+        );
+
+        if let Err(e) = self.open() {
+            error!(self.log, "Failed to create {}: {}", self.file_path, e);
+            ctx.stop();
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.flush();
+        info!(self.log, "Stopped.");
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Record {
+    pub direction: Direction,
+    pub msg: WorkerMessage,
+}
+
+impl Handler<Record> for SessionRecorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: Record, _ctx: &mut Self::Context) {
+        let record = SessionRecord {
+            ts: now(),
+            direction: msg.direction,
+            msg: msg.msg,
+        };
+
+        if let Err(e) = self.write(&record) {
+            error!(self.log, "Failed to write to {}: {}", self.file_path, e);
+        }
+    }
+}
+
+impl SessionRecorder {
+    /// The session is over.
+    fn handle_close_task(
+        &mut self,
+        _msg: CloseTask,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        ctx.stop();
+    }
+}
+
+handler_impl_close_task!(SessionRecorder);
+
+/// Start recording the session if the task is configured to be recorded.
+pub fn start(
+    task_name: &str,
+    task_uuid: &str,
+) -> Option<Addr<SessionRecorder>> {
+    if !TASK_PATTERNS.iter().any(|re| re.is_match(task_name)) {
+        return None;
+    }
+
+    let task_name = task_name.to_string();
+    let task_uuid = task_uuid.to_string();
+    Some(SessionRecorder::start_in_arbiter(
+        &arbiter_pool::next(),
+        move |_| SessionRecorder::new(&task_name, &task_uuid),
+    ))
+}
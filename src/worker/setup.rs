@@ -12,6 +12,9 @@ use crate::{
     },
 };
 
+#[cfg(feature = "testing")]
+use crate::testing::fake_controller;
+
 pub fn setup(
     task_uuid: &str,
     control_addr: Option<Recipient<ControlMessage>>,
@@ -54,6 +57,10 @@ pub fn setup_with_controller(
                 task_name,
             );
         },
+        #[cfg(feature = "testing")]
+        ControllerAddr::Fake(addr) => {
+            fake_controller::start_task(addr, msg, worker_message_addr);
+        },
         _ => {
             panic!("Unexpected ControllerAddr::None");
         },
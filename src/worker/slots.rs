@@ -0,0 +1,257 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    core::env,
+    worker::plugin::WorkerPlugin,
+};
+
+lazy_static! {
+    static ref PARAMS: SlotsParams =
+        env::load_opt("worker_slots").unwrap_or_default();
+
+    /// Controller ID pattern --> Limit
+    static ref CONTROLLERS: Vec<(Regex, usize)> = PARAMS.controllers.iter()
+        .map(|(c, limit)| (Regex::new(c).unwrap(), *limit))
+        .collect();
+
+    /// Updated by the controllers, read by `status`.
+    static ref STATUS: Mutex<BTreeMap<String, Vec<ReservationStatus>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// `[worker_slots]` configuration section.
+#[derive(Deserialize)]
+struct SlotsParams {
+    /// Tasks a worker runs at the same time.
+    #[serde(default = "default_max_concurrent_tasks")]
+    max_concurrent_tasks: usize,
+
+    /// Plugin name --> Limit instead of `max_concurrent_tasks`, e.g. pages
+    /// of a headless browser.
+    #[serde(default)]
+    plugins: BTreeMap<String, usize>,
+
+    /// Controller ID pattern --> Upper limit. The first match is used.
+    #[serde(default)]
+    controllers: BTreeMap<String, usize>,
+
+    /// A reservation not claimed by the task for that long is released,
+    /// e.g. when the task client has failed to start. 0 for never.
+    #[serde(default = "default_reservation_lease_s")]
+    reservation_lease_s: u64,
+}
+
+fn default_max_concurrent_tasks() -> usize { 1 }
+
+fn default_reservation_lease_s() -> u64 { 30 }
+
+impl Default for SlotsParams {
+    fn default() -> Self {
+        Self {
+            max_concurrent_tasks: default_max_concurrent_tasks(),
+            plugins: BTreeMap::new(),
+            controllers: BTreeMap::new(),
+            reservation_lease_s: default_reservation_lease_s(),
+        }
+    }
+}
+
+/// Tasks the worker of the controller may run at the same time with the
+/// plugin.
+pub fn max_concurrent_tasks(
+    controller_id: &str,
+    plugin: WorkerPlugin,
+) -> usize {
+    let limit = PARAMS.plugins.get(WorkerPlugin::as_str(plugin))
+        .copied()
+        .unwrap_or(PARAMS.max_concurrent_tasks);
+
+    let controller_limit = CONTROLLERS.iter()
+        .find(|(re, _)| re.is_match(controller_id))
+        .map(|(_, limit)| *limit)
+        .unwrap_or(limit);
+
+    limit.min(controller_limit).max(1)
+}
+
+/// A reservation of a controller for a task.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReservationStatus {
+    pub task_uuid: String,
+
+    /// The task client has registered with the controller.
+    pub claimed: bool,
+}
+
+struct Reservation {
+    reserved_at: Instant,
+    lease: Duration,
+    claimed: bool,
+}
+
+/// The tasks a controller has been reserved for, at most `capacity`.
+pub struct TaskSlots {
+    capacity: usize,
+
+    /// Task UUID --> Reservation
+    tasks: HashMap<String, Reservation>,
+}
+
+impl TaskSlots {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tasks: HashMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The tasks already reserved keep their slots if the capacity shrinks.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub fn free(&self) -> usize {
+        self.capacity.saturating_sub(self.tasks.len())
+    }
+
+    pub fn contains(&self, task_uuid: &str) -> bool {
+        self.tasks.contains_key(task_uuid)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// `False` if there is no free slot for a new task. The reservation
+    /// expires unless claimed within `lease`, zero for never.
+    pub fn reserve(&mut self, task_uuid: &str, lease: Duration) -> bool {
+        if self.contains(task_uuid) {
+            return true;
+        }
+
+        if self.free() == 0 {
+            return false;
+        }
+
+        self.tasks.insert(task_uuid.to_string(), Reservation {
+            reserved_at: Instant::now(),
+            lease,
+            claimed: false,
+        });
+        true
+    }
+
+    /// The task has started: the reservation does not expire anymore.
+    /// `False` if the task is not reserved, e.g. the lease has expired.
+    pub fn claim(&mut self, task_uuid: &str) -> bool {
+        match self.tasks.get_mut(task_uuid) {
+            Some(r) => {
+                r.claimed = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn release(&mut self, task_uuid: &str) {
+        self.tasks.remove(task_uuid);
+    }
+
+    /// Release the reservations not claimed within their lease. Returns
+    /// their task UUIDs.
+    pub fn expire(&mut self) -> Vec<String> {
+        let expired: Vec<String> = self.tasks.iter()
+            .filter(|(_, r)| {
+                !r.claimed
+                    && !r.lease.is_zero()
+                    && r.reserved_at.elapsed() >= r.lease
+            })
+            .map(|(task_uuid, _)| task_uuid.clone())
+            .collect();
+
+        for task_uuid in &expired {
+            self.tasks.remove(task_uuid);
+        }
+
+        expired
+    }
+
+    pub fn status(&self) -> Vec<ReservationStatus> {
+        let mut status: Vec<_> = self.tasks.iter()
+            .map(|(task_uuid, r)| ReservationStatus {
+                task_uuid: task_uuid.clone(),
+                claimed: r.claimed,
+            })
+            .collect();
+        status.sort_by(|a, b| a.task_uuid.cmp(&b.task_uuid));
+
+        status
+    }
+}
+
+/// Publish the reservations of the controller for `status`.
+pub fn publish(controller_id: &str, reservations: Vec<ReservationStatus>) {
+    let mut status = STATUS.lock().unwrap();
+    if reservations.is_empty() {
+        status.remove(controller_id);
+    } else {
+        status.insert(controller_id.to_string(), reservations);
+    }
+}
+
+/// Controller ID --> Reservations
+pub fn status() -> BTreeMap<String, Vec<ReservationStatus>> {
+    STATUS.lock().unwrap().clone()
+}
+
+/// `worker_slots.reservation_lease_s`
+pub fn reservation_lease() -> Duration {
+    Duration::from_secs(PARAMS.reservation_lease_s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve_and_release() {
+        let lease = Duration::from_secs(60);
+        let mut slots = TaskSlots::new(2);
+        assert!(slots.reserve("a", lease));
+        assert!(slots.reserve("b", lease));
+        assert!(slots.reserve("a", lease));
+        assert!(!slots.reserve("c", lease));
+
+        slots.set_capacity(1);
+        assert_eq!(slots.free(), 0);
+        slots.release("a");
+        assert!(!slots.reserve("c", lease));
+        slots.release("b");
+        assert!(slots.reserve("c", lease));
+    }
+
+    #[test]
+    fn expire_unclaimed() {
+        let mut slots = TaskSlots::new(3);
+        slots.reserve("a", Duration::ZERO);
+        slots.reserve("b", Duration::from_nanos(1));
+        slots.reserve("c", Duration::from_nanos(1));
+        assert!(slots.claim("c"));
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(slots.expire(), vec!["b".to_string()]);
+        assert!(!slots.claim("b"));
+        assert_eq!(slots.status().len(), 2);
+    }
+}
@@ -0,0 +1,292 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde::de::IgnoredAny;
+use serde_derive::Deserialize;
+use slog::Logger;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use crate::{
+    core::{env, logger::create_logger},
+    worker::{
+        client::WorkerClient,
+        plugin::WorkerPlugin,
+        processor::{self, TaskWrapperItem, TaskWrapperItemMessage},
+        task::{GenTaskDefinition, TaskStatus, TaskWrapper, WorkerTask},
+        task_labels::Labels,
+        tracker::{self, TaskFilter, TaskUpdate, TaskUpdateTag},
+    },
+};
+
+/// The definition of the startup tasks.
+pub type StartupTaskDefinition = GenTaskDefinition<serde_json::Value>;
+
+type TaskFactory = fn(StartupTaskDefinition) -> TaskWrapperItem;
+
+/// ID of the task update recipient.
+const SUBSCRIBER_ID: &str = "startup_tasks";
+
+lazy_static! {
+    /// Client name --> Task factory
+    static ref CLIENTS: Mutex<HashMap<String, TaskFactory>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Run the startup tasks of `client` by `C`. The startup tasks not naming
+/// a client are run by the only client registered.
+pub fn register_client<C>(client: &str)
+where
+    C: WorkerClient<TaskDefinition = StartupTaskDefinition>,
+    C: Actor<Context = Context<C>> + Send + Sync,
+    WorkerTask<C>: TaskWrapper + 'static,
+{
+    TaskRegistry::new().client::<C>(client).register();
+}
+
+/// The clients to register at once, see `register_client`.
+#[derive(Default)]
+pub struct TaskRegistry {
+    /// Client name --> Task factory
+    clients: HashMap<String, TaskFactory>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn client<C>(mut self, client: &str) -> Self
+    where
+        C: WorkerClient<TaskDefinition = StartupTaskDefinition>,
+        C: Actor<Context = Context<C>> + Send + Sync,
+        WorkerTask<C>: TaskWrapper + 'static,
+    {
+        self.clients.insert(
+            client.to_string(),
+            |definition| Box::new(WorkerTask::<C>::new(definition)),
+        );
+        self
+    }
+
+    pub fn register(self) {
+        CLIENTS.lock().unwrap().extend(self.clients);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    fn restarts(self, status: TaskStatus) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => status == TaskStatus::FinishedFailure,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// `[[startup_tasks]]` configuration section.
+#[derive(Clone, Deserialize)]
+struct StartupTask {
+    name: String,
+
+    /// See `register_client`.
+    #[serde(default)]
+    client: String,
+
+    plugin: WorkerPlugin,
+
+    #[serde(default)]
+    executor_path: String,
+
+    #[serde(default)]
+    params: serde_json::Value,
+
+    #[serde(default)]
+    labels: Labels,
+
+    /// Run a new instance of the task once finished.
+    #[serde(default)]
+    restart: RestartPolicy,
+
+    #[serde(default = "default_restart_delay_s")]
+    restart_delay_s: u64,
+}
+
+fn default_restart_delay_s() -> u64 { 5 }
+
+impl StartupTask {
+    fn definition(&self) -> StartupTaskDefinition {
+        let mut definition = StartupTaskDefinition::new(
+            self.plugin,
+            &self.executor_path,
+            self.params.clone(),
+            &self.name,
+        );
+        definition.labels = self.labels.clone();
+        definition
+    }
+}
+
+/// Submits the startup tasks and restarts them by their policy.
+pub struct StartupTasks {
+    log: Logger,
+
+    /// Task name --> Task
+    tasks: HashMap<String, StartupTask>,
+}
+
+impl StartupTasks {
+    fn submit(&self, task: &StartupTask) {
+        let factory = {
+            let clients = CLIENTS.lock().unwrap();
+            match clients.get(&task.client) {
+                Some(f) => Some(*f),
+                None if task.client.is_empty() && clients.len() == 1 => {
+                    clients.values().next().copied()
+                },
+                None => None,
+            }
+        };
+
+        let factory = match factory {
+            Some(f) => f,
+            None => {
+                error!(
+                    self.log,
+                    "No client \"{}\" registered for [NAME] {}",
+                    task.client,
+                    task.name,
+                );
+                return;
+            },
+        };
+
+        let item = factory(task.definition());
+        info!(
+            self.log,
+            "Submit [TASK UUID] {} [NAME] {}",
+            item.uuid(),
+            task.name,
+        );
+
+        processor::start().do_send(TaskWrapperItemMessage(item));
+    }
+
+    fn handle_task_update(
+        &mut self,
+        msg: TaskUpdate,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        if msg.tag != TaskUpdateTag::Finished {
+            return;
+        }
+
+        let task = match self.tasks.get(&msg.name) {
+            Some(t) if t.restart.restarts(msg.status) => t.clone(),
+            _ => return,
+        };
+
+        info!(
+            self.log,
+            "[TASK UUID] {} [NAME] {} finished {:?}. Restart in {} s",
+            msg.task_uuid,
+            msg.name,
+            msg.status,
+            task.restart_delay_s,
+        );
+
+        let delay = Duration::from_secs(task.restart_delay_s);
+        ctx.run_later(delay, move |act, _| act.submit(&task));
+    }
+}
+
+impl Default for StartupTasks {
+    fn default() -> Self {
+        let log = create_logger("startup_tasks");
+        let configured: Vec<StartupTask> =
+            env::load_opt("startup_tasks").unwrap_or_default();
+
+        let mut tasks = HashMap::new();
+        for task in configured {
+            if tasks.contains_key(&task.name) {
+                warn!(log, "Duplicate startup task [NAME] {}", task.name);
+                continue;
+            }
+            tasks.insert(task.name.clone(), task);
+        }
+
+        Self { log, tasks }
+    }
+}
+
+impl Actor for StartupTasks {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Startup Tasks started: {}", self.tasks.len());
+
+        tracker::register_task_update_recipient(
+            SUBSCRIBER_ID.to_string(),
+            ctx.address().recipient(),
+        );
+
+        for task in self.tasks.values() {
+            if task.restart != RestartPolicy::Never {
+                tracker::subscribe_by_name_filtered(
+                    task.name.clone(),
+                    SUBSCRIBER_ID.to_string(),
+                    TaskFilter::finished(),
+                );
+            }
+
+            self.submit(task);
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Startup Tasks stopped.");
+    }
+}
+
+impl Supervised for StartupTasks {}
+
+impl SystemService for StartupTasks {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Startup Tasks system service started.")
+    }
+}
+
+handler_impl_task_update!(StartupTasks);
+
+/// Any `[[startup_tasks]]` configured.
+pub fn enabled() -> bool {
+    env::load_opt::<Vec<IgnoredAny>>("startup_tasks")
+        .is_some_and(|tasks| !tasks.is_empty())
+}
+
+pub fn start() -> Addr<StartupTasks> {
+    StartupTasks::from_registry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_policy() {
+        use RestartPolicy::*;
+        use TaskStatus::*;
+
+        assert!(!Never.restarts(FinishedFailure));
+        assert!(OnFailure.restarts(FinishedFailure));
+        assert!(!OnFailure.restarts(FinishedSuccess));
+        assert!(Always.restarts(FinishedSuccess));
+    }
+}
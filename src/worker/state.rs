@@ -1,8 +1,10 @@
 use actix::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 use slog::Logger;
 use std::fmt;
 
 use crate::worker::{
+    health::WorkerHealth,
     plugin::WorkerPlugin,
     reprocessor::{self, WorkerReady, TaskReprocessor},
 };
@@ -51,10 +53,45 @@ impl fmt::Debug for WS {
     }
 }
 
+/// Reported by the worker in the `started` details.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkerCapabilities {
+    /// Plugin names the worker is able to load.
+    pub plugins: Vec<String>,
+
+    #[serde(default)]
+    pub protocol_version: u32,
+
+    /// Tasks the worker is able to run at the same time. 0 if not limited.
+    #[serde(default)]
+    pub max_concurrency: usize,
+
+    #[serde(default)]
+    pub node_version: String,
+}
+
+impl WorkerCapabilities {
+    /// `None` if the worker does not report the capabilities.
+    pub fn from_details(details: &serde_json::Value) -> Option<Self> {
+        serde_json::from_value(details.clone()).ok()
+    }
+
+    pub fn supports(&self, plugin: WorkerPlugin) -> bool {
+        plugin == WorkerPlugin::None
+            || self.plugins.iter().any(|p| p == WorkerPlugin::as_str(plugin))
+    }
+}
+
 pub struct WorkerState {
     id: String,
     current_state: WS,
     plugin: WorkerPlugin,
+
+    /// `None` until reported, e.g. by an older worker.
+    capabilities: Option<WorkerCapabilities>,
+
+    health: WorkerHealth,
+
     log: Logger,
     task_reprocessor: Addr<TaskReprocessor>,
 }
@@ -65,6 +102,8 @@ impl WorkerState {
             id,
             current_state: WS::Initial,
             plugin: WorkerPlugin::None,
+            capabilities: None,
+            health: WorkerHealth::default(),
             log,
             task_reprocessor: reprocessor::start(),
         }
@@ -145,6 +184,10 @@ impl WorkerState {
         self.current_state = state;
     }
 
+    pub fn current_plugin(&self) -> WorkerPlugin {
+        self.plugin
+    }
+
     pub fn is_plugin(&self, plugin: WorkerPlugin) -> bool {
         self.plugin == plugin
     }
@@ -156,4 +199,49 @@ impl WorkerState {
         debug!(self.log, "[PLUGIN] ({:?}) => ({:?})", self.plugin, plugin);
         self.plugin = plugin;
     }
+
+    pub fn capabilities(&self) -> Option<&WorkerCapabilities> {
+        self.capabilities.as_ref()
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: WorkerCapabilities) {
+        debug!(self.log, "[CAPABILITIES] {:?}", capabilities);
+        self.capabilities = Some(capabilities);
+    }
+
+    /// Any plugin is assumed to be supported until the worker reports the
+    /// capabilities.
+    pub fn supports_plugin(&self, plugin: WorkerPlugin) -> bool {
+        self.capabilities.as_ref().is_none_or(|c| c.supports(plugin))
+    }
+
+    pub fn health(&self) -> &WorkerHealth {
+        &self.health
+    }
+
+    pub fn health_mut(&mut self) -> &mut WorkerHealth {
+        &mut self.health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn capabilities_from_details() {
+        assert!(WorkerCapabilities::from_details(&json!({})).is_none());
+
+        let c = WorkerCapabilities::from_details(&json!({
+            "plugins": ["basic"],
+            "protocol_version": 2,
+            "max_concurrency": 4,
+            "node_version": "v18.17.0",
+        })).unwrap();
+        assert!(c.supports(WorkerPlugin::Basic));
+        assert!(c.supports(WorkerPlugin::None));
+        assert!(!c.supports(WorkerPlugin::HeadlessBrowser));
+        assert_eq!(c.max_concurrency, 4);
+    }
 }
@@ -8,20 +8,39 @@ use uuid::Uuid;
 use crate::{
     center::send::*,
     control::message::StopTask,
+    core::telemetry,
+    storage::checkpoint,
     worker::{
+        cancellation::CancellationToken,
         client::*,
         controller::{WorkerController},
+        params_schema,
         plugin::{WorkerPlugin},
+        task_labels::Labels,
         task_reader::TaskReader,
-        tracker,
-        worker_message::{WorkerMessage, Dest, WorkerMessagePayload},
+        tracker::{self, TaskFilter},
+        worker_message::{
+            WorkerMessage,
+            Dest,
+            WorkerMessagePayload,
+            PROTOCOL_VERSION,
+        },
     },
+    utils::json::merge_patch,
 };
 
+#[cfg(feature = "testing")]
+use crate::testing::FakeController;
+
 #[derive(Clone)]
 pub enum ControllerAddr {
     Controller(Addr<WorkerController>),
     Reader(Addr<TaskReader>),
+
+    /// See `testing::run_task`.
+    #[cfg(feature = "testing")]
+    Fake(Addr<FakeController>),
+
     None,
 }
 
@@ -40,12 +59,18 @@ pub struct TaskExecutionContext {
     pub parent_task_uuid: String,
     pub stop_task_addr: Recipient<StopTask>,
     pub controller_addr: ControllerAddr,
+
+    /// Shared with the client.
+    pub cancellation: CancellationToken,
 }
 
 impl TaskExecutionContext {
     pub fn send_worker_message(&self, msg: WorkerMessage) {
-        if let ControllerAddr::Controller(addr) = &self.controller_addr {
-            addr.do_send(msg);
+        match &self.controller_addr {
+            ControllerAddr::Controller(addr) => addr.do_send(msg),
+            #[cfg(feature = "testing")]
+            ControllerAddr::Fake(addr) => addr.do_send(msg),
+            _ => {},
         }
     }
 }
@@ -73,6 +98,68 @@ pub trait TaskWrapper: Send + Sync {
     fn plugin(&self) -> WorkerPlugin;
 
     fn name(&self) -> &str;
+
+    fn orphan_policy(&self) -> OrphanPolicy;
+
+    fn labels(&self) -> Labels;
+
+    fn queue(&self) -> &str;
+
+    fn set_default_plugin(&mut self, plugin: WorkerPlugin);
+
+    /// `params` of the task definition.
+    fn params(&self) -> serde_json::Value;
+
+    /// Apply the JSON merge patch to the `params` of the task definition,
+    /// e.g. before the task is restarted.
+    fn patch_params(
+        &mut self,
+        patch: &serde_json::Value,
+    ) -> Result<(), String>;
+
+    /// The schema of the task definition or registered for the name. See
+    /// `params_schema`.
+    fn params_schema(&self) -> Option<serde_json::Value>;
+
+    fn validate_params(&self) -> Result<(), String> {
+        match self.params_schema() {
+            Some(schema) => params_schema::validate(&schema, &self.params()),
+            None => Ok(()),
+        }
+    }
+
+    /// The whole task definition, e.g. to persist the task.
+    fn definition(&self) -> serde_json::Value;
+
+    /// Type name of the client, the key a persisted task is restored by.
+    /// See `reprocessor::restore_with`.
+    fn client_type(&self) -> &'static str;
+}
+
+/// What the task tree does with the children still running when their
+/// parent finishes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanPolicy {
+    /// The children keep running and are closed along with the parent.
+    #[default]
+    Keep,
+
+    /// The children are stopped.
+    CascadeStop,
+
+    /// The children keep running on their own: closing the parent does not
+    /// close them.
+    Detach,
+
+    /// The parent is not reported finished until all the children are.
+    WaitForChildren,
+}
+
+impl OrphanPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == OrphanPolicy::default()
+    }
 }
 
 pub trait TaskDefinition {
@@ -86,6 +173,32 @@ pub trait TaskDefinition {
     fn plugin(&self) -> WorkerPlugin;
 
     fn name(&self) -> &str;
+
+    fn orphan_policy(&self) -> OrphanPolicy {
+        OrphanPolicy::default()
+    }
+
+    fn labels(&self) -> Labels {
+        Labels::new()
+    }
+
+    /// The queue whose controllers run the task, the default one if empty.
+    fn queue(&self) -> &str {
+        ""
+    }
+
+    /// Set by the queue if the task does not require any plugin.
+    fn set_default_plugin(&mut self, _plugin: WorkerPlugin) {}
+
+    /// The updates of the task its parent is subscribed to.
+    fn parent_updates(&self) -> TaskFilter {
+        TaskFilter::default()
+    }
+
+    /// JSON Schema of the params. See `params_schema`.
+    fn params_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -108,6 +221,34 @@ pub struct GenTaskDefinition<P> {
 
     /// Worker plugin that must be active to execute the task.
     pub plugin: WorkerPlugin,
+
+    /// Optional: browser profile shared by the tasks to reuse a session.
+    /// See `plugin::BrowserProfile`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub profile: String,
+
+    /// The children still running when the task finishes.
+    #[serde(default, skip_serializing_if = "OrphanPolicy::is_default")]
+    pub orphan_policy: OrphanPolicy,
+
+    /// Optional: to group the tasks, e.g. by customer or site.
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+
+    /// Optional: the queue whose controllers run the task. See
+    /// `controller_pool::QueueParams`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub queue: String,
+
+    /// Optional: the updates sent to the parent, all by default. A parent of
+    /// many children may only want e.g. the finished ones.
+    #[serde(default, skip_serializing_if = "TaskFilter::is_default")]
+    pub parent_updates: TaskFilter,
+
+    /// Optional: JSON Schema the params are validated with before the task
+    /// is dispatched. See `params_schema`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params_schema: Option<serde_json::Value>,
 }
 
 impl<P> TaskDefinition for GenTaskDefinition<P> {
@@ -124,6 +265,24 @@ impl<P> TaskDefinition for GenTaskDefinition<P> {
     fn plugin(&self) -> WorkerPlugin { self.plugin }
 
     fn name(&self) -> &str { &self.name }
+
+    fn orphan_policy(&self) -> OrphanPolicy { self.orphan_policy }
+
+    fn labels(&self) -> Labels { self.labels.clone() }
+
+    fn queue(&self) -> &str { &self.queue }
+
+    fn parent_updates(&self) -> TaskFilter { self.parent_updates.clone() }
+
+    fn params_schema(&self) -> Option<serde_json::Value> {
+        self.params_schema.clone()
+    }
+
+    fn set_default_plugin(&mut self, plugin: WorkerPlugin) {
+        if self.plugin == WorkerPlugin::None {
+            self.plugin = plugin;
+        }
+    }
 }
 
 impl<P> GenTaskDefinition<P>
@@ -144,6 +303,12 @@ where
             parent_task_uuid: String::new(),
             worker_id: String::new(),
             plugin,
+            profile: String::new(),
+            orphan_policy: OrphanPolicy::default(),
+            labels: Labels::new(),
+            queue: String::new(),
+            parent_updates: TaskFilter::default(),
+            params_schema: None,
         }
     }
 
@@ -162,9 +327,50 @@ where
             parent_task_uuid,
             worker_id: String::new(),
             plugin,
+            profile: String::new(),
+            orphan_policy: OrphanPolicy::default(),
+            labels: Labels::new(),
+            queue: String::new(),
+            parent_updates: TaskFilter::default(),
+            params_schema: None,
         }
     }
 
+    /// Run the task in the browser `profile`.
+    pub fn with_profile(mut self, profile: &str) -> Self {
+        self.profile = profile.to_string();
+        self
+    }
+
+    /// Manage the children running when the task finishes by `policy`.
+    pub fn with_orphan_policy(mut self, policy: OrphanPolicy) -> Self {
+        self.orphan_policy = policy;
+        self
+    }
+
+    pub fn with_label(mut self, key: &str, value: &str) -> Self {
+        self.labels.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Run the task by the controllers of `queue`.
+    pub fn with_queue(mut self, queue: &str) -> Self {
+        self.queue = queue.to_string();
+        self
+    }
+
+    /// Send the parent only the updates matching `filter`.
+    pub fn with_parent_updates(mut self, filter: TaskFilter) -> Self {
+        self.parent_updates = filter;
+        self
+    }
+
+    /// Validate the params with `schema` before the task is dispatched.
+    pub fn with_params_schema(mut self, schema: serde_json::Value) -> Self {
+        self.params_schema = Some(schema);
+        self
+    }
+
     pub fn new_none_plugin(params: P, name: &str) -> Self {
         Self::new(WorkerPlugin::None, "", params, name)
     }
@@ -194,7 +400,11 @@ where
             worker_id: self.worker_id.clone(),
             task_uuid: self.task_uuid.clone(),
             plugin: WorkerPlugin::as_str(self.plugin).to_string(),
+            profile: self.profile.clone(),
             data,
+            correlation_id: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            trace: telemetry::child_of_task(&self.task_uuid),
         };
 
         WorkerMessage::new(payload)
@@ -211,7 +421,11 @@ where
             worker_id: self.worker_id.clone(),
             task_uuid: self.task_uuid.clone(),
             plugin: WorkerPlugin::as_str(self.plugin).to_string(),
+            profile: self.profile.clone(),
             data,
+            correlation_id: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            trace: telemetry::child_of_task(&self.task_uuid),
         };
 
         WorkerMessage::new(payload)
@@ -263,7 +477,7 @@ where
     C: Actor<Context=Context<C>>,
     Self: Send + Sync,
     C::TaskDefinition: Clone + TaskDefinition + Send + Sync +
-        serde::Serialize,
+        serde::Serialize + serde::de::DeserializeOwned,
 {
     fn execute_in_arbiter(
         &self,
@@ -281,21 +495,27 @@ where
                 parent_task_uuid.clone(),
                 self.task_definition.name().into(),
                 false,
+                self.task_definition.parent_updates(),
             );
         }
 
+        let cancellation = CancellationToken::new();
         let client_ctx = ClientContext {
             task_uuid: self.task_uuid.clone(),
             worker_id: self.worker_id.clone(),
             controller_addr,
             task_definition: self.task_definition.clone(),
+            cancellation: cancellation.clone(),
+            checkpoint: checkpoint::last(self.task_definition.name()),
         };
         let client_addr = C::start_in_arbiter_(arbiter, client_ctx);
 
+        telemetry::start_task(&self.task_uuid, &parent_task_uuid);
         send_center_task_started(
             &self.task_uuid,
             &self.task_definition,
             self.task_definition.name(),
+            self.task_definition.labels(),
         );
 
         TaskExecutionContext {
@@ -303,6 +523,7 @@ where
             parent_task_uuid,
             stop_task_addr: client_addr.recipient::<StopTask>(),
             controller_addr: controller_addr_clone,
+            cancellation,
         }
     }
 
@@ -327,5 +548,59 @@ where
     fn plugin(&self) -> WorkerPlugin { self.task_definition.plugin() }
 
     fn name(&self) -> &str { self.task_definition.name() }
+
+    fn orphan_policy(&self) -> OrphanPolicy {
+        self.task_definition.orphan_policy()
+    }
+
+    fn labels(&self) -> Labels { self.task_definition.labels() }
+
+    fn queue(&self) -> &str { self.task_definition.queue() }
+
+    fn set_default_plugin(&mut self, plugin: WorkerPlugin) {
+        self.task_definition.set_default_plugin(plugin);
+    }
+
+    fn params_schema(&self) -> Option<serde_json::Value> {
+        self.task_definition.params_schema()
+            .or_else(|| params_schema::get(self.task_definition.name()))
+    }
+
+    fn definition(&self) -> serde_json::Value {
+        serde_json::to_value(&self.task_definition).unwrap_or_default()
+    }
+
+    fn client_type(&self) -> &'static str {
+        std::any::type_name::<C>()
+    }
+
+    fn params(&self) -> serde_json::Value {
+        serde_json::to_value(&self.task_definition)
+            .ok()
+            .and_then(|mut d| d.get_mut("params").map(|p| p.take()))
+            .unwrap_or_default()
+    }
+
+    fn patch_params(
+        &mut self,
+        patch: &serde_json::Value,
+    ) -> Result<(), String> {
+        let mut definition = serde_json::to_value(&self.task_definition)
+            .map_err(|e| e.to_string())?;
+
+        let params = definition.get_mut("params")
+            .ok_or_else(|| "The task definition has no params".to_string())?;
+        merge_patch(params, patch);
+
+        if let Some(schema) = self.params_schema() {
+            params_schema::validate(&schema, params)?;
+        }
+
+        // Not applied unless the whole definition is still valid.
+        self.task_definition = serde_json::from_value(definition)
+            .map_err(|e| format!("Invalid params: {}", e))?;
+
+        Ok(())
+    }
 }
 
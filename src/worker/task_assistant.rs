@@ -8,6 +8,7 @@ use std::{
 use crate::{
     core::logger::create_logger,
     worker::{
+        circuit_breaker,
         tracker::{self, TaskUpdate},
         task::TaskStatus,
         task_tree::self,
@@ -78,21 +79,25 @@ impl TaskAssistant {
             TaskStatus::FinishedFailure => {
                 let item = self.tasks.get(&msg.task_uuid).unwrap();
 
+                // Not before the circuit of the task name is closed.
+                let restart_delay = Duration::from_millis(
+                    item.restart_delay as u64
+                ).max(circuit_breaker::open_for(&msg.name).unwrap_or_default());
+
                 debug!(
                     self.log,
                     "Finished FAILURE [TASK UUID] {}. Restarting task in {} \
                         ms.",
                     msg.task_uuid,
-                    item.restart_delay,
+                    restart_delay.as_millis(),
                 );
 
-                let restart_delay = item.restart_delay as u64;
                 let task_uuid = msg.task_uuid.clone();
 
                 self.tasks.remove(&msg.task_uuid);
 
                 ctx.run_later(
-                    Duration::from_millis(restart_delay),
+                    restart_delay,
                     |_, _| task_tree::restart_task(task_uuid),
                 );
             },
@@ -0,0 +1,172 @@
+use lazy_static::lazy_static;
+use std::{collections::HashMap, fmt, sync::Mutex};
+use tokio::sync::oneshot;
+
+use crate::{
+    impl_task_client,
+    worker::{
+        client::GenClientContext,
+        client_template::{Flow, TaskClient},
+        processor::{self, Rejection},
+        task::{GenTaskDefinition, TaskStatus, WorkerTask},
+    },
+};
+
+type ResultSender = oneshot::Sender<Result<serde_json::Value, TaskError>>;
+
+lazy_static! {
+    /// Task UUID --> Future to resolve
+    static ref PENDING: Mutex<HashMap<String, ResultSender>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug)]
+pub enum TaskError {
+    /// The definition does not serialize.
+    InvalidDefinition(String),
+
+    /// Rejected by the processor, e.g. the params are invalid.
+    Rejected(Rejection),
+
+    /// The task has finished without a result, e.g. it has failed.
+    Failed,
+
+    /// The result does not deserialize into the type expected.
+    InvalidResult(String),
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskError::InvalidDefinition(e) => {
+                write!(f, "Invalid task definition: {}", e)
+            },
+            TaskError::Rejected(r) => write!(f, "{}", r),
+            TaskError::Failed => write!(f, "Task finished without result"),
+            TaskError::InvalidResult(e) => {
+                write!(f, "Invalid task result: {}", e)
+            },
+        }
+    }
+}
+
+/// Resolves the future of `run_worker_task` with the first result.
+#[derive(Clone)]
+pub struct FutureClient {
+    ctx: GenClientContext<serde_json::Value>,
+}
+
+impl FutureClient {
+    fn resolve(&self, result: Result<serde_json::Value, TaskError>) {
+        let sender = PENDING.lock().unwrap().remove(&self.ctx.task_uuid);
+        if let Some(sender) = sender {
+            let _ = sender.send(result);
+        }
+    }
+}
+
+impl TaskClient for FutureClient {
+    type Params = serde_json::Value;
+
+    fn new(ctx: GenClientContext<serde_json::Value>) -> Self {
+        Self { ctx }
+    }
+
+    fn client_ctx(&self) -> &GenClientContext<serde_json::Value> {
+        &self.ctx
+    }
+
+    fn on_result(
+        &mut self,
+        result: serde_json::Value,
+        _ctx: &mut actix::Context<Self>,
+    ) -> Flow {
+        self.resolve(Ok(result));
+        Flow::Finish
+    }
+
+    /// Nothing to resolve if the result has arrived.
+    fn on_stop(
+        &mut self,
+        _status: TaskStatus,
+        _ctx: &mut actix::Context<Self>,
+    ) {
+        self.resolve(Err(TaskError::Failed));
+    }
+}
+
+impl_task_client!(FutureClient);
+
+/// The future of the result of `task_uuid`.
+fn register(
+    task_uuid: &str,
+) -> oneshot::Receiver<Result<serde_json::Value, TaskError>> {
+    let (sender, receiver) = oneshot::channel();
+    PENDING.lock().unwrap().insert(task_uuid.to_string(), sender);
+    receiver
+}
+
+async fn wait<T>(
+    receiver: oneshot::Receiver<Result<serde_json::Value, TaskError>>,
+) -> Result<T, TaskError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let result = receiver.await.unwrap_or(Err(TaskError::Failed))?;
+    serde_json::from_value(result)
+        .map_err(|e| TaskError::InvalidResult(e.to_string()))
+}
+
+/// Submit the task and wait for its result. Wrap it into a timeout if the
+/// task may never finish.
+pub async fn run_worker_task<P, T>(
+    definition: GenTaskDefinition<P>,
+) -> Result<T, TaskError>
+where
+    P: serde::Serialize + Clone,
+    T: serde::de::DeserializeOwned,
+{
+    let definition = serde_json::to_value(definition)
+        .and_then(serde_json::from_value)
+        .map_err(|e| TaskError::InvalidDefinition(e.to_string()))?;
+
+    let task = WorkerTask::<FutureClient>::new(definition);
+    let task_uuid = task.task_uuid.clone();
+    let receiver = register(&task_uuid);
+
+    if let Err(rejection) = processor::try_submit(Box::new(task)).await {
+        PENDING.lock().unwrap().remove(&task_uuid);
+        return Err(TaskError::Rejected(rejection));
+    }
+
+    wait(receiver).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::{
+        testing::{self, FakeController},
+        worker::plugin::WorkerPlugin,
+    };
+
+    #[actix::test]
+    async fn resolves_with_result() {
+        let task = WorkerTask::<FutureClient>::new(GenTaskDefinition::new(
+            WorkerPlugin::Basic,
+            "echo.js",
+            json!({}),
+            "echo",
+        ));
+        let receiver = register(&task.task_uuid);
+
+        let controller = FakeController::new()
+            .respond(json!({ "task_result": { "n": 1 } }));
+        testing::run_task(&task, controller, testing::DEFAULT_TIMEOUT).await;
+
+        let result: serde_json::Value = wait(receiver).await.unwrap();
+        assert_eq!(result, json!({ "n": 1 }));
+    }
+}
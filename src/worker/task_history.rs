@@ -0,0 +1,401 @@
+use actix::prelude::*;
+use chrono::SecondsFormat;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use slog::Logger;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, prelude::*},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::OnceCell;
+
+use crate::{
+    core::{
+        env::{self, PATOKA_ROOT_DIR},
+        logger::create_logger,
+        timestamp::{self, Timestamp},
+    },
+    storage::{
+        backend::{Backend, Statement},
+        db_executor,
+    },
+    worker::{
+        task::TaskStatus,
+        tracker::TaskUpdate,
+    },
+};
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS patoka_task_events (\
+    task_uuid text NOT NULL, \
+    ts text NOT NULL, \
+    data text NOT NULL)";
+
+lazy_static! {
+    static ref PARAMS: TaskHistoryParams =
+        env::load_opt("task_history").unwrap_or_default();
+}
+
+/// `[task_history]` configuration section of the append-only log of the
+/// task updates, a JSON line per update or a database row.
+#[derive(Deserialize)]
+struct TaskHistoryParams {
+    #[serde(default)]
+    enabled: bool,
+
+    /// "file" (default) or "db" for the database of `app.db`.
+    #[serde(default = "default_storage")]
+    storage: String,
+
+    #[serde(default = "default_path")]
+    path: String,
+
+    #[serde(default = "default_flush_interval_ms")]
+    flush_interval_ms: u64,
+}
+
+fn default_storage() -> String { "file".to_string() }
+
+fn default_path() -> String { "data/task_history.jsonl".to_string() }
+
+fn default_flush_interval_ms() -> u64 { 1000 }
+
+impl Default for TaskHistoryParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            storage: default_storage(),
+            path: default_path(),
+            flush_interval_ms: default_flush_interval_ms(),
+        }
+    }
+}
+
+impl TaskHistoryParams {
+    fn in_db(&self) -> bool {
+        self.storage == "db"
+    }
+
+    fn path(&self) -> PathBuf {
+        PathBuf::from(env::full_path(
+            &self.path,
+            "$PATOKA_ROOT_DIR",
+            &PATOKA_ROOT_DIR,
+        ))
+    }
+}
+
+pub fn enabled() -> bool {
+    PARAMS.enabled
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskEvent {
+    pub task_uuid: String,
+    pub name: String,
+
+    /// "started", "updated", "finished", etc.
+    pub tag: String,
+
+    pub status: TaskStatus,
+    pub ts: Timestamp,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl TaskEvent {
+    fn of(msg: &TaskUpdate) -> Self {
+        Self {
+            task_uuid: msg.task_uuid.clone(),
+            name: msg.name.clone(),
+            tag: msg.tag.as_str().to_string(),
+            status: msg.status,
+            ts: timestamp::now(),
+            duration_ms: msg.duration_ms,
+        }
+    }
+}
+
+/// The events of `task_uuid` in `path`, oldest first.
+fn read_file(path: &Path, task_uuid: &str) -> Result<Vec<TaskEvent>, String> {
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut events = vec![];
+    for line in io::BufReader::new(file).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if !line.contains(task_uuid) {
+            continue;
+        }
+
+        let event: TaskEvent = serde_json::from_str(&line)
+            .map_err(|e| e.to_string())?;
+        if event.task_uuid == task_uuid {
+            events.push(event);
+        }
+    }
+
+    Ok(events)
+}
+
+fn append_file(path: &Path, events: &[TaskEvent]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut data = String::new();
+    for event in events {
+        data.push_str(&serde_json::to_string(event)?);
+        data.push('\n');
+    }
+
+    OpenOptions::new().create(true).append(true).open(path)?
+        .write_all(data.as_bytes())
+}
+
+/// The database and whether its table has been created.
+#[derive(Clone)]
+struct EventTable {
+    backend: Arc<dyn Backend>,
+    created: Arc<OnceCell<()>>,
+}
+
+impl EventTable {
+    fn new() -> Option<Self> {
+        Some(Self {
+            backend: db_executor::backend()?,
+            created: Arc::new(OnceCell::new()),
+        })
+    }
+
+    async fn backend(&self) -> Result<&Arc<dyn Backend>, String> {
+        self.created.get_or_try_init(|| async {
+            self.backend.execute(Statement::new(CREATE_TABLE, vec![])).await
+                .map(|_| ())
+        }).await?;
+
+        Ok(&self.backend)
+    }
+
+    async fn append(&self, events: Vec<TaskEvent>) -> Result<(), String> {
+        let mut rows = vec![];
+        for event in events {
+            let data = serde_json::to_string(&event)
+                .map_err(|e| e.to_string())?;
+            let ts = event.ts.to_rfc3339_opts(SecondsFormat::Micros, true);
+            rows.push(vec![json!(event.task_uuid), json!(ts), json!(data)]);
+        }
+
+        self.backend().await?.execute_batch(
+            "INSERT INTO patoka_task_events (task_uuid, ts, data) \
+             VALUES ($1, $2, $3)".to_string(),
+            rows,
+        ).await?;
+
+        Ok(())
+    }
+
+    async fn read(&self, task_uuid: &str) -> Result<Vec<TaskEvent>, String> {
+        let rows = self.backend().await?.query(Statement::new(
+            "SELECT data FROM patoka_task_events WHERE task_uuid = $1 \
+             ORDER BY ts",
+            vec![json!(task_uuid)],
+        )).await?;
+
+        rows.iter()
+            .map(|r| {
+                let data = r["data"].as_str().unwrap_or_default();
+                serde_json::from_str(data).map_err(|e| e.to_string())
+            })
+            .collect()
+    }
+}
+
+/// The events of the task, oldest first.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<TaskEvent>, String>")]
+pub struct ReplayTask {
+    pub task_uuid: String,
+}
+
+pub struct TaskHistory {
+    log: Logger,
+
+    /// Not written yet.
+    pending: Vec<TaskEvent>,
+
+    /// `None` if kept in the file or until the database is initialized.
+    table: Option<EventTable>,
+}
+
+impl TaskHistory {
+    fn handle_task_update(
+        &mut self,
+        msg: TaskUpdate,
+        _ctx: &mut <Self as Actor>::Context,
+    ) {
+        self.pending.push(TaskEvent::of(&msg));
+    }
+
+    fn flush(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        if !PARAMS.in_db() {
+            if let Err(e) = append_file(&PARAMS.path(), &self.pending) {
+                error!(self.log, "Failed to write the task events: {}", e);
+            }
+            self.pending.clear();
+            return;
+        }
+
+        if self.table.is_none() {
+            self.table = EventTable::new();
+        }
+        let table = match self.table {
+            Some(ref t) => t.clone(),
+            None => {
+                debug!(self.log, "Storage is not initialized yet.");
+                return;
+            },
+        };
+
+        let events = std::mem::take(&mut self.pending);
+        let log = self.log.clone();
+
+        // The next events are written once these ones are.
+        async move {
+            if let Err(e) = table.append(events).await {
+                error!(log, "Failed to write the task events: {}", e);
+            }
+        }
+        .into_actor(self)
+        .wait(ctx);
+    }
+}
+
+impl Default for TaskHistory {
+    fn default() -> Self {
+        Self {
+            log: create_logger("task_history"),
+            pending: vec![],
+            table: None,
+        }
+    }
+}
+
+impl Actor for TaskHistory {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Task History started: {}", PARAMS.storage);
+
+        let interval = Duration::from_millis(PARAMS.flush_interval_ms.max(1));
+        ctx.run_interval(interval, |act, ctx| act.flush(ctx));
+    }
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.flush(ctx);
+        Running::Stop
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Task History stopped.");
+    }
+}
+
+impl Supervised for TaskHistory {}
+
+impl SystemService for TaskHistory {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Task History system service started.")
+    }
+}
+
+handler_impl_task_update!(TaskHistory);
+
+impl Handler<ReplayTask> for TaskHistory {
+    type Result = ResponseFuture<Result<Vec<TaskEvent>, String>>;
+
+    fn handle(
+        &mut self,
+        msg: ReplayTask,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let pending: Vec<TaskEvent> = self.pending.iter()
+            .filter(|e| e.task_uuid == msg.task_uuid)
+            .cloned()
+            .collect();
+
+        if !PARAMS.in_db() {
+            let events = read_file(&PARAMS.path(), &msg.task_uuid)
+                .map(|mut events| {
+                    events.extend(pending);
+                    events
+                });
+            return Box::pin(async move { events });
+        }
+
+        let table = self.table.clone().or_else(EventTable::new);
+        Box::pin(async move {
+            let mut events = match table {
+                Some(t) => t.read(&msg.task_uuid).await?,
+                None => vec![],
+            };
+            events.extend(pending);
+            Ok(events)
+        })
+    }
+}
+
+pub fn start() -> Addr<TaskHistory> {
+    TaskHistory::from_registry()
+}
+
+/// The updates of the task logged so far, oldest first.
+pub async fn history(task_uuid: &str) -> Result<Vec<TaskEvent>, String> {
+    start()
+        .send(ReplayTask { task_uuid: task_uuid.to_string() })
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::tracker::TaskUpdateTag;
+
+    #[test]
+    fn file_roundtrip() {
+        let path = std::env::temp_dir()
+            .join(format!("patoka_task_history_{}.jsonl", std::process::id()));
+        let update = |uuid: &str, tag, status| TaskEvent::of(&TaskUpdate::new(
+            uuid.to_string(),
+            status,
+            tag,
+            "task_a".to_string(),
+        ));
+
+        let events = [
+            update("a", TaskUpdateTag::Started, TaskStatus::Running),
+            update("b", TaskUpdateTag::Started, TaskStatus::Running),
+            update("a", TaskUpdateTag::Finished, TaskStatus::FinishedFailure),
+        ];
+        append_file(&path, &events[..2]).unwrap();
+        append_file(&path, &events[2..]).unwrap();
+
+        let history = read_file(&path, "a").unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(history, vec![events[0].clone(), events[2].clone()]);
+        assert_eq!(history[1].tag, "finished");
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+/// Arbitrary labels of a task, e.g. `customer=acme`, to group the tasks.
+pub type Labels = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+/// Comma separated requirements the labels must all meet:
+///
+/// * `key=value`
+/// * `key!=value`, also met if there is no such label
+/// * `key`, the label is set
+/// * `!key`, the label is not set
+///
+/// An empty selector matches any labels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+impl LabelSelector {
+    pub fn parse(selector: &str) -> Result<Self, String> {
+        let requirements = selector.split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+            .map(parse_requirement)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { requirements })
+    }
+
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.requirements.iter().all(|r| match r {
+            Requirement::Equals(k, v) => labels.get(k) == Some(v),
+            Requirement::NotEquals(k, v) => labels.get(k) != Some(v),
+            Requirement::Exists(k) => labels.contains_key(k),
+            Requirement::NotExists(k) => !labels.contains_key(k),
+        })
+    }
+}
+
+fn parse_requirement(r: &str) -> Result<Requirement, String> {
+    let key = |k: &str| {
+        let k = k.trim();
+        if k.is_empty() || k.contains(['=', '!']) {
+            Err(format!("Invalid label selector: {}", r))
+        } else {
+            Ok(k.to_string())
+        }
+    };
+
+    if let Some((k, v)) = r.split_once("!=") {
+        Ok(Requirement::NotEquals(key(k)?, v.trim().to_string()))
+    } else if let Some((k, v)) = r.split_once('=') {
+        Ok(Requirement::Equals(key(k)?, v.trim().to_string()))
+    } else if let Some(k) = r.strip_prefix('!') {
+        Ok(Requirement::NotExists(key(k)?))
+    } else {
+        Ok(Requirement::Exists(key(r)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select() {
+        let labels: Labels = [("customer", "acme"), ("env", "staging")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let matches = |s: &str| LabelSelector::parse(s).unwrap()
+            .matches(&labels);
+
+        assert!(matches(""));
+        assert!(matches("customer=acme"));
+        assert!(matches("customer = acme, env!=prod"));
+        assert!(matches("env,!site"));
+        assert!(matches("site!=a"));
+        assert!(!matches("customer=acme,env=prod"));
+        assert!(!matches("site"));
+        assert!(!matches("!env"));
+
+        assert!(LabelSelector::parse("=acme").is_err());
+        assert!(LabelSelector::parse("a!b").is_err());
+    }
+}
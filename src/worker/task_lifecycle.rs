@@ -0,0 +1,156 @@
+use serde_derive::Serialize;
+use std::fmt;
+
+/// The lifecycle of a task in the task tree:
+///
+/// ```text
+/// Running <--> Suspended
+///    |            |
+///    v            |
+/// Stopping <------+
+///    |
+///    v
+/// Finished --> Closing --> Closed
+/// ```
+///
+/// A task may also finish by itself while running or suspended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleState {
+    Running,
+    Suspended,
+    Stopping,
+    Finished,
+    Closing,
+    Closed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    Pause,
+    Resume,
+    Stop,
+
+    /// The client has reported the task finished.
+    Finish,
+
+    /// Allowed once the task has finished.
+    Close,
+
+    /// The controller and the tracker have been told to close the task.
+    Closed,
+}
+
+/// The result of a valid transition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Changed(LifecycleState),
+
+    /// The event has taken effect already, e.g. a repeated `Stop`.
+    Unchanged,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub state: LifecycleState,
+    pub event: LifecycleEvent,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} is not valid in {:?}", self.event, self.state)
+    }
+}
+
+impl LifecycleState {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            LifecycleState::Finished
+                | LifecycleState::Closing
+                | LifecycleState::Closed
+        )
+    }
+
+    /// The transition table.
+    pub fn on(
+        self,
+        event: LifecycleEvent,
+    ) -> Result<Transition, InvalidTransition> {
+        use LifecycleEvent as E;
+        use LifecycleState as S;
+        use Transition::*;
+
+        let t = match (self, event) {
+            (S::Running, E::Pause) => Changed(S::Suspended),
+            (S::Suspended, E::Pause) => Unchanged,
+
+            (S::Suspended, E::Resume) => Changed(S::Running),
+            (S::Running, E::Resume) => Unchanged,
+
+            (S::Running | S::Suspended, E::Stop) => Changed(S::Stopping),
+            (S::Stopping, E::Stop) => Unchanged,
+            (s, E::Stop) if s.is_finished() => Unchanged,
+
+            (S::Running | S::Suspended | S::Stopping, E::Finish) => {
+                Changed(S::Finished)
+            },
+            (s, E::Finish) if s.is_finished() => Unchanged,
+
+            (S::Finished, E::Close) => Changed(S::Closing),
+            (S::Closing | S::Closed, E::Close) => Unchanged,
+
+            (S::Closing, E::Closed) => Changed(S::Closed),
+            (S::Closed, E::Closed) => Unchanged,
+
+            (state, event) => return Err(InvalidTransition { state, event }),
+        };
+
+        Ok(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LifecycleEvent as E;
+    use LifecycleState as S;
+    use Transition::*;
+
+    #[test]
+    fn transition_table() {
+        let table = [
+            (S::Running, E::Pause, Ok(Changed(S::Suspended))),
+            (S::Running, E::Resume, Ok(Unchanged)),
+            (S::Running, E::Stop, Ok(Changed(S::Stopping))),
+            (S::Running, E::Finish, Ok(Changed(S::Finished))),
+            (S::Running, E::Close, Err(())),
+            (S::Running, E::Closed, Err(())),
+            (S::Suspended, E::Pause, Ok(Unchanged)),
+            (S::Suspended, E::Resume, Ok(Changed(S::Running))),
+            (S::Suspended, E::Stop, Ok(Changed(S::Stopping))),
+            (S::Suspended, E::Finish, Ok(Changed(S::Finished))),
+            (S::Stopping, E::Pause, Err(())),
+            (S::Stopping, E::Resume, Err(())),
+            (S::Stopping, E::Stop, Ok(Unchanged)),
+            (S::Stopping, E::Finish, Ok(Changed(S::Finished))),
+            (S::Stopping, E::Close, Err(())),
+            (S::Finished, E::Pause, Err(())),
+            (S::Finished, E::Stop, Ok(Unchanged)),
+            (S::Finished, E::Finish, Ok(Unchanged)),
+            (S::Finished, E::Close, Ok(Changed(S::Closing))),
+            (S::Finished, E::Closed, Err(())),
+            (S::Closing, E::Close, Ok(Unchanged)),
+            (S::Closing, E::Closed, Ok(Changed(S::Closed))),
+            (S::Closed, E::Stop, Ok(Unchanged)),
+            (S::Closed, E::Close, Ok(Unchanged)),
+            (S::Closed, E::Closed, Ok(Unchanged)),
+            (S::Closed, E::Resume, Err(())),
+        ];
+
+        for (state, event, expected) in table {
+            let t = state.on(event).map_err(|_| ());
+            assert_eq!(t, expected, "{:?} on {:?}", event, state);
+        }
+    }
+}
@@ -8,7 +8,7 @@ use slog::Logger;
 use std::{
     collections::{HashMap, HashSet},
     fs::{self, File,  OpenOptions},
-    io::{BufReader},
+    io::{BufRead, BufReader},
     sync::{Mutex, RwLock},
     time::Duration,
     thread,time,
@@ -16,11 +16,13 @@ use std::{
 
 use crate::{
     core::{
+        app_state::{self, AppMode},
         arbiter_pool,
         env,
         logger::create_logger,
     },
     worker::{
+        session_recorder::{Direction, SessionRecord},
         worker_message::*,
     },
 };
@@ -33,6 +35,8 @@ lazy_static! {
         RwLock::new(ReadersSettings::load());
 }
 
+const MAINTENANCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct TaskReader {
     task_name: String,
     settings: ReaderSettings,
@@ -60,7 +64,16 @@ impl TaskReader {
 
         let client_addr = self.client_addr.clone().unwrap();
 
-        let file_path = format!("data/tasks/{}", self.task_name);
+        if app_state::mode() == AppMode::Maintenance {
+            debug!(self.log, "Wait for the maintenance to end.");
+            ctx.run_later(MAINTENANCE_CHECK_INTERVAL, Self::send_all);
+            return;
+        }
+
+        let file_path = match self.settings.file {
+            Some(ref f) => f.clone(),
+            None => format!("data/tasks/{}", self.task_name),
+        };
 
         let file = match File::open(&file_path) {
             Ok(f) => f,
@@ -72,33 +85,61 @@ impl TaskReader {
 
         let reader = BufReader::new(file);
 
-        let deserializer = serde_json::Deserializer::from_reader(reader);
-        let iterator = deserializer.into_iter::<WorkerMessage>();
+        let iterator: TimedMessageIter = match self.settings.format {
+            InputFormat::WorkerMessage => untimed(read_worker_messages(reader)),
+            InputFormat::Csv => untimed(read_csv(reader, &self.settings)),
+            InputFormat::Ndjson => untimed(read_ndjson(reader)),
+            InputFormat::Session => read_session(reader),
+        };
 
-        // Send all messages to the task.
+        // Send all messages to the task. The timed ones are sent after the
+        // delay since the first message.
         let mut msg_counter = 0;
+        let mut last_offset = Duration::ZERO;
         for item in iterator {
             match item {
-                Ok(wm) => {
+                Ok((offset, wm)) => {
                     if !self.should_be_sent(&wm) {
                         debug!(self.log, "Skip WORKER MESSAGE {:?}", wm);
                         continue;
                     }
 
-                    debug!(self.log, "Send WORKER MESSAGE {:?}", wm);
-                    client_addr.do_send(wm);
                     msg_counter += 1;
+                    last_offset = last_offset.max(offset);
+
+                    if offset.is_zero() {
+                        debug!(self.log, "Send WORKER MESSAGE {:?}", wm);
+                        client_addr.do_send(wm);
+                        continue;
+                    }
+
+                    let client_addr = client_addr.clone();
+                    ctx.run_later(offset, move |act, _| {
+                        debug!(act.log, "Send WORKER MESSAGE {:?}", wm);
+                        client_addr.do_send(wm);
+                    });
                 },
                 Err(e) => {
                     error!(
                         self.log,
-                        "Encountered invalid worker message {:?}",
+                        "Encountered invalid {:?} record: {}",
+                        self.settings.format,
                         e,
                     );
                 },
             }
         }
 
+        if last_offset.is_zero() {
+            self.all_sent(msg_counter, ctx);
+        } else {
+            ctx.run_later(last_offset, move |act, ctx| {
+                act.all_sent(msg_counter, ctx);
+            });
+        }
+    }
+
+    fn all_sent(&mut self, msg_counter: usize, ctx: &mut Context<Self>) {
         if self.settings.loop_interval > 0 {
             info!(
                 self.log,
@@ -125,6 +166,11 @@ impl TaskReader {
     }
 
     fn should_be_sent(&self, msg: &WorkerMessage) -> bool {
+        if self.settings.format == InputFormat::Session {
+            // The whole session is replayed.
+            return true;
+        }
+
         if let Some(_) = msg.result::<serde_json::Value>() {
             return self.settings.message_types.contains("task_result");
         }
@@ -254,7 +300,8 @@ impl TaskReaders {
 
         let task_reader_addr = TaskReader::start_in_arbiter(
             &arbiter_addr,
-            move |_| {
+            move |c| {
+                arbiter_pool::track(&c.address());
                 TaskReader::new(task_name_clone, settings)
             }
         );
@@ -280,10 +327,48 @@ impl TaskReaders {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum InputFormat {
+    /// JSON-serialized worker messages as written by the task writer.
+    WorkerMessage,
+
+    /// Rows with a header. Every row is sent as a `task_result` message.
+    Csv,
+
+    /// A JSON record per line. Every record is sent as a `task_result`
+    /// message.
+    Ndjson,
+
+    /// A session recorded by `session_recorder`. The messages sent to the
+    /// client are replayed keeping the recorded intervals between them.
+    Session,
+}
+
+fn default_format() -> InputFormat { InputFormat::WorkerMessage }
+
+fn default_delimiter() -> char { ',' }
+
 #[derive(Debug, Clone, Deserialize)]
 struct ReaderSettings {
     message_types: HashSet<String>,
 
+    #[serde(default = "default_format")]
+    format: InputFormat,
+
+    /// Default: `data/tasks/{task name}`
+    #[serde(default)]
+    file: Option<String>,
+
+    /// CSV column --> `task_result` key. The key may be a dot separated
+    /// path, e.g. "params.url". All the columns are taken as they are if no
+    /// mapping specified.
+    #[serde(default)]
+    columns: HashMap<String, String>,
+
+    #[serde(default = "default_delimiter")]
+    delimiter: char,
+
     #[serde(default)]
     delay: u64,
 
@@ -325,6 +410,126 @@ impl ReadersSettings {
     }
 }
 
+type MessageIter = Box<dyn Iterator<Item = Result<WorkerMessage, String>>>;
+
+/// Messages with the delay to send them after.
+type TimedMessageIter =
+    Box<dyn Iterator<Item = Result<(Duration, WorkerMessage), String>>>;
+
+fn untimed(iter: MessageIter) -> TimedMessageIter {
+    Box::new(iter.map(|item| item.map(|msg| (Duration::ZERO, msg))))
+}
+
+fn read_session(reader: BufReader<File>) -> TimedMessageIter {
+    let deserializer = serde_json::Deserializer::from_reader(reader);
+    let mut first_ts = None;
+    Box::new(
+        deserializer.into_iter::<SessionRecord>()
+            .filter(|item| match item {
+                Ok(r) => r.direction == Direction::ToClient,
+                Err(_) => true,
+            })
+            .map(move |item| {
+                let record = item.map_err(|e| e.to_string())?;
+                let first_ts = *first_ts.get_or_insert(record.ts);
+                let offset = (record.ts - first_ts).to_std()
+                    .unwrap_or(Duration::ZERO);
+                Ok((offset, record.msg))
+            })
+    )
+}
+
+fn read_worker_messages(reader: BufReader<File>) -> MessageIter {
+    let deserializer = serde_json::Deserializer::from_reader(reader);
+    Box::new(
+        deserializer.into_iter::<WorkerMessage>()
+            .map(|item| item.map_err(|e| e.to_string()))
+    )
+}
+
+fn read_ndjson(reader: BufReader<File>) -> MessageIter {
+    Box::new(
+        reader.lines()
+            .filter(|line| match line {
+                Ok(l) => !l.trim().is_empty(),
+                Err(_) => true,
+            })
+            .map(|line| {
+                let line = line.map_err(|e| e.to_string())?;
+                let record: serde_json::Value = serde_json::from_str(&line)
+                    .map_err(|e| e.to_string())?;
+                Ok(task_result_message(record))
+            })
+    )
+}
+
+fn read_csv(reader: BufReader<File>, settings: &ReaderSettings) -> MessageIter {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(settings.delimiter as u8)
+        .from_reader(reader);
+
+    let headers = match csv_reader.headers() {
+        Ok(h) => h.clone(),
+        Err(e) => return Box::new(std::iter::once(Err(e.to_string()))),
+    };
+
+    let columns = settings.columns.clone();
+    Box::new(
+        csv_reader.into_records().map(move |row| {
+            let row = row.map_err(|e| e.to_string())?;
+            Ok(task_result_message(map_csv_row(&headers, &row, &columns)))
+        })
+    )
+}
+
+fn map_csv_row(
+    headers: &csv::StringRecord,
+    row: &csv::StringRecord,
+    columns: &HashMap<String, String>,
+) -> serde_json::Value {
+    let mut record = json!({});
+    for (column, value) in headers.iter().zip(row.iter()) {
+        let key = if columns.is_empty() {
+            column
+        } else {
+            match columns.get(column) {
+                Some(key) => key.as_str(),
+                None => continue,
+            }
+        };
+
+        set_path(&mut record, key, json!(value));
+    }
+
+    record
+}
+
+/// Set `value` at the dot separated `path` creating the objects on the way.
+fn set_path(v: &mut serde_json::Value, path: &str, value: serde_json::Value) {
+    let mut keys = path.split('.').peekable();
+    let mut current = v;
+    while let Some(key) = keys.next() {
+        if keys.peek().is_none() {
+            env::set_key_value(current, key.to_string(), value);
+            return;
+        }
+
+        if !current.get(key).is_some_and(|c| c.is_object()) {
+            env::set_key_value(current, key.to_string(), json!({}));
+        }
+        current = current.get_mut(key).unwrap();
+    }
+}
+
+fn task_result_message(record: serde_json::Value) -> WorkerMessage {
+    let mut payload = WorkerMessagePayload::new();
+    payload.dest = Dest::Client;
+    payload.data = json!({ "task_result": record });
+
+    WorkerMessage::new(payload)
+}
+
 pub fn register_task(
     reader_addr: &Addr<TaskReader>,
     client: Recipient<WorkerMessage>,
@@ -336,6 +541,13 @@ pub fn register_task(
     });
 }
 
+/// Apply `task_readers` of the configuration to the readers started from
+/// now on.
+pub fn reload_settings() -> Result<(), String> {
+    *READERS_SETTINGS.write().unwrap() = ReadersSettings::load();
+    Ok(())
+}
+
 pub fn get_reader(task_name: &str) -> Option<Addr<TaskReader>> {
     let mut task_readers = TASK_READERS.lock().unwrap();
     task_readers.get_reader(task_name)
@@ -346,3 +558,28 @@ fn remove_reader(task_name: &str) {
     let mut task_readers = TASK_READERS.lock().unwrap();
     task_readers.remove_reader(task_name);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_row_mapping() {
+        let headers = csv::StringRecord::from(vec!["Name", "Link", "Extra"]);
+        let row = csv::StringRecord::from(vec!["a", "http://a", "x"]);
+
+        let mut columns = HashMap::new();
+        columns.insert("Name".to_string(), "name".to_string());
+        columns.insert("Link".to_string(), "params.url".to_string());
+
+        assert_eq!(
+            map_csv_row(&headers, &row, &columns),
+            json!({ "name": "a", "params": { "url": "http://a" } }),
+        );
+
+        assert_eq!(
+            map_csv_row(&headers, &row, &HashMap::new()),
+            json!({ "Name": "a", "Link": "http://a", "Extra": "x" }),
+        );
+    }
+}
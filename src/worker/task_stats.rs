@@ -0,0 +1,367 @@
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use slog::Logger;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    center::send::send_control_msg,
+    control::{
+        command::*,
+        message::*,
+        registry,
+    },
+    core::{env, logger::create_logger},
+    worker::{
+        circuit_breaker,
+        task::TaskStatus,
+        tracker::TaskUpdate,
+    },
+};
+
+/// All the names.
+const ALL: &str = "*";
+
+lazy_static! {
+    static ref PARAMS: TaskStatsParams =
+        env::load_opt("task_stats").unwrap_or_default();
+
+    static ref BREAKER: FailureRateParams =
+        env::load_opt("circuit_breaker").unwrap_or_default();
+}
+
+/// `[task_stats]` configuration section of the outcomes and durations of
+/// the finished tasks by name.
+#[derive(Deserialize)]
+struct TaskStatsParams {
+    #[serde(default)]
+    enabled: bool,
+
+    #[serde(default = "default_windows_s")]
+    windows_s: Vec<u64>,
+
+    /// Outcomes kept per name, the oldest are dropped first.
+    #[serde(default = "default_max_samples")]
+    max_samples: usize,
+}
+
+fn default_windows_s() -> Vec<u64> { vec![60, 900, 3600] }
+
+fn default_max_samples() -> usize { 10_000 }
+
+impl Default for TaskStatsParams {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            windows_s: default_windows_s(),
+            max_samples: default_max_samples(),
+        }
+    }
+}
+
+/// The failure rate keys of `[circuit_breaker]`.
+#[derive(Deserialize)]
+struct FailureRateParams {
+    /// 0 disables.
+    #[serde(default)]
+    max_failure_rate: f64,
+
+    #[serde(default = "default_window_s")]
+    window_s: u64,
+
+    /// Fewer outcomes in the window do not open the circuit.
+    #[serde(default = "default_min_samples")]
+    min_samples: usize,
+}
+
+fn default_window_s() -> u64 { 300 }
+
+fn default_min_samples() -> usize { 10 }
+
+impl Default for FailureRateParams {
+    fn default() -> Self {
+        Self {
+            max_failure_rate: 0.0,
+            window_s: default_window_s(),
+            min_samples: default_min_samples(),
+        }
+    }
+}
+
+struct Outcome {
+    at: Instant,
+    success: bool,
+
+    /// Unknown if the tracker has not seen the start.
+    duration: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WindowStats {
+    pub window_s: u64,
+    pub successes: usize,
+    pub failures: usize,
+    pub failure_rate: f64,
+    pub avg_duration_ms: Option<u64>,
+    pub p95_duration_ms: Option<u64>,
+}
+
+impl WindowStats {
+    fn of<'a, I>(window_s: u64, outcomes: I) -> Self
+    where
+        I: Iterator<Item = &'a Outcome>,
+    {
+        let since = Instant::now().checked_sub(Duration::from_secs(window_s));
+        let mut stats = WindowStats { window_s, ..Default::default() };
+        let mut durations = Vec::new();
+
+        for o in outcomes.filter(|o| since.is_none_or(|s| o.at >= s)) {
+            if o.success {
+                stats.successes += 1;
+            } else {
+                stats.failures += 1;
+            }
+            durations.extend(o.duration.map(|d| d.as_millis() as u64));
+        }
+
+        let total = stats.successes + stats.failures;
+        if total > 0 {
+            stats.failure_rate = stats.failures as f64 / total as f64;
+        }
+
+        if !durations.is_empty() {
+            durations.sort_unstable();
+            let sum: u64 = durations.iter().sum();
+            stats.avg_duration_ms = Some(sum / durations.len() as u64);
+            let p95 = (durations.len() * 95).div_ceil(100) - 1;
+            stats.p95_duration_ms = Some(durations[p95]);
+        }
+
+        stats
+    }
+}
+
+/// The stats over each of `windows_s`.
+fn by_window<'a, F, I>(outcomes: F) -> Vec<WindowStats>
+where
+    F: Fn() -> I,
+    I: Iterator<Item = &'a Outcome>,
+{
+    PARAMS.windows_s.iter()
+        .map(|w| WindowStats::of(*w, outcomes()))
+        .collect()
+}
+
+/// `data` is optional.
+#[derive(Deserialize)]
+struct TaskStatsCommand(Option<TaskStatsQuery>);
+
+#[derive(Default, Deserialize)]
+struct TaskStatsQuery {
+    #[serde(default)]
+    name: Option<String>,
+}
+
+impl Command for TaskStatsCommand {
+    const NAME: &'static str = "task_stats";
+
+    /// Name --> Stats by window
+    type Response = HashMap<String, Vec<WindowStats>>;
+}
+
+pub struct TaskStats {
+    log: Logger,
+    commands: Arc<CommandRouter<Self>>,
+
+    /// Name --> Outcomes, oldest first
+    outcomes: HashMap<String, VecDeque<Outcome>>,
+}
+
+impl TaskStats {
+    fn handle_task_update(
+        &mut self,
+        msg: TaskUpdate,
+        _ctx: &mut <Self as Actor>::Context,
+    ) {
+        let success = match msg.status {
+            TaskStatus::FinishedSuccess => true,
+            TaskStatus::FinishedFailure => false,
+            _ => return,
+        };
+
+        let outcome = Outcome {
+            at: Instant::now(),
+            success,
+            duration: msg.duration_ms.map(Duration::from_millis),
+        };
+
+        let outcomes = self.outcomes.entry(msg.name.clone()).or_default();
+        if outcomes.len() >= PARAMS.max_samples.max(1) {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(outcome);
+
+        if !success {
+            self.check_failure_rate(&msg.name);
+        }
+    }
+
+    fn check_failure_rate(&self, name: &str) {
+        if BREAKER.max_failure_rate <= 0.0 {
+            return;
+        }
+
+        let stats = WindowStats::of(
+            BREAKER.window_s,
+            self.outcomes.get(name).into_iter().flatten(),
+        );
+
+        if stats.successes + stats.failures >= BREAKER.min_samples
+            && stats.failure_rate >= BREAKER.max_failure_rate
+        {
+            circuit_breaker::trip(
+                name,
+                &format!(
+                    "{:.0}% of {} tasks failed in {} s",
+                    stats.failure_rate * 100.0,
+                    stats.successes + stats.failures,
+                    BREAKER.window_s,
+                ),
+            );
+        }
+    }
+
+    fn stats(&self, name: Option<&str>) -> HashMap<String, Vec<WindowStats>> {
+        let mut stats = HashMap::new();
+        for (n, outcomes) in &self.outcomes {
+            if name.is_none_or(|name| name == n) {
+                stats.insert(n.clone(), by_window(|| outcomes.iter()));
+            }
+        }
+
+        if name.is_none_or(|name| name == ALL) {
+            let all = by_window(|| self.outcomes.values().flatten());
+            stats.insert(ALL.to_string(), all);
+        }
+
+        stats
+    }
+
+    fn handle_control_message(
+        &mut self,
+        msg: ControlMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        debug!(self.log, "[CONTROL] {:?}", msg);
+
+        let commands = self.commands.clone();
+        send_control_msg(commands.route(self, msg, ctx));
+    }
+}
+
+impl Default for TaskStats {
+    fn default() -> Self {
+        Self {
+            log: create_logger("task_stats"),
+            commands: Arc::new(
+                CommandRouter::new()
+                    .add::<TaskStatsCommand>()
+            ),
+            outcomes: HashMap::new(),
+        }
+    }
+}
+
+impl CommandHandler<TaskStatsCommand> for TaskStats {
+    fn handle_command(
+        &mut self,
+        args: TaskStatsCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<HashMap<String, Vec<WindowStats>>, CommandError> {
+        let query = args.0.unwrap_or_default();
+        Ok(self.stats(query.name.as_deref()))
+    }
+}
+
+impl Actor for TaskStats {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.log, "Task Stats started.");
+
+        registry::register("stats".to_string(), ctx.address().recipient());
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Task Stats stopped.");
+    }
+}
+
+impl Supervised for TaskStats {}
+
+impl SystemService for TaskStats {
+    fn service_started(&mut self, _ctx: &mut Self::Context) {
+        info!(self.log, "Task Stats system service started.")
+    }
+}
+
+handler_impl_task_update!(TaskStats);
+
+handler_impl_control_message!(TaskStats);
+
+/// The statistics of all the names.
+pub struct GetTaskStats;
+
+impl Message for GetTaskStats {
+    type Result = HashMap<String, Vec<WindowStats>>;
+}
+
+impl Handler<GetTaskStats> for TaskStats {
+    type Result = MessageResult<GetTaskStats>;
+
+    fn handle(
+        &mut self,
+        _msg: GetTaskStats,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        MessageResult(self.stats(None))
+    }
+}
+
+/// `task_stats.enabled`, or the circuit breaker opening on the failure
+/// rate, which the stats are kept for.
+pub fn enabled() -> bool {
+    PARAMS.enabled || BREAKER.max_failure_rate > 0.0
+}
+
+pub fn start() -> Addr<TaskStats> {
+    TaskStats::from_registry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_stats() {
+        let now = Instant::now();
+        let outcomes: Vec<Outcome> = (1..=20)
+            .map(|i| Outcome {
+                at: now,
+                success: i % 4 != 0,
+                duration: Some(Duration::from_millis(i * 10)),
+            })
+            .collect();
+
+        let stats = WindowStats::of(60, outcomes.iter());
+        assert_eq!((stats.successes, stats.failures), (15, 5));
+        assert_eq!(stats.failure_rate, 0.25);
+        assert_eq!(stats.avg_duration_ms, Some(105));
+        assert_eq!(stats.p95_duration_ms, Some(190));
+    }
+}
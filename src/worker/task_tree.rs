@@ -1,25 +1,55 @@
 use actix::prelude::*;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 use slog::Logger;
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
     center::{
         connector::{self, CenterConnector},
         message,
+        send::send_control_msg,
     },
     control::{
-        message::{CloseTask, ControlMessage, RestartTask, StopTask},
+        command::{Command, CommandError, CommandHandler, CommandRouter},
+        message::{
+            CloseTask,
+            ControlMessage,
+            PauseTask,
+            RestartTask,
+            RestartTaskWith,
+            ResumeTask,
+            StopTask,
+        },
         registry,
     },
     core::{
         app_state::{self, *},
+        env,
         logger::create_logger,
+        monitor::{self, MailboxProbe},
     },
+    handler_impl_mailbox_probe,
     transport::message::RawMessage,
     worker::{
+        controller::{ReleaseReservation, TaskFinished},
         processor::{self, TaskWrapperItem, TaskWrapperItemMessage},
-        tracker::{self, TaskUpdate},
+        reprocessor::{self, ReprocessTask},
+        tracker::{self, TaskUpdate, TaskUpdateTag},
         task::*,
+        task_labels::{LabelSelector, Labels},
+        task_lifecycle::{
+            LifecycleEvent,
+            LifecycleState,
+            Transition,
+        },
     },
 };
 
@@ -32,7 +62,10 @@ struct TaskTreeItem {
     /// To replay.
     pub task: TaskWrapperItem,
 
-    pub task_status: TaskStatus,
+    pub state: LifecycleState,
+
+    /// The last task update or command.
+    pub active_at: Instant,
 }
 
 impl TaskTreeItem {
@@ -44,19 +77,50 @@ impl TaskTreeItem {
             ctx,
             child_tasks: HashSet::new(),
             task,
-            task_status: TaskStatus::Running,
+            state: LifecycleState::Running,
+            active_at: Instant::now(),
         }
     }
 
     pub fn task_finished(&self) -> bool {
-        self.task_status == TaskStatus::FinishedSuccess
-            || self.task_status == TaskStatus::FinishedFailure
+        self.state.is_finished()
+    }
+}
+
+/// Tasks closed by the sweep since the start.
+static SWEPT_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// `[task_tree]` configuration section.
+#[derive(Deserialize)]
+struct TaskTreeParams {
+    /// A task neither updated nor commanded for that long is considered
+    /// lost, e.g. its worker is gone, and is failed and closed. 0 for
+    /// never.
+    #[serde(default = "default_inactivity_timeout_s")]
+    inactivity_timeout_s: u64,
+
+    #[serde(default = "default_sweep_interval_s")]
+    sweep_interval_s: u64,
+}
+
+fn default_inactivity_timeout_s() -> u64 { 86400 }
+
+fn default_sweep_interval_s() -> u64 { 60 }
+
+impl Default for TaskTreeParams {
+    fn default() -> Self {
+        Self {
+            inactivity_timeout_s: default_inactivity_timeout_s(),
+            sweep_interval_s: default_sweep_interval_s(),
+        }
     }
 }
 
 pub struct TaskTree {
     log: Logger,
 
+    params: TaskTreeParams,
+
     center_connector_addr: Addr<CenterConnector>,
 
     app_state_addr: Addr<AppState>,
@@ -67,6 +131,147 @@ pub struct TaskTree {
     tasks_to_close: HashSet<String>,
 
     tasks_to_restart: HashSet<String>,
+
+    /// Lost with their worker process, handed to the task reprocessor once
+    /// closed.
+    tasks_to_reprocess: HashSet<String>,
+
+    /// The tasks finished but waiting for their children to finish.
+    /// See `OrphanPolicy::WaitForChildren`.
+    awaiting_children: HashSet<String>,
+
+    commands: Arc<CommandRouter<Self>>,
+}
+
+/// `data` is the task UUID.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct StopTaskCommand {
+    task_uuid: String,
+}
+
+impl Command for StopTaskCommand {
+    const NAME: &'static str = "stop_task";
+    type Response = ();
+}
+
+/// `data` is the task UUID.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct CloseTaskCommand {
+    task_uuid: String,
+}
+
+impl Command for CloseTaskCommand {
+    const NAME: &'static str = "close_task";
+    type Response = ();
+}
+
+/// `data` is the task UUID.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct RestartTaskCommand {
+    task_uuid: String,
+}
+
+impl Command for RestartTaskCommand {
+    const NAME: &'static str = "restart_task";
+    type Response = ();
+}
+
+#[derive(Deserialize)]
+struct RestartTaskWithCommand {
+    task_uuid: String,
+
+    /// Merged into the `params` of the task definition.
+    params_patch: serde_json::Value,
+}
+
+impl Command for RestartTaskWithCommand {
+    const NAME: &'static str = "restart_task_with";
+    type Response = ();
+}
+
+/// `data` is a regex the whole task name matches, e.g. `crawl_site_.*`.
+/// Responds with the UUIDs of the tasks matched.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct StopTasksByNameCommand {
+    pattern: String,
+}
+
+impl Command for StopTasksByNameCommand {
+    const NAME: &'static str = "stop_tasks_by_name";
+    type Response = Vec<String>;
+}
+
+/// See `StopTasksByNameCommand`.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct RestartTasksByNameCommand {
+    pattern: String,
+}
+
+impl Command for RestartTasksByNameCommand {
+    const NAME: &'static str = "restart_tasks_by_name";
+    type Response = Vec<String>;
+}
+
+/// See `StopTasksByNameCommand`.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct CloseTasksByNameCommand {
+    pattern: String,
+}
+
+impl Command for CloseTasksByNameCommand {
+    const NAME: &'static str = "close_tasks_by_name";
+    type Response = Vec<String>;
+}
+
+/// `data` is a label selector, e.g. `customer=acme,env!=prod`. See
+/// `LabelSelector`.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct TasksByLabelsCommand {
+    selector: String,
+}
+
+#[derive(Serialize)]
+struct TaskInfo {
+    task_uuid: String,
+    name: String,
+    state: LifecycleState,
+    labels: Labels,
+}
+
+impl Command for TasksByLabelsCommand {
+    const NAME: &'static str = "tasks_by_labels";
+    type Response = Vec<TaskInfo>;
+}
+
+/// `data` is the task UUID.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct PauseTaskCommand {
+    task_uuid: String,
+}
+
+impl Command for PauseTaskCommand {
+    const NAME: &'static str = "pause_task";
+    type Response = ();
+}
+
+/// `data` is the task UUID.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct ResumeTaskCommand {
+    task_uuid: String,
+}
+
+impl Command for ResumeTaskCommand {
+    const NAME: &'static str = "resume_task";
+    type Response = ();
 }
 
 impl TaskTree {
@@ -86,44 +291,149 @@ impl TaskTree {
         self.restart_task(msg.task_uuid);
     }
 
+    fn handle_pause_task(
+        &mut self,
+        msg: PauseTask,
+        _ctx: &mut <Self as Actor>::Context
+    ) {
+        self.pause_task(msg.task_uuid);
+    }
+
+    fn handle_resume_task(
+        &mut self,
+        msg: ResumeTask,
+        _ctx: &mut <Self as Actor>::Context
+    ) {
+        self.resume_task(msg.task_uuid);
+    }
+
     fn handle_task_update(
         &mut self,
         msg: TaskUpdate,
         _ctx: &mut <Self as Actor>::Context
     ) {
+        if let Some(item) = self.tasks.get_mut(&msg.task_uuid) {
+            item.active_at = Instant::now();
+        }
+
         match msg.status {
             TaskStatus::FinishedSuccess | TaskStatus::FinishedFailure => {
                 debug!(self.log, "Finished [TASK UUID] {}.", msg.task_uuid);
+                self.task_finished(msg.task_uuid);
+            },
+            _ => {
+            },
+        }
+    }
 
-                if let Some(item) = self.tasks.get_mut(&msg.task_uuid) {
-                    item.task_status = msg.status;
-                } else {
-                    warn!(
-                        self.log,
-                        "Received TaskUpdate for unknown [TASK UUID] {}",
-                        msg.task_uuid,
-                    );
-                }
+    fn task_finished(&mut self, task_uuid: String) {
+        if !self.tasks.contains_key(&task_uuid) {
+            warn!(
+                self.log,
+                "Received TaskUpdate for unknown [TASK UUID] {}",
+                task_uuid,
+            );
+        } else {
+            if !self.handle_orphans(&task_uuid) {
+                return;
+            }
+
+            if !self.apply(&task_uuid, LifecycleEvent::Finish) {
+                // Reported finished already.
+                return;
+            }
+
+            let ctx = &self.tasks[&task_uuid].ctx;
+            if let ControllerAddr::Controller(ref a) = ctx.controller_addr {
+                a.do_send(TaskFinished { task_uuid: task_uuid.clone() });
+            }
+        }
+
+        // Send a "task finished" message to the center.
+        let c_msg = message::create_no_data(
+            message::Dest::Center,
+            message::Subject::TaskStatusUpdate,
+            task_uuid.clone(),
+            "finished".to_string(),
+        );
+
+        self.center_connector_addr.do_send(
+            RawMessage::from(c_msg)
+        );
 
-                // Send a "task finished" message to the center.
-                let c_msg = message::create_no_data(
-                    message::Dest::Center,
-                    message::Subject::TaskStatusUpdate,
-                    msg.task_uuid.clone(),
-                    "finished".to_string(),
+        let parent_task_uuid = self.tasks.get(&task_uuid)
+            .map(|item| item.ctx.parent_task_uuid.clone())
+            .unwrap_or_default();
+
+        if self.tasks_to_close.contains(&task_uuid) {
+            self.close_task(task_uuid);
+        }
+
+        // The parent may have been waiting for its last child.
+        if self.awaiting_children.contains(&parent_task_uuid) {
+            self.task_finished(parent_task_uuid);
+        }
+    }
+
+    /// Apply the orphan policy of the task finishing while some of its
+    /// children are running. `False` if the task is not finished until its
+    /// children are.
+    fn handle_orphans(&mut self, task_uuid: &str) -> bool {
+        let item = &self.tasks[task_uuid];
+        let running: Vec<String> = item.child_tasks.iter()
+            .filter(|c| self.tasks.get(*c).is_some_and(|i| !i.task_finished()))
+            .cloned()
+            .collect();
+
+        if running.is_empty() {
+            self.awaiting_children.remove(task_uuid);
+            return true;
+        }
+
+        match item.task.orphan_policy() {
+            OrphanPolicy::Keep => {},
+            OrphanPolicy::CascadeStop => {
+                debug!(
+                    self.log,
+                    "Stop {} children of [TASK UUID] {}",
+                    running.len(),
+                    task_uuid,
                 );
 
-                self.center_connector_addr.do_send(
-                    RawMessage::from(c_msg)
+                for child_task_uuid in running {
+                    self.stop_task(child_task_uuid);
+                }
+            },
+            OrphanPolicy::Detach => {
+                debug!(
+                    self.log,
+                    "Detach {} children of [TASK UUID] {}",
+                    running.len(),
+                    task_uuid,
                 );
 
-                if self.tasks_to_close.contains(&msg.task_uuid) {
-                    self.close_task(msg.task_uuid);
+                for child_task_uuid in running {
+                    self.tasks.get_mut(task_uuid).unwrap()
+                        .child_tasks.remove(&child_task_uuid);
+                    if let Some(child) = self.tasks.get_mut(&child_task_uuid) {
+                        child.ctx.parent_task_uuid.clear();
+                    }
                 }
             },
-            _ => {
+            OrphanPolicy::WaitForChildren => {
+                debug!(
+                    self.log,
+                    "[TASK UUID] {} waits for {} children.",
+                    task_uuid,
+                    running.len(),
+                );
+
+                self.awaiting_children.insert(task_uuid.to_string());
+                return false;
             },
         }
+
+        true
     }
 
     fn process_new_task(&mut self, msg: NewTask) {
@@ -157,91 +467,228 @@ impl TaskTree {
     ) {
         debug!(self.log, "[CONTROL] {:?}", msg);
 
-        match msg.cmd.as_ref() {
-            "stop_task" => {
-                self.stop_task(msg.data.as_str().unwrap().to_string());
-            },
-            "close_task" => {
-                self.close_task(msg.data.as_str().unwrap().to_string());
-            },
-            "restart_task" => {
-                self.restart_task(msg.data.as_str().unwrap().to_string());
+        let commands = self.commands.clone();
+        send_control_msg(commands.route(self, msg, ctx));
+    }
+
+    fn check_task_known(&self, task_uuid: &str) -> Result<(), CommandError> {
+        if self.tasks.contains_key(task_uuid) {
+            Ok(())
+        } else {
+            Err(CommandError::Failed(
+                format!("Unknown [TASK UUID] {}", task_uuid)
+            ))
+        }
+    }
+
+    /// The tasks in the tree the whole name of which matches `pattern`.
+    fn tasks_by_name(
+        &self,
+        pattern: &str,
+    ) -> Result<Vec<String>, CommandError> {
+        let re = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| CommandError::InvalidArgs(e.to_string()))?;
+
+        Ok(self.tasks.iter()
+            .filter(|(_, item)| re.is_match(item.task.name()))
+            .map(|(uuid, _)| uuid.clone())
+            .collect())
+    }
+
+    fn stop_task(&mut self, task_uuid: String) {
+        let children = match self.tasks.get(&task_uuid) {
+            Some(item) => item.child_tasks.clone(),
+            None => {
+                warn!(
+                    self.log,
+                    "Tried to stop unknown [TASK UUID] {}",
+                    task_uuid,
+                );
+                return;
             },
-            _ => {
-                warn!(self.log, "Unknown [CMD] {}", msg.cmd);
-            }
+        };
+
+        for child_task_uuid in children {
+            self.stop_task(child_task_uuid);
         }
+
+        if !self.apply(&task_uuid, LifecycleEvent::Stop) {
+            debug!(
+                self.log,
+                "Will not stop [TASK UUID] {} [STATE] {:?}",
+                task_uuid,
+                self.tasks[&task_uuid].state,
+            );
+            return;
+        }
+
+        debug!(self.log, "Stop [TASK UUID] {}", task_uuid);
+
+        let item = &self.tasks[&task_uuid];
+        item.ctx.cancellation.cancel();
+
+        let msg = StopTask { task_uuid };
+
+        if let ControllerAddr::Controller(ref a) = item.ctx.controller_addr {
+            a.do_send(msg.clone())
+        }
+
+        item.ctx.stop_task_addr.do_send(msg);
     }
 
-    fn stop_task(&self, task_uuid: String) {
-        if let Some(item) = self.tasks.get(&task_uuid) {
-            for child_task_uuid in item.child_tasks.clone() {
-                self.stop_task(child_task_uuid.clone());
+    fn pause_task(&mut self, task_uuid: String) {
+        let item = match self.tasks.get(&task_uuid) {
+            Some(item) => item,
+            None => {
+                warn!(
+                    self.log,
+                    "Tried to pause unknown [TASK UUID] {}",
+                    task_uuid,
+                );
+                return;
             }
+        };
 
-            if item.task_finished() {
-                debug!(self.log, "[TASK UUID] {} is finished.", task_uuid);
-            } else {
-                debug!(self.log, "Stop [TASK UUID] {}", task_uuid);
+        for child_task_uuid in item.child_tasks.clone() {
+            self.pause_task(child_task_uuid);
+        }
 
-                let msg = StopTask { task_uuid };
+        if !self.apply(&task_uuid, LifecycleEvent::Pause) {
+            debug!(
+                self.log,
+                "Will not pause [TASK UUID] {} [STATE] {:?}",
+                task_uuid,
+                self.tasks[&task_uuid].state,
+            );
+            return;
+        }
 
-                if let ControllerAddr::Controller(ref a) =
-                    item.ctx.controller_addr
-                {
-                    a.do_send(msg.clone())
-                }
+        debug!(self.log, "Pause [TASK UUID] {}", task_uuid);
 
-                item.ctx.stop_task_addr.do_send(msg);
-            }
-        } else {
-            warn!(self.log, "Tried to stop unknown [TASK UUID] {}", task_uuid);
+        let item = &self.tasks[&task_uuid];
+        if let ControllerAddr::Controller(ref a) = item.ctx.controller_addr {
+            a.do_send(PauseTask { task_uuid: task_uuid.clone() });
         }
-    }
 
-    fn close_task(&mut self, task_uuid: String) {
-        // Ensure the task is finished, then close, and then sometimes restart.
-        let mut remove = false;
-        if let Some(item) = self.tasks.get(&task_uuid) {
-            if item.task_finished() {
-                debug!(self.log, "Close [TASK UUID] {}", task_uuid);
-
-                remove = true;
-                let msg = CloseTask { task_uuid: task_uuid.clone() };
-
-                if let ControllerAddr::Controller(ref a) =
-                    item.ctx.controller_addr
-                {
-                    a.do_send(msg.clone());
-                }
+        self.send_task_status(&task_uuid, TaskStatus::Suspended, "suspended");
+    }
 
-                tracker::start().do_send(msg);
-            } else {
-                // First stop the task.
-                self.tasks_to_close.insert(task_uuid.clone());
-                self.stop_task(task_uuid.clone());
+    fn resume_task(&mut self, task_uuid: String) {
+        let item = match self.tasks.get(&task_uuid) {
+            Some(item) => item,
+            None => {
+                warn!(
+                    self.log,
+                    "Tried to resume unknown [TASK UUID] {}",
+                    task_uuid,
+                );
+                return;
             }
+        };
 
-            for child_task_uuid in item.child_tasks.clone() {
-                self.close_task(child_task_uuid);
-            }
+        for child_task_uuid in item.child_tasks.clone() {
+            self.resume_task(child_task_uuid);
+        }
 
-        } else {
-            warn!(
+        if !self.apply(&task_uuid, LifecycleEvent::Resume) {
+            debug!(
                 self.log,
-                "Tried to close unknown [TASK UUID] {}",
+                "Will not resume [TASK UUID] {} [STATE] {:?}",
                 task_uuid,
+                self.tasks[&task_uuid].state,
             );
+            return;
+        }
+
+        debug!(self.log, "Resume [TASK UUID] {}", task_uuid);
+
+        let item = &self.tasks[&task_uuid];
+        if let ControllerAddr::Controller(ref a) = item.ctx.controller_addr {
+            a.do_send(ResumeTask { task_uuid: task_uuid.clone() });
         }
 
-        if !remove {
+        self.send_task_status(&task_uuid, TaskStatus::Running, "resumed");
+    }
+
+    /// Notify the tracker and the center about the task status change.
+    fn send_task_status(
+        &self,
+        task_uuid: &str,
+        status: TaskStatus,
+        message: &str,
+    ) {
+        let name = match self.tasks.get(task_uuid) {
+            Some(item) => item.task.name().to_string(),
+            None => String::new(),
+        };
+
+        tracker::start().do_send(TaskUpdate::new(
+            task_uuid.to_string(),
+            status,
+            TaskUpdateTag::Updated,
+            name,
+        ));
+
+        let c_msg = message::create_no_data(
+            message::Dest::Center,
+            message::Subject::TaskStatusUpdate,
+            task_uuid.to_string(),
+            message.to_string(),
+        );
+
+        self.center_connector_addr.do_send(RawMessage::from(c_msg));
+    }
+
+    fn close_task(&mut self, task_uuid: String) {
+        // Ensure the task is finished, then close, and then sometimes restart.
+        let (finished, children) = match self.tasks.get(&task_uuid) {
+            Some(item) => (item.task_finished(), item.child_tasks.clone()),
+            None => {
+                warn!(
+                    self.log,
+                    "Tried to close unknown [TASK UUID] {}",
+                    task_uuid,
+                );
+                return;
+            },
+        };
+
+        if !finished {
+            // First stop the task.
+            self.tasks_to_close.insert(task_uuid.clone());
+            self.stop_task(task_uuid.clone());
+        }
+
+        // Children are closed first, so that the task is not gone from the
+        // tree while they are.
+        for child_task_uuid in children {
+            self.close_task(child_task_uuid);
+        }
+
+        if !finished || !self.apply(&task_uuid, LifecycleEvent::Close) {
             return;
         }
 
+        debug!(self.log, "Close [TASK UUID] {}", task_uuid);
+
+        let item = &self.tasks[&task_uuid];
+        let msg = CloseTask { task_uuid: task_uuid.clone() };
+
+        if let ControllerAddr::Controller(ref a) = item.ctx.controller_addr {
+            a.do_send(msg.clone());
+            a.do_send(ReleaseReservation {
+                task_uuid: task_uuid.clone(),
+            });
+        }
+
+        tracker::start().do_send(msg);
+        self.apply(&task_uuid, LifecycleEvent::Closed);
+
         let item = self.tasks.remove(&task_uuid);
         self.tasks_to_close.remove(&task_uuid);
+        self.awaiting_children.remove(&task_uuid);
 
-        if self.tasks_to_restart.contains(&task_uuid) {
+        if self.tasks_to_restart.remove(&task_uuid) {
             match item {
                 Some(mut i) => {
                     debug!(
@@ -261,8 +708,86 @@ impl TaskTree {
                     );
                 }
             }
+        } else if self.tasks_to_reprocess.remove(&task_uuid) {
+            if let Some(i) = item {
+                debug!(self.log, "Reprocess [TASK UUID] {}", task_uuid);
+                reprocessor::start().do_send(ReprocessTask { task: i.task });
+            }
+        }
+    }
+
+    /// Apply the lifecycle event to the known task. `False` if the state has
+    /// not changed: the event has taken effect already or is not valid in
+    /// the state.
+    fn apply(&mut self, task_uuid: &str, event: LifecycleEvent) -> bool {
+        let item = match self.tasks.get_mut(task_uuid) {
+            Some(item) => item,
+            None => return false,
+        };
+
+        match item.state.on(event) {
+            Ok(Transition::Changed(state)) => {
+                item.state = state;
+                item.active_at = Instant::now();
+                true
+            },
+            Ok(Transition::Unchanged) => false,
+            Err(e) => {
+                warn!(self.log, "[TASK UUID] {}: {}", task_uuid, e);
+                false
+            },
+        }
+    }
+
+    /// Fail and close the tasks inactive for longer than
+    /// `inactivity_timeout_s`. The suspended tasks are expected to be idle.
+    fn sweep(&mut self) {
+        if self.params.inactivity_timeout_s == 0 {
+            return;
+        }
+
+        let timeout = Duration::from_secs(self.params.inactivity_timeout_s);
+        let lost: Vec<(String, String)> = self.tasks.iter()
+            .filter(|(uuid, item)| {
+                item.state != LifecycleState::Suspended
+                    && item.state != LifecycleState::Closing
+                    && item.active_at.elapsed() >= timeout
+                    && !self.awaiting_children.contains(*uuid)
+            })
+            .map(|(uuid, item)| (uuid.clone(), item.task.name().to_string()))
+            .collect();
+
+        for (task_uuid, name) in lost {
+            warn!(
+                self.log,
+                "Close inactive [TASK UUID] {} [NAME] {}",
+                task_uuid,
+                name,
+            );
+
+            SWEPT_TASKS.fetch_add(1, Ordering::Relaxed);
+
+            // Reset, so that the task is not swept again before closed.
+            if let Some(item) = self.tasks.get_mut(&task_uuid) {
+                item.active_at = Instant::now();
+            }
+
+            if !self.tasks[&task_uuid].task_finished() {
+                self.stop_task(task_uuid.clone());
 
-            self.tasks_to_restart.remove(&task_uuid);
+                // Through the tracker, so that the subscribers and the
+                // center learn the task has failed. The task is closed once
+                // the update is back.
+                self.tasks_to_close.insert(task_uuid.clone());
+                tracker::start().do_send(TaskUpdate::new(
+                    task_uuid,
+                    TaskStatus::FinishedFailure,
+                    TaskUpdateTag::Finished,
+                    name,
+                ));
+            } else {
+                self.close_task(task_uuid);
+            }
         }
     }
 
@@ -280,18 +805,220 @@ impl TaskTree {
             );
         }
     }
+
+    /// Like `restart_task`, but the task keeps its UUID and is run again by
+    /// the task reprocessor, e.g. with a backoff and until quarantined.
+    fn reprocess_task(&mut self, task_uuid: String) {
+        if self.tasks.contains_key(&task_uuid) {
+            debug!(self.log, "To reprocess [TASK UUID] {}", task_uuid);
+
+            self.tasks_to_reprocess.insert(task_uuid.clone());
+            self.close_task(task_uuid);
+        } else {
+            warn!(
+                self.log,
+                "Tried to reprocess unknown [TASK UUID] {}",
+                task_uuid,
+            );
+        }
+    }
+
+    /// The patch is applied to the definition kept to replay the task, so
+    /// the running instance is not affected.
+    fn restart_task_with(
+        &mut self,
+        task_uuid: String,
+        params_patch: &serde_json::Value,
+    ) -> Result<(), String> {
+        let item = self.tasks.get_mut(&task_uuid)
+            .ok_or_else(|| format!("Unknown task {}", task_uuid))?;
+
+        item.task.patch_params(params_patch)?;
+        info!(self.log, "Patched params of [TASK UUID] {}", task_uuid);
+
+        self.restart_task(task_uuid);
+        Ok(())
+    }
 }
 
 impl Default for TaskTree {
     fn default() -> Self {
         TaskTree {
             log: create_logger("task_tree"),
+            params: env::load_opt("task_tree").unwrap_or_default(),
             center_connector_addr: connector::start(),
             app_state_addr: app_state::start(),
             tasks: HashMap::new(),
             tasks_to_close: HashSet::new(),
             tasks_to_restart: HashSet::new(),
+            tasks_to_reprocess: HashSet::new(),
+            awaiting_children: HashSet::new(),
+            commands: Arc::new(
+                CommandRouter::new()
+                    .add::<StopTaskCommand>()
+                    .add::<CloseTaskCommand>()
+                    .add::<RestartTaskCommand>()
+                    .add::<RestartTaskWithCommand>()
+                    .add::<StopTasksByNameCommand>()
+                    .add::<RestartTasksByNameCommand>()
+                    .add::<CloseTasksByNameCommand>()
+                    .add::<TasksByLabelsCommand>()
+                    .add::<PauseTaskCommand>()
+                    .add::<ResumeTaskCommand>()
+            ),
+        }
+    }
+}
+
+impl CommandHandler<StopTaskCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: StopTaskCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.check_task_known(&args.task_uuid)?;
+        self.stop_task(args.task_uuid);
+        Ok(())
+    }
+}
+
+impl CommandHandler<CloseTaskCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: CloseTaskCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.check_task_known(&args.task_uuid)?;
+        self.close_task(args.task_uuid);
+        Ok(())
+    }
+}
+
+impl CommandHandler<PauseTaskCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: PauseTaskCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.check_task_known(&args.task_uuid)?;
+        self.pause_task(args.task_uuid);
+        Ok(())
+    }
+}
+
+impl CommandHandler<ResumeTaskCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: ResumeTaskCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.check_task_known(&args.task_uuid)?;
+        self.resume_task(args.task_uuid);
+        Ok(())
+    }
+}
+
+impl CommandHandler<RestartTaskCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: RestartTaskCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.check_task_known(&args.task_uuid)?;
+        self.restart_task(args.task_uuid);
+        Ok(())
+    }
+}
+
+impl CommandHandler<StopTasksByNameCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: StopTasksByNameCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<Vec<String>, CommandError> {
+        let task_uuids = self.tasks_by_name(&args.pattern)?;
+        for task_uuid in &task_uuids {
+            self.stop_task(task_uuid.clone());
+        }
+        Ok(task_uuids)
+    }
+}
+
+impl CommandHandler<RestartTasksByNameCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: RestartTasksByNameCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<Vec<String>, CommandError> {
+        let task_uuids = self.tasks_by_name(&args.pattern)?;
+        for task_uuid in &task_uuids {
+            // Might have been closed along with its parent.
+            if self.tasks.contains_key(task_uuid) {
+                self.restart_task(task_uuid.clone());
+            }
         }
+        Ok(task_uuids)
+    }
+}
+
+impl CommandHandler<CloseTasksByNameCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: CloseTasksByNameCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<Vec<String>, CommandError> {
+        let task_uuids = self.tasks_by_name(&args.pattern)?;
+        for task_uuid in &task_uuids {
+            if self.tasks.contains_key(task_uuid) {
+                self.close_task(task_uuid.clone());
+            }
+        }
+        Ok(task_uuids)
+    }
+}
+
+impl CommandHandler<TasksByLabelsCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: TasksByLabelsCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<Vec<TaskInfo>, CommandError> {
+        let selector = LabelSelector::parse(&args.selector)
+            .map_err(CommandError::InvalidArgs)?;
+
+        Ok(self.tasks.iter()
+            .filter_map(|(uuid, item)| {
+                let labels = item.task.labels();
+                selector.matches(&labels).then(|| TaskInfo {
+                    task_uuid: uuid.clone(),
+                    name: item.task.name().to_string(),
+                    state: item.state,
+                    labels,
+                })
+            })
+            .collect())
+    }
+}
+
+impl CommandHandler<RestartTaskWithCommand> for TaskTree {
+    fn handle_command(
+        &mut self,
+        args: RestartTaskWithCommand,
+        _msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<(), CommandError> {
+        self.check_task_known(&args.task_uuid)?;
+        self.restart_task_with(args.task_uuid, &args.params_patch)
+            .map_err(CommandError::InvalidArgs)
     }
 }
 
@@ -307,6 +1034,11 @@ impl Actor for TaskTree {
             "task_tree".to_string(),
             ctx.address().recipient(),
         );
+
+        monitor::watch_mailbox("task_tree", ctx.address().recipient());
+
+        let interval = Duration::from_secs(self.params.sweep_interval_s.max(1));
+        ctx.run_interval(interval, |act, _ctx| act.sweep());
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -335,15 +1067,72 @@ impl Handler<NewTask> for TaskTree {
     }
 }
 
+impl Handler<RestartTaskWith> for TaskTree {
+    type Result = Result<(), String>;
+
+    fn handle(
+        &mut self,
+        msg: RestartTaskWith,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.restart_task_with(msg.task_uuid, &msg.params_patch)
+    }
+}
+
+/// The worker process running the task has been lost.
+pub struct ReprocessLostTask {
+    pub task_uuid: String,
+}
+
+impl Message for ReprocessLostTask {
+    type Result = ();
+}
+
+impl Handler<ReprocessLostTask> for TaskTree {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: ReprocessLostTask,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.reprocess_task(msg.task_uuid);
+    }
+}
+
 handler_impl_control_message!(TaskTree);
 handler_impl_task_update!(TaskTree);
 handler_impl_stop_task!(TaskTree);
 handler_impl_restart_task!(TaskTree);
+handler_impl_pause_task!(TaskTree);
+handler_impl_resume_task!(TaskTree);
+handler_impl_mailbox_probe!(TaskTree);
+
+/// Tasks closed for inactivity since the start.
+pub fn swept_tasks() -> usize {
+    SWEPT_TASKS.load(Ordering::Relaxed)
+}
 
 pub fn restart_task(task_uuid: String) {
     start().do_send(RestartTask { task_uuid });
 }
 
+pub fn restart_task_with(task_uuid: String, params_patch: serde_json::Value) {
+    start().do_send(RestartTaskWith { task_uuid, params_patch });
+}
+
+pub fn reprocess_task(task_uuid: String) {
+    start().do_send(ReprocessLostTask { task_uuid });
+}
+
+pub fn pause_task(task_uuid: String) {
+    start().do_send(PauseTask { task_uuid });
+}
+
+pub fn resume_task(task_uuid: String) {
+    start().do_send(ResumeTask { task_uuid });
+}
+
 impl Supervised for TaskTree {}
 
 impl SystemService for TaskTree {
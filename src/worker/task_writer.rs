@@ -7,20 +7,28 @@ use serde_derive::{Deserialize};
 use slog::Logger;
 use std::{
     collections::{HashMap, HashSet},
-    fs::{self, File,  OpenOptions},
-    io::prelude::*,
-    sync::{Mutex, RwLock}
+    sync::{Mutex, RwLock},
 };
 
 use crate::{
+    control::message::CloseTask,
     core::{
         arbiter_pool,
+        disk_guard,
         env,
         logger::create_logger,
+        monitor::*,
+    },
+    worker::{
+        result_chunk::{ResultAssembler, ResultChunk},
+        task_sink::{self, Sink, SinkContext},
+        worker_message::*,
     },
-    worker::worker_message::*,
 };
 
+/// Kept in memory while `disk_guard::writes_paused`, the rest is dropped.
+const MAX_PAUSED_RECORDS: usize = 100_000;
+
 lazy_static! {
     static ref TASK_WRITERS: Mutex<TaskWriters> =
         Mutex::new(TaskWriters::new());
@@ -29,26 +37,166 @@ lazy_static! {
         RwLock::new(WritersSettings::load());
 }
 
-struct TaskWriter {
+pub struct TaskWriter {
     task_name: String,
     settings: WriterSettings,
-    file_path: String,
     log: Logger,
+
+    /// `None` until started.
+    sink: Option<Box<dyn Sink>>,
+
+    /// Periodically flush the sink.
+    regular_check_timer: RegularCheckTimer,
+
+    /// Message, Data. Written once the disk space is freed.
+    paused: Vec<(WorkerMessage, String)>,
+
+    /// Since the writes have been paused.
+    dropped: usize,
+
+    /// Task UUID --> The result being received in chunks.
+    results: HashMap<String, ResultAssembler>,
 }
 
 impl TaskWriter {
     fn new(task_name: String, settings: WriterSettings) -> Self {
-        let file_path = format!("data/tasks/{}", task_name);
+        let regular_check_timer = RegularCheckTimer::new_ms(
+            settings.flush_interval_ms.max(1)
+        );
 
         Self {
             log: create_logger(&format!("task_writer_{}", task_name)),
             task_name,
             settings,
-            file_path,
+            sink: None,
+            regular_check_timer,
+            paused: Vec::new(),
+            dropped: 0,
+            results: HashMap::new(),
         }
     }
 
+    fn write(&mut self, msg: &WorkerMessage, data: &str) {
+        if let Some(ref mut sink) = self.sink {
+            if let Err(e) = sink.write(msg, data) {
+                error!(
+                    self.log,
+                    "Failed to write to {}: {}",
+                    self.settings.sink,
+                    e,
+                );
+            }
+        }
+    }
+
+    /// Keep the record while the disk space is critical.
+    fn pause(&mut self, msg: WorkerMessage, data: String) {
+        if self.paused.len() < MAX_PAUSED_RECORDS {
+            self.paused.push((msg, data));
+            return;
+        }
+
+        if self.dropped == 0 {
+            error!(
+                self.log,
+                "Writes paused with {} records kept. Dropping the rest.",
+                self.paused.len(),
+            );
+        }
+        self.dropped += 1;
+    }
+
+    fn write_paused(&mut self) {
+        if self.paused.is_empty() {
+            return;
+        }
+
+        info!(
+            self.log,
+            "Write {} records kept while paused. {} dropped.",
+            self.paused.len(),
+            self.dropped,
+        );
+
+        for (msg, data) in std::mem::take(&mut self.paused) {
+            self.write(&msg, &data);
+        }
+        self.dropped = 0;
+    }
+
+    fn flush(&mut self) {
+        if let Some(ref mut sink) = self.sink {
+            if let Err(e) = sink.flush() {
+                error!(
+                    self.log,
+                    "Failed to flush {}: {}",
+                    self.settings.sink,
+                    e,
+                );
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        // Lost otherwise.
+        self.write_paused();
+
+        if let Some(ref mut sink) = self.sink {
+            if let Err(e) = sink.close() {
+                error!(
+                    self.log,
+                    "Failed to close {}: {}",
+                    self.settings.sink,
+                    e,
+                );
+            }
+        }
+    }
+
+    /// The `task_result` record once the last chunk is in. The result text
+    /// is written as is, not parsed.
+    fn assemble(
+        &mut self,
+        msg: &WorkerMessage,
+        chunk: ResultChunk,
+    ) -> Option<String> {
+        let task_uuid = &msg.payload.task_uuid;
+        let collected = self.results.entry(task_uuid.clone())
+            .or_default()
+            .collect(chunk);
+
+        let result = match collected {
+            Ok(Some(result)) => result,
+            Ok(None) => return None,
+            Err(e) => {
+                error!(
+                    self.log,
+                    "Drop the result of [TASK UUID] {}: {}",
+                    task_uuid,
+                    e,
+                );
+                self.results.remove(task_uuid);
+                return None;
+            }
+        };
+        self.results.remove(task_uuid);
+
+        let mut record = msg.clone();
+        record.payload.data = json!({ "task_result": null });
+        let data = json!(record).to_string().replacen(
+            r#""task_result":null"#,
+            &format!(r#""task_result":{}"#, result),
+            1,
+        );
+
+        Some(data)
+    }
+
     fn should_be_written(&self, msg: &WorkerMessage) -> bool {
+        if msg.payload.data.get("result_chunk").is_some() {
+            return self.settings.message_types.contains("task_result");
+        }
+
         if let Some(_) = msg.result::<serde_json::Value>() {
             return self.settings.message_types.contains("task_result");
         }
@@ -71,24 +219,77 @@ impl Actor for TaskWriter {
     fn started(&mut self, ctx: &mut Self::Context) {
         info!(self.log, "Started.");
 
-        // Create the output folder if needed.
-        fs::create_dir_all("data/tasks").unwrap();
+        let options = serde_json::Value::Object(self.settings.options.clone());
+        let sink_ctx = SinkContext {
+            task_name: &self.task_name,
+            options: &options,
+            log: &self.log,
+        };
+
+        match task_sink::create_sink(&self.settings.sink, &sink_ctx) {
+            Ok(sink) => self.sink = Some(sink),
+            Err(e) => {
+                error!(
+                    self.log,
+                    "Failed to create {} sink: {}",
+                    self.settings.sink,
+                    e,
+                );
+                ctx.stop();
+                return;
+            }
+        }
 
-        // Create / truncate the output file.
-        let mut file = OpenOptions::new()
-            .read(false)
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(&self.file_path).unwrap();
+        self.regular_check_timer.reset::<Self>(ctx);
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.close();
         info!(self.log, "Stopped.");
         remove_writer(&self.task_name);
     }
 }
 
+impl Handler<RegularCheckMessage> for TaskWriter {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: RegularCheckMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        if disk_guard::writes_paused() {
+            self.regular_check_timer.reset::<Self>(ctx);
+            return;
+        }
+
+        self.write_paused();
+        self.flush();
+        self.regular_check_timer.reset::<Self>(ctx);
+    }
+}
+
+impl TaskWriter {
+    /// Flush the data of the closed task.
+    fn handle_close_task(
+        &mut self,
+        msg: CloseTask,
+        _ctx: &mut <Self as Actor>::Context,
+    ) {
+        if self.results.remove(&msg.task_uuid).is_some() {
+            warn!(
+                self.log,
+                "Closed [TASK UUID] {} before its last result chunk",
+                msg.task_uuid,
+            );
+        }
+
+        self.close();
+    }
+}
+
+handler_impl_close_task!(TaskWriter);
+
 impl Handler<WorkerMessage> for TaskWriter {
 
     type Result = ();
@@ -103,18 +304,22 @@ impl Handler<WorkerMessage> for TaskWriter {
             return;
         }
 
-        debug!(self.log, "Write WORKER MESSAGE {:?}", msg);
+        let data = match msg.result_chunk() {
+            Some(chunk) => match self.assemble(&msg, chunk) {
+                Some(data) => data,
+                None => return,
+            },
+            None => json!(msg).to_string(),
+        };
 
-        let data = json!(msg).to_string();
+        debug!(self.log, "Write WORKER MESSAGE {:?}", msg);
 
-        let mut file = OpenOptions::new()
-            .read(false)
-            .append(true)
-            .create(true)
-            .open(&self.file_path).unwrap();
+        if disk_guard::writes_paused() {
+            self.pause(msg, data);
+            return;
+        }
 
-        file.write(data.as_bytes()).unwrap();
-        file.write(b"\n").unwrap();
+        self.write(&msg, &data);
     }
 }
 
@@ -136,18 +341,18 @@ impl TaskWriters {
     fn get_writer(
         &mut self,
         task_name: &str
-    ) -> Option<Recipient<WorkerMessage>> {
+    ) -> Option<Addr<TaskWriter>> {
         if let Some(w) = self.writers.get(task_name) {
             info!(self.log, "Got task writer for [TASK NAME] {}", task_name);
 
-            return Some(w.clone().recipient());
+            return Some(w.clone());
         }
 
         let settings = WRITERS_SETTINGS.read().unwrap();
 
         if let Some(s) = settings.get(task_name) {
             let w = self.create_writer(task_name.into(), s);
-            return Some(w.clone().recipient());
+            return Some(w);
         }
 
         info!(
@@ -203,9 +408,27 @@ impl TaskWriters {
     }
 }
 
+fn default_sink() -> String { "file".to_string() }
+
+fn default_flush_interval_ms() -> u64 { 1000 }
+
 #[derive(Debug, Clone, Deserialize)]
 struct WriterSettings {
     message_types: HashSet<String>,
+
+    /// "file", "postgres", "webhook", "s3" or a sink registered with
+    /// `task_sink::register_sink`.
+    #[serde(default = "default_sink")]
+    sink: String,
+
+    /// The sink is flushed at least that often.
+    #[serde(default = "default_flush_interval_ms")]
+    flush_interval_ms: u64,
+
+    /// Sink specific settings, e.g. `max_size_mb` of "file" or `url` of
+    /// "webhook".
+    #[serde(flatten)]
+    options: serde_json::Map<String, serde_json::Value>,
 }
 
 struct WritersSettings {
@@ -241,7 +464,14 @@ impl WritersSettings {
     }
 }
 
-pub fn get_writer(task_name: &str) -> Option<Recipient<WorkerMessage>> {
+/// Apply `task_writers` of the configuration to the writers started from
+/// now on.
+pub fn reload_settings() -> Result<(), String> {
+    *WRITERS_SETTINGS.write().unwrap() = WritersSettings::load();
+    Ok(())
+}
+
+pub fn get_writer(task_name: &str) -> Option<Addr<TaskWriter>> {
     let mut task_writers = TASK_WRITERS.lock().unwrap();
     task_writers.get_writer(task_name)
 }
@@ -1,32 +1,51 @@
 use actix::prelude::*;
+use serde::de::IgnoredAny;
+use serde_derive::{Deserialize, Serialize};
 use slog::Logger;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::{
     center::{
         connector,
-        message::CenterMessage,
+        message::{self, CenterMessage, CenterMessagePayload},
+        reporting,
         send::*,
     },
     control::{
+        command::{Command, CommandError, CommandHandler, CommandRouter},
         message::{CloseTask, ControlMessage},
         registry,
     },
     core::{
-        app_state,
+        app_state::{self, app_id},
+        backpressure,
+        error_bus::{self, PatokaError},
         logger::create_logger,
-        monitor::*,
+        monitor::{self, *},
+        notifier::{self, EventKind},
+        status_aggregator::{self, GetStatusSnapshot, StatusSnapshot},
+        telemetry,
     },
+    handler_impl_mailbox_probe,
     transport::message::RawMessage,
     worker::{
+        circuit_breaker,
+        name_pattern::NamePattern,
+        unique_names,
         task::{TaskStatus},
         task_assistant::self,
+        task_history,
+        task_labels::Labels,
+        task_stats,
         task_tree::{self, TaskTree},
     },
 };
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TaskUpdateTag {
     Unknown = 0,
     Started = 1,
@@ -35,6 +54,18 @@ pub enum TaskUpdateTag {
     Question = 4,
 }
 
+impl TaskUpdateTag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskUpdateTag::Unknown => "unknown",
+            TaskUpdateTag::Started => "started",
+            TaskUpdateTag::Updated => "updated",
+            TaskUpdateTag::Finished => "finished",
+            TaskUpdateTag::Question => "question",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TaskUpdate {
     pub task_uuid: String,
@@ -48,6 +79,14 @@ pub struct TaskUpdate {
     /// 0 = unknown; 1 = started; 2 = updated (current state); 3 = finished;
     /// 4 = task question.
     pub tag: TaskUpdateTag,
+
+    /// The labels of the task definition. The tracker forwards the last
+    /// ones known with every update of the task.
+    pub labels: Labels,
+
+    /// Time since the task has started, set by the tracker in the finished
+    /// updates.
+    pub duration_ms: Option<u64>,
 }
 
 impl TaskUpdate {
@@ -63,6 +102,8 @@ impl TaskUpdate {
             center_msg: None,
             tag,
             name,
+            labels: Labels::new(),
+            duration_ms: None,
         }
     }
 
@@ -79,9 +120,26 @@ impl TaskUpdate {
             center_msg: Some(RawMessage::from(center_msg)),
             tag,
             name,
+            labels: Labels::new(),
+            duration_ms: None,
         }
     }
 
+    pub fn with_labels(mut self, labels: Labels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_duration_ms(mut self, duration_ms: Option<u64>) -> Self {
+        self.duration_ms = duration_ms;
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status == TaskStatus::FinishedSuccess
+            || self.status == TaskStatus::FinishedFailure
+    }
+
     pub fn str_short(&self) -> String {
         format!(
             "TASK UPDATE [TASK UUID] {} [NAME] {} [STATUS] {:?} [TAG] {:?}",
@@ -114,10 +172,57 @@ macro_rules! handler_impl_task_update {
     }
 }
 
+/// The updates of a task a subscriber gets, all of them by default, e.g.
+/// `{ "tags": ["finished"] }`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskFilter {
+    /// Only these statuses, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub statuses: Vec<TaskStatus>,
+
+    /// Only these tags, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<TaskUpdateTag>,
+}
+
+impl TaskFilter {
+    pub fn finished() -> Self {
+        Self::tags(vec![TaskUpdateTag::Finished])
+    }
+
+    pub fn statuses(statuses: Vec<TaskStatus>) -> Self {
+        Self { statuses, tags: vec![] }
+    }
+
+    pub fn tags(tags: Vec<TaskUpdateTag>) -> Self {
+        Self { statuses: vec![], tags }
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.statuses.is_empty() && self.tags.is_empty()
+    }
+
+    pub fn matches(&self, msg: &TaskUpdate) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&msg.status))
+            && (self.tags.is_empty() || self.tags.contains(&msg.tag))
+    }
+}
+
+/// Module name used to publish errors.
+const MODULE: &str = "task_tracker";
+
+/// How often the subscribers of dead actors are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
 type TaskSubscriber = Recipient<TaskUpdate>;
 
-/// UUID --> TaskSubscriber
-type TaskSubscribers = HashMap<String, TaskSubscriber>;
+struct Subscriber {
+    recipient: TaskSubscriber,
+    filter: TaskFilter,
+}
+
+/// UUID --> Subscriber
+type TaskSubscribers = HashMap<String, Subscriber>;
 
 pub struct TaskSubscription {
     /// True to subscribe, False to unsubscribe.
@@ -136,6 +241,9 @@ pub struct TaskSubscription {
     /// If None, a subscription is possible for the already registered
     /// recipient `subscriber_uuid`.
     subscriber: Option<TaskSubscriber>,
+
+    /// Evaluated by the tracker before forwarding an update.
+    filter: TaskFilter,
 }
 
 impl TaskSubscription {
@@ -151,6 +259,7 @@ impl TaskSubscription {
             name: String::new(),
             by_name: false,
             subscriber: Some(subscriber),
+            filter: TaskFilter::default(),
         }
     }
 
@@ -162,6 +271,7 @@ impl TaskSubscription {
             name: String::new(),
             by_name: false,
             subscriber: None,
+            filter: TaskFilter::default(),
         }
     }
 
@@ -178,9 +288,15 @@ impl TaskSubscription {
             name,
             by_name,
             subscriber: None,
+            filter: TaskFilter::default(),
         }
     }
 
+    pub fn with_filter(mut self, filter: TaskFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
     pub fn unsubscribe_by_name(name: String, subscriber_uuid: String) -> Self {
         Self {
             subscribe: false,
@@ -189,6 +305,7 @@ impl TaskSubscription {
             name,
             by_name: true,
             subscriber: None,
+            filter: TaskFilter::default(),
         }
     }
 }
@@ -234,6 +351,11 @@ struct TrackerItem {
 
     /// Tag --> Message
     center_messages: HashMap<TaskUpdateTag, RawMessage>,
+
+    labels: Labels,
+
+    /// The last start of the task, or its first update.
+    started_at: Option<Instant>,
 }
 
 impl TrackerItem {
@@ -242,9 +364,15 @@ impl TrackerItem {
             task_uuid,
             subscribers: TaskSubscribers::new(),
             center_messages: HashMap::new(),
+            labels: Labels::new(),
+            started_at: None,
         }
     }
 
+    fn duration_ms(&self) -> Option<u64> {
+        self.started_at.map(|s| s.elapsed().as_millis() as u64)
+    }
+
     pub fn debug_info(&self) -> String {
         format!("[TRACKER ITEM] [TASK UUID] {} [SUBSCRIBERS] {} \
             [CENTER MESSAGES] {}",
@@ -261,25 +389,71 @@ pub struct TaskTracker {
     /// Task UUID --> Item
     items: HashMap<String, TrackerItem>,
 
-    /// Periodically generate status report.
-    report_status_timer: ReportStatusTimer,
+    /// Center messages waiting to be sent as a batch. See
+    /// `reporting::batch_interval`.
+    center_batch: Vec<RawMessage>,
 
     task_tree_addr: Addr<TaskTree>,
 
     /// ID --> Recipient
     task_update_recipients: HashMap<String, TaskSubscriber>,
 
-    /// Task Name --> Subscribers
+    /// Task Name or Pattern --> Subscribers. See `name_pattern`.
     subscribers_by_name: HashMap<String, TaskSubscribers>,
+
+    /// Pattern --> Matcher, of the subscriptions by a name pattern.
+    name_patterns: HashMap<String, NamePattern>,
+
+    /// Task Name --> Patterns matching it. Cleared when the patterns change.
+    matching_patterns: HashMap<String, Vec<String>>,
+
+    commands: Arc<CommandRouter<Self>>,
+}
+
+/// Resend the center messages of the task `orig_id`. `data` is ignored.
+#[derive(Deserialize)]
+struct SendCenterMessagesCommand(IgnoredAny);
+
+impl Command for SendCenterMessagesCommand {
+    const NAME: &'static str = "send_center_messages";
+
+    /// The number of messages sent.
+    type Response = usize;
 }
 
 impl TaskTracker {
     fn subscribe(&mut self, msg: TaskSubscription) {
-        let subscriber = self.get_recipient(&msg);
+        let subscriber = match self.get_recipient(&msg) {
+            Some(recipient) => Subscriber {
+                recipient,
+                filter: msg.filter.clone(),
+            },
+            None => return,
+        };
 
         if msg.by_name {
             if msg.name.is_empty() {
-                panic!("Tried to subscribe by name but the name is empty.");
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    "Tried to subscribe by name but the name is empty."
+                        .to_string(),
+                ));
+                return;
+            }
+
+            let pattern = match NamePattern::parse(&msg.name) {
+                Ok(p) => p,
+                Err(e) => {
+                    error_bus::publish(PatokaError::error(MODULE, e));
+                    return;
+                }
+            };
+
+            if !pattern.is_exact()
+                && !self.name_patterns.contains_key(&msg.name)
+            {
+                self.name_patterns.insert(msg.name.clone(), pattern);
+                self.matching_patterns.clear();
             }
 
             if let Some(s) = self.subscribers_by_name.get_mut(&msg.name) {
@@ -301,15 +475,18 @@ impl TaskTracker {
         }
 
         // Check if the subscriber is already subscribed to the task by name.
-        if let Some(s) = self.subscribers_by_name.get(&msg.name) {
-            if s.contains_key(&msg.subscriber_uuid) {
+        for key in self.name_keys(&msg.name) {
+            let subscribed = self.subscribers_by_name.get(&key)
+                .is_some_and(|s| s.contains_key(&msg.subscriber_uuid));
+
+            if subscribed {
                 debug!(
                     self.log,
                     "[SUBSCRIBER UUID] {} is already subscribed to \
                         [TASK UUID] {} by [NAME] {}",
                     msg.subscriber_uuid,
                     msg.task_uuid,
-                    msg.name,
+                    key,
                 );
 
                 return;
@@ -337,16 +514,32 @@ impl TaskTracker {
     fn unsubscribe(&mut self, msg: TaskSubscription) {
         if msg.by_name {
             if msg.name.is_empty() {
-                panic!("Tried to unsubscribe by name but the name is empty.");
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    "Tried to unsubscribe by name but the name is empty."
+                        .to_string(),
+                ));
+                return;
             }
 
             if let Some(s) = self.subscribers_by_name.get_mut(&msg.name) {
                 s.remove(&msg.subscriber_uuid);
+
+                if s.is_empty() {
+                    self.subscribers_by_name.remove(&msg.name);
+                    if self.name_patterns.remove(&msg.name).is_some() {
+                        self.matching_patterns.clear();
+                    }
+                }
             } else {
-                panic!(
-                    "Tried to unsubscribe from unknown [NAME] {}",
-                    msg.name,
-                );
+                error_bus::publish(PatokaError::error(
+                    MODULE,
+                    format!(
+                        "Tried to unsubscribe from unknown [NAME] {}",
+                        msg.name,
+                    ),
+                ));
+                return;
             }
 
             debug!(
@@ -373,20 +566,21 @@ impl TaskTracker {
         );
     }
 
-    fn handle_control_msg(&self, msg: ControlMessage) {
+    fn handle_control_msg(
+        &mut self,
+        msg: ControlMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
         debug!(self.log, "[CONTROL] {:?}", msg);
 
-        match msg.cmd.as_ref() {
-            "send_center_messages" => {
-                self.cmd_send_center_messages(msg);
-            },
-            _ => {
-                warn!(self.log, "Unknown [CMD] {}", msg.cmd)
-            }
-        }
+        let commands = self.commands.clone();
+        send_control_msg(commands.route(self, msg, ctx));
     }
 
-    fn cmd_send_center_messages(&self, msg: ControlMessage) {
+    fn cmd_send_center_messages(
+        &self,
+        msg: &ControlMessage,
+    ) -> Result<usize, CommandError> {
         let task_uuid = &msg.orig_id;
 
         if let Some(item) = self.items.get(task_uuid) {
@@ -407,35 +601,119 @@ impl TaskTracker {
                 TaskUpdateTag::Question,
             ];
 
+            let mut sent = 0;
             for tag in tag_orger {
                 if let Some(c_msg) = item.center_messages.get(&tag) {
                     connector_addr.do_send(c_msg.clone());
+                    sent += 1;
                 }
             }
 
+            Ok(sent)
         } else {
             warn!(
                 self.log,
                 "[CMD SEND CENTER MESSAGES] Unknown [TASK UUID] {}",
                 task_uuid,
             );
+
+            Err(CommandError::Failed(
+                format!("Unknown [TASK UUID] {}", task_uuid)
+            ))
+        }
+    }
+
+    /// Send `c_msg` directly or add it to the batch.
+    fn send_to_center(&mut self, c_msg: RawMessage, ctx: &mut Context<Self>) {
+        let interval = match reporting::batch_interval() {
+            Some(i) => i,
+            None => {
+                connector::start().do_send(c_msg);
+                return;
+            },
+        };
+
+        if self.center_batch.is_empty() {
+            ctx.run_later(interval, |act, _| act.flush_center_batch());
+        }
+
+        self.center_batch.push(c_msg);
+    }
+
+    /// The keys of `subscribers_by_name` matching the task `name`: the name
+    /// itself and the patterns.
+    fn name_keys(&mut self, name: &str) -> Vec<String> {
+        if !self.matching_patterns.contains_key(name) {
+            let patterns = self.name_patterns.iter()
+                .filter(|(_, p)| p.matches(name))
+                .map(|(k, _)| k.clone())
+                .collect();
+            self.matching_patterns.insert(name.to_string(), patterns);
+        }
+
+        let mut keys = vec![name.to_string()];
+        keys.extend(self.matching_patterns[name].iter().cloned());
+        keys
+    }
+
+    /// Remove the subscribers and the recipients whose actors have
+    /// stopped.
+    fn prune_subscribers(&mut self) {
+        let mut pruned = 0;
+        let mut prune = |subscribers: &mut TaskSubscribers| {
+            let len = subscribers.len();
+            subscribers.retain(|_, s| s.recipient.connected());
+            pruned += len - subscribers.len();
+        };
+
+        self.items.values_mut().for_each(|i| prune(&mut i.subscribers));
+        self.subscribers_by_name.values_mut().for_each(&mut prune);
+
+        let len = self.task_update_recipients.len();
+        self.task_update_recipients.retain(|_, r| r.connected());
+        pruned += len - self.task_update_recipients.len();
+
+        // The patterns nobody is subscribed to any more.
+        self.subscribers_by_name.retain(|_, s| !s.is_empty());
+        let len = self.name_patterns.len();
+        let names = &self.subscribers_by_name;
+        self.name_patterns.retain(|p, _| names.contains_key(p));
+        if self.name_patterns.len() != len {
+            self.matching_patterns.clear();
+        }
+
+        if pruned > 0 {
+            debug!(self.log, "Pruned {} dead subscribers", pruned);
+        }
+    }
+
+    fn flush_center_batch(&mut self) {
+        let payloads: Vec<serde_json::Value> = self.center_batch.drain(..)
+            .filter_map(|m| serde_json::from_str(&m.body).ok())
+            .collect();
+
+        if payloads.is_empty() {
+            return;
         }
+
+        let c_msg = message::create(
+            message::Dest::Center,
+            message::Subject::TaskUpdateBatch,
+            app_id(),
+            "task_update_batch".to_string(),
+            payloads,
+        );
+
+        connector::start().do_send(RawMessage::from(c_msg));
     }
 
     fn handle_task_update(
         &mut self,
         msg: TaskUpdate,
-        _ctx: &mut <Self as Actor>::Context
+        ctx: &mut <Self as Actor>::Context
     ) {
         //debug!(self.log, "Received task update {:?}", msg);
 
-        let msg_short = TaskUpdate::new(
-            msg.task_uuid.clone(),
-            msg.status,
-            msg.tag,
-            msg.name.clone(),
-        );
-
         if !self.items.contains_key(&msg.task_uuid) {
             debug!(
                 self.log,
@@ -447,31 +725,100 @@ impl TaskTracker {
             self.items.insert(msg.task_uuid.clone(), item);
         }
 
-        // Forward the update message to all the task subscribers.
+        let name_keys = self.name_keys(&msg.name);
         let item = self.items.get_mut(&msg.task_uuid).unwrap();
 
-        for s in item.subscribers.values() {
-            //if let Err(e) = s.do_send(msg_short.clone()) {
-            if let Err(e) = s.try_send(msg_short.clone()) {
-                panic!(
-                    "Failed to send task status update to subscriber \
-                        [ERROR] {}",
-                    e
+        // Only the started and updated messages carry the labels.
+        if !msg.labels.is_empty() {
+            item.labels = msg.labels.clone();
+        }
+
+        // The item may have been created by a subscription before the task
+        // has started.
+        if msg.tag == TaskUpdateTag::Started || item.started_at.is_none() {
+            item.started_at = Some(Instant::now());
+        }
+
+        let duration_ms = if msg.is_finished() {
+            item.duration_ms()
+        } else {
+            None
+        };
+
+        let msg_short = TaskUpdate::new(
+            msg.task_uuid.clone(),
+            msg.status,
+            msg.tag,
+            msg.name.clone(),
+        )
+        .with_labels(item.labels.clone())
+        .with_duration_ms(duration_ms);
+
+        let center_msg = match (msg.center_msg, duration_ms) {
+            (Some(c_msg), Some(d)) => Some(add_duration(c_msg, d)),
+            (c_msg, _) => c_msg,
+        };
+
+        // Forward the update message to all the task subscribers.
+
+        item.subscribers.retain(|uuid, s| {
+            if !s.filter.matches(&msg_short) {
+                return true;
+            }
+
+            let sent = backpressure::do_send(
+                "tracker.subscriber",
+                &s.recipient,
+                msg_short.clone(),
+            );
+            if !sent {
+                debug!(
+                    self.log,
+                    "Pruned dead [SUBSCRIBER UUID] {} of [TASK UUID] {}",
+                    uuid,
+                    msg_short.task_uuid,
                 );
             }
+            sent
+        });
+
+        if let Some(ref c_msg) = center_msg {
+            item.center_messages.insert(msg.tag, c_msg.clone());
         }
 
-        if let(Some(c_msg)) = msg.center_msg {
-            connector::start().do_send(c_msg.clone());
-            item.center_messages.insert(msg.tag, c_msg);
+        // Subscribers by name, once each even if several patterns match.
+        let mut notified = HashSet::new();
+        for key in &name_keys {
+            let subscribers = match self.subscribers_by_name.get_mut(key) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            subscribers.retain(|uuid, s| {
+                if !s.recipient.connected() {
+                    debug!(
+                        self.log,
+                        "Pruned dead [SUBSCRIBER UUID] {} of [NAME] {}",
+                        uuid,
+                        key,
+                    );
+                    return false;
+                }
+
+                if s.filter.matches(&msg_short)
+                    && notified.insert(uuid.clone())
+                {
+                    backpressure::do_send(
+                        "tracker.subscriber_by_name",
+                        &s.recipient,
+                        msg_short.clone(),
+                    );
+                }
+                true
+            });
         }
 
-        // Subscribers by name.
-        if let Some(subscribers) = self.subscribers_by_name.get(&msg.name) {
-            for s in subscribers.values() {
-                s.do_send(msg_short.clone());
-            }
-        } else {
+        if notified.is_empty() {
             debug!(
                 self.log,
                 "No subscribers by name [NAME] {}",
@@ -480,19 +827,62 @@ impl TaskTracker {
         }
 
         // Always send to the task tree.
-        self.task_tree_addr.do_send(msg_short.clone());
+        backpressure::do_send(
+            "tracker.task_tree",
+            &self.task_tree_addr,
+            msg_short.clone(),
+        );
+
+        // The task assistant restarts the task once the circuit is closed.
+        circuit_breaker::record(&msg_short.name, msg_short.status);
 
         // Always send to the task assistant.
-        task_assistant::start().do_send(msg_short.clone());
+        backpressure::do_send(
+            "tracker.task_assistant",
+            &task_assistant::start(),
+            msg_short.clone(),
+        );
+
+        if task_stats::enabled() {
+            backpressure::do_send(
+                "tracker.task_stats",
+                &task_stats::start(),
+                msg_short.clone(),
+            );
+        }
+
+        if task_history::enabled() {
+            backpressure::do_send(
+                "tracker.task_history",
+                &task_history::start(),
+                msg_short.clone(),
+            );
+        }
 
         // Always send to the app state.
-        app_state::start().do_send(msg_short.clone());
+        backpressure::do_send(
+            "tracker.app_state",
+            &app_state::start(),
+            msg_short.clone(),
+        );
 
         debug!(self.log, "{}", item.debug_info());
 
-        if msg_short.status == TaskStatus::FinishedSuccess ||
-            msg_short.status == TaskStatus::FinishedFailure
-        {
+        if let Some(c_msg) = center_msg {
+            self.send_to_center(c_msg, ctx);
+        }
+
+        if msg_short.status == TaskStatus::FinishedFailure {
+            notifier::notify(
+                EventKind::TaskFailed,
+                &msg_short.task_uuid,
+                format!("{} has finished with failure", msg_short.name),
+            );
+        }
+
+        if msg_short.is_finished() {
+            unique_names::release(&msg_short.name, &msg_short.task_uuid);
+
             // Remove the task's subscriptions to other tasks and the other
             // tasks' subscriptions to the task.
             self.task_update_recipients.remove(&msg_short.task_uuid);
@@ -515,7 +905,12 @@ impl TaskTracker {
         ctx: &mut <Self as Actor>::Context,
     ) {
         self.items.remove(&msg.task_uuid);
-        send_center_task_closed(&msg.task_uuid);
+
+        // Keep the order of the task updates.
+        let c_msg = RawMessage::from(center_task_closed(&msg.task_uuid));
+        self.send_to_center(c_msg, ctx);
+        telemetry::finish_task(&msg.task_uuid);
+
         app_state::start().do_send(msg);
     }
 
@@ -525,11 +920,14 @@ impl TaskTracker {
         addr: TaskSubscriber
     ) {
         if let Some(v) = self.task_update_recipients.insert(id.clone(), addr) {
-            panic!(
-                "Tried to register task update recipient multiple times \
-                    [ID] {}.",
-                id,
-            );
+            error_bus::publish(PatokaError::error(
+                MODULE,
+                format!(
+                    "Tried to register task update recipient multiple times \
+                        [ID] {}.",
+                    id,
+                ),
+            ));
         } else {
             debug!(self.log, "Registered task update recipient [ID] {}.", id);
         }
@@ -545,19 +943,23 @@ impl TaskTracker {
         }
     }
 
-    fn get_recipient(&self, msg: &TaskSubscription) -> TaskSubscriber {
+    fn get_recipient(&self, msg: &TaskSubscription) -> Option<TaskSubscriber> {
         match msg.subscriber {
-            Some(ref s) => s.clone(),
+            Some(ref s) => Some(s.clone()),
             None => {
                 if let Some(s) = self.task_update_recipients.get(
                     &msg.subscriber_uuid
                 ) {
-                    s.clone()
+                    Some(s.clone())
                 } else {
-                    panic!(
-                        "Unknown task update recipient [ID] {}.",
-                        msg.subscriber_uuid,
-                    );
+                    error_bus::publish(PatokaError::error(
+                        MODULE,
+                        format!(
+                            "Unknown task update recipient [ID] {}.",
+                            msg.subscriber_uuid,
+                        ),
+                    ).task(&msg.task_uuid));
+                    None
                 }
             }
         }
@@ -567,16 +969,32 @@ impl TaskTracker {
 impl Default for TaskTracker {
     fn default() -> Self {
         TaskTracker {
-            log: create_logger("task_tracker"),
+            log: create_logger(MODULE),
             items: HashMap::new(),
-            report_status_timer: ReportStatusTimer::new_s(5),
+            center_batch: vec![],
             task_tree_addr: task_tree::start(),
             task_update_recipients: HashMap::new(),
             subscribers_by_name: HashMap::new(),
+            name_patterns: HashMap::new(),
+            matching_patterns: HashMap::new(),
+            commands: Arc::new(
+                CommandRouter::new().add::<SendCenterMessagesCommand>()
+            ),
         }
     }
 }
 
+impl CommandHandler<SendCenterMessagesCommand> for TaskTracker {
+    fn handle_command(
+        &mut self,
+        _args: SendCenterMessagesCommand,
+        msg: &ControlMessage,
+        _ctx: &mut Self::Context,
+    ) -> Result<usize, CommandError> {
+        self.cmd_send_center_messages(msg)
+    }
+}
+
 impl Actor for TaskTracker {
     type Context = Context<Self>;
 
@@ -590,7 +1008,11 @@ impl Actor for TaskTracker {
             ctx.address().recipient::<ControlMessage>(),
         );
 
-        self.report_status_timer.reset::<Self>(ctx);
+        monitor::watch_mailbox(MODULE, ctx.address().recipient());
+
+        status_aggregator::register("tracker", ctx.address().recipient());
+
+        ctx.run_interval(PRUNE_INTERVAL, |act, _ctx| act.prune_subscribers());
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
@@ -632,6 +1054,7 @@ impl Handler<RegisterTaskUpdateRecipient> for TaskTracker {
 
 handler_impl_task_update!(TaskTracker);
 handler_impl_close_task!(TaskTracker);
+handler_impl_mailbox_probe!(TaskTracker);
 
 impl Supervised for TaskTracker {}
 
@@ -641,23 +1064,17 @@ impl SystemService for TaskTracker {
     }
 }
 
-impl Handler<ReportStatusMessage> for TaskTracker {
-    type Result = ();
+impl Handler<GetStatusSnapshot> for TaskTracker {
+    type Result = MessageResult<GetStatusSnapshot>;
 
     fn handle(
         &mut self,
-        _msg: ReportStatusMessage,
-        ctx: &mut Self::Context
+        _msg: GetStatusSnapshot,
+        _ctx: &mut Self::Context
     ) -> Self::Result {
-        let number_of_tracking_tasks = self.items.len();
-
-        /*info!(
-            self.log,
-            "[STATUS] Number of tracking tasks: {}.",
-            number_of_tracking_tasks,
-        );*/
-
-        self.report_status_timer.reset::<Self>(ctx);
+        MessageResult(
+            StatusSnapshot::new().with("tracking_tasks", self.items.len())
+        )
     }
 }
 
@@ -669,7 +1086,7 @@ impl Handler<ControlMessage> for TaskTracker {
         msg: ControlMessage,
         ctx: &mut Self::Context
     ) -> Self::Result {
-        self.handle_control_msg(msg);
+        self.handle_control_msg(msg, ctx);
     }
 }
 
@@ -722,6 +1139,26 @@ impl Handler<DismissTaskQuestion> for TaskTracker {
     }
 }
 
+/// Add `duration_ms` to the data of the center message, e.g. of the
+/// `TaskStatusUpdate`. The data other than an object or null is left as is.
+fn add_duration(c_msg: RawMessage, duration_ms: u64) -> RawMessage {
+    let parsed = RawMessage::to::<CenterMessagePayload>(c_msg.clone());
+    let mut c_msg = match parsed {
+        Ok(m) => m,
+        Err(_) => return c_msg,
+    };
+
+    let data = &mut c_msg.payload.data;
+    if data.is_null() {
+        *data = serde_json::json!({});
+    }
+    if let Some(data) = data.as_object_mut() {
+        data.insert("duration_ms".to_string(), duration_ms.into());
+    }
+
+    RawMessage::from(c_msg)
+}
+
 pub fn send(
     task_uuid: String,
     status: TaskStatus,
@@ -763,11 +1200,24 @@ pub fn subscribe(
     );
 }
 
+pub fn subscribe_filtered(
+    task_uuid: String,
+    subscriber_uuid: String,
+    subscriber: TaskSubscriber,
+    filter: TaskFilter,
+) {
+    start().do_send(
+        TaskSubscription::subscribe(task_uuid, subscriber_uuid, subscriber)
+            .with_filter(filter)
+    );
+}
+
 pub fn subscribe_no_addr(
     task_uuid: String,
     subscriber_uuid: String,
     name: String,
     by_name: bool,
+    filter: TaskFilter,
 ) {
     start().do_send(
         TaskSubscription::subscribe_no_addr(
@@ -775,10 +1225,11 @@ pub fn subscribe_no_addr(
             subscriber_uuid,
             name,
             by_name,
-        )
+        ).with_filter(filter)
     );
 }
 
+/// `name` may be a pattern, e.g. "site.**". See `name_pattern`.
 pub fn subscribe_by_name(name: String, subscriber_uuid: String) {
     start().do_send(
         TaskSubscription::subscribe_no_addr(
@@ -790,6 +1241,21 @@ pub fn subscribe_by_name(name: String, subscriber_uuid: String) {
     );
 }
 
+pub fn subscribe_by_name_filtered(
+    name: String,
+    subscriber_uuid: String,
+    filter: TaskFilter,
+) {
+    start().do_send(
+        TaskSubscription::subscribe_no_addr(
+            String::new(),
+            subscriber_uuid,
+            name,
+            true,
+        ).with_filter(filter)
+    );
+}
+
 pub fn unsubscribe(task_uuid: String, subscriber_uuid: String) {
     start().do_send(
         TaskSubscription::unsubscribe(task_uuid, subscriber_uuid)
@@ -805,3 +1271,27 @@ pub fn unsubscribe_by_name(name: String, subscriber_uuid: String) {
 pub fn start() -> Addr<TaskTracker> {
     TaskTracker::from_registry()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_filter() {
+        let update = |status, tag| {
+            TaskUpdate::new(String::new(), status, tag, String::new())
+        };
+        let started = update(TaskStatus::Running, TaskUpdateTag::Started);
+        let failed =
+            update(TaskStatus::FinishedFailure, TaskUpdateTag::Finished);
+
+        assert!(TaskFilter::default().matches(&started));
+        assert!(!TaskFilter::finished().matches(&started));
+        assert!(TaskFilter::finished().matches(&failed));
+
+        let filter: TaskFilter = serde_json::from_str(
+            r#"{ "statuses": ["finished_success"], "tags": ["finished"] }"#
+        ).unwrap();
+        assert!(!filter.matches(&failed));
+    }
+}
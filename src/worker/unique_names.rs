@@ -0,0 +1,127 @@
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use crate::{
+    core::env,
+    worker::name_pattern::NamePattern,
+};
+
+lazy_static! {
+    static ref PARAMS: UniqueTasksParams =
+        env::load_opt("unique_tasks").unwrap_or_default();
+
+    static ref PATTERNS: Vec<NamePattern> = PARAMS.names.iter()
+        .filter_map(|n| NamePattern::parse(n).ok())
+        .collect();
+
+    /// Task Name --> UUID of the task having claimed it
+    static ref CLAIMED: Mutex<HashMap<String, String>> =
+        Mutex::new(HashMap::new());
+}
+
+/// `[unique_tasks]` configuration section of the task names run one task at
+/// a time.
+#[derive(Default, Deserialize)]
+struct UniqueTasksParams {
+    #[serde(default)]
+    names: Vec<String>,
+
+    #[serde(default)]
+    mode: UniqueMode,
+}
+
+/// What the processor does with a task whose name is claimed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UniqueMode {
+    #[default]
+    Reject,
+
+    /// Process it once the name is released.
+    Queue,
+
+    /// Keep the latest task submitted, replacing the pending one if any,
+    /// and process it once the name is released. E.g. for refresh jobs
+    /// whose intermediate runs would be outdated anyway.
+    LatestWins,
+}
+
+/// A task not processed as another of its name is running.
+#[derive(Clone, Debug, Serialize)]
+pub struct UniqueRejection {
+    pub task_uuid: String,
+    pub name: String,
+    pub running_task_uuid: String,
+}
+
+impl fmt::Display for UniqueRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[TASK UUID] {} rejected: [NAME] {} is already running \
+                [TASK UUID] {}",
+            self.task_uuid,
+            self.name,
+            self.running_task_uuid,
+        )
+    }
+}
+
+pub fn mode() -> UniqueMode {
+    PARAMS.mode
+}
+
+pub fn is_unique(name: &str) -> bool {
+    PATTERNS.iter().any(|p| p.matches(name))
+}
+
+/// Claim `name` for the task, again if already claimed by it.
+pub fn claim(name: &str, task_uuid: &str) -> Result<(), UniqueRejection> {
+    let mut claimed = CLAIMED.lock().unwrap();
+    match claimed.get(name) {
+        Some(uuid) if uuid != task_uuid => Err(UniqueRejection {
+            task_uuid: task_uuid.to_string(),
+            name: name.to_string(),
+            running_task_uuid: uuid.clone(),
+        }),
+        Some(_) => Ok(()),
+        None => {
+            claimed.insert(name.to_string(), task_uuid.to_string());
+            Ok(())
+        },
+    }
+}
+
+pub fn is_claimed(name: &str) -> bool {
+    CLAIMED.lock().unwrap().contains_key(name)
+}
+
+/// Release `name` if claimed by the task.
+pub fn release(name: &str, task_uuid: &str) {
+    let mut claimed = CLAIMED.lock().unwrap();
+    if claimed.get(name).is_some_and(|uuid| uuid == task_uuid) {
+        claimed.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claims() {
+        let name = "unique_names_test";
+        assert!(claim(name, "a").is_ok());
+        assert!(claim(name, "a").is_ok());
+
+        let rejection = claim(name, "b").unwrap_err();
+        assert_eq!(rejection.running_task_uuid, "a");
+
+        release(name, "b");
+        assert!(is_claimed(name));
+        release(name, "a");
+        assert!(claim(name, "b").is_ok());
+        release(name, "b");
+    }
+}
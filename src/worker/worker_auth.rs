@@ -0,0 +1,40 @@
+use crate::core::env;
+
+/// `worker_auth.tokens.<worker_id>`, else `worker_auth.token`.
+pub fn token(worker_id: &str) -> Option<String> {
+    env::get_opt_var(&format!("worker_auth.tokens.{}", worker_id))
+        .or_else(|| env::get_opt_var("worker_auth.token"))
+        .filter(|t| !t.is_empty())
+}
+
+/// `details.token` of a `started` or a heartbeat response of an external
+/// worker.
+pub fn verify(expected: &str, details: &serde_json::Value) -> bool {
+    match details.get("token").and_then(|t| t.as_str()) {
+        Some(token) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+        None => false,
+    }
+}
+
+/// Does not tell by the time how much of the token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn verify_token() {
+        assert!(verify("secret", &json!({ "token": "secret" })));
+        assert!(!verify("secret", &json!({ "token": "secreT" })));
+        assert!(!verify("secret", &json!({ "token": "secret1" })));
+        assert!(!verify("secret", &json!({})));
+    }
+}
@@ -3,10 +3,98 @@ use serde_json;
 use std::fmt;
 
 use crate::{
+    core::telemetry::TraceContext,
     transport::message::*,
     worker::plugin::{WorkerPlugin},
 };
 
+/// Version of the Controller <-> Worker protocol spoken by this side.
+///
+/// 1. No `protocol_version`.
+/// 2. The worker copies `correlation_id` of a request to the reply.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest worker protocol still supported.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Messages without `protocol_version` are from the older workers.
+pub(crate) fn legacy_protocol_version() -> u32 { 1 }
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// A message of a protocol version this side does not support.
+    Unsupported(u32),
+
+    /// The worker supports protocol versions `.0..=.1` only.
+    Incompatible(u32, u32),
+
+    /// The message does not match its protocol version.
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Unsupported(v) => write!(
+                f,
+                "Unsupported worker protocol version {} (supported {}..={})",
+                v,
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION,
+            ),
+            ProtocolError::Incompatible(min, max) => write!(
+                f,
+                "Worker protocol versions {}..={} are incompatible with \
+                    {}..={}",
+                min,
+                max,
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION,
+            ),
+            ProtocolError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The highest protocol version supported by both sides.
+pub fn negotiate(
+    worker_min: u32,
+    worker_max: u32,
+) -> Result<u32, ProtocolError> {
+    let version = worker_max.min(PROTOCOL_VERSION);
+    if version < MIN_PROTOCOL_VERSION || version < worker_min {
+        return Err(ProtocolError::Incompatible(worker_min, worker_max));
+    }
+
+    Ok(version)
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    #[serde(default = "legacy_protocol_version")]
+    protocol_version: u32,
+}
+
+/// Parse a message from the worker. A message of a newer protocol is
+/// reported as such rather than as a format error.
+pub fn parse(msg: RawMessage) -> Result<WorkerMessage, ProtocolError> {
+    let version = serde_json::from_str::<VersionOnly>(&msg.body)
+        .map(|v| v.protocol_version)
+        .unwrap_or_else(|_| legacy_protocol_version());
+
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(ProtocolError::Unsupported(version));
+    }
+
+    RawMessage::to::<WorkerMessagePayload>(msg).map_err(|e| {
+        if version > PROTOCOL_VERSION {
+            ProtocolError::Unsupported(version)
+        } else {
+            ProtocolError::Invalid(e)
+        }
+    })
+}
+
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Dest {
@@ -15,6 +103,10 @@ pub enum Dest {
     Worker,
     ExternalIn,
     ExternalOut,
+
+    /// A remote node hosting worker processes. `worker_id` is the node ID.
+    Node,
+
     Unknown,
 }
 
@@ -26,6 +118,7 @@ impl Dest {
             "worker" => Dest::Worker,
             "external_in" => Dest::ExternalIn,
             "external_out" => Dest::ExternalOut,
+            "node" => Dest::Node,
             _ => Dest::Unknown,
         }
     }
@@ -37,6 +130,7 @@ impl Dest {
             Dest::Worker => "worker",
             Dest::ExternalIn => "external_in",
             Dest::ExternalOut => "external_out",
+            Dest::Node => "node",
             _ => "unknown",
         }
     }
@@ -55,7 +149,23 @@ pub struct WorkerMessagePayload {
     pub task_uuid: String,
     #[serde(default)]
     pub plugin: String,
+
+    /// Browser profile the plugin must be set up with.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub profile: String,
+
     pub data: serde_json::Value,
+
+    /// Set on a request awaiting a reply. The worker copies it to the reply.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub correlation_id: String,
+
+    /// The negotiated version on the messages to the worker.
+    #[serde(default = "legacy_protocol_version")]
+    pub protocol_version: u32,
+
+    #[serde(flatten)]
+    pub trace: TraceContext,
 }
 
 impl WorkerMessagePayload {
@@ -75,7 +185,11 @@ impl WorkerMessagePayload {
             worker_id: String::new(),
             task_uuid: String::new(),
             plugin: WorkerPlugin::as_str(WorkerPlugin::Basic).to_string(),
+            profile: String::new(),
             data: serde_json::to_value({}).unwrap(),
+            correlation_id: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            trace: TraceContext::default(),
         }
     }
 }
@@ -124,3 +238,36 @@ macro_rules! handler_impl_worker_message {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_version() {
+        assert_eq!(negotiate(1, 1).unwrap(), 1);
+        let version = negotiate(1, PROTOCOL_VERSION + 1).unwrap();
+        assert_eq!(version, PROTOCOL_VERSION);
+        assert!(negotiate(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2).is_err());
+    }
+
+    #[test]
+    fn parse_legacy_and_newer() {
+        let legacy = RawMessage {
+            identity: new_identity(),
+            body: r#"{"dest":"client","worker_id":"w","task_uuid":"t",
+                "data":{}}"#.to_string(),
+        };
+        let msg = parse(legacy).unwrap();
+        assert_eq!(msg.payload.protocol_version, 1);
+
+        let newer = RawMessage {
+            identity: new_identity(),
+            body: r#"{"protocol_version":99,"dest":{"node":"n"}}"#.to_string(),
+        };
+        match parse(newer) {
+            Err(ProtocolError::Unsupported(99)) => {},
+            r => panic!("Unexpected {:?}", r.map(|m| m.payload.header())),
+        }
+    }
+}
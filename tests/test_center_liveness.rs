@@ -0,0 +1,70 @@
+use serde_json::json;
+use std::{io::Write, time::Duration};
+
+use patoka::{
+    center::{self, connector, message::{self, Subject}},
+    core::env,
+    testing::MockCenter,
+    transport::message::RawMessage,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn send(entity_id: &str) {
+    connector::start().do_send(RawMessage::from(message::create(
+        message::Dest::Center,
+        Subject::TaskResult,
+        entity_id.to_string(),
+        String::new(),
+        json!({}),
+    )));
+}
+
+async fn wait_until<F: Fn() -> bool>(f: F) -> bool {
+    let deadline = std::time::Instant::now() + TIMEOUT;
+    while !f() {
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        actix::clock::sleep(Duration::from_millis(50)).await;
+    }
+    true
+}
+
+fn alive() -> bool {
+    connector::status()[0].alive
+}
+
+#[actix::test]
+async fn test_buffer_while_center_is_down() {
+    let center = MockCenter::bind("tcp://127.0.0.1:*").unwrap();
+    let address = center.address().to_string();
+
+    let config_path = std::env::temp_dir().join("patoka_center_liveness.toml");
+    let mut config = std::fs::File::create(&config_path).unwrap();
+    write!(
+        config,
+        "[center]\naddress = \"{}\"\nping_interval_s = 1\n\
+         pong_timeout_s = 2\n",
+        address,
+    ).unwrap();
+    env::load(config_path.to_str().unwrap()).unwrap();
+
+    center::router::start();
+
+    assert!(wait_until(alive).await);
+    assert!(!center.received_subject(Subject::Ping).is_empty());
+
+    drop(center);
+    assert!(wait_until(|| !alive()).await);
+
+    send("task_1");
+    assert!(wait_until(|| connector::status()[0].buffered == 1).await);
+
+    let center = MockCenter::bind(&address).unwrap();
+    let result = |m: &message::CenterMessagePayload| m.entity_id == "task_1";
+    assert!(center.wait_for(TIMEOUT, result).await.is_some());
+    assert_eq!(connector::status()[0].buffered, 0);
+
+    let _ = std::fs::remove_file(config_path);
+}
@@ -0,0 +1,90 @@
+use actix::prelude::*;
+use serde_json::json;
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use patoka::{
+    center::{self, connector, message},
+    control::{message::*, registry},
+    core::env,
+    testing::MockCenter,
+    transport::message::RawMessage,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct ControlReceiver {
+    received: Arc<Mutex<Vec<ControlMessage>>>,
+}
+
+impl Actor for ControlReceiver {
+    type Context = Context<Self>;
+}
+
+impl Handler<ControlMessage> for ControlReceiver {
+    type Result = ();
+
+    fn handle(&mut self, msg: ControlMessage, _ctx: &mut Self::Context) {
+        self.received.lock().unwrap().push(msg);
+    }
+}
+
+#[actix::test]
+async fn test_mock_center() {
+    let center = MockCenter::bind("tcp://127.0.0.1:*").unwrap();
+
+    // The connector buffers the messages until the router is connected.
+    let config_path = std::env::temp_dir().join("patoka_mock_center.toml");
+    let mut config = std::fs::File::create(&config_path).unwrap();
+    write!(config, "[center]\naddress = \"{}\"\n", center.address())
+        .unwrap();
+    env::load(config_path.to_str().unwrap()).unwrap();
+
+    center::router::start();
+
+    connector::start().do_send(RawMessage::from(message::create(
+        message::Dest::Center,
+        message::Subject::TaskStatusUpdate,
+        "task_1".to_string(),
+        "started".to_string(),
+        json!({}),
+    )));
+
+    let update = center.wait_for(TIMEOUT, |m| {
+        m.subject == message::Subject::TaskStatusUpdate
+    }).await.expect("No task status update");
+    assert_eq!(update.entity_id, "task_1");
+    assert_eq!(update.message, "started");
+
+    // Control messages are routed to the registered entity.
+    let received = Arc::new(Mutex::new(vec![]));
+    let receiver = ControlReceiver { received: received.clone() }.start();
+    registry::register("task_1".to_string(), receiver.recipient());
+
+    center.send_control(ControlMessage {
+        uuid: "control_1".to_string(),
+        type_: Type::Request,
+        dest_id: "task_1".to_string(),
+        orig_id: "center".to_string(),
+        cmd: "stop_task".to_string(),
+        data: json!({}),
+        role: None,
+        trace: Default::default(),
+    });
+
+    for _ in 0..500 {
+        if !received.lock().unwrap().is_empty() {
+            break;
+        }
+        actix::clock::sleep(Duration::from_millis(10)).await;
+    }
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].cmd, "stop_task");
+
+    let _ = std::fs::remove_file(config_path);
+}
@@ -0,0 +1,61 @@
+use serde_json::json;
+use std::{io::Write, time::Duration};
+
+use patoka::{
+    center::{self, connector, message::{self, Subject}},
+    core::env,
+    testing::MockCenter,
+    transport::message::RawMessage,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn send(subject: Subject, entity_id: &str) {
+    connector::start().do_send(RawMessage::from(message::create(
+        message::Dest::Center,
+        subject,
+        entity_id.to_string(),
+        String::new(),
+        json!({}),
+    )));
+}
+
+#[actix::test]
+async fn test_replicate_by_subject() {
+    let production = MockCenter::bind("tcp://127.0.0.1:*").unwrap();
+    let staging = MockCenter::bind("tcp://127.0.0.1:*").unwrap();
+
+    let config_path = std::env::temp_dir().join("patoka_multi_center.toml");
+    let mut config = std::fs::File::create(&config_path).unwrap();
+    write!(
+        config,
+        "[center]\naddress = \"{}\"\n\n\
+         [center.endpoints.staging]\naddress = \"{}\"\n\
+         subjects = [\"task_result\"]\n",
+        production.address(),
+        staging.address(),
+    ).unwrap();
+    env::load(config_path.to_str().unwrap()).unwrap();
+
+    center::router::start();
+
+    send(Subject::TaskStatusUpdate, "task_1");
+    send(Subject::TaskResult, "task_1");
+
+    let result = |m: &message::CenterMessagePayload| {
+        m.subject == Subject::TaskResult
+    };
+    assert!(production.wait_for(TIMEOUT, result).await.is_some());
+    assert!(staging.wait_for(TIMEOUT, result).await.is_some());
+
+    let updates = production.received_subject(Subject::TaskStatusUpdate);
+    assert_eq!(updates.len(), 1);
+    let updates = staging.received_subject(Subject::TaskStatusUpdate);
+    assert!(updates.is_empty());
+
+    let status = connector::status();
+    assert_eq!(status.len(), 2);
+    assert!(status.iter().all(|s| s.connected));
+
+    let _ = std::fs::remove_file(config_path);
+}
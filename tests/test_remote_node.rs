@@ -0,0 +1,81 @@
+use serde_json::json;
+use std::{io::Write, time::Duration};
+
+use patoka::{
+    core::env,
+    transport::router::CONTEXT,
+    worker::{
+        dispatcher,
+        node_registry::{self, StartWorker},
+        router,
+    },
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const ROUTER_ADDRESS: &str = "tcp://127.0.0.1:38431";
+
+fn node_message(subject: &str) -> String {
+    json!({
+        "dest": "node",
+        "worker_id": "node_1",
+        "task_uuid": "",
+        "data": { "subject": subject, "details": {} },
+    }).to_string()
+}
+
+async fn wait_until<F: Fn() -> bool>(f: F) -> bool {
+    let deadline = std::time::Instant::now() + TIMEOUT;
+    while !f() {
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        actix::clock::sleep(Duration::from_millis(20)).await;
+    }
+    true
+}
+
+#[actix::test]
+async fn test_register_and_start_worker() {
+    let config_path = std::env::temp_dir().join("patoka_remote_node.toml");
+    let mut config = std::fs::File::create(&config_path).unwrap();
+    write!(
+        config,
+        "[worker_routers.remote]\nbind = \"{}\"\n\n\
+         [worker_nodes.nodes.node_1]\nrouter = \"remote\"\n\
+         controllers = [\"^7$\"]\n",
+        ROUTER_ADDRESS,
+    ).unwrap();
+    env::load(config_path.to_str().unwrap()).unwrap();
+
+    dispatcher::start();
+    router::start();
+    node_registry::start();
+
+    assert_eq!(node_registry::node_for_controller("7"), Some("node_1"));
+    assert_eq!(router::for_controller("7").unwrap().name, "remote");
+
+    let node = CONTEXT.socket(zmq::DEALER).unwrap();
+    node.connect(ROUTER_ADDRESS).unwrap();
+    node.send(node_message("register").as_bytes(), 0).unwrap();
+
+    let online = || node_registry::status().iter()
+        .any(|n| n.node_id == "node_1" && n.online);
+    assert!(wait_until(online).await);
+
+    node_registry::start().do_send(StartWorker {
+        node_id: "node_1".to_string(),
+        worker_id: "7".to_string(),
+        controller: ROUTER_ADDRESS.to_string(),
+    });
+
+    let readable = || node.poll(zmq::POLLIN, 0).is_ok_and(|n| n > 0);
+    assert!(wait_until(readable).await);
+
+    let body = node.recv_string(0).unwrap().unwrap();
+    let msg: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(msg["dest"], "node");
+    assert_eq!(msg["data"]["subject"], "start_worker");
+    assert_eq!(msg["data"]["details"]["worker_id"], "7");
+
+    let _ = std::fs::remove_file(config_path);
+}
@@ -0,0 +1,49 @@
+use actix::prelude::*;
+
+use patoka::{
+    core::logger::create_logger,
+    transport::{
+        message::RawMessage,
+        router::{MessageRouter, CONTEXT},
+        router_registry::{self, StopAndJoin},
+    },
+};
+
+struct Sink;
+
+impl Actor for Sink {
+    type Context = Context<Self>;
+}
+
+impl Handler<RawMessage> for Sink {
+    type Result = ();
+
+    fn handle(&mut self, _msg: RawMessage, _ctx: &mut Self::Context) {}
+}
+
+#[actix::test]
+async fn test_stop_and_join() {
+    let frontend = "inproc://shutdown_router_fe";
+    let backend = "inproc://shutdown_router_be";
+
+    MessageRouter::start(
+        create_logger("shutdown_router"),
+        Sink.start().recipient(),
+        frontend.to_string(),
+        backend.to_string(),
+        false,
+    );
+
+    // Let the router bind.
+    actix::clock::sleep(std::time::Duration::from_millis(100)).await;
+
+    router_registry::start()
+        .send(StopAndJoin { address: Some(backend.to_string()) })
+        .await
+        .unwrap();
+
+    // The sockets are closed: the addresses are free again.
+    let socket = CONTEXT.socket(zmq::ROUTER).unwrap();
+    socket.bind(frontend).unwrap();
+    socket.bind(backend).unwrap();
+}
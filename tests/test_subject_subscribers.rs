@@ -0,0 +1,75 @@
+use actix::prelude::*;
+use serde_json::json;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use patoka::center::{
+    dispatcher::{self, RegisterEntity, RegisterSubjectSubscriber},
+    message::{self, CenterMessage, Subject},
+};
+
+struct Receiver {
+    received: Arc<Mutex<Vec<CenterMessage>>>,
+}
+
+impl Actor for Receiver {
+    type Context = Context<Self>;
+}
+
+impl Handler<CenterMessage> for Receiver {
+    type Result = ();
+
+    fn handle(&mut self, msg: CenterMessage, _ctx: &mut Self::Context) {
+        self.received.lock().unwrap().push(msg);
+    }
+}
+
+fn receiver() -> (Recipient<CenterMessage>, Arc<Mutex<Vec<CenterMessage>>>) {
+    let received = Arc::new(Mutex::new(vec![]));
+    let addr = Receiver { received: received.clone() }.start();
+    (addr.recipient(), received)
+}
+
+fn to_app(subject: Subject, entity_id: &str) -> CenterMessage {
+    message::create(
+        message::Dest::App,
+        subject,
+        entity_id.to_string(),
+        String::new(),
+        json!({}),
+    )
+}
+
+#[actix::test]
+async fn test_subject_fan_out() {
+    let dispatcher = dispatcher::start();
+
+    let (entity, entity_received) = receiver();
+    dispatcher.do_send(RegisterEntity {
+        entity_id: "task_1".to_string(),
+        entity_addr: entity,
+    });
+
+    let (analytics, analytics_received) = receiver();
+    dispatcher.do_send(RegisterSubjectSubscriber::register(
+        Subject::TaskResult,
+        "analytics".to_string(),
+        analytics,
+    ));
+
+    dispatcher.do_send(to_app(Subject::TaskResult, "task_1"));
+    dispatcher.do_send(to_app(Subject::TaskResult, "task_2"));
+    dispatcher.do_send(to_app(Subject::TaskQuestion, "task_1"));
+
+    actix::clock::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(entity_received.lock().unwrap().len(), 2);
+
+    let analytics_received = analytics_received.lock().unwrap();
+    let entities: Vec<_> = analytics_received.iter()
+        .map(|m| m.payload.entity_id.as_str())
+        .collect();
+    assert_eq!(entities, ["task_1", "task_2"]);
+}
@@ -0,0 +1,157 @@
+use actix::prelude::*;
+use serde_json::json;
+use std::time::Duration;
+
+use patoka::{
+    control::message::StopTask,
+    testing::{self, clock, FakeController},
+    worker::{
+        client::{ClientContext, GenClientContext, WorkerClient},
+        client_template::TaskClient,
+        plugin::WorkerPlugin,
+        setup::setup_with_controller,
+        task::{GenTaskDefinition, WorkerTask},
+        worker_message::WorkerMessage,
+    },
+};
+
+type Definition = GenTaskDefinition<serde_json::Value>;
+
+/// Stops on the first task result.
+#[derive(Clone)]
+struct EchoClient {
+    ctx: ClientContext<Definition>,
+}
+
+impl WorkerClient for EchoClient {
+    type TaskDefinition = Definition;
+
+    fn new(ctx: ClientContext<Definition>) -> Self {
+        Self { ctx }
+    }
+}
+
+impl Actor for EchoClient {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        setup_with_controller(
+            &self.ctx.task_uuid,
+            None,
+            None,
+            ctx.address().recipient(),
+            &self.ctx.controller_addr,
+            self.ctx.task_definition.make_message(),
+            self.ctx.task_definition.name.clone(),
+        );
+    }
+}
+
+impl Handler<WorkerMessage> for EchoClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: WorkerMessage, ctx: &mut Self::Context) {
+        if msg.result::<serde_json::Value>().is_some() {
+            ctx.stop();
+        }
+    }
+}
+
+impl Handler<StopTask> for EchoClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: StopTask, ctx: &mut Self::Context) {
+        self.handle_stop_task(msg, ctx);
+    }
+}
+
+/// `EchoClient` on the client template.
+#[derive(Clone)]
+struct TemplateClient {
+    ctx: GenClientContext<serde_json::Value>,
+}
+
+impl TaskClient for TemplateClient {
+    type Params = serde_json::Value;
+
+    fn new(ctx: GenClientContext<serde_json::Value>) -> Self {
+        Self { ctx }
+    }
+
+    fn client_ctx(&self) -> &GenClientContext<serde_json::Value> {
+        &self.ctx
+    }
+}
+
+patoka::impl_task_client!(TemplateClient);
+
+fn task() -> WorkerTask<EchoClient> {
+    WorkerTask::new(Definition::new(
+        WorkerPlugin::Basic,
+        "echo.js",
+        json!({ "url": "http://a" }),
+        "echo",
+    ))
+}
+
+#[actix::test]
+async fn test_run_task_to_completion() {
+    clock::pause();
+
+    let controller = FakeController::new()
+        .respond_after(
+            Duration::from_secs(30),
+            json!({ "task_result": { "ok": true } }),
+        );
+
+    let started = clock::now();
+    let run = testing::run_task(
+        &task(),
+        controller,
+        testing::DEFAULT_TIMEOUT,
+    ).await;
+
+    assert!(run.finished);
+    assert!(clock::now() - started >= Duration::from_secs(30));
+    assert_eq!(run.received.len(), 1);
+    assert_eq!(run.received[0].payload.task_uuid, run.task_uuid);
+}
+
+#[actix::test]
+async fn test_run_task_timeout() {
+    clock::pause();
+
+    let controller = FakeController::new()
+        .respond(json!({ "task_question": {} }));
+
+    let run = testing::run_task(
+        &task(),
+        controller,
+        Duration::from_secs(5),
+    ).await;
+
+    assert!(!run.finished);
+}
+
+#[actix::test]
+async fn test_template_client_finishes_on_result() {
+    clock::pause();
+
+    let controller = FakeController::new()
+        .respond(json!({ "task_result": { "ok": true } }));
+
+    let task = WorkerTask::<TemplateClient>::new(Definition::new(
+        WorkerPlugin::Basic,
+        "echo.js",
+        json!({ "url": "http://a" }),
+        "echo",
+    ));
+    let run = testing::run_task(
+        &task,
+        controller,
+        testing::DEFAULT_TIMEOUT,
+    ).await;
+
+    assert!(run.finished);
+    assert_eq!(run.received.len(), 1);
+}
@@ -196,3 +196,93 @@ fn test_timeout_timer() {
     assert_eq!(timeout_messages_rx.load(Ordering::Relaxed), 1);
 
 }
+
+/// Takes `latency` to handle each interval message.
+struct FixedRateActor {
+    interval_timer: Timer<IntervalMessage>,
+    timeout_timer: Timer<TimeoutMessage>,
+    interval_messages_rx: Arc<AtomicUsize>,
+    timeout_messages_rx: Arc<AtomicUsize>,
+    latency: Duration,
+}
+
+impl Actor for FixedRateActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.interval_timer.start_interval::<Self>(
+            ctx,
+            Duration::from_millis(100),
+        );
+
+        // Cancelled before it fires.
+        self.timeout_timer =
+            Timer::after::<Self>(ctx, Duration::from_millis(500));
+
+        ctx.notify_later(StopMessage, Duration::from_millis(1050));
+    }
+}
+
+impl Handler<IntervalMessage> for FixedRateActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: IntervalMessage,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.interval_messages_rx.fetch_add(1, Ordering::Relaxed);
+        std::thread::sleep(self.latency);
+
+        if self.timeout_timer.is_active() {
+            self.timeout_timer.cancel::<Self>(ctx);
+        }
+    }
+}
+
+impl Handler<TimeoutMessage> for FixedRateActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: TimeoutMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.timeout_messages_rx.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Handler<StopMessage> for FixedRateActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: StopMessage,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        System::current().stop();
+    }
+}
+
+#[test]
+fn test_fixed_rate_timer() {
+    let interval_messages_rx = Arc::new(AtomicUsize::new(0));
+    let timeout_messages_rx = Arc::new(AtomicUsize::new(0));
+
+    let system = System::new();
+
+    let actor = FixedRateActor {
+        interval_timer: Timer::new(),
+        timeout_timer: Timer::new(),
+        interval_messages_rx: Arc::clone(&interval_messages_rx),
+        timeout_messages_rx: Arc::clone(&timeout_messages_rx),
+        latency: Duration::from_millis(40),
+    };
+    system.block_on(async { actor.start(); });
+
+    system.run().unwrap();
+
+    // Not delayed by the latency.
+    assert_eq!(interval_messages_rx.load(Ordering::Relaxed), 10);
+    assert_eq!(timeout_messages_rx.load(Ordering::Relaxed), 0);
+}